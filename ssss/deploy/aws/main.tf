@@ -49,6 +49,23 @@ resource "aws_kms_alias" "signer" {
   target_key_id = aws_kms_key.signer.key_id
 }
 
+resource "aws_kms_key" "kek" {
+  description             = "Escrin SSSS stored key wrapper (${terraform.workspace})"
+  key_usage               = "ENCRYPT_DECRYPT"
+  enable_key_rotation     = true
+  deletion_window_in_days = 30
+  tags                    = local.tags
+
+  lifecycle {
+    prevent_destroy = true
+  }
+}
+
+resource "aws_kms_alias" "kek" {
+  name          = "alias/escrin-kek-${terraform.workspace}"
+  target_key_id = aws_kms_key.kek.key_id
+}
+
 resource "aws_dynamodb_table" "secrets" {
   name         = "escrin-secrets-${terraform.workspace}"
   billing_mode = "PAY_PER_REQUEST"
@@ -98,6 +115,43 @@ resource "aws_dynamodb_table" "verifiers" {
   }
 }
 
+resource "aws_dynamodb_table" "nonces" {
+  name         = "escrin-nonces-${terraform.workspace}"
+  billing_mode = "PAY_PER_REQUEST"
+  hash_key     = "id"
+  tags         = local.tags
+
+  attribute {
+    name = "id"
+    type = "S"
+  }
+
+  ttl {
+    attribute_name = "expiry"
+    enabled        = true
+  }
+
+  lifecycle {
+    prevent_destroy = false
+  }
+}
+
+resource "aws_dynamodb_table" "grant_ledgers" {
+  name         = "escrin-grant-ledgers-${terraform.workspace}"
+  billing_mode = "PAY_PER_REQUEST"
+  hash_key     = "identity"
+  tags         = local.tags
+
+  attribute {
+    name = "identity"
+    type = "S"
+  }
+
+  lifecycle {
+    prevent_destroy = false
+  }
+}
+
 data "aws_iam_policy_document" "policy" {
   statement {
     effect = "Allow"
@@ -110,6 +164,18 @@ data "aws_iam_policy_document" "policy" {
     ]
   }
 
+  statement {
+    effect = "Allow"
+    actions = [
+      "kms:DescribeKey",
+      "kms:Encrypt",
+      "kms:Decrypt",
+    ]
+    resources = [
+      "${aws_kms_key.kek.arn}",
+    ]
+  }
+
   statement {
     effect = "Allow"
     actions = [
@@ -123,6 +189,8 @@ data "aws_iam_policy_document" "policy" {
     resources = [
       "${aws_dynamodb_table.secrets.arn}",
       "${aws_dynamodb_table.verifiers.arn}",
+      "${aws_dynamodb_table.nonces.arn}",
+      "${aws_dynamodb_table.grant_ledgers.arn}",
     ]
   }
 }
@@ -122,7 +122,7 @@ resource "azurerm_role_assignment" "instance" {
 
 
 locals {
-  storage_tables = ["secretversions", "verifiers"]
+  storage_tables = ["secretversions", "verifiers", "nonces", "grantledgers"]
 }
 
 resource "azurerm_storage_table" "storage" {
@@ -35,8 +35,13 @@ pub enum Command {
         #[command(flatten)]
         il: IdentityLocatorArgs,
 
-        #[arg(short, long, value_enum, required = true)]
-        verifier: PolicyVerifier,
+        /// The name of the verifier with which the SSSSs will evaluate the policy (e.g., `nitro`).
+        #[arg(short, long, required = true)]
+        verifier: String,
+
+        /// The file from which to read the JSON grant limits that the SSSSs will enforce, if any.
+        #[arg(long, value_hint = ValueHint::FilePath)]
+        limits_path: Option<String>,
 
         /// The file from which to read the JSON policy document or stdin if not specified.
         #[arg(value_hint = ValueHint::FilePath)]
@@ -224,26 +229,6 @@ pub struct WritePermitterArgs {
     pub wallet: Wallet,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
-#[value(rename_all = "lowercase")]
-pub enum PolicyVerifier {
-    Mock,
-    Nitro,
-}
-
-impl std::fmt::Display for PolicyVerifier {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::Mock => "mock",
-                Self::Nitro => "nitro",
-            }
-        )
-    }
-}
-
 macro_rules! impl_deref_for_args {
     ($( $ty:ty { $prop:ident: $target:ty }),+ $(,)? ) => {
         $(
@@ -9,19 +9,75 @@ use ethers::{
     signers::{LocalWallet, Signer as _},
     types::{transaction::eip712::Eip712 as _, Address, H256},
 };
-use eyre::{ensure, Context, Result};
+use eyre::{ensure, Context};
 use headers::Header as _;
 use rand::RngCore as _;
 use reqwest::{Method, RequestBuilder, Response, StatusCode};
-use ssss::types::{api::*, *};
+use ssss::{
+    keypair::{HpkeKeyPair, KeyPair, MlKemKeyPair},
+    types::{api::*, *},
+};
 use tokio::sync::OnceCell;
 
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The ways in which a request to an SSSS can fail.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The SSSS rejected the request.
+    #[error("request failed: {}", .response.error)]
+    Api {
+        status: StatusCode,
+        response: ErrorResponse,
+    },
+    #[error("received unexpected response status: {0}")]
+    UnexpectedStatus(StatusCode),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("{0:#}")]
+    Other(eyre::Report),
+}
+
+impl Error {
+    /// Returns the reason that the SSSS gave for rejecting the request, if it did.
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Self::Api { response, .. } => Some(response.code),
+            _ => None,
+        }
+    }
+
+    /// Returns the structured information that the SSSS gave along with the error code.
+    pub fn details(&self) -> Option<&ErrorDetails> {
+        match self {
+            Self::Api { response, .. } => Some(&response.details),
+            _ => None,
+        }
+    }
+}
+
+impl From<eyre::Report> for Error {
+    fn from(e: eyre::Report) -> Self {
+        Self::Other(e)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SsssClient {
     pub url: url::Url,
     client: reqwest::Client,
     remote_signer: OnceCell<Address>,
-    remote_ek: Arc<Mutex<Option<EphemeralKey>>>,
+    remote_ek: Arc<Mutex<Option<RemoteKey>>>,
+    formats: Vec<PayloadFormat>,
+}
+
+/// The SSSS's ephemeral key and the payload formats it supports.
+#[derive(Clone, Debug)]
+struct RemoteKey {
+    ek: EphemeralKey,
+    formats: Vec<PayloadFormat>,
 }
 
 impl SsssClient {
@@ -31,25 +87,56 @@ impl SsssClient {
             url: ssss,
             remote_signer: Default::default(),
             remote_ek: Default::default(),
+            formats: vec![
+                PayloadFormat::Hpke(HpkeSuite::new(
+                    HpkeKem::X25519HkdfSha256,
+                    HpkeAead::Aes256Gcm,
+                )),
+                PayloadFormat::P384EcdhAes256GcmSiv,
+            ],
         }
     }
 
+    /// Sets the encrypted payload formats that the client may use, in order of preference.
+    /// The first format also supported by the SSSS will be used.
+    pub fn with_formats(mut self, formats: Vec<PayloadFormat>) -> Self {
+        self.formats = formats;
+        self
+    }
+
+    /// Prefers the hybrid P-384 + ML-KEM-768 payload format when the SSSS supports it.
+    pub fn with_post_quantum(mut self) -> Self {
+        self.formats
+            .retain(|f| *f != PayloadFormat::P384MlKem768Aes256GcmSiv);
+        self.formats
+            .insert(0, PayloadFormat::P384MlKem768Aes256GcmSiv);
+        self
+    }
+
     pub async fn signer(&self) -> Result<Address> {
         self.remote_signer
             .get_or_try_init(|| async {
                 let identity = self.fetch_remote_identity().await?;
-                let mut remote_ek = self.remote_ek.lock().unwrap();
-                if identity.ephemeral.expiry
-                    > remote_ek.as_ref().map(|k| k.expiry).unwrap_or_default()
-                {
-                    *remote_ek = Some(identity.ephemeral);
-                }
-                Ok(identity.signer)
+                let signer = identity.signer;
+                self.update_remote_key(identity);
+                Ok(signer)
             })
             .await
             .copied()
     }
 
+    fn update_remote_key(&self, identity: IdentityResponse) -> RemoteKey {
+        let key = RemoteKey {
+            ek: identity.ephemeral,
+            formats: identity.formats,
+        };
+        let mut remote_ek = self.remote_ek.lock().unwrap();
+        if key.ek.expiry > remote_ek.as_ref().map(|k| k.ek.expiry).unwrap_or_default() {
+            *remote_ek = Some(key.clone());
+        }
+        key
+    }
+
     async fn fetch_remote_identity(&self) -> Result<IdentityResponse> {
         Ok(send_request(self.client.get(self.url("v1/identity")))
             .await?
@@ -57,20 +144,64 @@ impl SsssClient {
             .await?)
     }
 
-    async fn ephemeral_key(&self) -> Result<EphemeralKey> {
+    async fn ephemeral_key(&self) -> Result<RemoteKey> {
         {
             let remote_ek = self.remote_ek.lock().unwrap();
             match &*remote_ek {
-                Some(ek) if ek.expiry > ssss::utils::now() + 5 * 60 => return Ok(ek.clone()),
+                Some(key) if key.ek.expiry > ssss::utils::now() + 5 * 60 => return Ok(key.clone()),
                 _ => {}
             }
         }
         let identity = self.fetch_remote_identity().await?;
-        let mut remote_ek = self.remote_ek.lock().unwrap();
-        if identity.ephemeral.expiry > remote_ek.as_ref().map(|k| k.expiry).unwrap_or_default() {
-            *remote_ek = Some(identity.ephemeral.clone());
-        }
-        Ok(identity.ephemeral)
+        Ok(self.update_remote_key(identity))
+    }
+
+    /// Returns the most preferred payload format that is supported by the SSSS.
+    async fn negotiate_format(&self) -> Result<(PayloadFormat, RemoteKey)> {
+        let remote = self.ephemeral_key().await?;
+        let format = self
+            .formats
+            .iter()
+            .copied()
+            .find(|format| {
+                remote.formats.contains(format)
+                    && match format {
+                        PayloadFormat::Hpke(suite) => {
+                            remote.ek.hpke.iter().any(|pk| pk.kem == suite.kem)
+                        }
+                        PayloadFormat::P384MlKem768Aes256GcmSiv => remote.ek.ml_kem_768.is_some(),
+                        _ => true,
+                    }
+            })
+            .ok_or_else(|| eyre::eyre!("no encrypted payload format is supported by the SSSS"))?;
+        Ok((format, remote))
+    }
+
+    /// Returns the policy verifiers that are available on the SSSS.
+    pub async fn verifiers(&self) -> Result<Vec<VerifierInfo>> {
+        Ok(send_request(self.client.get(self.url("v1/verifiers")))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Requests a challenge that an enclave can embed in its attestation nonce.
+    pub async fn challenge(&self) -> Result<ChallengeResponse> {
+        Ok(send_request(self.client.post(self.url("v1/challenges")))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Asks the SSSS whether it would accept the policy without storing it.
+    pub async fn validate_policy(&self, policy: &PolicyDocument) -> Result<()> {
+        let res = send_request(
+            self.client
+                .post(self.url("v1/policies/validate"))
+                .json(policy),
+        )
+        .await?;
+        expect_status(&res, StatusCode::NO_CONTENT)
     }
 
     pub async fn set_policy(
@@ -94,43 +225,18 @@ impl SsssClient {
                 }),
         )
         .await?;
-        ensure!(
-            res.status() == StatusCode::NO_CONTENT,
-            "failed to set policy: received unexpected response status: {}",
-            res.status()
-        );
-        Ok(())
+        expect_status(&res, StatusCode::NO_CONTENT)
     }
 
     pub async fn deal_share(
         &self,
         id: &ShareId,
         share: api::SecretShare,
-        signer: &LocalWallet,
+        signer: impl Into<RequestSigner<'_>>,
     ) -> Result<()> {
-        let mut payload = serde_json::to_vec(&share)?;
-
-        let kp = ssss::keypair::KeyPair::ephemeral();
-
-        let mut nonce = [0u8; 12];
-        rand::thread_rng().fill_bytes(&mut nonce);
-
-        let ssss_key = self.ephemeral_key().await?;
-
-        let cipher = kp.derive_shared_cipher(ssss_key.pk, ssss::keypair::DEAL_SHARES_DOMAIN_SEP);
-        cipher
-            .encrypt_in_place(&nonce.into(), &[], &mut payload)
-            .unwrap();
-
-        let body = EncryptedPayload {
-            format: EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
-                curve: CurveP384,
-                pk: *kp.public_key(),
-                nonce,
-                recipient_key_id: ssss_key.key_id,
-            },
-            payload: payload.into(),
-        };
+        let body = self
+            .encrypt_request(&share, ssss::keypair::DEAL_SHARES_DOMAIN_SEP)
+            .await?;
 
         let ShareId {
             identity:
@@ -145,13 +251,16 @@ impl SsssClient {
         let paq =
             format!("v1/shares/{secret_name}/{chain}/{registry:x}/{identity}?version={version}");
 
-        let res = send_request(self.make_escrin1_req(Method::POST, paq, &body, signer)?).await?;
-        ensure!(res.status() == StatusCode::CREATED, "share not dealt");
-
-        Ok(())
+        let res =
+            send_request(self.make_escrin2_req(Method::POST, paq, &body, signer.into())?).await?;
+        expect_status(&res, StatusCode::CREATED)
     }
 
-    pub async fn commit_share(&self, id: &ShareId, signer: &LocalWallet) -> Result<()> {
+    pub async fn commit_share(
+        &self,
+        id: &ShareId,
+        signer: impl Into<RequestSigner<'_>>,
+    ) -> Result<()> {
         let ShareId {
             identity:
                 IdentityLocator {
@@ -166,7 +275,7 @@ impl SsssClient {
             "v1/shares/{secret_name}/{chain}/{registry:x}/{identity}/commit?version={version}"
         );
 
-        send_request(self.make_escrin1_req(Method::POST, paq, &(), signer)?).await?;
+        send_request(self.make_escrin2_req(Method::POST, paq, &(), signer.into())?).await?;
 
         Ok(())
     }
@@ -174,9 +283,9 @@ impl SsssClient {
     pub async fn get_share(
         &self,
         id: &ShareId,
-        signer: &LocalWallet,
+        signer: impl Into<RequestSigner<'_>>,
     ) -> Result<ssss::types::api::SecretShare> {
-        let kp = ssss::keypair::KeyPair::ephemeral();
+        let kp = ResponseKeyPair::generate(self.negotiate_format().await?.0);
 
         let ShareId {
             identity:
@@ -193,16 +302,74 @@ impl SsssClient {
             kp.fingerprint() // bind the requester public key to the request
         );
 
-        let res = send_request(
-            self.make_escrin1_req(Method::GET, paq, &(), signer)?
-                .header(
-                    RequesterPublicKeyHeader::name().as_str(),
-                    RequesterPublicKeyHeader(*kp.public_key()).to_string(),
-                ),
-        )
-        .await?;
+        let res =
+            send_request(kp.attach(self.make_escrin2_req(Method::GET, paq, &(), signer.into())?))
+                .await?;
+
+        Ok(decrypt_enc_payload::<ShareBody>(
+            res.json().await?,
+            &kp,
+            ssss::keypair::GET_SHARE_DOMAIN_SEP,
+        )?
+        .share)
+    }
+
+    pub async fn put_secret(
+        &self,
+        id: &KeyId,
+        key: WrappedKey,
+        signer: impl Into<RequestSigner<'_>>,
+    ) -> Result<()> {
+        let body = self
+            .encrypt_request(&PutKeyRequest { key }, ssss::keypair::PUT_SECRET_DOMAIN_SEP)
+            .await?;
+
+        let KeyId {
+            name,
+            identity:
+                IdentityLocator {
+                    chain,
+                    registry,
+                    id: identity,
+                },
+            version,
+        } = id;
+        let paq = format!("v1/secrets/{name}/{chain}/{registry:x}/{identity}?version={version}");
 
-        Ok(decrypt_enc_payload::<ShareBody>(res.json().await?, kp)?.share)
+        let res =
+            send_request(self.make_escrin2_req(Method::PUT, paq, &body, signer.into())?).await?;
+        expect_status(&res, StatusCode::CREATED)
+    }
+
+    pub async fn get_secret(
+        &self,
+        id: &KeyId,
+        signer: impl Into<RequestSigner<'_>>,
+    ) -> Result<WrappedKey> {
+        let kp = ResponseKeyPair::generate(self.negotiate_format().await?.0);
+
+        let KeyId {
+            name,
+            identity:
+                IdentityLocator {
+                    chain,
+                    registry,
+                    id: identity,
+                },
+            version,
+        } = id;
+        let paq = format!(
+            "v1/secrets/{name}/{chain}/{registry:x}/{identity}?version={version}&pk={}",
+            kp.fingerprint() // bind the requester public key to the request
+        );
+
+        let res =
+            send_request(kp.attach(self.make_escrin2_req(Method::GET, paq, &(), signer.into())?))
+                .await?;
+
+        let KeyResponse { key } =
+            decrypt_enc_payload(res.json().await?, &kp, ssss::keypair::GET_SECRET_DOMAIN_SEP)?;
+        Ok(Vec::from(key.0).into())
     }
 
     /// Returns whether the SSSS optimistically granted the permit.
@@ -244,7 +411,7 @@ impl SsssClient {
         };
 
         let req = match signer {
-            Some(signer) => self.make_escrin1_req(method, paq, &params, signer)?,
+            Some(signer) => self.make_escrin2_req(method, paq, &params, signer.into())?,
             None => self
                 .client
                 .request(method.clone(), self.url(paq))
@@ -254,13 +421,79 @@ impl SsssClient {
         Ok(send_request(req).await?.json().await?)
     }
 
-    fn make_escrin1_req(
+    /// Encrypts a request body to the SSSS's current ephemeral key.
+    async fn encrypt_request(
+        &self,
+        body: &impl serde::Serialize,
+        domain_sep: &[u8],
+    ) -> eyre::Result<EncryptedPayload> {
+        let mut payload = serde_json::to_vec(body)?;
+
+        let (format, RemoteKey { ek: ssss_key, .. }) = self.negotiate_format().await?;
+
+        if let PayloadFormat::Hpke(suite) = format {
+            let ssss_pk = ssss_key
+                .hpke
+                .iter()
+                .find(|pk| pk.kem == suite.kem)
+                .expect("negotiated format");
+            let (enc, ct) = ssss::keypair::hpke_seal(suite, &ssss_pk.pk, domain_sep, &payload)
+                .map_err(|e| eyre::eyre!("encryption error: {e}"))?;
+            return Ok(EncryptedPayload {
+                format: EncryptedPayloadFormat::Hpke {
+                    suite,
+                    enc: enc.into(),
+                    recipient_key_id: ssss_key.key_id,
+                },
+                payload: ct.into(),
+            });
+        }
+
+        let kp = KeyPair::ephemeral();
+
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let (cipher, mlkem_ct) = match (format, &ssss_key.ml_kem_768) {
+            (PayloadFormat::P384MlKem768Aes256GcmSiv, Some(ek)) => {
+                let (cipher, ct) = kp.encapsulate_hybrid_cipher(ssss_key.pk, ek, domain_sep)?;
+                (cipher, Some(ct))
+            }
+            _ => (kp.derive_shared_cipher(ssss_key.pk, domain_sep), None),
+        };
+        cipher
+            .encrypt_in_place(&nonce.into(), &[], &mut payload)
+            .map_err(|e| eyre::eyre!("encryption error: {e}"))?;
+
+        let pk = *kp.public_key();
+        let recipient_key_id = ssss_key.key_id;
+        Ok(EncryptedPayload {
+            format: match mlkem_ct {
+                Some(ct) => EncryptedPayloadFormat::P384MlKem768Aes256GcmSiv {
+                    curve: CurveP384,
+                    pk,
+                    ct: ct.into(),
+                    nonce,
+                    recipient_key_id,
+                },
+                None => EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
+                    curve: CurveP384,
+                    pk,
+                    nonce,
+                    recipient_key_id,
+                },
+            },
+            payload: payload.into(),
+        })
+    }
+
+    fn make_escrin2_req(
         &self,
         method: Method,
         paq: impl AsRef<str>,
         body: &impl serde::Serialize,
-        signer: &LocalWallet,
-    ) -> Result<RequestBuilder> {
+        signer: RequestSigner<'_>,
+    ) -> eyre::Result<RequestBuilder> {
         let req = self.client.request(method.clone(), self.url(paq.as_ref()));
         let (body_hash, req) = if matches!(method, Method::GET | Method::HEAD) {
             (Default::default(), req)
@@ -268,29 +501,51 @@ impl SsssClient {
             let body_bytes = serde_json::to_vec(&body)?;
             (keccak256(body_bytes).into(), req.json(body))
         };
-        let req721 = SsssRequest {
+        let req721 = SsssRequest2 {
             method: method.to_string(),
             url: format!("{}/{}", self.url.authority(), paq.as_ref()),
             body: body_hash,
+            issued_at: ssss::utils::now(),
+            nonce: H256::random(),
         };
-        Self::attach_escrin1_sig(req, req721, signer)
+        Self::attach_escrin2_sig(req, req721, signer)
     }
 
-    fn attach_escrin1_sig(
+    fn attach_escrin2_sig(
         req: RequestBuilder,
-        req721: SsssRequest,
-        signer: &LocalWallet,
-    ) -> Result<RequestBuilder> {
+        req721: SsssRequest2,
+        signer: RequestSigner<'_>,
+    ) -> eyre::Result<RequestBuilder> {
         let req_hash = req721.encode_eip712()?;
-        let sig = signer.sign_hash(req_hash.into())?;
+        let (sig, requester, req) = match signer {
+            RequestSigner::Wallet(wallet) => {
+                (wallet.sign_hash(req_hash.into())?, wallet.address(), req)
+            }
+            RequestSigner::Session(session) => (
+                session.key.sign_hash(req_hash.into())?,
+                session.grant.grant.requester,
+                req.header(
+                    SessionGrantHeader::name().as_str(),
+                    SessionGrantHeader(session.grant.clone()).to_string(),
+                ),
+            ),
+        };
         Ok(req
             .header(
                 SignatureHeader::name().as_str(),
-                SignatureHeader(sig).to_string(),
+                SignatureHeader::from(sig).to_string(),
             )
             .header(
                 RequesterHeader::name().as_str(),
-                RequesterHeader(signer.address()).to_string(),
+                RequesterHeader(requester).to_string(),
+            )
+            .header(
+                IssuedAtHeader::name().as_str(),
+                IssuedAtHeader(req721.issued_at).to_string(),
+            )
+            .header(
+                RequestNonceHeader::name().as_str(),
+                RequestNonceHeader(req721.nonce).to_string(),
             ))
     }
 
@@ -299,26 +554,174 @@ impl SsssClient {
     }
 }
 
+/// Who signs a request: either the requester's own wallet or a session key acting for it.
+#[derive(Clone, Copy)]
+pub enum RequestSigner<'a> {
+    Wallet(&'a LocalWallet),
+    Session(&'a Session),
+}
+
+impl<'a> From<&'a LocalWallet> for RequestSigner<'a> {
+    fn from(wallet: &'a LocalWallet) -> Self {
+        Self::Wallet(wallet)
+    }
+}
+
+impl<'a> From<&'a Session> for RequestSigner<'a> {
+    fn from(session: &'a Session) -> Self {
+        Self::Session(session)
+    }
+}
+
+/// A short-lived key authorized by a requester to make requests for a single identity,
+/// so that the requester's own key need not be kept online.
+#[derive(Clone, Debug)]
+pub struct Session {
+    key: LocalWallet,
+    grant: SignedSessionGrant,
+}
+
+impl Session {
+    /// Creates a new session key and has `requester` sign a grant to it for the
+    /// comma-separated `scope` (e.g., `shares,secrets`) that is valid for `lifetime`, which may
+    /// be at most [`MAX_SESSION_LIFETIME`] seconds.
+    pub fn grant(
+        requester: &LocalWallet,
+        IdentityLocator {
+            chain,
+            registry,
+            id,
+        }: IdentityLocator,
+        scope: &str,
+        lifetime: std::time::Duration,
+    ) -> eyre::Result<Self> {
+        ensure!(
+            lifetime.as_secs() <= MAX_SESSION_LIFETIME,
+            "session lifetime exceeds {MAX_SESSION_LIFETIME} seconds"
+        );
+        let key = LocalWallet::new(&mut rand::thread_rng());
+        let now = ssss::utils::now();
+        let grant = SsssSessionGrant {
+            requester: requester.address(),
+            session_key: key.address(),
+            chain,
+            registry,
+            identity: id.0,
+            scope: scope.into(),
+            not_before: now,
+            expiry: now + lifetime.as_secs(),
+        };
+        let signature = requester.sign_hash(grant.encode_eip712()?.into())?;
+        Ok(Self {
+            key,
+            grant: SignedSessionGrant {
+                grant,
+                signature: signature.to_vec().into(),
+            },
+        })
+    }
+
+    pub fn expiry(&self) -> u64 {
+        self.grant.grant.expiry
+    }
+}
+
+/// The key pair to which the SSSS should encrypt its response.
+enum ResponseKeyPair {
+    P384(KeyPair),
+    Hybrid(KeyPair, Box<MlKemKeyPair>),
+    Hpke(HpkeSuite, HpkeKeyPair),
+}
+
+impl ResponseKeyPair {
+    fn generate(format: PayloadFormat) -> Self {
+        match format {
+            PayloadFormat::Hpke(suite) => Self::Hpke(suite, HpkeKeyPair::ephemeral(suite.kem)),
+            PayloadFormat::P384MlKem768Aes256GcmSiv => {
+                Self::Hybrid(KeyPair::ephemeral(), Box::new(MlKemKeyPair::ephemeral()))
+            }
+            _ => Self::P384(KeyPair::ephemeral()),
+        }
+    }
+
+    fn fingerprint(&self) -> String {
+        match self {
+            Self::P384(kp) | Self::Hybrid(kp, _) => kp.fingerprint(),
+            Self::Hpke(_, kp) => kp.fingerprint(),
+        }
+    }
+
+    fn attach(&self, req: RequestBuilder) -> RequestBuilder {
+        match self {
+            Self::P384(kp) => req.header(
+                RequesterPublicKeyHeader::name().as_str(),
+                RequesterPublicKeyHeader(*kp.public_key()).to_string(),
+            ),
+            Self::Hybrid(kp, mlkem) => req
+                .header(
+                    RequesterPublicKeyHeader::name().as_str(),
+                    RequesterPublicKeyHeader(*kp.public_key()).to_string(),
+                )
+                .header(
+                    RequesterMlKemPublicKeyHeader::name().as_str(),
+                    RequesterMlKemPublicKeyHeader(mlkem.public_key().into()).to_string(),
+                ),
+            Self::Hpke(suite, kp) => req.header(
+                RequesterHpkePublicKeyHeader::name().as_str(),
+                RequesterHpkePublicKeyHeader {
+                    suite: *suite,
+                    pk: kp.public_key().pk,
+                }
+                .to_string(),
+            ),
+        }
+    }
+}
+
 fn decrypt_enc_payload<T: serde::de::DeserializeOwned>(
     enc_payload: EncryptedPayload,
-    kp: ssss::keypair::KeyPair,
-) -> Result<T> {
-    let EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
-        curve: CurveP384,
-        pk: ppk,
-        nonce,
-        ..
-    } = enc_payload.format
-    else {
-        unreachable!("unsupported encrypted response format");
+    kp: &ResponseKeyPair,
+    domain_sep: &[u8],
+) -> eyre::Result<T> {
+    let payload = match (enc_payload.format, kp) {
+        (
+            EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
+                curve: CurveP384,
+                pk: ppk,
+                nonce,
+                ..
+            },
+            ResponseKeyPair::P384(kp),
+        ) => {
+            let mut payload: Vec<u8> = enc_payload.payload.0.into();
+            let cipher = kp.derive_shared_cipher(ppk, domain_sep);
+            cipher
+                .decrypt_in_place(&nonce.into(), &[], &mut payload)
+                .map_err(|e| eyre::eyre!("decryption error: {e}"))?;
+            payload
+        }
+        (
+            EncryptedPayloadFormat::P384MlKem768Aes256GcmSiv {
+                curve: CurveP384,
+                pk: ppk,
+                ct,
+                nonce,
+                ..
+            },
+            ResponseKeyPair::Hybrid(kp, mlkem),
+        ) => {
+            let mut payload: Vec<u8> = enc_payload.payload.0.into();
+            let cipher = kp.decapsulate_hybrid_cipher(mlkem, ppk, &ct, domain_sep)?;
+            cipher
+                .decrypt_in_place(&nonce.into(), &[], &mut payload)
+                .map_err(|e| eyre::eyre!("decryption error: {e}"))?;
+            payload
+        }
+        (EncryptedPayloadFormat::Hpke { suite, enc, .. }, ResponseKeyPair::Hpke(_, kp)) => kp
+            .open(suite, &enc, domain_sep, &enc_payload.payload)
+            .map_err(|e| eyre::eyre!("decryption error: {e}"))?,
+        _ => eyre::bail!("unexpected encrypted response format"),
     };
-    let mut payload: Vec<u8> = enc_payload.payload.0.into();
-
-    let cipher = kp.derive_shared_cipher(ppk, ssss::keypair::GET_SHARE_DOMAIN_SEP);
-    cipher
-        .decrypt_in_place(&nonce.into(), &[], &mut payload)
-        .unwrap();
-
     Ok(serde_json::from_slice(&payload)?)
 }
 
@@ -326,15 +729,27 @@ async fn send_request(req: RequestBuilder) -> Result<Response> {
     let res = req.send().await?;
 
     if !res.status().is_success() {
+        let status = res.status();
         let res_text = res.text().await?;
-        let ErrorResponse { error } =
-            serde_json::from_str(&res_text).unwrap_or(ErrorResponse { error: res_text });
-        Err(eyre::eyre!("request failed: {error}"))
+        let response = serde_json::from_str::<ErrorResponse>(&res_text).unwrap_or(ErrorResponse {
+            error: res_text,
+            code: Default::default(),
+            details: Default::default(),
+            policy_error: None,
+        });
+        Err(Error::Api { status, response })
     } else {
         Ok(res)
     }
 }
 
+fn expect_status(res: &Response, status: StatusCode) -> Result<()> {
+    if res.status() != status {
+        return Err(Error::UnexpectedStatus(res.status()));
+    }
+    Ok(())
+}
+
 pub fn calculate_threshold(num_sssss: u64, threshold: f64) -> u64 {
     if threshold > 1.0 {
         threshold as u64
@@ -346,7 +761,7 @@ pub fn calculate_threshold(num_sssss: u64, threshold: f64) -> u64 {
 pub fn generate_signer_proof(
     signers: &[Address],
     signatories: &[Address],
-) -> Result<(Vec<H256>, Vec<bool>, Vec<Address>)> {
+) -> eyre::Result<(Vec<H256>, Vec<bool>, Vec<Address>)> {
     let tempdir = tempfile::tempdir()?;
 
     ensure!(
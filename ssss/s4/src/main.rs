@@ -54,6 +54,7 @@ async fn main() -> Result<()> {
                 },
             il,
             verifier,
+            limits_path,
             policy_path,
             sssss: cli::Sssss { sssss },
         } => {
@@ -62,14 +63,27 @@ async fn main() -> Result<()> {
                 None => Box::new(std::io::stdin()),
             };
             let policy: serde_json::Value = serde_json::from_reader(input)?;
+            let limits = match limits_path {
+                Some(p) => Some(serde_json::from_reader(std::fs::File::open(p)?)?),
+                None => None,
+            };
 
             let policy_doc = &PolicyDocument {
-                verifier: verifier.to_string(),
+                verifier,
                 policy,
+                limits,
             };
             let policy_doc_hash = keccak256(serde_json::to_vec(policy_doc)?);
             let permitter = *permitter;
 
+            try_join_all(sssss.iter().map(|ssss_url| async move {
+                SsssClient::new(ssss_url.clone())
+                    .validate_policy(policy_doc)
+                    .await
+                    .wrap_err_with(|| format!("{ssss_url} rejected the policy"))
+            }))
+            .await?;
+
             let (chain, provider) = get_provider(&gateway).await?;
             let provider = provider.with_signer(wallet.private_key.with_chain_id(chain));
             let ssss_permitter = SsssPermitter::new(permitter, provider.into());
@@ -279,14 +293,14 @@ async fn main() -> Result<()> {
                 ssss_clients
                     .iter()
                     .zip(shares.into_iter())
-                    .map(|(ssss, share)| ssss.deal_share(&share_id, share, &wallet)),
+                    .map(|(ssss, share)| ssss.deal_share(&share_id, share, &*wallet)),
             )
             .await?;
 
             try_join_all(
                 ssss_clients
                     .iter()
-                    .map(|ssss| ssss.commit_share(&share_id, &wallet)),
+                    .map(|ssss| ssss.commit_share(&share_id, &*wallet)),
             )
             .await?;
 
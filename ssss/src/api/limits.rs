@@ -0,0 +1,249 @@
+//! Enforces the grant limits that a policy document may set for its identity.
+//!
+//! The grants and revocations that the limits depend on are kept in a per-identity ledger that
+//! is replaced as a whole using the store's versioned writes, so replicas that update it at the
+//! same time cannot both succeed and the limits hold across all of them.
+
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+
+use super::Error;
+use crate::{backend::Store, types::*, utils::now, verify};
+
+/// How many times to re-read the ledger when another writer updates it first.
+const MAX_LEDGER_ATTEMPTS: usize = 8;
+
+/// The recent grants and revocations of one identity.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct GrantLedger {
+    /// When each recipient was granted the identity, oldest first.
+    #[serde(default)]
+    grants: BTreeMap<Address, Vec<u64>>,
+    /// When each recipient's grant expires.
+    #[serde(default)]
+    holders: BTreeMap<Address, u64>,
+    /// When each recipient was last revoked.
+    #[serde(default)]
+    revocations: BTreeMap<Address, u64>,
+}
+
+impl GrantLedger {
+    /// Removes entries that can no longer affect the limits.
+    fn prune(&mut self, limits: &GrantLimits, now: u64) {
+        let window_start = match limits.max_grants {
+            Some(GrantRate { window, .. }) => now.saturating_sub(window),
+            None => u64::MAX,
+        };
+        self.grants.retain(|_, times| {
+            times.retain(|t| *t > window_start);
+            !times.is_empty()
+        });
+        self.holders.retain(|_, expiry| *expiry > now);
+        let cooldown = limits.revoke_cooldown.unwrap_or_default();
+        self.revocations
+            .retain(|_, revoked| revoked.saturating_add(cooldown) > now);
+    }
+
+    /// Returns why the request would exceed the limits, if it would.
+    pub(super) fn check(
+        &self,
+        limits: &GrantLimits,
+        recipient: Address,
+        grant: bool,
+        now: u64,
+    ) -> Result<(), String> {
+        if !grant {
+            return Ok(());
+        }
+        if let (Some(cooldown), Some(revoked)) =
+            (limits.revoke_cooldown, self.revocations.get(&recipient))
+        {
+            let until = revoked.saturating_add(cooldown);
+            if until > now {
+                return Err(format!(
+                    "recipient was revoked and may be granted after {until}"
+                ));
+            }
+        }
+        if let Some(GrantRate { count, window }) = limits.max_grants {
+            let window_start = now.saturating_sub(window);
+            let recent = self
+                .grants
+                .get(&recipient)
+                .map(|times| times.iter().filter(|t| **t > window_start).count())
+                .unwrap_or_default();
+            if recent >= count as usize {
+                return Err(format!(
+                    "recipient was granted {recent} times in the last {window} seconds"
+                ));
+            }
+        }
+        if let Some(max_holders) = limits.max_holders {
+            let holders = self
+                .holders
+                .iter()
+                .filter(|(holder, expiry)| **holder != recipient && **expiry > now)
+                .count();
+            if holders >= max_holders as usize {
+                return Err(format!("identity already has {holders} holders"));
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, recipient: Address, grant: bool, duration: u64, now: u64) {
+        if grant {
+            self.grants.entry(recipient).or_default().push(now);
+            let expiry = self.holders.entry(recipient).or_default();
+            *expiry = (*expiry).max(now.saturating_add(duration));
+        } else {
+            self.holders.remove(&recipient);
+            self.revocations.insert(recipient, now);
+        }
+    }
+}
+
+/// Returns the limits set by a policy document, if it is well-formed and sets any.
+pub(super) fn from_policy(policy_bytes: &[u8]) -> Option<GrantLimits> {
+    serde_json::from_slice::<PolicyDocument>(policy_bytes)
+        .ok()?
+        .limits
+}
+
+/// Reads the identity's ledger and its version, which is zero if none has been stored.
+pub(super) async fn load(
+    backend: &impl Store,
+    identity: IdentityLocator,
+    limits: &GrantLimits,
+) -> Result<(u64, GrantLedger), Error> {
+    let Some((version, ledger)) = backend.get_grant_ledger(identity).await? else {
+        return Ok(Default::default());
+    };
+    let mut ledger: GrantLedger = serde_json::from_slice(&ledger)
+        .map_err(|e| anyhow!("failed to decode grant ledger: {e}"))?;
+    ledger.prune(limits, now());
+    Ok((version, ledger))
+}
+
+/// Checks the request against the limits and records it in the identity's ledger.
+pub(super) async fn record(
+    backend: &impl Store,
+    identity: IdentityLocator,
+    limits: &GrantLimits,
+    recipient: Address,
+    grant: bool,
+    duration: u64,
+) -> Result<(), Error> {
+    for _ in 0..MAX_LEDGER_ATTEMPTS {
+        let (version, mut ledger) = load(backend, identity, limits).await?;
+        let now = now();
+        ledger
+            .check(limits, recipient, grant, now)
+            .map_err(|e| Error::Verification(verify::Error::LimitExceeded(e)))?;
+        ledger.record(recipient, grant, duration, now);
+        let ledger = serde_json::to_vec(&ledger).map_err(anyhow::Error::from)?;
+        if backend
+            .put_grant_ledger(identity, version + 1, ledger)
+            .await?
+        {
+            return Ok(());
+        }
+    }
+    Err(anyhow!("grant ledger is too contended to update").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000;
+
+    fn limits() -> GrantLimits {
+        GrantLimits {
+            max_grants: Some(GrantRate {
+                count: 2,
+                window: 60,
+            }),
+            max_holders: Some(2),
+            revoke_cooldown: Some(30),
+        }
+    }
+
+    #[test]
+    fn grant_rate() {
+        let (limits, alice) = (limits(), Address::repeat_byte(1));
+        let mut ledger = GrantLedger::default();
+        ledger.record(alice, true, 10, NOW - 90);
+        ledger.record(alice, true, 10, NOW - 50);
+        ledger.record(alice, true, 10, NOW - 20);
+        assert!(ledger.check(&limits, alice, true, NOW).is_err());
+        assert!(ledger.check(&limits, alice, false, NOW).is_ok());
+        assert!(ledger.check(&limits, alice, true, NOW + 11).is_ok());
+
+        ledger.prune(&limits, NOW);
+        assert_eq!(ledger.grants[&alice], [NOW - 50, NOW - 20]);
+    }
+
+    #[test]
+    fn max_holders() {
+        let limits = limits();
+        let [alice, bob, carol] = [1, 2, 3].map(Address::repeat_byte);
+        let mut ledger = GrantLedger::default();
+        ledger.record(alice, true, 100, NOW);
+        ledger.record(bob, true, 10, NOW);
+        assert!(ledger.check(&limits, carol, true, NOW).is_err());
+        // Current holders may renew their grants.
+        assert!(ledger.check(&limits, bob, true, NOW).is_ok());
+        assert!(ledger.check(&limits, carol, true, NOW + 10).is_ok());
+
+        ledger.record(bob, false, 0, NOW + 1);
+        assert!(ledger.check(&limits, carol, true, NOW + 1).is_ok());
+    }
+
+    #[test]
+    fn revoke_cooldown() {
+        let (limits, alice) = (limits(), Address::repeat_byte(1));
+        let mut ledger = GrantLedger::default();
+        ledger.record(alice, false, 0, NOW);
+        assert!(ledger.check(&limits, alice, true, NOW + 29).is_err());
+        assert!(ledger.check(&limits, alice, true, NOW + 30).is_ok());
+
+        ledger.prune(&limits, NOW + 30);
+        assert_eq!(ledger, GrantLedger::default());
+    }
+
+    #[tokio::test]
+    async fn record_persists() {
+        let backend = crate::backend::memory::Backend::generate();
+        let identity = IdentityLocator {
+            chain: 31337,
+            registry: Address::random(),
+            id: rand::random(),
+        };
+        let limits = GrantLimits {
+            max_holders: Some(1),
+            ..Default::default()
+        };
+        let [alice, bob] = [1, 2].map(Address::repeat_byte);
+        record(&backend, identity, &limits, alice, true, 60)
+            .await
+            .unwrap();
+        assert!(matches!(
+            record(&backend, identity, &limits, bob, true, 60).await,
+            Err(Error::Verification(verify::Error::LimitExceeded(_)))
+        ));
+        record(&backend, identity, &limits, alice, false, 0)
+            .await
+            .unwrap();
+        record(&backend, identity, &limits, bob, true, 60)
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.get_grant_ledger(identity).await.unwrap().unwrap().0,
+            3
+        );
+    }
+}
@@ -13,12 +13,17 @@ use axum::{
     response::Response,
 };
 use axum_extra::TypedHeader;
-use ethers::types::{transaction::eip712::Eip712 as _, Address, Signature, H256};
+use ethers::{
+    core::utils::keccak256,
+    types::{transaction::eip712::Eip712 as _, Address, Bytes, Signature, H256},
+};
+use futures_util::future::BoxFuture;
 use pin_project_lite::pin_project;
 use tiny_keccak::{Hasher as _, Keccak};
 
 use super::Error;
 use crate::{
+    backend::Store,
     eth,
     types::{api::*, *},
 };
@@ -45,13 +50,293 @@ pub async fn permitted_requester(
     }
 }
 
+/// Verifies requester signatures and records the nonces of accepted escrin2 requests in the
+/// store, which may be shared by several SSSS instances.
+#[derive(Clone)]
+pub struct RequestAuth<S> {
+    host: Authority,
+    allow_escrin1: bool,
+    providers: eth::Providers,
+    code_cache: eth::CodeCache,
+    /// The maximum difference, in seconds, between an escrin2 request's issued-at time and now.
+    max_age: u64,
+    store: S,
+}
+
+impl<S: Store> RequestAuth<S> {
+    const DEFAULT_MAX_AGE: u64 = 5 * 60;
+
+    pub fn new(host: Authority, allow_escrin1: bool, providers: eth::Providers, store: S) -> Self {
+        Self {
+            host,
+            allow_escrin1,
+            providers,
+            code_cache: Default::default(),
+            max_age: Self::DEFAULT_MAX_AGE,
+            store,
+        }
+    }
+
+    fn check_fresh(&self, issued_at: u64) -> Result<(), Error> {
+        if crate::utils::now().abs_diff(issued_at) > self.max_age {
+            return Err(Error::Unauthorized("request is not fresh".into()));
+        }
+        Ok(())
+    }
+
+    /// Records that `requester` has used `nonce` until the request is no longer fresh.
+    async fn use_nonce(
+        &self,
+        requester: Address,
+        nonce: H256,
+        issued_at: u64,
+    ) -> Result<(), Error> {
+        let id = NonceId::new(
+            NonceKind::Request,
+            H256(keccak256([requester.as_bytes(), nonce.as_bytes()].concat())),
+        );
+        if !self.store.put_nonce(id, issued_at + self.max_age).await? {
+            return Err(Error::Replayed("request nonce already used".into()));
+        }
+        Ok(())
+    }
+
+    fn request_hash(
+        &self,
+        method: &Method,
+        path_and_query: &PathAndQuery,
+        scheme: SignatureScheme,
+        body: Option<H256>,
+    ) -> H256 {
+        let method = method.to_string();
+        let url = format!("{}{path_and_query}", self.host);
+        let body = body.unwrap_or_default();
+        H256(
+            match scheme {
+                SignatureScheme::Escrin1 => SsssRequest { method, url, body }.encode_eip712(),
+                SignatureScheme::Escrin2 { issued_at, nonce } => SsssRequest2 {
+                    method,
+                    url,
+                    body,
+                    issued_at,
+                    nonce,
+                }
+                .encode_eip712(),
+            }
+            .unwrap(),
+        )
+    }
+
+    /// Verifies an ECDSA signature made by an externally owned account.
+    /// The request's nonce must then be used by [`RequestAuth::finish_verification`].
+    fn verify_sig(
+        &self,
+        method: Method,
+        path_and_query: PathAndQuery,
+        sig: Signature,
+        requester: Address,
+        scheme: SignatureScheme,
+        body: Option<H256>,
+    ) -> Result<(), Error> {
+        let req721_hash = self.request_hash(&method, &path_and_query, scheme, body);
+        let recovered_requester = sig
+            .recover(req721_hash)
+            .map_err(|_| Error::Forbidden("invalid eip712 signature".into()))?;
+        if recovered_requester != requester {
+            return Err(Error::Forbidden(format!(
+                "{scheme} signature validation failed"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verifies an EIP-1271 signature made by a contract account.
+    /// The request's nonce must then be used by [`RequestAuth::finish_verification`].
+    #[allow(clippy::too_many_arguments)]
+    async fn verify_contract_sig(
+        &self,
+        chain: ChainId,
+        method: Method,
+        path_and_query: PathAndQuery,
+        sig: Bytes,
+        requester: Address,
+        scheme: SignatureScheme,
+        body: Option<H256>,
+    ) -> Result<(), Error> {
+        let provider = self
+            .providers
+            .get(&chain)
+            .ok_or(Error::UnsupportedChain(chain))?;
+        let req721_hash = self.request_hash(&method, &path_and_query, scheme, body);
+        if !eth::is_valid_contract_signature(provider.clone(), requester, req721_hash, sig)
+            .await
+            .map_err(|e| Error::Unhandled(e.into()))?
+        {
+            return Err(Error::Forbidden(format!(
+                "{scheme} contract signature validation failed"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verifies the request's signature and then uses its nonce.
+    ///
+    /// ECDSA recovery is tried first, so the requester's code is looked up to check an EIP-1271
+    /// signature only if the signature does not recover to the requester. Session keys are
+    /// never contracts, so `allow_contract` is false for requests made with them.
+    #[allow(clippy::too_many_arguments)]
+    async fn verify_request_sig(
+        &self,
+        chain: ChainId,
+        method: Method,
+        path_and_query: PathAndQuery,
+        sig: Bytes,
+        requester: Address,
+        scheme: SignatureScheme,
+        body: Option<H256>,
+        allow_contract: bool,
+    ) -> Result<(), Error> {
+        let res = match Signature::try_from(&*sig) {
+            Ok(ecdsa_sig) => self.verify_sig(
+                method.clone(),
+                path_and_query.clone(),
+                ecdsa_sig,
+                requester,
+                scheme,
+                body,
+            ),
+            Err(_) => Err(Error::BadRequest("invalid ECDSA signature".into())),
+        };
+        if let Err(e) = res {
+            if !allow_contract || !self.is_contract(chain, requester).await? {
+                return Err(e);
+            }
+            self.verify_contract_sig(chain, method, path_and_query, sig, requester, scheme, body)
+                .await?;
+        }
+        self.finish_verification(requester, scheme).await
+    }
+
+    async fn finish_verification(
+        &self,
+        requester: Address,
+        scheme: SignatureScheme,
+    ) -> Result<(), Error> {
+        if let SignatureScheme::Escrin2 { issued_at, nonce } = scheme {
+            self.use_nonce(requester, nonce, issued_at).await?;
+        }
+        Ok(())
+    }
+
+    /// Verifies that `grant` authorizes its session key to make the request on behalf of
+    /// `requester`.
+    async fn verify_session_grant(
+        &self,
+        SignedSessionGrant { grant, signature }: SignedSessionGrant,
+        requester: Address,
+        PathIdentityLocator {
+            chain,
+            registry,
+            identity,
+        }: PathIdentityLocator,
+        resource: &str,
+    ) -> Result<Address, Error> {
+        if grant.requester != requester {
+            return Err(Error::Forbidden(
+                "session grant was not issued by the requester".into(),
+            ));
+        }
+        if grant.chain != chain
+            || grant.registry != registry
+            || grant.identity != identity.0
+            || !grant.allows(resource)
+        {
+            return Err(Error::Forbidden(
+                "request is outside of the session grant's scope".into(),
+            ));
+        }
+        if grant.expiry.saturating_sub(grant.not_before) > MAX_SESSION_LIFETIME {
+            return Err(Error::Forbidden(format!(
+                "session grant is valid for longer than {MAX_SESSION_LIFETIME} seconds"
+            )));
+        }
+        let now = crate::utils::now();
+        if now < grant.not_before || now >= grant.expiry {
+            return Err(Error::Unauthorized("session grant is not active".into()));
+        }
+        let grant_hash = H256(
+            grant
+                .encode_eip712()
+                .map_err(|e| Error::BadRequest(format!("invalid session grant: {e}")))?,
+        );
+        // The requester's code is looked up only if the signature does not recover to them.
+        let recovered = Signature::try_from(&*signature)
+            .ok()
+            .and_then(|sig| sig.recover(grant_hash).ok());
+        let valid = if recovered == Some(requester) {
+            true
+        } else if self.is_contract(chain, requester).await? {
+            let provider = self
+                .providers
+                .get(&chain)
+                .ok_or(Error::UnsupportedChain(chain))?;
+            eth::is_valid_contract_signature(provider.clone(), requester, grant_hash, signature)
+                .await
+                .map_err(|e| Error::Unhandled(e.into()))?
+        } else {
+            false
+        };
+        if !valid {
+            return Err(Error::Forbidden(
+                "session grant signature validation failed".into(),
+            ));
+        }
+        Ok(grant.session_key)
+    }
+
+    async fn is_contract(&self, chain: ChainId, requester: Address) -> Result<bool, Error> {
+        let provider = self
+            .providers
+            .get(&chain)
+            .ok_or(Error::UnsupportedChain(chain))?;
+        self.code_cache
+            .has_code(chain, &**provider, requester)
+            .await
+            .map_err(|e| Error::Unhandled(e.into()))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SignatureScheme {
+    /// The legacy scheme, which signs only the method, URL, and body.
+    Escrin1,
+    Escrin2 {
+        issued_at: u64,
+        nonce: H256,
+    },
+}
+
+impl std::fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Escrin1 => write!(f, "escrin1"),
+            Self::Escrin2 { .. } => write!(f, "escrin2"),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(level = "info", skip_all)]
-pub async fn escrin1(
+pub async fn escrin2<S: Store>(
     method: Method,
     OriginalUri(uri): OriginalUri,
+    Path(locator): Path<PathIdentityLocator>,
     sig: Option<TypedHeader<SignatureHeader>>,
     requester: Option<TypedHeader<RequesterHeader>>,
-    State(host): State<Authority>,
+    issued_at: Option<TypedHeader<IssuedAtHeader>>,
+    nonce: Option<TypedHeader<RequestNonceHeader>>,
+    session_grant: Option<TypedHeader<SessionGrantHeader>>,
+    State(auth): State<RequestAuth<S>>,
     req: Request,
     next: Next,
 ) -> Result<Response, Error> {
@@ -63,6 +348,43 @@ pub async fn escrin1(
             "Header of type `signature` was missing".into(),
         ));
     };
+    let scheme = match (issued_at, nonce) {
+        (
+            Some(TypedHeader(IssuedAtHeader(issued_at))),
+            Some(TypedHeader(RequestNonceHeader(nonce))),
+        ) => {
+            auth.check_fresh(issued_at)?;
+            SignatureScheme::Escrin2 { issued_at, nonce }
+        }
+        (None, None) if auth.allow_escrin1 => SignatureScheme::Escrin1,
+        (None, None) => {
+            return Err(Error::Unauthorized(
+                "escrin1 signatures are not accepted".into(),
+            ))
+        }
+        _ => {
+            return Err(Error::BadRequest(
+                "escrin2 requests must have both `issued-at` and `request-nonce` headers".into(),
+            ))
+        }
+    };
+    let chain = locator.chain;
+    // Requests made with a session key are signed by it, but are otherwise treated
+    // as having been made by the requester that granted the session.
+    let (signer, is_session) = match session_grant {
+        Some(TypedHeader(SessionGrantHeader(grant))) => {
+            let resource = uri.path().split('/').nth(2).unwrap_or_default();
+            let session_key = auth
+                .verify_session_grant(grant, requester, locator, resource)
+                .await?;
+            (session_key, true)
+        }
+        None => (requester, false),
+    };
+    let path_and_query = uri
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| PathAndQuery::from_static(""));
     Ok(next
         .run(match method {
             Method::OPTIONS => req,
@@ -70,24 +392,29 @@ pub async fn escrin1(
                 axum::body::Body::new(SignatureChecker {
                     inner: b,
                     hasher: Some(Keccak::v256()),
-                    host,
+                    auth,
+                    chain,
                     method,
                     uri,
                     sig,
-                    requester,
+                    requester: signer,
+                    scheme,
+                    allow_contract: !is_session,
+                    pending: None,
                 })
             }),
             Method::GET | Method::DELETE => {
-                verify_sig(
+                auth.verify_request_sig(
+                    chain,
                     method,
-                    host,
-                    uri.path_and_query()
-                        .cloned()
-                        .unwrap_or_else(|| PathAndQuery::from_static("")),
+                    path_and_query,
                     sig,
-                    requester,
+                    signer,
+                    scheme,
                     None,
-                )?;
+                    !is_session,
+                )
+                .await?;
                 req
             }
             m => return Err(Error::BadRequest(format!("unsupported method: {m}"))),
@@ -95,51 +422,28 @@ pub async fn escrin1(
         .await)
 }
 
-fn verify_sig(
-    method: Method,
-    host: Authority,
-    path_and_query: PathAndQuery,
-    sig: Signature,
-    requester: Address,
-    body: Option<H256>,
-) -> Result<(), Error> {
-    let req721_hash = H256(
-        SsssRequest {
-            method: method.to_string(),
-            url: format!("{host}{path_and_query}"),
-            body: body.unwrap_or_default(),
-        }
-        .encode_eip712()
-        .unwrap(),
-    );
-    let recovered_requester = sig
-        .recover(req721_hash)
-        .map_err(|_| Error::Forbidden("invalid eip712 signature".into()))?;
-    if recovered_requester != requester {
-        return Err(Error::Forbidden(
-            "escrin1 signature validation failed".into(),
-        ));
-    }
-    Ok(())
-}
-
 pin_project! {
-    #[derive(Clone)]
-    struct SignatureChecker<B> {
+    struct SignatureChecker<B, S> {
         #[pin]
         inner: B,
         hasher: Option<Keccak>,
+        auth: RequestAuth<S>,
+        chain: ChainId,
         method: Method,
-        host: Authority,
         uri: Uri,
-        sig: Signature,
-        requester: Address
+        sig: Bytes,
+        requester: Address,
+        scheme: SignatureScheme,
+        allow_contract: bool,
+        // Verifies the signature over the whole body and then uses the request's nonce.
+        pending: Option<BoxFuture<'static, Result<(), Error>>>,
     }
 }
 
-impl<B> http_body::Body for SignatureChecker<B>
+impl<B, S> http_body::Body for SignatureChecker<B, S>
 where
     B: http_body::Body,
+    S: Store,
     B::Data: AsRef<[u8]>,
     B::Error: Into<anyhow::Error>,
 {
@@ -151,6 +455,13 @@ where
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
         let this = self.project();
+        if let Some(pending) = this.pending.as_mut() {
+            let Poll::Ready(res) = pending.as_mut().poll(cx) else {
+                return Poll::Pending;
+            };
+            *this.pending = None;
+            return Poll::Ready(res.err().map(Err));
+        }
         let res = match this.inner.poll_frame(cx) {
             Poll::Pending => return Poll::Pending,
             Poll::Ready(None) => {
@@ -160,20 +471,38 @@ where
                     .ok_or_else(|| Error::BadRequest("body already consumed".into()))?
                     .finalize(&mut body_hash);
 
-                match verify_sig(
+                let auth = this.auth.clone();
+                let (chain, method, sig, requester, scheme, allow_contract) = (
+                    *this.chain,
                     this.method.clone(),
-                    this.host.clone(),
-                    this.uri
-                        .path_and_query()
-                        .cloned()
-                        .unwrap_or_else(|| PathAndQuery::from_static("")),
-                    *this.sig,
+                    this.sig.clone(),
                     *this.requester,
-                    Some(body_hash.into()),
-                ) {
-                    Ok(()) => None,
-                    Err(e) => Some(Err(e)),
-                }
+                    *this.scheme,
+                    *this.allow_contract,
+                );
+                let path_and_query = this
+                    .uri
+                    .path_and_query()
+                    .cloned()
+                    .unwrap_or_else(|| PathAndQuery::from_static(""));
+                let mut pending: BoxFuture<'static, _> = Box::pin(async move {
+                    auth.verify_request_sig(
+                        chain,
+                        method,
+                        path_and_query,
+                        sig,
+                        requester,
+                        scheme,
+                        Some(body_hash.into()),
+                        allow_contract,
+                    )
+                    .await
+                });
+                let Poll::Ready(res) = pending.as_mut().poll(cx) else {
+                    *this.pending = Some(pending);
+                    return Poll::Pending;
+                };
+                res.err().map(Err)
             }
             Poll::Ready(Some(Ok(frame))) => {
                 if let Some(data) = frame.data_ref() {
@@ -191,7 +520,8 @@ where
     }
 
     fn is_end_stream(&self) -> bool {
-        self.inner.is_end_stream()
+        // The body does not end until its signature has been checked.
+        self.hasher.is_none() && self.pending.is_none() && self.inner.is_end_stream()
     }
 
     fn size_hint(&self) -> http_body::SizeHint {
@@ -204,6 +534,13 @@ pub struct PathChainId {
     chain: ChainId,
 }
 
+#[derive(Clone, serde::Deserialize)]
+pub struct PathIdentityLocator {
+    chain: ChainId,
+    registry: Address,
+    identity: IdentityId,
+}
+
 #[tracing::instrument(level = "info", skip_all)]
 pub async fn ensure_supported_chain(
     State(providers): State<eth::Providers>,
@@ -216,3 +553,173 @@ pub async fn ensure_supported_chain(
     }
     Ok(next.run(req).await)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory;
+
+    fn auth_with_store(store: memory::Backend) -> RequestAuth<memory::Backend> {
+        RequestAuth::new(
+            Authority::from_static("127.0.0.1:1075"),
+            false,
+            Default::default(),
+            store,
+        )
+    }
+
+    fn auth() -> RequestAuth<memory::Backend> {
+        auth_with_store(memory::Backend::generate())
+    }
+
+    #[test]
+    fn freshness() {
+        let auth = auth();
+        let now = crate::utils::now();
+        auth.check_fresh(now).unwrap();
+        auth.check_fresh(now - RequestAuth::<memory::Backend>::DEFAULT_MAX_AGE + 1)
+            .unwrap();
+        auth.check_fresh(now - RequestAuth::<memory::Backend>::DEFAULT_MAX_AGE - 10)
+            .unwrap_err();
+        auth.check_fresh(now + RequestAuth::<memory::Backend>::DEFAULT_MAX_AGE + 10)
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn nonce_reuse() {
+        let auth = auth();
+        let now = crate::utils::now();
+        let (alice, bob) = (Address::random(), Address::random());
+        let nonce = H256::random();
+        auth.use_nonce(alice, nonce, now).await.unwrap();
+        assert!(matches!(
+            auth.use_nonce(alice, nonce, now).await,
+            Err(Error::Replayed(_))
+        ));
+        auth.use_nonce(bob, nonce, now).await.unwrap();
+        auth.use_nonce(alice, H256::random(), now).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn session_grant_scope() {
+        let auth = auth();
+        let requester = Address::random();
+        let now = crate::utils::now();
+        let locator = || PathIdentityLocator {
+            chain: 1,
+            registry: Address::repeat_byte(1),
+            identity: IdentityId(H256::repeat_byte(2)),
+        };
+        let grant = |f: fn(&mut SsssSessionGrant)| {
+            let mut grant = SsssSessionGrant {
+                requester,
+                session_key: Address::random(),
+                chain: 1,
+                registry: Address::repeat_byte(1),
+                identity: H256::repeat_byte(2),
+                scope: "shares, secrets".into(),
+                not_before: now,
+                expiry: now + 60,
+            };
+            f(&mut grant);
+            SignedSessionGrant {
+                grant,
+                signature: Default::default(),
+            }
+        };
+        macro_rules! assert_rejected {
+            ($grant:expr, $resource:literal, $err:ident) => {
+                assert!(matches!(
+                    auth.verify_session_grant($grant, requester, locator(), $resource)
+                        .await,
+                    Err(Error::$err(_))
+                ));
+            };
+        }
+        assert_rejected!(
+            grant(|g| g.requester = Address::random()),
+            "shares",
+            Forbidden
+        );
+        assert_rejected!(grant(|g| g.chain = 2), "shares", Forbidden);
+        assert_rejected!(grant(|g| g.identity = H256::zero()), "shares", Forbidden);
+        assert_rejected!(grant(|_| {}), "permits", Forbidden);
+        assert_rejected!(grant(|g| g.expiry = 1), "secrets", Unauthorized);
+        assert_rejected!(grant(|g| g.not_before = u64::MAX), "secrets", Unauthorized);
+        assert_rejected!(
+            grant(|g| g.expiry = g.not_before + MAX_SESSION_LIFETIME + 1),
+            "secrets",
+            Forbidden
+        );
+        assert_rejected!(grant(|g| g.expiry = u64::MAX), "secrets", Forbidden);
+        // In scope, but the chain has no provider with which to check the grant's signer.
+        assert_rejected!(grant(|_| {}), "secrets", UnsupportedChain);
+    }
+
+    #[tokio::test]
+    async fn session_grant_signed_by_eoa() {
+        use ethers::signers::{LocalWallet, Signer as _};
+
+        // The chain has no provider, so the grant is accepted only if its signature is
+        // recovered without looking up the requester's code.
+        let auth = auth();
+        let now = crate::utils::now();
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let grant = SsssSessionGrant {
+            requester: wallet.address(),
+            session_key: Address::random(),
+            chain: 1,
+            registry: Address::repeat_byte(1),
+            identity: H256::repeat_byte(2),
+            scope: "secrets".into(),
+            not_before: now,
+            expiry: now + MAX_SESSION_LIFETIME,
+        };
+        let signature = wallet
+            .sign_hash(H256(grant.encode_eip712().unwrap()))
+            .unwrap();
+        let session_key = auth
+            .verify_session_grant(
+                SignedSessionGrant {
+                    grant: grant.clone(),
+                    signature: signature.to_vec().into(),
+                },
+                wallet.address(),
+                PathIdentityLocator {
+                    chain: 1,
+                    registry: Address::repeat_byte(1),
+                    identity: IdentityId(H256::repeat_byte(2)),
+                },
+                "secrets",
+            )
+            .await
+            .unwrap();
+        assert_eq!(session_key, grant.session_key);
+    }
+
+    #[tokio::test]
+    async fn nonce_shared() {
+        // Instances that share a store share nonces, so a request cannot be replayed to another.
+        let store = memory::Backend::generate();
+        let (auth1, auth2) = (auth_with_store(store.clone()), auth_with_store(store));
+        let now = crate::utils::now();
+        let nonce = H256::random();
+        auth1.use_nonce(Address::zero(), nonce, now).await.unwrap();
+        assert!(matches!(
+            auth2.use_nonce(Address::zero(), nonce, now).await,
+            Err(Error::Replayed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn nonce_expiry() {
+        let auth = auth();
+        let stale = crate::utils::now() - RequestAuth::<memory::Backend>::DEFAULT_MAX_AGE - 10;
+        auth.use_nonce(Address::zero(), H256::zero(), stale)
+            .await
+            .unwrap();
+        auth.use_nonce(Address::zero(), H256::zero(), stale)
+            .await
+            .unwrap();
+    }
+}
@@ -1,4 +1,6 @@
+mod limits;
 mod middleware;
+mod single_use;
 
 use std::net::{Ipv4Addr, SocketAddrV4};
 
@@ -19,26 +21,28 @@ use ethers::{
         utils::keccak256,
     },
     providers::Middleware,
-    types::{transaction::eip712::Eip712 as _, Address},
+    types::{transaction::eip712::Eip712 as _, Address, H256},
 };
 use futures_util::TryFutureExt as _;
-use ssss::keypair::{self, KeyPair, RotatingKeyPairProvider};
 use tower_http::cors;
 use vsss_rs::PedersenVerifierSet;
 
 use crate::{
     backend::{Signer, Store},
     eth,
+    keypair::{self, KeyPair, RotatingKeyPairProvider},
     types::{api::*, *},
-    verify,
+    verify::{self, VerifierRegistry},
 };
 
 #[derive(Clone)]
 struct AppState<B> {
     backend: B,
-    host: Authority,
     providers: eth::Providers,
     kps: RotatingKeyPairProvider<B>,
+    auth: middleware::RequestAuth<B>,
+    verifiers: VerifierRegistry,
+    challenge_key: single_use::ChallengeKey,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -50,9 +54,15 @@ enum Error {
     #[error("{0}")]
     Unauthorized(String),
     #[error("{0}")]
+    Verification(verify::Error),
+    #[error("{0}")]
+    Replayed(String),
+    #[error("{0}")]
     Forbidden(String),
     #[error("unsupported chain: {0}")]
     UnsupportedChain(ChainId),
+    #[error("{0}")]
+    InvalidPolicy(PolicyError),
     #[error("internal server error")]
     Unhandled(#[from] anyhow::Error),
 }
@@ -65,31 +75,84 @@ impl IntoResponse for Error {
         let status_code = match self {
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
-            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Unauthorized(_) | Self::Verification(_) | Self::Replayed(_) => {
+                StatusCode::UNAUTHORIZED
+            }
             Self::Forbidden(_) => StatusCode::FORBIDDEN,
             Self::UnsupportedChain(_) => StatusCode::MISDIRECTED_REQUEST,
+            Self::InvalidPolicy(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::Unhandled(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
+        let error = self.to_string();
+        let (code, details) = (self.code(), self.details());
+        let policy_error = match self {
+            Self::InvalidPolicy(e) => Some(e),
+            _ => None,
+        };
         (
             status_code,
             Json(ErrorResponse {
-                error: self.to_string(),
+                error,
+                code,
+                details,
+                policy_error,
             }),
         )
             .into_response()
     }
 }
 
-pub async fn serve<B: Store + Signer>(backend: B, providers: eth::Providers, host: Authority) {
+impl Error {
+    fn code(&self) -> ErrorCode {
+        match self {
+            Self::BadRequest(_) => ErrorCode::BadRequest,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::Unauthorized(_) => ErrorCode::Unauthorized,
+            Self::Verification(e) => e.code(),
+            Self::Replayed(_) => ErrorCode::Replayed,
+            Self::Forbidden(_) => ErrorCode::Forbidden,
+            Self::UnsupportedChain(_) => ErrorCode::UnsupportedChain,
+            Self::InvalidPolicy(_) => ErrorCode::InvalidPolicy,
+            Self::Unhandled(_) => ErrorCode::Internal,
+        }
+    }
+
+    fn details(&self) -> ErrorDetails {
+        match self {
+            Self::Verification(e) => e.details(),
+            Self::UnsupportedChain(chain) => ErrorDetails {
+                chain: Some(*chain),
+                ..Default::default()
+            },
+            _ => Default::default(),
+        }
+    }
+}
+
+pub async fn serve<B: Store + Signer>(
+    backend: B,
+    providers: eth::Providers,
+    verifiers: VerifierRegistry,
+    host: Authority,
+    allow_escrin1: bool,
+) {
     let bind_addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), host.port_u16().unwrap_or(443));
     let listener = tokio::net::TcpListener::bind(bind_addr).await.unwrap();
+    let challenge_key = single_use::ChallengeKey::load(&backend).await.unwrap();
     axum::serve(
         listener,
         make_router(AppState {
             backend: backend.clone(),
-            host,
+            auth: middleware::RequestAuth::new(
+                host,
+                allow_escrin1,
+                providers.clone(),
+                backend.clone(),
+            ),
             providers,
             kps: RotatingKeyPairProvider::new(backend),
+            verifiers,
+            challenge_key,
         }),
     )
     .await
@@ -103,6 +166,9 @@ fn make_router<S: Store + Signer>(state: AppState<S>) -> Router {
             "/v1",
             Router::new()
                 .route("/identity", get(get_ssss_identity))
+                .route("/verifiers", get(get_verifiers))
+                .route("/policies/validate", post(validate_policy))
+                .route("/challenges", post(issue_challenge))
                 .nest(
                     "/policies/:chain/:registry/:identity",
                     Router::new().route("/", post(set_policy)).layer(
@@ -117,9 +183,11 @@ fn make_router<S: Store + Signer>(state: AppState<S>) -> Router {
                     Router::new()
                         .route("/", post(acqrel_identity))
                         .route("/", delete(acqrel_identity))
+                        .route("/evaluate", post(evaluate_permit))
+                        .route("/evaluate", delete(evaluate_permit))
                         .layer(axum::middleware::from_fn_with_state(
-                            state.host.clone(),
-                            middleware::escrin1,
+                            state.auth.clone(),
+                            middleware::escrin2,
                         ))
                         .layer(axum::middleware::from_fn_with_state(
                             state.providers.clone(),
@@ -138,8 +206,8 @@ fn make_router<S: Store + Signer>(state: AppState<S>) -> Router {
                             middleware::permitted_requester,
                         ))
                         .layer(axum::middleware::from_fn_with_state(
-                            state.host.clone(),
-                            middleware::escrin1,
+                            state.auth.clone(),
+                            middleware::escrin2,
                         ))
                         .layer(axum::middleware::from_fn_with_state(
                             state.providers.clone(),
@@ -158,8 +226,8 @@ fn make_router<S: Store + Signer>(state: AppState<S>) -> Router {
                             middleware::permitted_requester,
                         ))
                         .layer(axum::middleware::from_fn_with_state(
-                            state.host.clone(),
-                            middleware::escrin1,
+                            state.auth.clone(),
+                            middleware::escrin2,
                         ))
                         .layer(axum::middleware::from_fn_with_state(
                             state.providers.clone(),
@@ -172,14 +240,19 @@ fn make_router<S: Store + Signer>(state: AppState<S>) -> Router {
         .layer(tower_http::trace::TraceLayer::new_for_http())
         .layer(
             cors::CorsLayer::new()
-                .allow_methods([Method::GET, Method::POST, Method::DELETE])
+                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
                 .allow_origin(cors::Any)
                 .allow_headers([
                     header::CONTENT_TYPE,
                     header::AUTHORIZATION,
                     SignatureHeader::name().clone(),
                     RequesterHeader::name().clone(),
+                    IssuedAtHeader::name().clone(),
+                    RequestNonceHeader::name().clone(),
+                    SessionGrantHeader::name().clone(),
                     RequesterPublicKeyHeader::name().clone(),
+                    RequesterMlKemPublicKeyHeader::name().clone(),
+                    RequesterHpkePublicKeyHeader::name().clone(),
                 ]),
         )
 }
@@ -209,29 +282,63 @@ async fn root() -> StatusCode {
 async fn get_ssss_identity<S: Store + Signer>(
     State(AppState { backend, kps, .. }): State<AppState<S>>,
 ) -> Result<Json<IdentityResponse>, Error> {
-    let latest_key_fut = kps
-        .with_latest_key(|id, kp, expiry| {
-            (
-                id.to_string(),
-                *kp.public_key(),
-                (expiry.duration_since(std::time::UNIX_EPOCH))
-                    .unwrap()
-                    .as_secs(),
-            )
-        })
-        .map_ok(|(key_id, pk, expiry)| EphemeralKey { key_id, pk, expiry });
+    let latest_key_fut = kps.with_latest_keys(|rkp| EphemeralKey {
+        key_id: rkp.id().to_string(),
+        pk: *rkp.keypair().public_key(),
+        expiry: (rkp.expiry().duration_since(std::time::UNIX_EPOCH))
+            .unwrap()
+            .as_secs(),
+        hpke: rkp.hpke_keys().iter().map(|kp| kp.public_key()).collect(),
+        ml_kem_768: Some(rkp.mlkem_key().public_key().into()),
+    });
     let signer_addr_fut = backend.signer_address();
     let (ephemeral, signer) = tokio::try_join!(latest_key_fut, signer_addr_fut)?;
-    Ok(Json(IdentityResponse { ephemeral, signer }))
+    let formats = std::iter::once(PayloadFormat::P384EcdhAes256GcmSiv)
+        .chain(HpkeSuite::ALL.map(PayloadFormat::Hpke))
+        .chain(std::iter::once(PayloadFormat::P384MlKem768Aes256GcmSiv))
+        .collect();
+    Ok(Json(IdentityResponse {
+        ephemeral,
+        signer,
+        formats,
+    }))
+}
+
+async fn get_verifiers<S: Store>(
+    State(AppState { verifiers, .. }): State<AppState<S>>,
+) -> Json<Vec<VerifierInfo>> {
+    Json(verifiers.info())
+}
+
+async fn validate_policy<S: Store>(
+    State(AppState { verifiers, .. }): State<AppState<S>>,
+    policy: axum::body::Bytes,
+) -> Result<StatusCode, Error> {
+    verifiers.validate(&policy).map_err(Error::InvalidPolicy)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn issue_challenge<S: Store>(
+    State(AppState { challenge_key, .. }): State<AppState<S>>,
+) -> Json<ChallengeResponse> {
+    let (challenge, expiry) = challenge_key.issue(crate::utils::now());
+    Json(ChallengeResponse { challenge, expiry })
 }
 
 async fn set_policy<S: Store>(
     Path((chain, registry, identity)): Path<(ChainId, Address, IdentityId)>,
     State(AppState {
-        providers, backend, ..
+        providers,
+        backend,
+        verifiers,
+        ..
     }): State<AppState<S>>,
     Json(SetPolicyRequest { permitter, policy }): Json<SetPolicyRequest>,
 ) -> Result<StatusCode, Error> {
+    verifiers
+        .validate(policy.get().as_bytes())
+        .map_err(Error::InvalidPolicy)?;
+
     let Some(provider) = providers.get(&chain) else {
         return Err(Error::UnsupportedChain(chain));
     };
@@ -266,87 +373,69 @@ async fn set_policy<S: Store>(
 async fn acqrel_identity<S: Store + Signer>(
     method: Method,
     Path((chain, registry, identity)): Path<(ChainId, Address, IdentityId)>,
-    State(AppState {
-        backend, providers, ..
-    }): State<AppState<S>>,
+    State(state): State<AppState<S>>,
     relayer: Option<TypedHeader<RequesterHeader>>,
-    Json(AcqRelIdentityRequest {
-        duration,
-        authorization,
-        context,
-        permitter,
-        recipient,
-        base_block,
-    }): Json<AcqRelIdentityRequest>,
+    Json(req): Json<AcqRelIdentityRequest>,
 ) -> Result<Json<PermitResponse>, Error> {
-    let Some(provider) = providers.get(&chain) else {
-        return Err(Error::UnsupportedChain(chain));
-    };
-    let current_block = provider
-        .get_block_number()
-        .await
-        .map_err(|e| Error::Unhandled(e.into()))?;
-    if base_block > current_block.low_u64() {
-        return Err(Error::BadRequest("base block is in the future".into()));
-    }
-
-    let policy_bytes = backend
-        .get_verifier(
-            PermitterLocator::new(chain, permitter),
-            IdentityLocator {
-                chain,
-                registry,
-                id: identity,
-            },
-        )
-        .await
-        .map_err(anyhow::Error::from)?
-        .ok_or_else(|| Error::NotFound("policy".into()))?;
-
-    let policy_hash = keccak256(&policy_bytes);
-    let current_policy_hash = eth::SsssPermitter::new(permitter, provider.clone())
-        .policy_hash(identity)
-        .await
-        .map_err(|e| Error::Unhandled(e.into()))?;
-
-    if policy_hash != current_policy_hash.0 {
-        return Err(Error::Unauthorized("policy not current".into()));
-    }
-
     let identity_locator = IdentityLocator {
         chain,
         registry,
         id: identity,
     };
-    let crate::verify::Verification {
-        nonce,
-        public_key,
-        duration,
-    } = verify::verify(
-        &policy_bytes,
-        match method {
-            Method::POST => verify::RequestKind::Grant {
-                duration: duration
-                    .ok_or_else(|| Error::BadRequest("missing duration".to_string()))?,
-            },
-            Method::DELETE => verify::RequestKind::Revoke,
-            _ => unreachable!(),
+    let (
+        verify::Verification {
+            nonce,
+            public_key,
+            duration,
+            measurement,
+            single_use,
         },
+        limits,
+    ) = evaluate_permit_request(
+        &state,
+        &method,
         identity_locator,
-        recipient,
-        &authorization,
-        &context,
         relayer.map(|r| r.0 .0),
+        &req,
+        &mut verify::Report::new(),
     )
-    .await
-    .map_err(|e| Error::Unauthorized(e.to_string()))?;
+    .await?;
+    let AcqRelIdentityRequest {
+        permitter,
+        recipient,
+        base_block,
+        ..
+    } = req;
+    let backend = state.backend;
+    let grant = method == Method::POST;
+    let duration = duration.unwrap_or_default();
+    single_use::consume(
+        &backend,
+        &state.challenge_key,
+        &single_use,
+        crate::utils::now(),
+    )
+    .await?;
+    // The limits are recorded only once the request's single-use values have been consumed,
+    // so that replaying a request cannot use up the recipient's allowance.
+    if let Some(limits) = &limits {
+        limits::record(
+            &backend,
+            identity_locator,
+            limits,
+            recipient,
+            grant,
+            duration,
+        )
+        .await?;
+    }
 
     let permit = SsssPermit {
         registry,
         identity: identity.0,
         recipient,
-        grant: method == Method::POST,
-        duration: duration.unwrap_or_default(),
+        grant,
+        duration,
         nonce: nonce.into(),
         pk: public_key.into(),
         baseblock: base_block.into(),
@@ -369,6 +458,15 @@ async fn acqrel_identity<S: Store + Signer>(
         backend.signer_address().map_err(Error::Unhandled),
         backend.sign(permit_hash.into()).map_err(Error::Unhandled)
     )?;
+    tracing::info!(
+        chain,
+        registry = ?registry,
+        identity = ?identity.0,
+        recipient = ?recipient,
+        grant = permit.grant,
+        measurement,
+        "issued permit"
+    );
     Ok(Json(PermitResponse {
         permit,
         signer,
@@ -376,10 +474,162 @@ async fn acqrel_identity<S: Store + Signer>(
     }))
 }
 
+async fn evaluate_permit<S: Store>(
+    method: Method,
+    Path((chain, registry, identity)): Path<(ChainId, Address, IdentityId)>,
+    State(state): State<AppState<S>>,
+    relayer: Option<TypedHeader<RequesterHeader>>,
+    Json(req): Json<AcqRelIdentityRequest>,
+) -> Result<Json<EvaluationReport>, Error> {
+    let mut report = verify::Report::dry_run();
+    let error: Option<Error> = match evaluate_permit_request(
+        &state,
+        &method,
+        IdentityLocator {
+            chain,
+            registry,
+            id: identity,
+        },
+        relayer.map(|r| r.0 .0),
+        &req,
+        &mut report,
+    )
+    .await
+    {
+        Ok(_) => None,
+        Err(e @ (Error::Unauthorized(_) | Error::Verification(_))) => Some(e),
+        Err(e) => return Err(e),
+    };
+    Ok(Json(EvaluationReport {
+        permitted: error.is_none(),
+        steps: report.into_steps(),
+        code: error.as_ref().map(|e| e.code()),
+        details: error.as_ref().map(|e| e.details()).unwrap_or_default(),
+        error: error.map(|e| e.to_string()),
+    }))
+}
+
+/// Checks a permit request against the identity's current policy, recording each check.
+/// Returns the verification and the grant limits that the policy sets, if any.
+async fn evaluate_permit_request<S: Store>(
+    AppState {
+        backend,
+        providers,
+        verifiers,
+        ..
+    }: &AppState<S>,
+    method: &Method,
+    identity_locator: IdentityLocator,
+    relayer: Option<Address>,
+    AcqRelIdentityRequest {
+        permitter,
+        recipient,
+        base_block,
+        duration,
+        authorization,
+        context,
+    }: &AcqRelIdentityRequest,
+    report: &mut verify::Report,
+) -> Result<(verify::Verification, Option<GrantLimits>), Error> {
+    let IdentityLocator {
+        chain,
+        id: identity,
+        ..
+    } = identity_locator;
+    let Some(provider) = providers.get(&chain) else {
+        return Err(Error::UnsupportedChain(chain));
+    };
+    let current_block = provider
+        .get_block_number()
+        .await
+        .map_err(|e| Error::Unhandled(e.into()))?;
+    if *base_block > current_block.low_u64() {
+        return Err(Error::BadRequest("base block is in the future".into()));
+    }
+
+    let policy_bytes = backend
+        .get_verifier(PermitterLocator::new(chain, *permitter), identity_locator)
+        .await
+        .map_err(anyhow::Error::from)?
+        .ok_or_else(|| Error::NotFound("policy".into()))?;
+
+    let policy_hash = keccak256(&policy_bytes);
+    let current_policy_hash = eth::SsssPermitter::new(*permitter, provider.clone())
+        .policy_hash(identity)
+        .await
+        .map_err(|e| Error::Unhandled(e.into()))?;
+
+    report
+        .check(
+            EvaluationStep::new("policy-freshness")
+                .expected(current_policy_hash)
+                .observed(H256(policy_hash)),
+            if policy_hash == current_policy_hash.0 {
+                Ok(())
+            } else {
+                Err(verify::Error::StalePolicy {
+                    expected: current_policy_hash,
+                    observed: H256(policy_hash),
+                })
+            },
+        )
+        .map_err(Error::Verification)?;
+
+    let kind = match *method {
+        Method::POST => verify::RequestKind::Grant {
+            duration: duration.ok_or_else(|| Error::BadRequest("missing duration".to_string()))?,
+        },
+        Method::DELETE => verify::RequestKind::Revoke,
+        _ => unreachable!(),
+    };
+    let verification = verifiers
+        .evaluate(
+            &policy_bytes,
+            verify::VerificationRequest {
+                kind,
+                identity: identity_locator,
+                permitter: *permitter,
+                recipient: *recipient,
+                base_block: *base_block,
+                authorization,
+                context,
+                relayer,
+            },
+            report,
+        )
+        .await
+        .map_err(Error::Verification)?;
+
+    let limits = limits::from_policy(&policy_bytes);
+    if let Some(limits) = &limits {
+        // This only previews the ledger. The request is checked again when it is recorded.
+        let (_, ledger) = limits::load(backend, identity_locator, limits).await?;
+        report
+            .check(
+                EvaluationStep::new("limits").expected(limits),
+                ledger
+                    .check(
+                        limits,
+                        *recipient,
+                        *method == Method::POST,
+                        crate::utils::now(),
+                    )
+                    .map_err(verify::Error::LimitExceeded),
+            )
+            .map_err(Error::Verification)?;
+    }
+
+    report
+        .finish((verification, limits))
+        .map_err(Error::Verification)
+}
+
 async fn get_share<S: Store>(
     Path((name, chain, registry, identity)): Path<(String, ChainId, Address, IdentityId)>,
     Query(GetShareQuery { version }): Query<GetShareQuery>,
     requester_pk: Option<TypedHeader<RequesterPublicKeyHeader>>,
+    requester_mlkem_pk: Option<TypedHeader<RequesterMlKemPublicKeyHeader>>,
+    requester_hpke_pk: Option<TypedHeader<RequesterHpkePublicKeyHeader>>,
     State(AppState { backend, .. }): State<AppState<S>>,
 ) -> Result<Either<Json<EncryptedPayload>, Json<ShareBody>>, Error> {
     let ss = backend
@@ -397,30 +647,17 @@ async fn get_share<S: Store>(
 
     let res = ShareBody { share: ss.into() };
 
-    let Some(peer_pk) = requester_pk else {
+    let Some(peer_pk) =
+        ResponseKey::from_headers(requester_pk, requester_mlkem_pk, requester_hpke_pk)
+    else {
         return Ok(Either::E2(Json(res)));
     };
 
-    let mut payload = serde_json::to_vec(&res).map_err(|e| Error::Unhandled(e.into()))?;
-
-    let ephemeral_identity = KeyPair::ephemeral();
-
-    let mut nonce = aes_gcm_siv::Nonce::default();
-    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut nonce);
-    ephemeral_identity
-        .derive_shared_cipher(*peer_pk.0, keypair::GET_SHARE_DOMAIN_SEP)
-        .encrypt_in_place(&nonce, &[], &mut payload)
-        .map_err(|e| Error::Unhandled(anyhow::anyhow!("encryption error: {e}")))?;
-
-    Ok(Either::E1(Json(EncryptedPayload {
-        format: EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
-            curve: CurveP384,
-            pk: *ephemeral_identity.public_key(),
-            nonce: nonce.into(),
-            recipient_key_id: Default::default(),
-        },
-        payload: payload.into(),
-    })))
+    Ok(Either::E1(Json(encrypt_response(
+        &res,
+        peer_pk,
+        keypair::GET_SHARE_DOMAIN_SEP,
+    )?)))
 }
 
 async fn deal_share<S: Store>(
@@ -429,29 +666,7 @@ async fn deal_share<S: Store>(
     State(AppState { backend, kps, .. }): State<AppState<S>>,
     Json(req): Json<MaybeEncryptedRequest<api::SecretShare>>,
 ) -> Result<StatusCode, Error> {
-    let ss = match req {
-        MaybeEncryptedRequest::Plain(ss) => ss,
-        MaybeEncryptedRequest::Encrypted(EncryptedPayload { format, payload }) => {
-            let EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
-                pk,
-                nonce,
-                recipient_key_id,
-                ..
-            } = format
-            else {
-                return Err(Error::BadRequest("unknown encrypted request format".into()));
-            };
-            let mut payload = Vec::from(payload.0);
-            kps.with_key(&recipient_key_id, |kp| {
-                kp.derive_shared_cipher(pk, keypair::DEAL_SHARES_DOMAIN_SEP)
-                    .decrypt_in_place(&nonce.into(), &[], &mut payload)
-            })
-            .await
-            .map_err(|_| Error::BadRequest("decryption failed".into()))?;
-            serde_json::from_slice(&payload)
-                .map_err(|e| Error::BadRequest(format!("invalid payload: {e}")))?
-        }
-    };
+    let ss = decrypt_request(&kps, req, keypair::DEAL_SHARES_DOMAIN_SEP).await?;
 
     let verifiers = ss
         .meta
@@ -540,9 +755,10 @@ async fn commit_share<S: Store>(
 async fn put_secret<S: Store>(
     Path((name, chain, registry, identity)): Path<(String, ChainId, Address, IdentityId)>,
     Query(GetKeyQuery { version }): Query<GetKeyQuery>,
-    State(AppState { backend, .. }): State<AppState<S>>,
-    Json(PutKeyRequest { key }): Json<PutKeyRequest>,
+    State(AppState { backend, kps, .. }): State<AppState<S>>,
+    Json(req): Json<MaybeEncryptedRequest<PutKeyRequest>>,
 ) -> Result<StatusCode, Error> {
+    let PutKeyRequest { key } = decrypt_request(&kps, req, keypair::PUT_SECRET_DOMAIN_SEP).await?;
     let created = backend
         .put_secret(
             KeyId {
@@ -567,8 +783,11 @@ async fn put_secret<S: Store>(
 async fn get_secret<S: Store>(
     Path((name, chain, registry, identity)): Path<(String, ChainId, Address, IdentityId)>,
     Query(GetKeyQuery { version }): Query<GetKeyQuery>,
+    requester_pk: Option<TypedHeader<RequesterPublicKeyHeader>>,
+    requester_mlkem_pk: Option<TypedHeader<RequesterMlKemPublicKeyHeader>>,
+    requester_hpke_pk: Option<TypedHeader<RequesterHpkePublicKeyHeader>>,
     State(AppState { backend, .. }): State<AppState<S>>,
-) -> Result<Json<KeyResponse>, Error> {
+) -> Result<Either<Json<EncryptedPayload>, Json<KeyResponse>>, Error> {
     let key = backend
         .get_secret(KeyId {
             name,
@@ -579,13 +798,24 @@ async fn get_secret<S: Store>(
             },
             version,
         })
-        .await?;
-    match key {
-        Some(key) => Ok(Json(KeyResponse {
-            key: key.into_vec().into(),
-        })),
-        None => Err(Error::NotFound("key".into())),
-    }
+        .await?
+        .ok_or_else(|| Error::NotFound("key".into()))?;
+
+    let res = KeyResponse {
+        key: key.into_vec().into(),
+    };
+
+    let Some(peer_pk) =
+        ResponseKey::from_headers(requester_pk, requester_mlkem_pk, requester_hpke_pk)
+    else {
+        return Ok(Either::E2(Json(res)));
+    };
+
+    Ok(Either::E1(Json(encrypt_response(
+        &res,
+        peer_pk,
+        keypair::GET_SECRET_DOMAIN_SEP,
+    )?)))
 }
 
 async fn delete_secret<S: Store>(
@@ -606,3 +836,163 @@ async fn delete_secret<S: Store>(
         .await?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Decrypts a request body that was encrypted to one of this SSSS's ephemeral keys.
+async fn decrypt_request<S: Store, T: serde::de::DeserializeOwned>(
+    kps: &RotatingKeyPairProvider<S>,
+    req: MaybeEncryptedRequest<T>,
+    domain_sep: &[u8],
+) -> Result<T, Error> {
+    let EncryptedPayload { format, payload } = match req {
+        MaybeEncryptedRequest::Plain(req) => return Ok(req),
+        MaybeEncryptedRequest::Encrypted(enc_payload) => enc_payload,
+    };
+    let payload = match format {
+        EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
+            pk,
+            nonce,
+            recipient_key_id,
+            ..
+        } => {
+            let mut payload = Vec::from(payload.0);
+            kps.with_key(&recipient_key_id, |kp| {
+                kp.derive_shared_cipher(pk, domain_sep).decrypt_in_place(
+                    &nonce.into(),
+                    &[],
+                    &mut payload,
+                )
+            })
+            .await
+            .map_err(Error::Unhandled)?
+            .ok_or_else(|| Error::BadRequest("unknown recipient key".into()))?
+            .map_err(|_| Error::BadRequest("decryption failed".into()))?;
+            payload
+        }
+        EncryptedPayloadFormat::P384MlKem768Aes256GcmSiv {
+            pk,
+            ct,
+            nonce,
+            recipient_key_id,
+            ..
+        } => {
+            let mut payload = Vec::from(payload.0);
+            kps.with_hybrid_key(&recipient_key_id, |kp, mlkem| {
+                kp.decapsulate_hybrid_cipher(mlkem, pk, &ct, domain_sep)
+                    .map_err(|_| ())?
+                    .decrypt_in_place(&nonce.into(), &[], &mut payload)
+                    .map_err(|_| ())
+            })
+            .await
+            .map_err(Error::Unhandled)?
+            .ok_or_else(|| Error::BadRequest("unknown recipient key".into()))?
+            .map_err(|_| Error::BadRequest("decryption failed".into()))?;
+            payload
+        }
+        EncryptedPayloadFormat::Hpke {
+            suite,
+            enc,
+            recipient_key_id,
+        } => kps
+            .with_hpke_key(&recipient_key_id, suite.kem, |kp| {
+                kp.open(suite, &enc, domain_sep, &payload)
+            })
+            .await
+            .map_err(Error::Unhandled)?
+            .ok_or_else(|| Error::BadRequest("unknown recipient key".into()))?
+            .map_err(|_| Error::BadRequest("decryption failed".into()))?,
+    };
+    serde_json::from_slice(&payload).map_err(|e| Error::BadRequest(format!("invalid payload: {e}")))
+}
+
+/// The key to which a response should be encrypted.
+enum ResponseKey {
+    P384(p384::PublicKey),
+    Hybrid(p384::PublicKey, ethers::types::Bytes),
+    Hpke(RequesterHpkePublicKeyHeader),
+}
+
+impl ResponseKey {
+    /// Returns the requested response key, preferring HPKE, then hybrid, if several were provided.
+    fn from_headers(
+        p384_pk: Option<TypedHeader<RequesterPublicKeyHeader>>,
+        mlkem_pk: Option<TypedHeader<RequesterMlKemPublicKeyHeader>>,
+        hpke_pk: Option<TypedHeader<RequesterHpkePublicKeyHeader>>,
+    ) -> Option<Self> {
+        if let Some(TypedHeader(hpke_pk)) = hpke_pk {
+            return Some(Self::Hpke(hpke_pk));
+        }
+        let TypedHeader(RequesterPublicKeyHeader(pk)) = p384_pk?;
+        Some(match mlkem_pk {
+            Some(TypedHeader(RequesterMlKemPublicKeyHeader(ek))) => Self::Hybrid(pk, ek),
+            None => Self::P384(pk),
+        })
+    }
+}
+
+/// Encrypts a response body to the public key provided by the requester.
+fn encrypt_response(
+    res: &impl serde::Serialize,
+    peer_pk: ResponseKey,
+    domain_sep: &[u8],
+) -> Result<EncryptedPayload, Error> {
+    let mut payload = serde_json::to_vec(res).map_err(|e| Error::Unhandled(e.into()))?;
+
+    let (peer_pk, peer_mlkem_pk) = match peer_pk {
+        ResponseKey::P384(pk) => (pk, None),
+        ResponseKey::Hybrid(pk, ek) => (pk, Some(ek)),
+        ResponseKey::Hpke(RequesterHpkePublicKeyHeader { suite, pk }) => {
+            let (enc, ct) = keypair::hpke_seal(suite, &pk, domain_sep, &payload)
+                .map_err(|_| Error::BadRequest("invalid requester HPKE public key".into()))?;
+            return Ok(EncryptedPayload {
+                format: EncryptedPayloadFormat::Hpke {
+                    suite,
+                    enc: enc.into(),
+                    recipient_key_id: Default::default(),
+                },
+                payload: ct.into(),
+            });
+        }
+    };
+
+    let ephemeral_identity = KeyPair::ephemeral();
+
+    let (cipher, mlkem_ct) = match &peer_mlkem_pk {
+        Some(ek) => {
+            let (cipher, ct) = ephemeral_identity
+                .encapsulate_hybrid_cipher(peer_pk, ek, domain_sep)
+                .map_err(|e| Error::BadRequest(e.to_string()))?;
+            (cipher, Some(ct))
+        }
+        None => (
+            ephemeral_identity.derive_shared_cipher(peer_pk, domain_sep),
+            None,
+        ),
+    };
+
+    let mut nonce = aes_gcm_siv::Nonce::default();
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut nonce);
+    cipher
+        .encrypt_in_place(&nonce, &[], &mut payload)
+        .map_err(|e| Error::Unhandled(anyhow::anyhow!("encryption error: {e}")))?;
+
+    let pk = *ephemeral_identity.public_key();
+    let nonce = nonce.into();
+    Ok(EncryptedPayload {
+        format: match mlkem_ct {
+            Some(ct) => EncryptedPayloadFormat::P384MlKem768Aes256GcmSiv {
+                curve: CurveP384,
+                pk,
+                ct: ct.into(),
+                nonce,
+                recipient_key_id: Default::default(),
+            },
+            None => EncryptedPayloadFormat::P384EcdhAes256GcmSiv {
+                curve: CurveP384,
+                pk,
+                nonce,
+                recipient_key_id: Default::default(),
+            },
+        },
+        payload: payload.into(),
+    })
+}
@@ -0,0 +1,218 @@
+//! Issues challenges and records the single-use values that verified requests present.
+//!
+//! Challenges are stateless: each one is a random value and an expiry authenticated by a key
+//! that all replicas share through the store, so issuing one writes nothing and unauthenticated
+//! callers cannot fill the store. A challenge is recorded only once it has been redeemed.
+
+use anyhow::anyhow;
+use ethers::types::{Address, H256};
+use hmac::{Hmac, Mac as _};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::Error;
+use crate::{backend::Store, types::*, verify};
+
+/// How long an issued challenge may be redeemed for.
+pub(super) const CHALLENGE_TTL: u64 = 5 * 60;
+
+/// The name of the key that authenticates challenges. It is stored on a chain that no identity
+/// can be registered on, so it cannot collide with an identity's keys.
+const CHALLENGE_KEY_NAME: &str = "ssss-challenge-key";
+
+/// The number of random bytes at the start of a challenge.
+const RANDOM_LEN: usize = 8;
+
+/// The number of bytes of a challenge that are authenticated, namely the random bytes and the
+/// big-endian expiry. The remainder of the challenge is the truncated tag.
+const BODY_LEN: usize = RANDOM_LEN + 8;
+
+#[derive(Clone)]
+pub(super) struct ChallengeKey(Zeroizing<Vec<u8>>);
+
+impl ChallengeKey {
+    fn key_id() -> KeyId {
+        KeyId {
+            name: CHALLENGE_KEY_NAME.into(),
+            identity: IdentityLocator {
+                chain: 0,
+                registry: Address::zero(),
+                id: IdentityId(H256::zero()),
+            },
+            version: 1,
+        }
+    }
+
+    /// Loads the key shared by all replicas, generating it if no replica has yet done so.
+    pub(super) async fn load(store: &impl Store) -> Result<Self, anyhow::Error> {
+        let id = Self::key_id();
+        if let Some(key) = store.get_secret(id.clone()).await? {
+            return Ok(Self(Zeroizing::new(key.into_vec())));
+        }
+        let key = Zeroizing::new(rand::random::<[u8; 32]>().to_vec());
+        if store.put_secret(id.clone(), key.to_vec().into()).await? {
+            return Ok(Self(key));
+        }
+        // Another replica stored its key first.
+        let key = store
+            .get_secret(id)
+            .await?
+            .ok_or_else(|| anyhow!("challenge key vanished after being stored"))?;
+        Ok(Self(Zeroizing::new(key.into_vec())))
+    }
+
+    fn tag(&self, body: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("any key length is valid");
+        mac.update(body);
+        mac
+    }
+
+    /// Returns a new challenge that expires [`CHALLENGE_TTL`] seconds after `now`.
+    pub(super) fn issue(&self, now: u64) -> (H256, u64) {
+        let expiry = now + CHALLENGE_TTL;
+        let mut challenge = H256::zero();
+        challenge.0[..RANDOM_LEN].copy_from_slice(&rand::random::<[u8; RANDOM_LEN]>());
+        challenge.0[RANDOM_LEN..BODY_LEN].copy_from_slice(&expiry.to_be_bytes());
+        let tag = self.tag(&challenge.0[..BODY_LEN]).finalize().into_bytes();
+        challenge.0[BODY_LEN..].copy_from_slice(&tag[..32 - BODY_LEN]);
+        (challenge, expiry)
+    }
+
+    /// Returns the expiry of the challenge if it was issued using this key and has not expired.
+    pub(super) fn check(&self, challenge: &H256, now: u64) -> Option<u64> {
+        let (body, tag) = challenge.0.split_at(BODY_LEN);
+        self.tag(body).verify_truncated_left(tag).ok()?;
+        let expiry = u64::from_be_bytes(body[RANDOM_LEN..].try_into().unwrap());
+        (expiry > now).then_some(expiry)
+    }
+}
+
+/// Records the values that a verified request presented so that they cannot be presented again.
+///
+/// Either all of the values are recorded or, if any of them has already been used, none are,
+/// so that a rejected request does not use up the values that it shares with a legitimate one.
+pub(super) async fn consume(
+    backend: &impl Store,
+    challenge_key: &ChallengeKey,
+    verify::SingleUse {
+        nonces,
+        expiry,
+        challenge,
+    }: &verify::SingleUse,
+    now: u64,
+) -> Result<(), Error> {
+    let challenge = match challenge {
+        Some(challenge) => {
+            let challenge_expiry = challenge_key
+                .check(&challenge.digest, now)
+                .ok_or_else(|| Error::Replayed("unknown or expired challenge".into()))?;
+            Some((*challenge, challenge_expiry))
+        }
+        None => None,
+    };
+    let values = nonces
+        .iter()
+        .map(|&nonce| (nonce, *expiry))
+        .chain(challenge);
+    let mut recorded = Vec::new();
+    for (nonce, expiry) in values {
+        let res = backend.put_nonce(nonce, expiry).await;
+        if matches!(res, Ok(true)) {
+            recorded.push(nonce);
+            continue;
+        }
+        for nonce in recorded {
+            if let Err(e) = backend.take_nonce(nonce).await {
+                tracing::warn!(error = ?e, kind = %nonce.kind, "failed to release single-use value");
+            }
+        }
+        return match res {
+            Ok(_) => Err(Error::Replayed(format!("{} already used", nonce.kind))),
+            Err(e) => Err(e.into()),
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::memory;
+
+    const NOW: u64 = 1_000_000;
+
+    #[test]
+    fn challenge_roundtrip() {
+        let key = ChallengeKey(Zeroizing::new(vec![7; 32]));
+        let (challenge, expiry) = key.issue(NOW);
+        assert_eq!(expiry, NOW + CHALLENGE_TTL);
+        assert_eq!(key.check(&challenge, NOW), Some(expiry));
+        assert_eq!(key.check(&challenge, expiry), None);
+
+        let mut forged = challenge;
+        forged.0[RANDOM_LEN..BODY_LEN].copy_from_slice(&(expiry + 60).to_be_bytes());
+        assert_eq!(key.check(&forged, expiry), None);
+
+        let other = ChallengeKey(Zeroizing::new(vec![8; 32]));
+        assert_eq!(other.check(&challenge, NOW), None);
+    }
+
+    #[tokio::test]
+    async fn challenge_key_shared() {
+        let store = memory::Backend::generate();
+        let now = crate::utils::now();
+        let key = ChallengeKey::load(&store).await.unwrap();
+        let (challenge, _) = key.issue(now);
+        let replica_key = ChallengeKey::load(&store).await.unwrap();
+        assert!(replica_key.check(&challenge, now).is_some());
+    }
+
+    #[tokio::test]
+    async fn consume_all_or_nothing() {
+        let store = memory::Backend::generate();
+        let now = crate::utils::now();
+        let key = ChallengeKey::load(&store).await.unwrap();
+        let (challenge, _) = key.issue(now);
+        let challenge = NonceId::new(NonceKind::Challenge, challenge);
+        let [a, b] = [1, 2].map(|i| NonceId::new(NonceKind::Nonce, H256::repeat_byte(i)));
+        let expiry = now + 60;
+
+        // `b` has been used, so neither `a` nor the challenge may be used up.
+        assert!(store.put_nonce(b, expiry).await.unwrap());
+        let single_use = verify::SingleUse {
+            nonces: vec![a, b],
+            expiry,
+            challenge: Some(challenge),
+        };
+        assert!(matches!(
+            consume(&store, &key, &single_use, now).await,
+            Err(Error::Replayed(_))
+        ));
+        assert!(store.take_nonce(b).await.unwrap());
+
+        consume(&store, &key, &single_use, now).await.unwrap();
+        assert!(matches!(
+            consume(&store, &key, &single_use, now).await,
+            Err(Error::Replayed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn consume_rejects_unissued_challenge() {
+        let store = memory::Backend::generate();
+        let now = crate::utils::now();
+        let key = ChallengeKey::load(&store).await.unwrap();
+        let nonce = NonceId::new(NonceKind::Nonce, H256::repeat_byte(1));
+        let single_use = verify::SingleUse {
+            nonces: vec![nonce],
+            expiry: now + 60,
+            challenge: Some(NonceId::new(NonceKind::Challenge, H256::random())),
+        };
+        assert!(matches!(
+            consume(&store, &key, &single_use, now).await,
+            Err(Error::Replayed(_))
+        ));
+        // The challenge is checked first, so the nonce was not used up.
+        assert!(store.put_nonce(nonce, now + 60).await.unwrap());
+    }
+}
@@ -46,6 +46,8 @@ impl Backend {
 
     naming_fn!(secrets_table, "escrin-secrets");
     naming_fn!(verifiers_table, "escrin-verifiers");
+    naming_fn!(nonces_table, "escrin-nonces");
+    naming_fn!(grant_ledgers_table, "escrin-grant-ledgers");
     naming_fn!(kms_key, "alias/escrin-signer");
 
     async fn current_secret(
@@ -250,10 +252,63 @@ impl Store for Backend {
         Ok(Some(key.into()))
     }
 
+    async fn replace_secret(&self, id: KeyId, key: WrappedKey) -> Result<bool, Error> {
+        let res = self
+            .db
+            .update_item()
+            .table_name(self.secrets_table())
+            .key("id", id.to_attribute_value())
+            .key("version", N(id.version.to_string()))
+            .condition_expression("attribute_exists(secret)")
+            .update_expression("SET secret = :secret")
+            .expression_attribute_values(":secret", B(Blob::new(key.into_vec())))
+            .send()
+            .await
+            .map_err(aws_sdk_dynamodb::Error::from);
+        match res {
+            Ok(_) => Ok(true),
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     async fn delete_secret(&self, id: KeyId) -> Result<(), Error> {
         self.delete_secret_version(&id, id.version).await
     }
 
+    async fn list_secrets(&self) -> Result<Vec<KeyId>, Error> {
+        let mut ids = Vec::new();
+        let mut start_key = None;
+        loop {
+            let res = self
+                .db
+                .scan()
+                .table_name(self.secrets_table())
+                .filter_expression("begins_with(id, :prefix) AND attribute_exists(secret)")
+                .expression_attribute_values(":prefix", S("key-".into()))
+                .projection_expression("id, version")
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(aws_sdk_dynamodb::Error::from)?;
+            for item in res.items.unwrap_or_default() {
+                let Some(Ok(key)) = item.get("id").map(AttributeValue::as_s) else {
+                    continue;
+                };
+                let (name, identity) = FromKey::from_key(key)?;
+                ids.push(KeyId {
+                    name,
+                    identity,
+                    version: unpack_u64("version", &item),
+                });
+            }
+            start_key = res.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(ids);
+            }
+        }
+    }
+
     async fn put_verifier(
         &self,
         permitter: PermitterLocator,
@@ -320,6 +375,95 @@ impl Store for Backend {
             .map_err(aws_sdk_dynamodb::Error::from)?;
         Ok(())
     }
+
+    async fn put_nonce(&self, id: NonceId, expiry: u64) -> Result<bool, Error> {
+        // Expired items are removed by the table's TTL, but that may happen long after expiry.
+        let res = self
+            .db
+            .put_item()
+            .table_name(self.nonces_table())
+            .item("id", id.to_attribute_value())
+            .item("expiry", N(expiry.to_string()))
+            .condition_expression("attribute_not_exists(id) OR expiry <= :now")
+            .expression_attribute_values(":now", N(now().to_string()))
+            .send()
+            .await
+            .map_err(aws_sdk_dynamodb::Error::from);
+        match res {
+            Ok(_) => Ok(true),
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn take_nonce(&self, id: NonceId) -> Result<bool, Error> {
+        let res = self
+            .db
+            .delete_item()
+            .table_name(self.nonces_table())
+            .key("id", id.to_attribute_value())
+            .condition_expression("attribute_exists(id) AND expiry > :now")
+            .expression_attribute_values(":now", N(now().to_string()))
+            .send()
+            .await
+            .map_err(aws_sdk_dynamodb::Error::from);
+        match res {
+            Ok(_) => Ok(true),
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        let Some(mut item) = self
+            .db
+            .get_item()
+            .table_name(self.grant_ledgers_table())
+            .key("identity", identity.to_attribute_value())
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(aws_sdk_dynamodb::Error::from)?
+            .item
+        else {
+            return Ok(None);
+        };
+        Ok(Some((
+            unpack_u64("version", &item),
+            unpack_blob("ledger", &mut item).into_inner(),
+        )))
+    }
+
+    async fn put_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+        version: u64,
+        ledger: Vec<u8>,
+    ) -> Result<bool, Error> {
+        let req = self
+            .db
+            .put_item()
+            .table_name(self.grant_ledgers_table())
+            .item("identity", identity.to_attribute_value())
+            .item("version", N(version.to_string()))
+            .item("ledger", B(Blob::new(ledger)));
+        let req = if version == 1 {
+            req.condition_expression("attribute_not_exists(#i)")
+                .expression_attribute_names("#i", "identity")
+        } else {
+            req.condition_expression("#v = :prev")
+                .expression_attribute_names("#v", "version")
+                .expression_attribute_values(":prev", N(version.saturating_sub(1).to_string()))
+        };
+        match req.send().await.map_err(aws_sdk_dynamodb::Error::from) {
+            Ok(_) => Ok(true),
+            Err(aws_sdk_dynamodb::Error::ConditionalCheckFailedException(_)) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
 impl Signer for Backend {
@@ -370,6 +514,89 @@ impl Signer for Backend {
     }
 }
 
+/// A KMS symmetric key used to wrap the DEKs of stored keys.
+#[derive(Clone)]
+pub struct Kek {
+    kms: aws_sdk_kms::Client,
+    env: Environment,
+    current_arn: tokio::sync::OnceCell<String>,
+}
+
+impl Kek {
+    const PROVIDER: &'static str = "aws-kms";
+
+    pub async fn connect(env: Environment) -> Self {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::v2024_03_28()).await;
+        Self {
+            kms: aws_sdk_kms::Client::new(&config),
+            env,
+            current_arn: Default::default(),
+        }
+    }
+
+    naming_fn!(kms_key, "alias/escrin-kek");
+
+    /// Returns the KEK currently targeted by the alias.
+    /// Pointing the alias at a new key rotates the KEK once the SSSS is restarted.
+    pub async fn current(&self) -> Result<kek::KekRef, Error> {
+        let arn = self
+            .current_arn
+            .get_or_try_init(|| async {
+                self.kms
+                    .describe_key()
+                    .key_id(self.kms_key())
+                    .send()
+                    .await
+                    .map_err(aws_sdk_kms::Error::from)?
+                    .key_metadata
+                    .and_then(|m| m.arn)
+                    .ok_or_else(|| anyhow!("failed to describe KEK"))
+            })
+            .await?;
+        Ok(kek::KekRef {
+            provider: Self::PROVIDER.into(),
+            id: self.kms_key().into(),
+            version: arn.clone(),
+        })
+    }
+
+    pub async fn wrap(&self, dek: &[u8]) -> Result<(kek::KekRef, Vec<u8>), Error> {
+        let kek = self.current().await?;
+        let wrapped_dek = self
+            .kms
+            .encrypt()
+            .key_id(&kek.version)
+            .plaintext(Blob::new(dek.to_vec()))
+            .send()
+            .await
+            .map_err(aws_sdk_kms::Error::from)?
+            .ciphertext_blob
+            .ok_or_else(|| anyhow!("failed to wrap DEK"))?
+            .into_inner();
+        Ok((kek, wrapped_dek))
+    }
+
+    pub async fn unwrap(
+        &self,
+        kek: &kek::KekRef,
+        wrapped_dek: &[u8],
+    ) -> Result<zeroize::Zeroizing<Vec<u8>>, Error> {
+        anyhow::ensure!(kek.provider == Self::PROVIDER, "unknown KEK provider");
+        Ok(self
+            .kms
+            .decrypt()
+            .key_id(&kek.version)
+            .ciphertext_blob(Blob::new(wrapped_dek.to_vec()))
+            .send()
+            .await
+            .map_err(aws_sdk_kms::Error::from)?
+            .plaintext
+            .ok_or_else(|| anyhow!("failed to unwrap DEK"))?
+            .into_inner()
+            .into())
+    }
+}
+
 fn try_unpack_u64(key: &'static str, res: &HashMap<String, AttributeValue>) -> Option<u64> {
     res.get(key)?.as_n().ok()?.parse::<u64>().ok()
 }
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use azure_core::Etag;
 use azure_data_tables::prelude::*;
@@ -21,13 +21,16 @@ pub struct Backend {
 
 static SECRET_VERSIONS_TABLE: &str = "secretversions";
 static VERIFIERS_TABLE: &str = "verifiers";
+static NONCES_TABLE: &str = "nonces";
+static GRANT_LEDGERS_TABLE: &str = "grantledgers";
 static KMS_KEY: &str = "escrin-signer";
+static KEK_SECRET: &str = "escrin-kek";
 
 impl Backend {
     pub async fn connect(host: &Authority, env: Environment) -> Result<Self, Error> {
-        let unique_name = hex::encode(&<sha2::Sha256 as sha2::Digest>::digest(host.as_str())[0..8]);
+        let unique_name = unique_name(host);
         let sa_name = format!("{env}{unique_name}");
-        let kv_url = format!("https://{env}{unique_name}.vault.azure.net");
+        let kv_url = vault_url(host, env);
 
         let creds = Arc::new(azure_identity::DefaultAzureCredential::default());
         let secrets = Arc::new(SecretClient::new(&kv_url, creds.clone())?);
@@ -120,7 +123,7 @@ impl Backend {
             .insert::<_, ()>(SecretVersionEntity {
                 id: id.to_key(),
                 version: InvSortableInt(version),
-                guid: secret_entity.id.rsplit_once('/').unwrap().1.to_string(),
+                guid: secret_guid(&secret_entity.id)?.to_string(),
                 expiry,
             })?
             .return_entity(false)
@@ -140,6 +143,9 @@ impl Backend {
         else {
             return Ok(None);
         };
+        if m.guid.is_empty() {
+            return Ok(None); // deleted
+        }
         if let Some(expiry) = m.expiry {
             if expiry != 0 && expiry <= now() {
                 self.delete_secret_version(id, version).await?;
@@ -165,21 +171,96 @@ impl Backend {
         }
     }
 
-    async fn delete_secret_version(&self, id: &impl ToKey, version: u64) -> Result<(), Error> {
-        let Some(m) = self
-            .get_secret_meta(id, SecretVersion::Numbered(version))
-            .await?
-        else {
-            return Ok(());
-        };
-        self.secrets
-            .update(id.to_key())
-            .version(m.guid)
-            .enabled(false)
+    async fn get_secret_version(
+        &self,
+        id: &impl ToKey,
+        version: u64,
+    ) -> Result<Option<(Etag, SecretVersionEntity)>, Error> {
+        self.db
+            .table_client(SECRET_VERSIONS_TABLE)
+            .partition_key_client(id.to_key())
+            .entity_client(InvSortableInt(version).to_key())
+            .get::<SecretVersionEntity>()
             .into_future()
             .await
+            .map(|res| Some((res.etag, res.entity)))
             .or_else(default_if_notfound)
     }
+
+    async fn replace_secret(
+        &self,
+        id: &impl ToKey,
+        version: u64,
+        secret: String,
+    ) -> Result<bool, Error> {
+        let Some((etag, _)) = self.get_secret_version(id, version).await? else {
+            return Ok(false);
+        };
+        if self.get_secret(id, version).await?.is_none() {
+            return Ok(false);
+        }
+        self.secrets.set(id.to_key(), secret).into_future().await?;
+        let secret_entity = self.secrets.get(id.to_key()).into_future().await?;
+        let guid = secret_guid(&secret_entity.id)?;
+        // The version is repointed only if it has not changed since it was read, so a concurrent
+        // deletion cannot be undone by pointing it at the new, enabled vault version.
+        let res = self
+            .db
+            .table_client(SECRET_VERSIONS_TABLE)
+            .partition_key_client(id.to_key())
+            .entity_client(InvSortableInt(version).to_key())
+            .merge(serde_json::json!({ "guid": guid }), etag.into())?
+            .into_future()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if is_precondition_failed(&e) => {
+                self.secrets
+                    .update(id.to_key())
+                    .version(guid)
+                    .enabled(false)
+                    .into_future()
+                    .await
+                    .or_else(default_if_notfound)?;
+                Ok(false)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Clears the vault version from the version row and then disables it, so that deleted
+    /// versions can be told apart using the table alone.
+    async fn delete_secret_version(&self, id: &impl ToKey, version: u64) -> Result<(), Error> {
+        loop {
+            let Some((etag, m)) = self.get_secret_version(id, version).await? else {
+                return Ok(());
+            };
+            if m.guid.is_empty() {
+                return Ok(());
+            }
+            let res = self
+                .db
+                .table_client(SECRET_VERSIONS_TABLE)
+                .partition_key_client(id.to_key())
+                .entity_client(InvSortableInt(version).to_key())
+                .merge(serde_json::json!({ "guid": "" }), etag.into())?
+                .into_future()
+                .await;
+            match res {
+                Ok(_) => {}
+                Err(e) if is_precondition_failed(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+            return self
+                .secrets
+                .update(id.to_key())
+                .version(m.guid)
+                .enabled(false)
+                .into_future()
+                .await
+                .or_else(default_if_notfound);
+        }
+    }
 }
 
 impl Store for Backend {
@@ -261,10 +342,36 @@ impl Store for Backend {
         Ok(Some(hex::decode(k)?.into()))
     }
 
+    async fn replace_secret(&self, id: KeyId, key: WrappedKey) -> Result<bool, Error> {
+        self.replace_secret(&id, id.version, hex::encode(&key))
+            .await
+    }
+
     async fn delete_secret(&self, id: KeyId) -> Result<(), Error> {
         self.delete_secret_version(&id, id.version).await
     }
 
+    async fn list_secrets(&self) -> Result<Vec<KeyId>, Error> {
+        let pages: Vec<_> = self
+            .db
+            .table_client(SECRET_VERSIONS_TABLE)
+            .query()
+            .filter("PartitionKey ge 'key-' and PartitionKey lt 'key.' and guid ne ''")
+            .into_stream::<SecretVersionEntity>()
+            .try_collect()
+            .await?;
+        let mut ids = Vec::new();
+        for SecretVersionEntity { id, version, .. } in pages.into_iter().flat_map(|p| p.entities) {
+            let (name, identity) = FromKey::from_key(&id)?;
+            ids.push(KeyId {
+                name,
+                identity,
+                version: version.0,
+            });
+        }
+        Ok(ids)
+    }
+
     async fn put_verifier(
         &self,
         permitter: PermitterLocator,
@@ -312,6 +419,125 @@ impl Store for Backend {
             .map(|_| ())
             .or_else(default_if_notfound)
     }
+
+    async fn put_nonce(&self, id: NonceId, expiry: u64) -> Result<bool, Error> {
+        let entity = NonceEntity::new(id, expiry);
+        let table = self.db.table_client(NONCES_TABLE);
+        // An expired entity is deleted and the insert retried once. Inserts are atomic,
+        // so only one of several concurrent writers of the same nonce can succeed.
+        for _ in 0..2 {
+            let res = table
+                .insert::<_, ()>(entity.clone())?
+                .return_entity(false)
+                .into_future()
+                .await;
+            match res {
+                Ok(_) => return Ok(true),
+                Err(e) if is_conflict(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+            let Some((_, existing)) = self
+                .get_current::<NonceEntity>(NONCES_TABLE, &entity.kind, Some(&entity.digest))
+                .await?
+            else {
+                continue;
+            };
+            if existing.expiry > now() {
+                return Ok(false);
+            }
+            table
+                .partition_key_client(&entity.kind)
+                .entity_client(&entity.digest)
+                .delete()
+                .into_future()
+                .await
+                .map(|_| ())
+                .or_else(default_if_notfound)?;
+        }
+        Ok(false)
+    }
+
+    async fn take_nonce(&self, id: NonceId) -> Result<bool, Error> {
+        let NonceEntity { kind, digest, .. } = NonceEntity::new(id, 0);
+        let Some((_, existing)) = self
+            .get_current::<NonceEntity>(NONCES_TABLE, &kind, Some(&digest))
+            .await?
+        else {
+            return Ok(false);
+        };
+        // Only the caller whose delete succeeds has taken the nonce.
+        let deleted = self
+            .db
+            .table_client(NONCES_TABLE)
+            .partition_key_client(&kind)
+            .entity_client(&digest)
+            .delete()
+            .into_future()
+            .await
+            .map(|_| true)
+            .or_else(default_if_notfound)?;
+        Ok(deleted && existing.expiry > now())
+    }
+
+    async fn get_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        Ok(self
+            .get_current::<GrantLedgerEntity>(
+                GRANT_LEDGERS_TABLE,
+                &identity,
+                None::<&InvSortableInt>,
+            )
+            .await?
+            .map(|(_, e)| (e.version.0, e.ledger)))
+    }
+
+    async fn put_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+        version: u64,
+        ledger: Vec<u8>,
+    ) -> Result<bool, Error> {
+        let current_version = self
+            .get_grant_ledger(identity)
+            .await?
+            .map(|(v, _)| v)
+            .unwrap_or_default();
+        if version != current_version + 1 {
+            return Ok(false);
+        }
+        // Each version is a new row, so only one of several concurrent writers can insert it.
+        let table = self.db.table_client(GRANT_LEDGERS_TABLE);
+        let res = table
+            .insert::<_, ()>(GrantLedgerEntity {
+                identity,
+                version: InvSortableInt(version),
+                ledger,
+            })?
+            .return_entity(false)
+            .into_future()
+            .await;
+        match res {
+            Ok(_) => {}
+            Err(e) if is_conflict(&e) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        if current_version != 0 {
+            let res = table
+                .partition_key_client(identity.to_key())
+                .entity_client(InvSortableInt(current_version).to_key())
+                .delete()
+                .into_future()
+                .await
+                .map(|_| ())
+                .or_else(default_if_notfound);
+            if let Err(e) = res {
+                tracing::warn!(error = ?e, "failed to delete stale grant ledger");
+            }
+        }
+        Ok(true)
+    }
 }
 
 impl Signer for Backend {
@@ -350,6 +576,95 @@ impl Signer for Backend {
     }
 }
 
+/// A Key Vault secret holding a hex-encoded 256-bit key used to wrap the DEKs of stored keys.
+/// Rotation is done by adding a new version of the secret and restarting the SSSS.
+#[derive(Clone)]
+pub struct Kek {
+    secrets: Arc<SecretClient>,
+    current_version: tokio::sync::OnceCell<String>,
+    keys: Arc<std::sync::RwLock<HashMap<String, zeroize::Zeroizing<[u8; 32]>>>>,
+}
+
+impl Kek {
+    const PROVIDER: &'static str = "azure-kv";
+
+    pub async fn connect(host: &Authority, env: Environment) -> Result<Self, Error> {
+        let creds = Arc::new(azure_identity::DefaultAzureCredential::default());
+        Ok(Self {
+            secrets: Arc::new(SecretClient::new(&vault_url(host, env), creds)?),
+            current_version: Default::default(),
+            keys: Default::default(),
+        })
+    }
+
+    pub async fn current(&self) -> Result<kek::KekRef, Error> {
+        let version = self
+            .current_version
+            .get_or_try_init(|| async {
+                let secret = self.secrets.get(KEK_SECRET).into_future().await?;
+                let version = secret.id.rsplit_once('/').unwrap().1.to_string();
+                self.keys
+                    .write()
+                    .unwrap()
+                    .insert(version.clone(), decode_kek(&secret.value)?);
+                Ok::<_, Error>(version)
+            })
+            .await?;
+        Ok(kek::KekRef {
+            provider: Self::PROVIDER.into(),
+            id: KEK_SECRET.into(),
+            version: version.clone(),
+        })
+    }
+
+    async fn key(&self, version: &str) -> Result<zeroize::Zeroizing<[u8; 32]>, Error> {
+        if let Some(key) = self.keys.read().unwrap().get(version) {
+            return Ok(key.clone());
+        }
+        let secret = self
+            .secrets
+            .get(KEK_SECRET)
+            .version(version)
+            .into_future()
+            .await?;
+        let key = decode_kek(&secret.value)?;
+        self.keys
+            .write()
+            .unwrap()
+            .insert(version.to_string(), key.clone());
+        Ok(key)
+    }
+
+    pub async fn wrap(&self, dek: &[u8]) -> Result<(kek::KekRef, Vec<u8>), Error> {
+        let kek = self.current().await?;
+        let wrapped_dek = kek::aes_wrap(&*self.key(&kek.version).await?, dek)?;
+        Ok((kek, wrapped_dek))
+    }
+
+    pub async fn unwrap(
+        &self,
+        kek: &kek::KekRef,
+        wrapped_dek: &[u8],
+    ) -> Result<zeroize::Zeroizing<Vec<u8>>, Error> {
+        anyhow::ensure!(kek.provider == Self::PROVIDER, "unknown KEK provider");
+        kek::aes_unwrap(&*self.key(&kek.version).await?, wrapped_dek)
+    }
+}
+
+fn decode_kek(hex_key: &str) -> Result<zeroize::Zeroizing<[u8; 32]>, Error> {
+    let mut key = zeroize::Zeroizing::new([0u8; 32]);
+    hex::decode_to_slice(hex_key.trim(), &mut *key)?;
+    Ok(key)
+}
+
+fn unique_name(host: &Authority) -> String {
+    hex::encode(&<sha2::Sha256 as sha2::Digest>::digest(host.as_str())[0..8])
+}
+
+fn vault_url(host: &Authority, env: Environment) -> String {
+    format!("https://{env}{}.vault.azure.net", unique_name(host))
+}
+
 #[derive(Serialize, Deserialize)]
 struct EncodableSecretShare {
     meta: SecretShareMeta,
@@ -406,10 +721,21 @@ struct Expiring<T> {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct NonceEntity {
-    #[serde(rename = "PartitionKey", with = "serde_key")]
-    identity: IdentityLocator,
-    #[serde(rename = "RowKey", with = "hex::serde")]
-    nonce: Vec<u8>,
+    #[serde(rename = "PartitionKey")]
+    kind: String,
+    #[serde(rename = "RowKey")]
+    digest: String,
+    expiry: u64,
+}
+
+impl NonceEntity {
+    fn new(NonceId { kind, digest }: NonceId, expiry: u64) -> Self {
+        Self {
+            kind: kind.to_string(),
+            digest: format!("{digest:x}"),
+            expiry,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -430,6 +756,16 @@ struct VerifierEntity {
     config: Vec<u8>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct GrantLedgerEntity {
+    #[serde(rename = "PartitionKey", with = "serde_key")]
+    identity: IdentityLocator,
+    #[serde(rename = "RowKey")]
+    version: InvSortableInt,
+    #[serde(with = "hex::serde")]
+    ledger: Vec<u8>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct SecretVersionEntity {
     #[serde(rename = "PartitionKey")]
@@ -474,6 +810,22 @@ impl ToKey for InvSortableInt {
     }
 }
 
+fn is_conflict(e: &azure_core::Error) -> bool {
+    e.as_http_error().map(|e| e.status()) == Some(azure_core::StatusCode::Conflict)
+}
+
+fn is_precondition_failed(e: &azure_core::Error) -> bool {
+    e.as_http_error().map(|e| e.status()) == Some(azure_core::StatusCode::PreconditionFailed)
+}
+
+/// Returns the version of a vault secret from its identifier, which ends in the version.
+fn secret_guid(secret_id: &str) -> Result<&str, Error> {
+    secret_id
+        .rsplit_once('/')
+        .map(|(_, guid)| guid)
+        .ok_or_else(|| anyhow::anyhow!("malformed secret identifier: {secret_id}"))
+}
+
 fn default_if_notfound<T: Default>(e: azure_core::Error) -> Result<T, Error> {
     match e.kind() {
         azure_core::error::ErrorKind::HttpResponse {
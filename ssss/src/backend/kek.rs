@@ -0,0 +1,364 @@
+//! Envelope encryption of wrapped keys at rest.
+//!
+//! Each stored key is encrypted under a fresh data encryption key (DEK), which is itself wrapped by
+//! a key encryption key (KEK) held by a KMS or a local key file. The envelope records which KEK
+//! (and which version of it) wrapped the DEK, so entries wrapped by a retired KEK version can be
+//! detected on read and re-wrapped in the background.
+
+use std::{path::Path, sync::Arc};
+
+use aes_gcm_siv::{aead::Aead as _, Aes256GcmSiv, KeyInit as _};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::*;
+
+static ENVELOPE_MAGIC: &[u8] = b"ssss-env";
+const ENVELOPE_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "lowercase")]
+pub enum KekKind {
+    None,
+    Local,
+    #[cfg(feature = "aws")]
+    Aws,
+    #[cfg(feature = "azure")]
+    Azure,
+}
+
+/// Identifies the KEK that wrapped an envelope's DEK.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KekRef {
+    pub provider: String,
+    pub id: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    v: u8,
+    kek: KekRef,
+    dek: Vec<u8>,
+    nonce: [u8; 12],
+    ct: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub enum Kek {
+    Local(LocalKek),
+    #[cfg(feature = "aws")]
+    Aws(aws::Kek),
+    #[cfg(feature = "azure")]
+    Azure(azure::Kek),
+}
+
+impl Kek {
+    #[allow(unused_variables)]
+    pub async fn create(
+        kind: KekKind,
+        env: Environment,
+        host: &Authority,
+        key_file: Option<&Path>,
+    ) -> Result<Option<Self>, Error> {
+        Ok(Some(match kind {
+            KekKind::None => return Ok(None),
+            KekKind::Local => {
+                Self::Local(LocalKek::load(key_file.ok_or_else(|| {
+                    anyhow::anyhow!("a KEK file is required for local KEKs")
+                })?)?)
+            }
+            #[cfg(feature = "aws")]
+            KekKind::Aws => Self::Aws(aws::Kek::connect(env).await),
+            #[cfg(feature = "azure")]
+            KekKind::Azure => Self::Azure(azure::Kek::connect(host, env).await?),
+        }))
+    }
+
+    /// Returns the KEK that new envelopes are wrapped with.
+    pub async fn current(&self) -> Result<KekRef, Error> {
+        match self {
+            Self::Local(k) => Ok(k.current()),
+            #[cfg(feature = "aws")]
+            Self::Aws(k) => k.current().await,
+            #[cfg(feature = "azure")]
+            Self::Azure(k) => k.current().await,
+        }
+    }
+
+    async fn wrap_dek(&self, dek: &[u8]) -> Result<(KekRef, Vec<u8>), Error> {
+        match self {
+            Self::Local(k) => k.wrap(dek),
+            #[cfg(feature = "aws")]
+            Self::Aws(k) => k.wrap(dek).await,
+            #[cfg(feature = "azure")]
+            Self::Azure(k) => k.wrap(dek).await,
+        }
+    }
+
+    async fn unwrap_dek(
+        &self,
+        kek: &KekRef,
+        wrapped_dek: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, Error> {
+        match self {
+            Self::Local(k) => k.unwrap(kek, wrapped_dek),
+            #[cfg(feature = "aws")]
+            Self::Aws(k) => k.unwrap(kek, wrapped_dek).await,
+            #[cfg(feature = "azure")]
+            Self::Azure(k) => k.unwrap(kek, wrapped_dek).await,
+        }
+    }
+
+    /// Encrypts `plaintext` under a new DEK wrapped by the current KEK.
+    /// The `aad` binds the envelope to the location at which it is stored.
+    pub async fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let dek = Zeroizing::new(rand::random::<[u8; 32]>());
+        let mut nonce = [0u8; 12];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut nonce);
+        let ct = Aes256GcmSiv::new((&*dek).into())
+            .encrypt(
+                &nonce.into(),
+                aes_gcm_siv::aead::Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("envelope encryption failed: {e}"))?;
+        let (kek, dek) = self.wrap_dek(&*dek).await?;
+        let mut sealed = ENVELOPE_MAGIC.to_vec();
+        ciborium::into_writer(
+            &Envelope {
+                v: ENVELOPE_VERSION,
+                kek,
+                dek,
+                nonce,
+                ct,
+            },
+            &mut sealed,
+        )?;
+        Ok(sealed)
+    }
+
+    /// Decrypts an envelope created by [`Kek::seal`].
+    ///
+    /// Entries that were stored before envelope encryption was enabled are returned as-is if
+    /// `allow_unsealed` is set, and are otherwise rejected.
+    /// The returned flag is set when the entry should be re-sealed, which is the case for both
+    /// unsealed entries and those whose DEK was wrapped by a KEK other than the current one.
+    pub async fn open(
+        &self,
+        aad: &[u8],
+        sealed: &[u8],
+        allow_unsealed: bool,
+    ) -> Result<(Zeroizing<Vec<u8>>, bool), Error> {
+        let Some(envelope_bytes) = sealed.strip_prefix(ENVELOPE_MAGIC) else {
+            anyhow::ensure!(allow_unsealed, "stored key is not sealed by a KEK");
+            return Ok((sealed.to_vec().into(), true));
+        };
+        let Envelope {
+            v,
+            kek,
+            dek,
+            nonce,
+            ct,
+        } = ciborium::from_reader(envelope_bytes)?;
+        anyhow::ensure!(v == ENVELOPE_VERSION, "unsupported envelope version {v}");
+        let dek = self.unwrap_dek(&kek, &dek).await?;
+        let plaintext = Aes256GcmSiv::new_from_slice(&dek)
+            .map_err(|_| anyhow::anyhow!("invalid DEK"))?
+            .decrypt(&nonce.into(), aes_gcm_siv::aead::Payload { msg: &ct, aad })
+            .map_err(|e| anyhow::anyhow!("envelope decryption failed: {e}"))?;
+        let stale = self.current().await? != kek;
+        Ok((plaintext.into(), stale))
+    }
+}
+
+/// Wraps `dek` using AES-256-GCM-SIV under `kek`, prepending the random nonce.
+pub(super) fn aes_wrap(kek: &[u8; 32], dek: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut nonce = [0u8; 12];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut nonce);
+    let ct = Aes256GcmSiv::new(kek.into())
+        .encrypt(&nonce.into(), dek)
+        .map_err(|e| anyhow::anyhow!("DEK wrapping failed: {e}"))?;
+    Ok([&nonce[..], &ct].concat())
+}
+
+pub(super) fn aes_unwrap(kek: &[u8; 32], wrapped_dek: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+    anyhow::ensure!(wrapped_dek.len() > 12, "wrapped DEK too short");
+    let (nonce, ct) = wrapped_dek.split_at(12);
+    Ok(Aes256GcmSiv::new(kek.into())
+        .decrypt(aes_gcm_siv::Nonce::from_slice(nonce), ct)
+        .map_err(|e| anyhow::anyhow!("DEK unwrapping failed: {e}"))?
+        .into())
+}
+
+fn kek_fingerprint(kek: &[u8; 32]) -> String {
+    hex::encode(&<sha2::Sha256 as sha2::Digest>::digest(kek)[..8])
+}
+
+/// KEKs read from a local file containing one hex-encoded 256-bit key per line.
+/// The last key in the file is current; earlier keys are kept so that existing entries remain
+/// readable until they are re-wrapped.
+#[derive(Clone)]
+pub struct LocalKek {
+    keys: Arc<Vec<(String, Zeroizing<[u8; 32]>)>>,
+}
+
+impl LocalKek {
+    const PROVIDER: &'static str = "local";
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let contents = Zeroizing::new(std::fs::read_to_string(path)?);
+        Self::from_keys(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(|l| {
+                    let mut key = Zeroizing::new([0u8; 32]);
+                    hex::decode_to_slice(l.trim_start_matches("0x"), &mut *key)?;
+                    Ok(key)
+                })
+                .collect::<Result<Vec<_>, Error>>()?,
+        )
+    }
+
+    pub fn from_keys(keys: Vec<Zeroizing<[u8; 32]>>) -> Result<Self, Error> {
+        anyhow::ensure!(!keys.is_empty(), "no KEKs provided");
+        Ok(Self {
+            keys: Arc::new(keys.into_iter().map(|k| (kek_fingerprint(&k), k)).collect()),
+        })
+    }
+
+    fn current(&self) -> KekRef {
+        let (version, _) = self.keys.last().unwrap();
+        KekRef {
+            provider: Self::PROVIDER.into(),
+            id: Self::PROVIDER.into(),
+            version: version.clone(),
+        }
+    }
+
+    fn wrap(&self, dek: &[u8]) -> Result<(KekRef, Vec<u8>), Error> {
+        let (_, key) = self.keys.last().unwrap();
+        Ok((self.current(), aes_wrap(key, dek)?))
+    }
+
+    fn unwrap(&self, kek: &KekRef, wrapped_dek: &[u8]) -> Result<Zeroizing<Vec<u8>>, Error> {
+        anyhow::ensure!(kek.provider == Self::PROVIDER, "unknown KEK provider");
+        let (_, key) = self
+            .keys
+            .iter()
+            .find(|(version, _)| *version == kek.version)
+            .ok_or_else(|| anyhow::anyhow!("unknown KEK version {}", kek.version))?;
+        aes_unwrap(key, wrapped_dek)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_kek() -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(rand::random())
+    }
+
+    #[tokio::test]
+    async fn seal_open_roundtrip() {
+        let kek = Kek::Local(LocalKek::from_keys(vec![random_kek()]).unwrap());
+        let sealed = kek.seal(b"aad", b"secret").await.unwrap();
+        assert!(sealed.starts_with(ENVELOPE_MAGIC));
+        let (plaintext, stale) = kek.open(b"aad", &sealed, false).await.unwrap();
+        assert_eq!(&**plaintext, b"secret");
+        assert!(!stale);
+    }
+
+    #[tokio::test]
+    async fn open_wrong_aad() {
+        let kek = Kek::Local(LocalKek::from_keys(vec![random_kek()]).unwrap());
+        let sealed = kek.seal(b"aad", b"secret").await.unwrap();
+        assert!(kek.open(b"other", &sealed, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn open_unsealed() {
+        let kek = Kek::Local(LocalKek::from_keys(vec![random_kek()]).unwrap());
+        assert!(kek.open(b"aad", b"secret", false).await.is_err());
+        let (plaintext, stale) = kek.open(b"aad", b"secret", true).await.unwrap();
+        assert_eq!(&**plaintext, b"secret");
+        assert!(stale);
+    }
+
+    #[tokio::test]
+    async fn open_rotated() {
+        let old_kek = random_kek();
+        let kek1 = Kek::Local(LocalKek::from_keys(vec![old_kek.clone()]).unwrap());
+        let kek2 = Kek::Local(LocalKek::from_keys(vec![old_kek, random_kek()]).unwrap());
+        let sealed = kek1.seal(b"aad", b"secret").await.unwrap();
+        let (plaintext, stale) = kek2.open(b"aad", &sealed, false).await.unwrap();
+        assert_eq!(&**plaintext, b"secret");
+        assert!(stale);
+        let resealed = kek2.seal(b"aad", &plaintext).await.unwrap();
+        assert!(kek1.open(b"aad", &resealed, false).await.is_err());
+        assert!(!kek2.open(b"aad", &resealed, false).await.unwrap().1);
+    }
+
+    #[tokio::test]
+    async fn rewrap_secrets() {
+        let inner = memory::Backend::generate();
+        let old_kek = random_kek();
+        let store = |keys, allow_unsealed| DynBackend {
+            inner: DynBackendKind::Memory(inner.clone()),
+            kek: Some(Kek::Local(LocalKek::from_keys(keys).unwrap())),
+            allow_unsealed,
+        };
+        let id = |version| KeyId {
+            name: "omni".into(),
+            identity: IdentityLocator {
+                chain: 31337,
+                registry: Address::repeat_byte(1),
+                id: IdentityId::random(),
+            },
+            version,
+        };
+        let (sealed_id, unsealed_id) = (id(1), id(1));
+        let key = WrappedKey::from(vec![1u8; 32]);
+        assert!(store(vec![old_kek.clone()], false)
+            .put_secret(sealed_id.clone(), key.clone())
+            .await
+            .unwrap());
+        assert!(inner
+            .put_secret(unsealed_id.clone(), key.clone())
+            .await
+            .unwrap());
+
+        let new_kek = random_kek();
+        let rotated = store(vec![old_kek, new_kek.clone()], false);
+        assert!(rotated.get_secret(unsealed_id.clone()).await.is_err());
+        assert_eq!(rotated.rewrap_secrets().await.unwrap(), 1);
+        assert!(rotated.get_secret(unsealed_id.clone()).await.is_err());
+
+        let rotated = DynBackend {
+            allow_unsealed: true,
+            ..rotated
+        };
+        assert_eq!(rotated.rewrap_secrets().await.unwrap(), 1);
+        assert_eq!(rotated.rewrap_secrets().await.unwrap(), 0);
+        // Both keys can now be read using only the new KEK.
+        let current = store(vec![new_kek], false);
+        assert_eq!(
+            current.get_secret(sealed_id).await.unwrap(),
+            Some(key.clone())
+        );
+        assert_eq!(current.get_secret(unsealed_id).await.unwrap(), Some(key));
+    }
+
+    crate::make_backend_tests!(async {
+        DynBackend {
+            inner: DynBackendKind::Memory(memory::Backend::generate()),
+            kek: Some(Kek::Local(LocalKek::from_keys(vec![random_kek()]).unwrap())),
+            allow_unsealed: false,
+        }
+    });
+}
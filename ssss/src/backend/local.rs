@@ -82,10 +82,18 @@ impl Store for Local {
         todo!()
     }
 
+    async fn replace_secret(&self, id: KeyId, key: WrappedKey) -> Result<bool, Error> {
+        todo!()
+    }
+
     async fn delete_secret(&self, id: KeyId) -> Result<(), Error> {
         todo!()
     }
 
+    async fn list_secrets(&self) -> Result<Vec<KeyId>, Error> {
+        todo!()
+    }
+
     async fn put_verifier(
         &self,
         permitter: PermitterLocator,
@@ -111,6 +119,30 @@ impl Store for Local {
     ) -> Result<(), Error> {
         todo!()
     }
+
+    async fn put_nonce(&self, id: NonceId, expiry: u64) -> Result<bool, Error> {
+        todo!()
+    }
+
+    async fn take_nonce(&self, id: NonceId) -> Result<bool, Error> {
+        todo!()
+    }
+
+    async fn get_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        todo!()
+    }
+
+    async fn put_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+        version: u64,
+        ledger: Vec<u8>,
+    ) -> Result<bool, Error> {
+        todo!()
+    }
 }
 
 impl Signer for Local {
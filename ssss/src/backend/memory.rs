@@ -20,6 +20,8 @@ struct State {
     shares: RwLock<HashMap<IdentityNamedItem, BTreeMap<u64, Option<ExpiringSecretShare>>>>,
     keys: RwLock<HashMap<IdentityNamedItem, BTreeMap<u64, Option<WrappedKey>>>>,
     verifiers: RwLock<HashMap<PermitterIdentityLocator, Vec<u8>>>,
+    nonces: RwLock<HashMap<NonceId, u64>>,
+    grant_ledgers: RwLock<HashMap<IdentityLocator, (u64, Vec<u8>)>>,
 }
 
 #[derive(Clone)]
@@ -176,6 +178,18 @@ impl Store for Backend {
             .flatten())
     }
 
+    async fn replace_secret(&self, id: KeyId, key: WrappedKey) -> Result<bool, Error> {
+        let mut keys = self.state.keys.write().unwrap();
+        let Some(Some(current_key)) = keys
+            .get_mut(&(id.identity, id.name))
+            .and_then(|versions| versions.get_mut(&id.version))
+        else {
+            return Ok(false);
+        };
+        *current_key = key;
+        Ok(true)
+    }
+
     async fn delete_secret(&self, id: KeyId) -> Result<(), Error> {
         if let Some(versions) = self
             .state
@@ -191,6 +205,26 @@ impl Store for Backend {
         Ok(())
     }
 
+    async fn list_secrets(&self) -> Result<Vec<KeyId>, Error> {
+        Ok(self
+            .state
+            .keys
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|((identity, name), versions)| {
+                versions
+                    .iter()
+                    .filter(|(_, key)| key.is_some())
+                    .map(|(version, _)| KeyId {
+                        name: name.clone(),
+                        identity: *identity,
+                        version: *version,
+                    })
+            })
+            .collect())
+    }
+
     async fn put_verifier(
         &self,
         permitter: PermitterLocator,
@@ -237,6 +271,55 @@ impl Store for Backend {
             .remove(&(permitter, identity));
         Ok(())
     }
+
+    async fn put_nonce(&self, id: NonceId, expiry: u64) -> Result<bool, Error> {
+        let now = crate::utils::now();
+        let mut nonces = self.state.nonces.write().unwrap();
+        nonces.retain(|_, expiry| *expiry > now);
+        if nonces.contains_key(&id) {
+            return Ok(false);
+        }
+        nonces.insert(id, expiry);
+        Ok(true)
+    }
+
+    async fn take_nonce(&self, id: NonceId) -> Result<bool, Error> {
+        Ok(self
+            .state
+            .nonces
+            .write()
+            .unwrap()
+            .remove(&id)
+            .is_some_and(|expiry| expiry > crate::utils::now()))
+    }
+
+    async fn get_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        Ok(self
+            .state
+            .grant_ledgers
+            .read()
+            .unwrap()
+            .get(&identity)
+            .cloned())
+    }
+
+    async fn put_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+        version: u64,
+        ledger: Vec<u8>,
+    ) -> Result<bool, Error> {
+        let mut ledgers = self.state.grant_ledgers.write().unwrap();
+        let current_version = ledgers.get(&identity).map(|(v, _)| *v).unwrap_or_default();
+        if version != current_version + 1 {
+            return Ok(false);
+        }
+        ledgers.insert(identity, (version, ledger));
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
@@ -2,6 +2,7 @@
 pub mod aws;
 #[cfg(feature = "azure")]
 pub mod azure;
+pub mod kek;
 #[cfg(feature = "local")]
 pub mod local;
 pub mod memory;
@@ -50,8 +51,20 @@ pub trait Store: Clone + Send + Sync + 'static {
         id: KeyId,
     ) -> impl Future<Output = Result<Option<WrappedKey>, Error>> + Send;
 
+    /// Overwrites the contents of an existing key version, such as when re-wrapping it.
+    /// Returns `false` if the version does not exist.
+    fn replace_secret(
+        &self,
+        id: KeyId,
+        key: WrappedKey,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
     fn delete_secret(&self, id: KeyId) -> impl Future<Output = Result<(), Error>> + Send;
 
+    /// Returns the IDs of all key versions that have not been deleted, such as for re-wrapping
+    /// them under a new KEK.
+    fn list_secrets(&self) -> impl Future<Output = Result<Vec<KeyId>, Error>> + Send;
+
     fn put_verifier(
         &self,
         permitter: PermitterLocator,
@@ -71,6 +84,32 @@ pub trait Store: Clone + Send + Sync + 'static {
         permitter: PermitterLocator,
         identity: IdentityLocator,
     ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Records a single-use value until `expiry` (unix seconds).
+    /// Returns `false` if the value is already recorded and has not expired.
+    fn put_nonce(
+        &self,
+        id: NonceId,
+        expiry: u64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Removes a recorded value, returning whether it was present and had not expired.
+    fn take_nonce(&self, id: NonceId) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Returns the current version of the identity's grant ledger, if one has been stored.
+    fn get_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+    ) -> impl Future<Output = Result<Option<(u64, Vec<u8>)>, Error>> + Send;
+
+    /// Stores a new version of the identity's grant ledger.
+    /// Returns `false` if `version` does not directly follow the current version (or one if none).
+    fn put_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+        version: u64,
+        ledger: Vec<u8>,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
 }
 
 pub trait Signer: Clone + Send + Sync + 'static {
@@ -82,6 +121,71 @@ pub trait Signer: Clone + Send + Sync + 'static {
 #[derive(Clone)]
 pub struct DynBackend {
     inner: DynBackendKind,
+    kek: Option<kek::Kek>,
+    /// Whether keys that were stored before the KEK was configured may be read.
+    allow_unsealed: bool,
+}
+
+impl DynBackend {
+    async fn get_sealed_secret(&self, id: KeyId) -> Result<Option<WrappedKey>, Error> {
+        match &self.inner {
+            DynBackendKind::Memory(s) => s.get_secret(id).await,
+            #[cfg(feature = "aws")]
+            DynBackendKind::Aws(s) => s.get_secret(id).await,
+            #[cfg(feature = "azure")]
+            DynBackendKind::Azure(s) => s.get_secret(id).await,
+            #[cfg(feature = "local")]
+            DynBackendKind::Local(s) => s.get_secret(id).await,
+        }
+    }
+
+    /// Re-wraps every stored key that is not sealed under the current KEK, returning how many
+    /// were re-wrapped. Keys that cannot be opened are logged and skipped.
+    pub async fn rewrap_secrets(&self) -> Result<usize, Error> {
+        let Some(kek) = &self.kek else {
+            return Ok(0);
+        };
+        let mut rewrapped = 0;
+        for id in self.list_secrets().await? {
+            let Some(sealed) = self.get_sealed_secret(id.clone()).await? else {
+                continue;
+            };
+            let key = match kek
+                .open(&envelope_aad(&id), sealed.as_ref(), self.allow_unsealed)
+                .await
+            {
+                Ok((key, true)) => WrappedKey::from((*key).clone()),
+                Ok((_, false)) => continue,
+                Err(e) => {
+                    tracing::warn!(error = ?e, key = id.to_key(), version = id.version, "failed to open key");
+                    continue;
+                }
+            };
+            if self.replace_secret(id, key).await? {
+                rewrapped += 1;
+            }
+        }
+        Ok(rewrapped)
+    }
+
+    /// Re-wraps stale keys now and then every `interval`.
+    pub fn spawn_rewrap(&self, interval: Duration) {
+        if self.kek.is_none() {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match this.rewrap_secrets().await {
+                    Ok(0) => {}
+                    Ok(n) => tracing::info!(keys = n, "re-wrapped stale keys"),
+                    Err(e) => tracing::warn!(error = ?e, "failed to re-wrap stale keys"),
+                }
+            }
+        });
+    }
 }
 
 #[derive(Clone)]
@@ -161,6 +265,10 @@ impl Store for DynBackend {
     }
 
     async fn put_secret(&self, id: KeyId, key: WrappedKey) -> Result<bool, Error> {
+        let key = match &self.kek {
+            Some(kek) => kek.seal(&envelope_aad(&id), key.as_ref()).await?.into(),
+            None => key,
+        };
         match &self.inner {
             DynBackendKind::Memory(s) => s.put_secret(id, key).await,
             #[cfg(feature = "aws")]
@@ -173,14 +281,39 @@ impl Store for DynBackend {
     }
 
     async fn get_secret(&self, id: KeyId) -> Result<Option<WrappedKey>, Error> {
+        let key = self.get_sealed_secret(id.clone()).await?;
+        let (Some(kek), Some(key)) = (&self.kek, key.as_ref()) else {
+            return Ok(key);
+        };
+        let (key, stale) = kek
+            .open(&envelope_aad(&id), key.as_ref(), self.allow_unsealed)
+            .await?;
+        let key = WrappedKey::from((*key).clone());
+        if stale {
+            let this = self.clone();
+            let key = key.clone();
+            tokio::spawn(async move {
+                if let Err(e) = this.replace_secret(id.clone(), key).await {
+                    tracing::warn!(error = ?e, key = id.to_key(), "failed to re-wrap key");
+                }
+            });
+        }
+        Ok(Some(key))
+    }
+
+    async fn replace_secret(&self, id: KeyId, key: WrappedKey) -> Result<bool, Error> {
+        let key = match &self.kek {
+            Some(kek) => kek.seal(&envelope_aad(&id), key.as_ref()).await?.into(),
+            None => key,
+        };
         match &self.inner {
-            DynBackendKind::Memory(s) => s.get_secret(id).await,
+            DynBackendKind::Memory(s) => s.replace_secret(id, key).await,
             #[cfg(feature = "aws")]
-            DynBackendKind::Aws(s) => s.get_secret(id).await,
+            DynBackendKind::Aws(s) => s.replace_secret(id, key).await,
             #[cfg(feature = "azure")]
-            DynBackendKind::Azure(s) => s.get_secret(id).await,
+            DynBackendKind::Azure(s) => s.replace_secret(id, key).await,
             #[cfg(feature = "local")]
-            DynBackendKind::Local(s) => s.get_secret(id).await,
+            DynBackendKind::Local(s) => s.replace_secret(id, key).await,
         }
     }
 
@@ -196,6 +329,18 @@ impl Store for DynBackend {
         }
     }
 
+    async fn list_secrets(&self) -> Result<Vec<KeyId>, Error> {
+        match &self.inner {
+            DynBackendKind::Memory(s) => s.list_secrets().await,
+            #[cfg(feature = "aws")]
+            DynBackendKind::Aws(s) => s.list_secrets().await,
+            #[cfg(feature = "azure")]
+            DynBackendKind::Azure(s) => s.list_secrets().await,
+            #[cfg(feature = "local")]
+            DynBackendKind::Local(s) => s.list_secrets().await,
+        }
+    }
+
     async fn put_verifier(
         &self,
         permitter: PermitterLocator,
@@ -243,6 +388,62 @@ impl Store for DynBackend {
             DynBackendKind::Local(s) => s.clear_verifier(permitter, identity).await,
         }
     }
+
+    async fn put_nonce(&self, id: NonceId, expiry: u64) -> Result<bool, Error> {
+        match &self.inner {
+            DynBackendKind::Memory(s) => s.put_nonce(id, expiry).await,
+            #[cfg(feature = "aws")]
+            DynBackendKind::Aws(s) => s.put_nonce(id, expiry).await,
+            #[cfg(feature = "azure")]
+            DynBackendKind::Azure(s) => s.put_nonce(id, expiry).await,
+            #[cfg(feature = "local")]
+            DynBackendKind::Local(s) => s.put_nonce(id, expiry).await,
+        }
+    }
+
+    async fn take_nonce(&self, id: NonceId) -> Result<bool, Error> {
+        match &self.inner {
+            DynBackendKind::Memory(s) => s.take_nonce(id).await,
+            #[cfg(feature = "aws")]
+            DynBackendKind::Aws(s) => s.take_nonce(id).await,
+            #[cfg(feature = "azure")]
+            DynBackendKind::Azure(s) => s.take_nonce(id).await,
+            #[cfg(feature = "local")]
+            DynBackendKind::Local(s) => s.take_nonce(id).await,
+        }
+    }
+
+    async fn get_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+    ) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        match &self.inner {
+            DynBackendKind::Memory(s) => s.get_grant_ledger(identity).await,
+            #[cfg(feature = "aws")]
+            DynBackendKind::Aws(s) => s.get_grant_ledger(identity).await,
+            #[cfg(feature = "azure")]
+            DynBackendKind::Azure(s) => s.get_grant_ledger(identity).await,
+            #[cfg(feature = "local")]
+            DynBackendKind::Local(s) => s.get_grant_ledger(identity).await,
+        }
+    }
+
+    async fn put_grant_ledger(
+        &self,
+        identity: IdentityLocator,
+        version: u64,
+        ledger: Vec<u8>,
+    ) -> Result<bool, Error> {
+        match &self.inner {
+            DynBackendKind::Memory(s) => s.put_grant_ledger(identity, version, ledger).await,
+            #[cfg(feature = "aws")]
+            DynBackendKind::Aws(s) => s.put_grant_ledger(identity, version, ledger).await,
+            #[cfg(feature = "azure")]
+            DynBackendKind::Azure(s) => s.put_grant_ledger(identity, version, ledger).await,
+            #[cfg(feature = "local")]
+            DynBackendKind::Local(s) => s.put_grant_ledger(identity, version, ledger).await,
+        }
+    }
 }
 
 impl Signer for DynBackend {
@@ -306,13 +507,24 @@ impl std::fmt::Display for Environment {
     }
 }
 
+/// Binds a sealed key to its storage location so that envelopes cannot be swapped between keys.
+fn envelope_aad(id: &KeyId) -> Vec<u8> {
+    format!("{}-{}", id.to_key(), id.version).into_bytes()
+}
+
+/// Creates a store whose keys are sealed by `kek`, if any.
+/// Unless `allow_unsealed` is set, reading a key that was stored without a KEK is an error.
 #[allow(unused)]
 pub async fn create(
     backend: StoreKind,
     env: Environment,
     host: &Authority,
-) -> Result<impl Store + Signer, Error> {
+    kek: Option<kek::Kek>,
+    allow_unsealed: bool,
+) -> Result<DynBackend, Error> {
     Ok(DynBackend {
+        kek,
+        allow_unsealed,
         inner: match backend {
             StoreKind::Memory => DynBackendKind::Memory(memory::Backend::generate()),
             #[cfg(feature = "aws")]
@@ -403,6 +615,22 @@ impl ToKey for KeyId {
     }
 }
 
+impl FromKey for (String, IdentityLocator) {
+    fn from_key(key: &str) -> anyhow::Result<Self> {
+        let Some(key) = key.strip_prefix("key-") else {
+            anyhow::bail!("missing key prefix");
+        };
+        // Names may contain dashes, but identity locators always have three parts.
+        let Some((split, _)) = key.match_indices('-').rev().nth(2) else {
+            anyhow::bail!("missing identity locator");
+        };
+        Ok((
+            key[..split].to_string(),
+            IdentityLocator::from_key(&key[split + 1..])?,
+        ))
+    }
+}
+
 impl ToKey for PermitterLocator {
     fn to_key(&self) -> String {
         let Self { chain, permitter } = &self;
@@ -424,6 +652,13 @@ impl FromKey for PermitterLocator {
     }
 }
 
+impl ToKey for NonceId {
+    fn to_key(&self) -> String {
+        let Self { kind, digest } = &self;
+        format!("{kind}-{digest:#x}")
+    }
+}
+
 impl ToKey for Address {
     fn to_key(&self) -> String {
         format!("{self:#x}")
@@ -436,6 +671,12 @@ impl ToKey for ChainId {
     }
 }
 
+impl ToKey for String {
+    fn to_key(&self) -> String {
+        self.clone()
+    }
+}
+
 impl ToKey for () {
     fn to_key(&self) -> String {
         Default::default()
@@ -23,7 +23,12 @@ macro_rules! make_backend_tests {
             create_discontinuous_key_version,
             create_delete_create_key_version,
             create_second_key,
+            replace_key,
+            list_keys,
             roundtrip_verifier,
+            single_use_nonce,
+            take_nonce,
+            grant_ledger,
             roundtrip_signer,
         );
     };
@@ -464,6 +469,72 @@ pub async fn create_second_key(store: impl Store) {
     .expect("second key creation failed");
 }
 
+pub async fn replace_key(store: impl Store) {
+    let identity = IdentityId::random();
+    with_new_key(&store, identity, 1, |store, key_id| async move {
+        let (_, new_key) = make_key(identity, 1);
+        ensure!(
+            store
+                .replace_secret(key_id.clone(), new_key.clone())
+                .await?,
+            "key not replaced"
+        );
+        ensure!(
+            store.get_secret(key_id.clone()).await? == Some(new_key),
+            "replaced key not returned"
+        );
+        ensure!(
+            !store
+                .replace_secret(
+                    KeyId {
+                        version: 2,
+                        ..key_id.clone()
+                    },
+                    make_key(identity, 2).1
+                )
+                .await?,
+            "nonexistent key version replaced"
+        );
+        store.delete_secret(key_id.clone()).await?;
+        ensure!(
+            !store
+                .replace_secret(key_id.clone(), make_key(identity, 1).1)
+                .await?,
+            "deleted key replaced"
+        );
+        ensure!(
+            store.get_secret(key_id).await?.is_none(),
+            "deleted key restored"
+        );
+        Ok(())
+    })
+    .await
+    .expect("test failed")
+    .expect("key creation failed");
+}
+
+pub async fn list_keys(store: impl Store) {
+    let identity = IdentityId::random();
+    with_new_key(&store, identity, 1, |store, key_id1| async move {
+        with_new_key(store, identity, 2, |store, key_id2| async move {
+            let ids = store.list_secrets().await?;
+            ensure!(ids.contains(&key_id1), "first key version not listed");
+            ensure!(ids.contains(&key_id2), "second key version not listed");
+            store.delete_secret(key_id1.clone()).await?;
+            ensure!(
+                !store.list_secrets().await?.contains(&key_id1),
+                "deleted key version listed"
+            );
+            Ok(())
+        })
+        .await
+    })
+    .await
+    .expect("test failed")
+    .expect("first key creation failed")
+    .expect("second key creation failed");
+}
+
 pub async fn roundtrip_verifier(store: impl Store) {
     let chains: [u64; 2] = rand::random();
     let identity_ids: [IdentityId; 2] = rand::random();
@@ -556,6 +627,80 @@ pub async fn roundtrip_verifier(store: impl Store) {
         .await;
 }
 
+pub async fn single_use_nonce(store: impl Store) {
+    let now = crate::utils::now();
+    let id = NonceId::new(NonceKind::Document, H256::random());
+    assert!(store.put_nonce(id, now + 60).await.unwrap());
+    assert!(
+        !store.put_nonce(id, now + 60).await.unwrap(),
+        "nonce reused"
+    );
+    let other = NonceId::new(NonceKind::Nonce, id.digest);
+    assert!(store.put_nonce(other, now + 60).await.unwrap());
+
+    let expired = NonceId::new(NonceKind::Document, H256::random());
+    assert!(store.put_nonce(expired, now - 1).await.unwrap());
+    assert!(
+        store.put_nonce(expired, now + 60).await.unwrap(),
+        "expired nonce not reusable"
+    );
+}
+
+pub async fn take_nonce(store: impl Store) {
+    let now = crate::utils::now();
+    let id = NonceId::new(NonceKind::Challenge, H256::random());
+    assert!(!store.take_nonce(id).await.unwrap(), "unknown nonce taken");
+    assert!(store.put_nonce(id, now + 60).await.unwrap());
+    assert!(store.take_nonce(id).await.unwrap());
+    assert!(!store.take_nonce(id).await.unwrap(), "nonce taken twice");
+
+    let expired = NonceId::new(NonceKind::Challenge, H256::random());
+    assert!(store.put_nonce(expired, now - 1).await.unwrap());
+    assert!(
+        !store.take_nonce(expired).await.unwrap(),
+        "expired nonce taken"
+    );
+}
+
+pub async fn grant_ledger(store: impl Store) {
+    let identity = IdentityLocator {
+        chain: 31337,
+        registry: Address::random(),
+        id: rand::random(),
+    };
+    assert!(store.get_grant_ledger(identity).await.unwrap().is_none());
+    assert!(
+        !store
+            .put_grant_ledger(identity, 2, b"v2".to_vec())
+            .await
+            .unwrap(),
+        "discontinuous ledger version stored"
+    );
+    assert!(store
+        .put_grant_ledger(identity, 1, b"v1".to_vec())
+        .await
+        .unwrap());
+    assert_eq!(
+        store.get_grant_ledger(identity).await.unwrap(),
+        Some((1, b"v1".to_vec()))
+    );
+    assert!(
+        !store
+            .put_grant_ledger(identity, 1, b"v1'".to_vec())
+            .await
+            .unwrap(),
+        "ledger version overwritten"
+    );
+    assert!(store
+        .put_grant_ledger(identity, 2, b"v2".to_vec())
+        .await
+        .unwrap());
+    assert_eq!(
+        store.get_grant_ledger(identity).await.unwrap(),
+        Some((2, b"v2".to_vec()))
+    );
+}
+
 pub async fn roundtrip_signer(signer: impl Signer) {
     let addr = signer.signer_address().await.unwrap();
     futures_util::stream::repeat(())
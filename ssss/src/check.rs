@@ -0,0 +1,405 @@
+//! Checks that the configuration is safe to use in the environment that it names.
+//!
+//! The dev environment accepts any configuration that can run, but a prod SSSS refuses to
+//! start with settings that would lose secrets, trust local or unauthenticated services, or
+//! grant permits without checking them.
+
+use std::{fmt, net::IpAddr};
+
+use clap::ValueEnum;
+use ssss::backend::{kek::KekKind, Environment, StoreKind};
+#[cfg(feature = "nitro")]
+use ssss::verify::{StaleCrlPolicy, UnknownCrlStatus};
+
+use crate::cli::Args;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The SSSS will start, but the setting should be reviewed.
+    Warning,
+    /// The SSSS will not start.
+    Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub setting: &'static str,
+    pub message: String,
+}
+
+/// The settings in effect and any problems with them.
+#[derive(Clone, Debug)]
+pub struct ConfigReport {
+    pub env: Environment,
+    pub settings: Vec<(&'static str, String)>,
+    pub findings: Vec<Finding>,
+}
+
+impl ConfigReport {
+    /// Whether the SSSS may start with this configuration.
+    pub fn is_ok(&self) -> bool {
+        self.findings.iter().all(|f| f.severity != Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings
+            .iter()
+            .filter(|f| f.severity == Severity::Warning)
+    }
+
+    fn error(&mut self, setting: &'static str, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity: Severity::Error,
+            setting,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, setting: &'static str, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity: Severity::Warning,
+            setting,
+            message: message.into(),
+        });
+    }
+}
+
+impl fmt::Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.settings.iter().map(|(k, _)| k.len()).max();
+        for (setting, value) in self.settings.iter() {
+            writeln!(
+                f,
+                "{setting:width$}  {value}",
+                width = width.unwrap_or_default()
+            )?;
+        }
+        if !self.findings.is_empty() {
+            writeln!(f)?;
+        }
+        for Finding {
+            severity,
+            setting,
+            message,
+        } in self.findings.iter()
+        {
+            let severity = match severity {
+                Severity::Warning => "warning",
+                Severity::Error => "error",
+            };
+            writeln!(f, "{severity}: {setting}: {message}")?;
+        }
+        match (self.is_ok(), self.env) {
+            (true, Environment::Prod) => writeln!(f, "\nthe configuration is safe for prod"),
+            (true, Environment::Dev) => Ok(()),
+            (false, env) => writeln!(f, "\nthe configuration is not safe for {env}"),
+        }
+    }
+}
+
+/// Returns the settings in effect and any that are unsafe for the configured environment.
+pub fn check(args: &Args) -> ConfigReport {
+    let mut report = ConfigReport {
+        env: args.env,
+        settings: vec![
+            ("env", args.env.to_string()),
+            ("host", args.host.to_string()),
+            ("gateway", args.gateway.join(", ")),
+            ("store", value_name(args.store)),
+            ("kek", value_name(args.kek)),
+            ("kek-allow-unsealed", args.kek_allow_unsealed.to_string()),
+            ("allow-escrin1", args.allow_escrin1.to_string()),
+            (
+                "verifier",
+                match args.verifier.as_slice() {
+                    [] => "all".into(),
+                    names => names.join(", "),
+                },
+            ),
+        ],
+        findings: vec![],
+    };
+    #[cfg(feature = "mock")]
+    report
+        .settings
+        .push(("mock-verifier", args.mock_verifier.to_string()));
+    #[cfg(feature = "nitro")]
+    report.settings.push((
+        "nitro-crl-dir",
+        args.nitro_crl_dir
+            .as_ref()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_else(|| "none".into()),
+    ));
+
+    // The background tasks cannot run with a zero period, so it is refused in every environment.
+    if args.kek_rewrap_interval == 0 {
+        report.error(
+            "kek-rewrap-interval",
+            "the interval must be at least one second",
+        );
+    }
+    #[cfg(feature = "nitro")]
+    if args.nitro_crl_refresh == 0 {
+        report.error(
+            "nitro-crl-refresh",
+            "the interval must be at least one second",
+        );
+    }
+
+    if args.env != Environment::Prod {
+        return report;
+    }
+
+    if args.store == StoreKind::Memory {
+        report.error(
+            "store",
+            "the memory store loses all secrets when the SSSS stops",
+        );
+    }
+    #[cfg(feature = "local")]
+    if args.store == StoreKind::Local {
+        report.error(
+            "store",
+            "the local store keeps secrets only in a database on this machine",
+        );
+    }
+
+    if is_local(args.host.host()) {
+        report.error("host", format!("{} is not a public domain", args.host));
+    } else if unbracket(args.host.host()).parse::<IpAddr>().is_ok() {
+        report.error("host", format!("{} cannot be served with TLS", args.host));
+    }
+    if !matches!(args.host.port_u16(), None | Some(443)) {
+        report.error(
+            "host",
+            format!("{} is not served with TLS on port 443", args.host),
+        );
+    }
+
+    for gateway in args.gateway.iter() {
+        let url = match url::Url::parse(gateway) {
+            Ok(url) => url,
+            Err(e) => {
+                report.error("gateway", format!("{gateway} is not a valid URL: {e}"));
+                continue;
+            }
+        };
+        if url.host_str().map(is_local).unwrap_or(true) {
+            report.error("gateway", format!("{gateway} is a local gateway"));
+        } else if url.scheme() != "https" && url.scheme() != "wss" {
+            report.warning("gateway", format!("{gateway} is not using TLS"));
+        }
+    }
+
+    if args.kek == KekKind::None {
+        report.warning("kek", "stored keys are not wrapped at rest");
+    } else if args.kek_allow_unsealed {
+        report.warning(
+            "kek-allow-unsealed",
+            "stored keys that are not wrapped at rest are accepted",
+        );
+    }
+
+    if args.allow_escrin1 {
+        report.error(
+            "allow-escrin1",
+            "escrin1 request signatures can be replayed",
+        );
+    }
+
+    #[cfg(feature = "nitro")]
+    if verifier_enabled(args, "nitro") {
+        if args.nitro_crl_dir.is_none() {
+            report.warning(
+                "nitro-crl-dir",
+                "revoked Nitro attestation certificates are accepted",
+            );
+        } else {
+            if args.nitro_crl_unknown_status == UnknownCrlStatus::Allow {
+                report.warning(
+                    "nitro-crl-unknown-status",
+                    "Nitro certificates whose issuer has not published a CRL are accepted",
+                );
+            }
+            if args.nitro_crl_stale == StaleCrlPolicy::Warn {
+                report.warning(
+                    "nitro-crl-stale",
+                    "Nitro certificates are checked against CRLs that have expired",
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    if args.mock_verifier || args.verifier.iter().any(|v| v == "mock") {
+        report.error(
+            "mock-verifier",
+            "the mock verifier grants requests without checking them",
+        );
+    }
+
+    report
+}
+
+/// Whether the named built-in verifier will be registered.
+#[allow(unused)]
+fn verifier_enabled(args: &Args, name: &str) -> bool {
+    args.verifier.is_empty() || args.verifier.iter().any(|v| v == name)
+}
+
+fn is_local(host: &str) -> bool {
+    let host = unbracket(host);
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.is_loopback() || ip.is_unspecified(),
+        Err(_) => host.eq_ignore_ascii_case("localhost") || host.ends_with(".localhost"),
+    }
+}
+
+/// Removes the brackets that surround IPv6 addresses in URLs.
+fn unbracket(host: &str) -> &str {
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::uri::Authority;
+    use clap::Parser as _;
+
+    use super::*;
+
+    fn check_args(args: &[&str]) -> ConfigReport {
+        check(&Args::try_parse_from(std::iter::once("ssss").chain(args.iter().copied())).unwrap())
+    }
+
+    fn authority(host: &str) -> Authority {
+        host.parse().unwrap()
+    }
+
+    fn errors(report: &ConfigReport) -> Vec<&'static str> {
+        report
+            .findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .map(|f| f.setting)
+            .collect()
+    }
+
+    #[test]
+    fn dev_allows_defaults() {
+        let report = check_args(&[]);
+        assert!(report.is_ok());
+        assert!(report.findings.is_empty());
+        assert!(report.to_string().contains("memory"));
+    }
+
+    #[test]
+    fn prod_refuses_defaults() {
+        let report = check_args(&["--env", "prod"]);
+        assert!(!report.is_ok());
+        assert_eq!(
+            errors(&report),
+            ["store", "host", "host", "gateway", "allow-escrin1"]
+        );
+    }
+
+    #[test]
+    fn prod_accepts_safe_config() {
+        let report = check_args(&[
+            "--env=prod",
+            "--store=aws",
+            "--host=ssss.example.org",
+            "--gateway=https://rpc.example.org",
+            "--allow-escrin1=false",
+        ]);
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(
+            report.warnings().map(|f| f.setting).collect::<Vec<_>>(),
+            ["kek", "nitro-crl-dir"]
+        );
+    }
+
+    #[test]
+    fn prod_nitro_crls() {
+        let warnings = |args: &[&str]| {
+            let report = check_args(
+                &[
+                    "--env=prod",
+                    "--store=aws",
+                    "--kek=aws",
+                    "--host=ssss.example.org",
+                    "--gateway=https://rpc.example.org",
+                    "--allow-escrin1=false",
+                ]
+                .iter()
+                .chain(args)
+                .copied()
+                .collect::<Vec<_>>(),
+            );
+            assert!(report.is_ok(), "{report}");
+            report.warnings().map(|f| f.setting).collect::<Vec<_>>()
+        };
+        assert_eq!(warnings(&[]), ["nitro-crl-dir"]);
+        assert!(warnings(&["--verifier=jwt"]).is_empty());
+        assert!(warnings(&["--nitro-crl-dir=/crls"]).is_empty());
+        assert_eq!(
+            warnings(&["--nitro-crl-dir=/crls", "--nitro-crl-unknown-status=allow"]),
+            ["nitro-crl-unknown-status"]
+        );
+        assert_eq!(
+            warnings(&[
+                "--nitro-crl-dir=/crls",
+                "--nitro-crl-unknown-status=allow",
+                "--nitro-crl-stale=warn",
+            ]),
+            ["nitro-crl-unknown-status", "nitro-crl-stale"]
+        );
+    }
+
+    #[cfg(feature = "local")]
+    #[test]
+    fn prod_refuses_local_store() {
+        let report = check_args(&[
+            "--env=prod",
+            "--store=local",
+            "--host=ssss.example.org",
+            "--gateway=https://rpc.example.org",
+            "--allow-escrin1=false",
+        ]);
+        assert_eq!(errors(&report), ["store"]);
+        assert!(check_args(&["--store=local"]).is_ok());
+    }
+
+    #[test]
+    fn zero_intervals() {
+        let report = check_args(&["--kek-rewrap-interval=0"]);
+        assert_eq!(errors(&report), ["kek-rewrap-interval"]);
+        #[cfg(feature = "nitro")]
+        assert_eq!(
+            errors(&check_args(&["--nitro-crl-refresh=0"])),
+            ["nitro-crl-refresh"]
+        );
+    }
+
+    #[test]
+    fn local_hosts() {
+        for host in [
+            "localhost",
+            "api.localhost",
+            "127.0.0.1",
+            "[::1]",
+            "0.0.0.0",
+        ] {
+            assert!(is_local(authority(host).host()), "{host}");
+        }
+        assert!(!is_local(authority("ssss.example.org:443").host()));
+    }
+}
@@ -1,11 +1,14 @@
 use clap::{
-    ArgAction::{Append, Count},
-    Parser, ValueHint,
+    ArgAction::{Append, Count, Set},
+    Parser, Subcommand, ValueHint,
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(short, long, action = Count, default_value_t = 0)]
     pub verbosity: u8,
 
@@ -22,6 +25,94 @@ pub struct Args {
 
     #[arg(short, long, value_enum, default_value = "dev")]
     pub env: crate::backend::Environment,
+
+    /// The key encryption key used to wrap stored keys at rest, if any.
+    #[arg(long, value_enum, default_value = "none")]
+    pub kek: crate::backend::kek::KekKind,
+
+    /// The file containing local KEKs, one hex-encoded key per line with the current one last.
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub kek_file: Option<std::path::PathBuf>,
+
+    /// Whether to accept stored keys that are not sealed by the KEK, such as those stored before
+    /// it was configured. Only needed until they have all been re-wrapped.
+    #[arg(long)]
+    pub kek_allow_unsealed: bool,
+
+    /// How often (seconds) to re-wrap stored keys that are not sealed by the current KEK.
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    pub kek_rewrap_interval: u64,
+
+    /// Whether to accept legacy escrin1 request signatures, which can be replayed.
+    #[arg(long, action = Set, default_value_t = true)]
+    pub allow_escrin1: bool,
+
+    /// The built-in policy verifier(s) to enable. All compiled-in verifiers are enabled if unset.
+    #[arg(long, action = Append)]
+    pub verifier: Vec<String>,
+
+    /// Enables the `mock` verifier, which grants requests without checking them. Not allowed in
+    /// the prod environment.
+    #[cfg(feature = "mock")]
+    #[arg(long)]
+    pub mock_verifier: bool,
+
+    /// A directory of DER-encoded CRLs against which Nitro attestation certificates are checked.
+    #[cfg(feature = "nitro")]
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub nitro_crl_dir: Option<std::path::PathBuf>,
+
+    /// How often (seconds) to reload the Nitro CRL directory.
+    #[cfg(feature = "nitro")]
+    #[arg(long, default_value_t = 5 * 60)]
+    pub nitro_crl_refresh: u64,
+
+    /// What to do when a Nitro CRL has passed its next update time.
+    #[cfg(feature = "nitro")]
+    #[arg(long, value_enum, default_value = "reject")]
+    pub nitro_crl_stale: crate::verify::StaleCrlPolicy,
+
+    /// What to do when an issuer in the Nitro attestation chain has not published a CRL.
+    #[cfg(feature = "nitro")]
+    #[arg(long, value_enum, default_value = "deny")]
+    pub nitro_crl_unknown_status: crate::verify::UnknownCrlStatus,
+
+    /// A directory of Intel PCS collateral for verifying DCAP quotes.
+    #[cfg(feature = "dcap")]
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub dcap_collateral_dir: Option<std::path::PathBuf>,
+
+    /// A directory of JWKS files that JWT policies can name instead of embedding keys.
+    #[cfg(feature = "jwt")]
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub jwt_jwks_dir: Option<std::path::PathBuf>,
+
+    /// A directory of AMD ASK and VCEK certificates for verifying SEV-SNP reports.
+    #[cfg(feature = "snp")]
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub snp_cert_dir: Option<std::path::PathBuf>,
+
+    /// A directory of WebAssembly policy modules, each named by its SHA-256 hash.
+    #[cfg(feature = "wasm")]
+    #[arg(long, value_hint = ValueHint::DirPath)]
+    pub wasm_module_dir: Option<std::path::PathBuf>,
+
+    /// The fuel (roughly, instructions) that a policy module may use per request.
+    #[cfg(feature = "wasm")]
+    #[arg(long, default_value_t = 100_000_000)]
+    pub wasm_fuel: u64,
+
+    /// The memory (MiB) to which a policy module may grow.
+    #[cfg(feature = "wasm")]
+    #[arg(long, default_value_t = 16)]
+    pub wasm_memory: usize,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Reports the settings in effect and exits with an error if they are unsafe for the
+    /// environment, without starting the SSSS.
+    CheckConfig,
 }
 
 impl Args {
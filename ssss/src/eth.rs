@@ -1,4 +1,9 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use ethers::{
     abi::{AbiEncode as _, Detokenize},
@@ -7,11 +12,97 @@ use ethers::{
     types::{Address, Bytes, Signature, TxHash, H256, U256, U64},
 };
 use futures_util::TryStreamExt as _;
+use lru::LruCache;
 
 use crate::types::*;
 
 ethers::contract::abigen!(IdentityRegistryContract, "$ABI_DIR/IdentityRegistry.json");
 ethers::contract::abigen!(SsssPermitterContract, "$ABI_DIR/SsssPermitter.json");
+ethers::contract::abigen!(
+    Erc1271Contract,
+    r#"[function isValidSignature(bytes32 hash, bytes signature) external view returns (bytes4)]"#
+);
+
+/// The value returned by `isValidSignature` when the signature is valid.
+pub const ERC1271_MAGIC_VALUE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// Returns whether the contract at `signer` considers `signature` over `hash` to be valid.
+pub async fn is_valid_contract_signature<M: providers::Middleware>(
+    provider: Arc<M>,
+    signer: Address,
+    hash: H256,
+    signature: Bytes,
+) -> Result<bool, Error<M>> {
+    match Erc1271Contract::new(signer, provider)
+        .is_valid_signature(hash.0, signature)
+        .call()
+        .await
+    {
+        Ok(magic) => Ok(magic == ERC1271_MAGIC_VALUE),
+        Err(ethers::contract::ContractError::Revert(_)) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remembers which accounts have code so that contract requesters can be
+/// identified without querying the chain on every request.
+#[derive(Clone)]
+pub struct CodeCache {
+    #[allow(clippy::type_complexity)]
+    entries: Arc<Mutex<LruCache<(ChainId, Address), (bool, Instant)>>>,
+}
+
+impl Default for CodeCache {
+    fn default() -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl CodeCache {
+    /// Accounts can gain code (e.g., counterfactual wallets), so entries eventually expire.
+    const TTL: Duration = Duration::from_secs(10 * 60);
+    const DEFAULT_CAPACITY: NonZeroUsize = match NonZeroUsize::new(10_000) {
+        Some(capacity) => capacity,
+        None => unreachable!(),
+    };
+
+    /// Returns a cache that remembers at most `capacity` accounts, forgetting the least recently
+    /// used first.
+    pub fn with_capacity(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    pub async fn has_code<M: providers::Middleware>(
+        &self,
+        chain: ChainId,
+        provider: &M,
+        address: Address,
+    ) -> Result<bool, Error<M>> {
+        if let Some((has_code, checked_at)) =
+            self.entries.lock().unwrap().get(&(chain, address)).copied()
+        {
+            if checked_at.elapsed() < Self::TTL {
+                return Ok(has_code);
+            }
+        }
+        let has_code = !provider
+            .get_code(address, None)
+            .await
+            .map_err(Error::RpcProvider)?
+            .is_empty();
+        let mut entries = self.entries.lock().unwrap();
+        while let Some((_, (_, checked_at))) = entries.peek_lru() {
+            if checked_at.elapsed() < Self::TTL {
+                break;
+            }
+            entries.pop_lru();
+        }
+        entries.put((chain, address), (has_code, Instant::now()));
+        Ok(has_code)
+    }
+}
 
 #[derive(Clone)]
 pub struct IdentityRegistry<M> {
@@ -197,8 +288,9 @@ impl<M: providers::Middleware> SsssPermitter<M> {
 }
 
 pub type Providers = Arc<HashMap<ChainId, Provider>>;
-pub type Provider =
-    Arc<providers::Provider<providers::QuorumProvider<providers::RetryClient<providers::Http>>>>;
+pub type Provider = Arc<RpcClient>;
+pub type RpcClient =
+    providers::Provider<providers::QuorumProvider<providers::RetryClient<providers::Http>>>;
 
 pub async fn providers(
     rpcs: impl Iterator<Item = impl AsRef<str>>,
@@ -258,3 +350,67 @@ pub enum Error<M: providers::Middleware> {
     #[error("unsupported rpc url: {0}")]
     UnsupportedRpc(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use ethers::{abi::AbiEncode as _, providers::Provider};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn code_cache() {
+        let (provider, mock) = Provider::mocked();
+        let cache = CodeCache::default();
+        let contract = Address::random();
+
+        mock.push::<Bytes, _>(Bytes::from(vec![0x60, 0x80]))
+            .unwrap();
+        assert!(cache.has_code(1, &provider, contract).await.unwrap());
+        // Served from the cache, so no further responses are needed.
+        assert!(cache.has_code(1, &provider, contract).await.unwrap());
+
+        mock.push::<Bytes, _>(Bytes::default()).unwrap();
+        assert!(!cache.has_code(2, &provider, contract).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn code_cache_capacity() {
+        let (provider, mock) = Provider::mocked();
+        let cache = CodeCache::with_capacity(NonZeroUsize::new(2).unwrap());
+        let (a, b, c) = (Address::random(), Address::random(), Address::random());
+        for addr in [a, b, c] {
+            mock.push::<Bytes, _>(Bytes::default()).unwrap();
+            cache.has_code(1, &provider, addr).await.unwrap();
+        }
+        assert_eq!(cache.entries.lock().unwrap().len(), 2);
+        // The least recently used account was forgotten, so it is looked up again.
+        mock.push::<Bytes, _>(Bytes::from(vec![0x60, 0x80]))
+            .unwrap();
+        assert!(cache.has_code(1, &provider, a).await.unwrap());
+        assert!(!cache.entries.lock().unwrap().contains(&(1, b)));
+    }
+
+    #[tokio::test]
+    async fn contract_signature() {
+        let (provider, mock) = Provider::mocked();
+        let provider = Arc::new(provider);
+        let signer = Address::random();
+        let hash = H256::random();
+
+        mock.push::<Bytes, _>(Bytes::from(ERC1271_MAGIC_VALUE.encode()))
+            .unwrap();
+        assert!(
+            is_valid_contract_signature(provider.clone(), signer, hash, vec![1, 2, 3].into())
+                .await
+                .unwrap()
+        );
+
+        mock.push::<Bytes, _>(Bytes::from([0xff; 4].encode()))
+            .unwrap();
+        assert!(
+            !is_valid_contract_signature(provider.clone(), signer, hash, vec![1, 2, 3].into())
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -1,8 +1,10 @@
 #![forbid(unsafe_code)]
 #![deny(rust_2018_idioms)]
 
+pub mod api;
 pub mod backend;
 pub mod eth;
 pub mod keypair;
 pub mod types;
 pub mod utils;
+pub mod verify;
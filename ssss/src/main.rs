@@ -1,13 +1,15 @@
 #![forbid(unsafe_code)]
 #![deny(rust_2018_idioms)]
 
-mod api;
+mod check;
 mod cli;
-mod verify;
 
 use anyhow::Result;
-use ssss::{backend, eth, types};
-use tracing::{debug, trace};
+use ssss::{
+    api, backend, eth,
+    verify::{self, VerifierRegistry},
+};
+use tracing::{debug, trace, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -30,14 +32,86 @@ async fn main() -> Result<()> {
 
     debug!(args = ?args, "loaded config");
 
+    let config_report = check::check(&args);
+    if let Some(cli::Command::CheckConfig) = args.command {
+        print!("{config_report}");
+        anyhow::ensure!(config_report.is_ok(), "unsafe configuration");
+        return Ok(());
+    }
+    anyhow::ensure!(
+        config_report.is_ok(),
+        "refusing to start with an unsafe configuration:\n{config_report}"
+    );
+    for check::Finding {
+        setting, message, ..
+    } in config_report.warnings()
+    {
+        warn!(setting, "{message}");
+    }
+
     trace!("loading providers");
     let providers = eth::providers(args.gateway.iter()).await?;
 
     trace!("creating store");
-    let store = backend::create(args.store, args.env, &args.host).await?;
+    let kek =
+        backend::kek::Kek::create(args.kek, args.env, &args.host, args.kek_file.as_deref()).await?;
+    let store = backend::create(
+        args.store,
+        args.env,
+        &args.host,
+        kek,
+        args.kek_allow_unsealed,
+    )
+    .await?;
+    store.spawn_rewrap(std::time::Duration::from_secs(args.kek_rewrap_interval));
+
+    trace!("registering verifiers");
+    #[allow(unused_mut)]
+    let mut builtins = verify::Builtins::default();
+    #[cfg(feature = "mock")]
+    if args.mock_verifier {
+        warn!("enabling the mock verifier, which grants requests without checking them");
+        builtins.mock = Some(verify::MockVerifier);
+    }
+    #[cfg(feature = "multisig")]
+    {
+        builtins.multisig = verify::MultisigVerifier::with_providers(providers.clone());
+    }
+    #[cfg(feature = "nitro")]
+    if let Some(dir) = &args.nitro_crl_dir {
+        let crls = std::sync::Arc::new(verify::CrlStore::open(
+            dir,
+            args.nitro_crl_stale,
+            args.nitro_crl_unknown_status,
+        )?);
+        crls.spawn_refresh(std::time::Duration::from_secs(args.nitro_crl_refresh));
+        builtins.nitro = verify::NitroEnclaveVerifier::with_crls(crls);
+    }
+    #[cfg(feature = "dcap")]
+    if let Some(dir) = &args.dcap_collateral_dir {
+        builtins.dcap = verify::DcapVerifier::with_collateral_dir(dir);
+    }
+    #[cfg(feature = "jwt")]
+    if let Some(dir) = &args.jwt_jwks_dir {
+        builtins.jwt = verify::JwtVerifier::with_jwks_dir(dir);
+    }
+    #[cfg(feature = "snp")]
+    if let Some(dir) = &args.snp_cert_dir {
+        builtins.snp = verify::SnpVerifier::with_cert_dir(dir);
+    }
+    #[cfg(feature = "wasm")]
+    if let Some(dir) = &args.wasm_module_dir {
+        builtins.wasm =
+            verify::WasmVerifier::with_module_dir(dir).with_limits(verify::WasmLimits {
+                fuel: args.wasm_fuel,
+                memory: args.wasm_memory * 1024 * 1024,
+            });
+    }
+    let verifiers = VerifierRegistry::with_builtins(&args.verifier, builtins)?;
+    debug!(verifiers = ?verifiers, "registered verifiers");
 
     trace!("starting API task");
-    api::serve(store, providers, args.host).await;
+    api::serve(store, providers, verifiers, args.host, args.allow_escrin1).await;
 
     Ok(())
 }
@@ -2,12 +2,12 @@ use axum::http::header;
 use axum_extra::headers;
 use ethers::{
     core::k256::{self, elliptic_curve::sec1::FromEncodedPoint as _},
-    types::{Address, Bytes, Signature},
+    types::{Address, Bytes, Signature, H256},
 };
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
-use super::{SsssPermit, WrappedKey};
+use super::{SsssPermit, SsssSessionGrant, WrappedKey};
 
 pub static PEDERSEN_VSS_BLINDER_GENERATOR: Lazy<k256::ProjectivePoint> = Lazy::new(|| {
     let generator: k256::EncodedPoint =
@@ -21,6 +21,9 @@ pub static PEDERSEN_VSS_BLINDER_GENERATOR: Lazy<k256::ProjectivePoint> = Lazy::n
 pub struct IdentityResponse {
     pub ephemeral: EphemeralKey,
     pub signer: Address,
+    /// The encrypted payload formats accepted and produced by the SSSS.
+    #[serde(default = "default_payload_formats")]
+    pub formats: Vec<PayloadFormat>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -28,6 +31,122 @@ pub struct EphemeralKey {
     pub key_id: String,
     pub pk: p384::PublicKey,
     pub expiry: u64,
+    /// HPKE recipient keys having the same id and expiry as the P-384 key.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hpke: Vec<HpkePublicKey>,
+    /// The ML-KEM-768 encapsulation key used with `pk` by the hybrid payload format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ml_kem_768: Option<Bytes>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HpkePublicKey {
+    pub kem: HpkeKem,
+    pub pk: Bytes,
+}
+
+/// An encrypted payload format that can be negotiated between the SSSS and its clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum PayloadFormat {
+    P384EcdhAes256GcmSiv,
+    Hpke(HpkeSuite),
+    P384MlKem768Aes256GcmSiv,
+}
+
+fn default_payload_formats() -> Vec<PayloadFormat> {
+    vec![PayloadFormat::P384EcdhAes256GcmSiv]
+}
+
+/// An RFC 9180 cipher suite.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HpkeSuite {
+    pub kem: HpkeKem,
+    pub kdf: HpkeKdf,
+    pub aead: HpkeAead,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HpkeKem {
+    #[serde(rename = "x25519-hkdf-sha256")]
+    X25519HkdfSha256,
+    #[serde(rename = "p256-hkdf-sha256")]
+    P256HkdfSha256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HpkeKdf {
+    #[serde(rename = "hkdf-sha256")]
+    HkdfSha256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HpkeAead {
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+impl HpkeKem {
+    pub const ALL: [Self; 2] = [Self::X25519HkdfSha256, Self::P256HkdfSha256];
+}
+
+impl HpkeSuite {
+    pub const ALL: [Self; 4] = [
+        Self::new(HpkeKem::X25519HkdfSha256, HpkeAead::ChaCha20Poly1305),
+        Self::new(HpkeKem::X25519HkdfSha256, HpkeAead::Aes256Gcm),
+        Self::new(HpkeKem::P256HkdfSha256, HpkeAead::Aes256Gcm),
+        Self::new(HpkeKem::P256HkdfSha256, HpkeAead::ChaCha20Poly1305),
+    ];
+
+    pub const fn new(kem: HpkeKem, aead: HpkeAead) -> Self {
+        Self {
+            kem,
+            kdf: HpkeKdf::HkdfSha256,
+            aead,
+        }
+    }
+}
+
+impl std::fmt::Display for HpkeSuite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kem = match self.kem {
+            HpkeKem::X25519HkdfSha256 => "x25519-hkdf-sha256",
+            HpkeKem::P256HkdfSha256 => "p256-hkdf-sha256",
+        };
+        let kdf = match self.kdf {
+            HpkeKdf::HkdfSha256 => "hkdf-sha256",
+        };
+        let aead = match self.aead {
+            HpkeAead::Aes256Gcm => "aes-256-gcm",
+            HpkeAead::ChaCha20Poly1305 => "chacha20-poly1305",
+        };
+        write!(f, "{kem}/{kdf}/{aead}")
+    }
+}
+
+impl std::str::FromStr for HpkeSuite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('/');
+        let (Some(kem), Some(kdf), Some(aead), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(format!("invalid HPKE suite: {s}"));
+        };
+        let de = |v: &str| serde_json::Value::String(v.into());
+        Ok(Self {
+            kem: serde_json::from_value(de(kem)).map_err(|e| e.to_string())?,
+            kdf: serde_json::from_value(de(kdf)).map_err(|e| e.to_string())?,
+            aead: serde_json::from_value(de(aead)).map_err(|e| e.to_string())?,
+        })
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -49,6 +168,56 @@ pub struct AcqRelIdentityRequest {
     pub context: Bytes,
 }
 
+/// The outcome of evaluating a permit request without issuing a permit.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EvaluationReport {
+    /// Whether a permit would have been issued.
+    pub permitted: bool,
+    /// The checks that were made, in order.
+    pub steps: Vec<EvaluationStep>,
+    /// The error that would have been returned, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    #[serde(default, skip_serializing_if = "ErrorDetails::is_empty")]
+    pub details: ErrorDetails,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EvaluationStep {
+    pub check: String,
+    pub passed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl EvaluationStep {
+    pub fn new(check: impl Into<String>) -> Self {
+        Self {
+            check: check.into(),
+            passed: false,
+            expected: None,
+            observed: None,
+            message: None,
+        }
+    }
+
+    pub fn expected(mut self, expected: impl Serialize) -> Self {
+        self.expected = serde_json::to_value(expected).ok();
+        self
+    }
+
+    pub fn observed(mut self, observed: impl Serialize) -> Self {
+        self.observed = serde_json::to_value(observed).ok();
+        self
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PermitResponse {
     pub permit: SsssPermit,
@@ -56,6 +225,14 @@ pub struct PermitResponse {
     pub signature: Signature,
 }
 
+/// A single-use value that an enclave embeds in its attestation to prove freshness.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChallengeResponse {
+    pub challenge: H256,
+    /// The time (unix seconds) after which the challenge will not be accepted.
+    pub expiry: u64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetShareQuery {
     pub version: u64,
@@ -86,6 +263,27 @@ pub enum EncryptedPayloadFormat {
         #[serde(default, skip_serializing_if = "String::is_empty")]
         recipient_key_id: String,
     },
+    /// P-384 ECDH combined with ML-KEM-768 so that payloads remain confidential
+    /// even if one of the two is broken.
+    P384MlKem768Aes256GcmSiv {
+        curve: CurveP384,
+        pk: p384::PublicKey,
+        /// The ML-KEM-768 ciphertext.
+        ct: Bytes,
+        #[serde(with = "hex::serde")]
+        nonce: [u8; 12],
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        recipient_key_id: String,
+    },
+    /// RFC 9180 base mode single-shot encryption.
+    Hpke {
+        #[serde(flatten)]
+        suite: HpkeSuite,
+        /// The encapsulated key.
+        enc: Bytes,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        recipient_key_id: String,
+    },
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -160,16 +358,248 @@ pub struct KeyResponse {
     pub key: Bytes,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedSessionGrant {
+    pub grant: SsssSessionGrant,
+    /// The requester's signature over the grant: either ECDSA or EIP-1271.
+    pub signature: Bytes,
+}
+
+/// A session grant sent alongside a request signed by the session key, as base64url JSON.
+pub struct SessionGrantHeader(pub SignedSessionGrant);
+
+impl std::fmt::Display for SessionGrantHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use base64::prelude::*;
+        let json = serde_json::to_vec(&self.0).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", BASE64_URL_SAFE_NO_PAD.encode(json))
+    }
+}
+
+static SESSION_GRANT_HEADER_NAME: header::HeaderName =
+    header::HeaderName::from_static("session-grant");
+
+impl headers::Header for SessionGrantHeader {
+    fn name() -> &'static header::HeaderName {
+        &SESSION_GRANT_HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i header::HeaderValue>,
+    {
+        use base64::prelude::*;
+        let value = values.next().ok_or_else(headers::Error::invalid)?;
+        let json = BASE64_URL_SAFE_NO_PAD
+            .decode(value.as_bytes())
+            .map_err(|_| headers::Error::invalid())?;
+        Ok(Self(
+            serde_json::from_slice(&json).map_err(|_| headers::Error::invalid())?,
+        ))
+    }
+
+    fn encode<E: Extend<header::HeaderValue>>(&self, values: &mut E) {
+        values.extend(std::iter::once(
+            header::HeaderValue::from_str(&self.to_string()).unwrap(),
+        ));
+    }
+}
+
+/// A policy verifier that is available on the SSSS.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerifierInfo {
+    pub name: String,
+    /// The newest policy version understood by the verifier.
+    pub version: u64,
+    /// A JSON Schema describing the policies accepted by the verifier.
+    pub schema: serde_json::Value,
+}
+
+/// The reason that a policy document was rejected.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum PolicyError {
+    #[error("malformed policy document: {message}")]
+    Malformed { message: String },
+    #[error("unknown verifier `{verifier}`")]
+    UnknownVerifier { verifier: String },
+    #[error("{verifier} policy version {version} is not supported (latest is {supported})")]
+    UnsupportedVersion {
+        verifier: String,
+        version: u64,
+        supported: u64,
+    },
+    #[error("invalid {verifier} policy: {message}")]
+    Invalid { verifier: String, message: String },
+    #[error("invalid grant limits: {message}")]
+    InvalidLimits { message: String },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub error: String,
+    /// Missing from the responses of SSSSs that predate error codes.
+    #[serde(default)]
+    pub code: ErrorCode,
+    #[serde(default, skip_serializing_if = "ErrorDetails::is_empty")]
+    pub details: ErrorDetails,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_error: Option<PolicyError>,
+}
+
+/// A stable identifier of the reason that a request failed, which clients can match on instead
+/// of the error message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    BadRequest,
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    UnsupportedChain,
+    InvalidPolicy,
+    /// The identity's stored policy is not the one registered with its permitter.
+    StalePolicy,
+    UnknownVerifier,
+    PolicyDecode,
+    UnsupportedPolicyVersion,
+    AttestationDecode,
+    InvalidBinding,
+    BindingMismatch,
+    PcrMismatch,
+    /// The attestation or token is not yet valid, has expired, or is otherwise mistimed.
+    Timing,
+    /// A single-use value in the request has already been used.
+    Replayed,
+    /// The policy's grant limits do not allow the request.
+    LimitExceeded,
+    Internal,
+    /// A code that this version does not know of.
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+/// Structured information about an error, with only the fields relevant to its code set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorDetails {
+    /// The index of the PCR that did not match the policy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pcr: Option<usize>,
+    /// The binding that the attestation should have contained.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_binding: Option<Bytes>,
+    /// The hash of the policy registered with the permitter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_policy_hash: Option<H256>,
+    /// The hash of the policy stored by the SSSS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_policy_hash: Option<H256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_version: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<u64>,
+}
+
+impl ErrorDetails {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The unix timestamp (seconds) at which an escrin2 request was signed.
+pub struct IssuedAtHeader(pub u64);
+
+impl std::fmt::Display for IssuedAtHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+static ISSUED_AT_HEADER_NAME: header::HeaderName = header::HeaderName::from_static("issued-at");
+
+impl headers::Header for IssuedAtHeader {
+    fn name() -> &'static header::HeaderName {
+        &ISSUED_AT_HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i header::HeaderValue>,
+    {
+        Ok(Self(
+            values
+                .next()
+                .ok_or_else(headers::Error::invalid)?
+                .to_str()
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(headers::Error::invalid)?,
+        ))
+    }
+
+    fn encode<E: Extend<header::HeaderValue>>(&self, values: &mut E) {
+        values.extend(std::iter::once(header::HeaderValue::from(self.0)));
+    }
 }
 
-pub struct SignatureHeader(pub Signature);
+/// The single-use nonce of an escrin2 request.
+pub struct RequestNonceHeader(pub H256);
+
+impl std::fmt::Display for RequestNonceHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+static REQUEST_NONCE_HEADER_NAME: header::HeaderName =
+    header::HeaderName::from_static("request-nonce");
+
+impl headers::Header for RequestNonceHeader {
+    fn name() -> &'static header::HeaderName {
+        &REQUEST_NONCE_HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i header::HeaderValue>,
+    {
+        Ok(Self(
+            values
+                .next()
+                .ok_or_else(headers::Error::invalid)?
+                .to_str()
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(headers::Error::invalid)?,
+        ))
+    }
+
+    fn encode<E: Extend<header::HeaderValue>>(&self, values: &mut E) {
+        values.extend(std::iter::once(
+            header::HeaderValue::from_str(&self.to_string()).unwrap(),
+        ));
+    }
+}
+
+/// A request signature, which is either a 65-byte ECDSA signature or,
+/// for contract requesters, an arbitrary EIP-1271 signature.
+pub struct SignatureHeader(pub Bytes);
 
 impl std::fmt::Display for SignatureHeader {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "0x{}", hex::encode(self.0.to_vec()))
+        write!(f, "0x{}", hex::encode(&self.0))
+    }
+}
+
+impl From<Signature> for SignatureHeader {
+    fn from(sig: Signature) -> Self {
+        Self(sig.to_vec().into())
     }
 }
 
@@ -186,15 +616,12 @@ impl headers::Header for SignatureHeader {
         I: Iterator<Item = &'i header::HeaderValue>,
     {
         let sig_hex = values.next().ok_or_else(headers::Error::invalid)?;
-        let sig_bytes: Bytes = sig_hex
-            .to_str()
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .ok_or_else(headers::Error::invalid)?;
         Ok(Self(
-            (&*sig_bytes)
-                .try_into()
-                .map_err(|_| headers::Error::invalid())?,
+            sig_hex
+                .to_str()
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(headers::Error::invalid)?,
         ))
     }
 
@@ -206,7 +633,7 @@ impl headers::Header for SignatureHeader {
 }
 
 impl std::ops::Deref for SignatureHeader {
-    type Target = Signature;
+    type Target = Bytes;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -259,6 +686,91 @@ impl std::ops::Deref for RequesterHeader {
     }
 }
 
+/// The HPKE public key to which a response should be encrypted, as `<suite>;<hex pk>`.
+pub struct RequesterHpkePublicKeyHeader {
+    pub suite: HpkeSuite,
+    pub pk: Bytes,
+}
+
+impl std::fmt::Display for RequesterHpkePublicKeyHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};{}", self.suite, hex::encode(&self.pk))
+    }
+}
+
+static REQUESTER_HPKE_PUBKEY_HEADER_NAME: header::HeaderName =
+    header::HeaderName::from_static("requester-hpke-pk");
+
+impl headers::Header for RequesterHpkePublicKeyHeader {
+    fn name() -> &'static header::HeaderName {
+        &REQUESTER_HPKE_PUBKEY_HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i header::HeaderValue>,
+    {
+        let (suite, pk) = values
+            .next()
+            .ok_or_else(headers::Error::invalid)?
+            .to_str()
+            .ok()
+            .and_then(|s| s.split_once(';'))
+            .ok_or_else(headers::Error::invalid)?;
+        Ok(Self {
+            suite: suite.parse().map_err(|_| headers::Error::invalid())?,
+            pk: pk.parse().map_err(|_| headers::Error::invalid())?,
+        })
+    }
+
+    fn encode<E: Extend<header::HeaderValue>>(&self, values: &mut E) {
+        values.extend(std::iter::once(
+            header::HeaderValue::from_str(&self.to_string()).unwrap(),
+        ));
+    }
+}
+
+/// The ML-KEM-768 encapsulation key which, together with the key in the `requester-pk`
+/// header, requests a response in the hybrid payload format.
+pub struct RequesterMlKemPublicKeyHeader(pub Bytes);
+
+impl std::fmt::Display for RequesterMlKemPublicKeyHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+static REQUESTER_MLKEM_PUBKEY_HEADER_NAME: header::HeaderName =
+    header::HeaderName::from_static("requester-mlkem-pk");
+
+impl headers::Header for RequesterMlKemPublicKeyHeader {
+    fn name() -> &'static header::HeaderName {
+        &REQUESTER_MLKEM_PUBKEY_HEADER_NAME
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        Self: Sized,
+        I: Iterator<Item = &'i header::HeaderValue>,
+    {
+        let ek_hex = values.next().ok_or_else(headers::Error::invalid)?;
+        Ok(Self(
+            ek_hex
+                .to_str()
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(headers::Error::invalid)?,
+        ))
+    }
+
+    fn encode<E: Extend<header::HeaderValue>>(&self, values: &mut E) {
+        values.extend(std::iter::once(
+            header::HeaderValue::from_str(&self.to_string()).unwrap(),
+        ));
+    }
+}
+
 pub struct RequesterPublicKeyHeader(pub p384::PublicKey);
 
 impl std::fmt::Display for RequesterPublicKeyHeader {
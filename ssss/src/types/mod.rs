@@ -74,6 +74,43 @@ impl PermitterLocator {
     }
 }
 
+/// A value that may be used only once, identified by its digest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NonceId {
+    pub kind: NonceKind,
+    pub digest: H256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NonceKind {
+    /// A challenge issued by this SSSS that has been redeemed.
+    Challenge,
+    /// A nonce embedded in an attestation.
+    Nonce,
+    /// An attestation document.
+    Document,
+    /// The nonce of a signed API request.
+    Request,
+}
+
+impl NonceId {
+    pub fn new(kind: NonceKind, digest: H256) -> Self {
+        Self { kind, digest }
+    }
+}
+
+impl std::fmt::Display for NonceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Challenge => "challenge",
+            Self::Nonce => "nonce",
+            Self::Document => "document",
+            Self::Request => "request",
+        })
+    }
+}
+
 #[derive(Clone)]
 #[cfg_attr(test, derive(Debug, PartialEq, Eq))]
 pub struct SecretShare {
@@ -143,10 +180,103 @@ pub struct SsssRequest {
     pub body: H256,
 }
 
+/// An escrin2 request, which additionally commits to when it was made and to a unique nonce
+/// so that it cannot be replayed.
+#[derive(Clone, Debug, Default, EthAbiType, Eip712)]
+#[eip712(name = "SSSS", version = "2")]
+pub struct SsssRequest2 {
+    pub method: String,
+    pub url: String,
+    pub body: H256,
+    pub issued_at: u64,
+    pub nonce: H256,
+}
+
+/// The longest time, in seconds, between a session grant's `not_before` and `expiry`.
+pub const MAX_SESSION_LIFETIME: u64 = 24 * 60 * 60;
+
+/// Authorizes a short-lived session key to sign requests on behalf of `requester`.
+/// Grants that are valid for longer than [`MAX_SESSION_LIFETIME`] are rejected.
+#[derive(Clone, Debug, Default, EthAbiType, Eip712, Serialize, Deserialize)]
+#[eip712(name = "SSSS", version = "2")]
+#[serde(rename_all = "camelCase")]
+pub struct SsssSessionGrant {
+    pub requester: Address,
+    pub session_key: Address,
+    pub chain: u64,
+    pub registry: Address,
+    pub identity: H256,
+    /// The comma-separated API resources (`permits`, `shares`, `secrets`) that may be accessed.
+    pub scope: String,
+    pub not_before: u64,
+    pub expiry: u64,
+}
+
+impl SsssSessionGrant {
+    pub fn allows(&self, resource: &str) -> bool {
+        self.scope.split(',').any(|s| s.trim() == resource)
+    }
+}
+
+/// An approver's consent to a permit request, as required by the `multisig` verifier.
+#[derive(Clone, Debug, Default, EthAbiType, Eip712, Serialize, Deserialize)]
+#[eip712(name = "SSSS", version = "2")]
+#[serde(rename_all = "camelCase")]
+pub struct SsssPermitApproval {
+    pub chain: u64,
+    pub registry: Address,
+    pub identity: H256,
+    pub permitter: Address,
+    pub recipient: Address,
+    pub grant: bool,
+    /// The requested duration, or zero for revocations.
+    pub duration: u64,
+    pub base_block: u64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PolicyDocument {
     pub verifier: String,
     pub policy: serde_json::Value,
+    /// Limits on how the identity may be granted, enforced regardless of the verifier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<GrantLimits>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrantLimits {
+    /// The most grants that any one recipient may receive within a window.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_grants: Option<GrantRate>,
+    /// The most recipients that may hold the identity at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_holders: Option<u32>,
+    /// How long (seconds) a recipient must wait after being revoked before it is granted again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoke_cooldown: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrantRate {
+    pub count: u32,
+    /// The length (seconds) of the sliding window in which grants are counted.
+    pub window: u64,
+}
+
+impl GrantLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(GrantRate { count, window }) = self.max_grants {
+            if count == 0 || window == 0 {
+                return Err("max_grants count and window must be positive".into());
+            }
+        }
+        if self.max_holders == Some(0) {
+            return Err("max_holders must be positive".into());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default, EthAbiType, Eip712, Serialize, Deserialize)]
@@ -0,0 +1,701 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{anyhow, ensure};
+use ethers::types::Bytes;
+use serde::Deserialize;
+
+use super::*;
+
+/// Rules may not be nested more deeply than this.
+const MAX_DEPTH: usize = 8;
+
+/// Combines the policies of other verifiers using all-of, any-of, and k-of-n rules.
+///
+/// The authorization is a JSON object that maps the id of each leaf of the policy's rule to
+/// that leaf's hex-encoded authorization. Leaves without an authorization are not satisfied.
+///
+/// Only the leaves that satisfy the rule contribute to the verification. The permit's duration
+/// is the shortest of theirs, and their single-use values are all consumed. The permit's nonce
+/// and public key are those of the first contributing leaf (in policy order) that reports a
+/// public key; any other contributing leaf that reports a public key must report the same one.
+/// Likewise, contributing leaves that redeem a challenge must all redeem the same one.
+#[derive(Clone, Debug, Default)]
+pub struct CompositeVerifier {
+    leaves: VerifierRegistry,
+}
+
+impl CompositeVerifier {
+    /// Returns a verifier whose leaves may use the verifiers of `leaves`.
+    pub fn new(leaves: VerifierRegistry) -> Self {
+        Self { leaves }
+    }
+
+    /// Evaluates a rule, returning the verifications of the leaves that satisfied it.
+    fn evaluate_rule<'a>(
+        &'a self,
+        rule: &'a Rule,
+        path: String,
+        req: VerificationRequest<'a>,
+        authorizations: &'a BTreeMap<String, Bytes>,
+        report: &'a mut Report,
+    ) -> BoxFuture<'a, Result<Vec<Verification>, Error>> {
+        Box::pin(async move {
+            if let Rule::Leaf(Leaf {
+                id,
+                verifier,
+                policy,
+            }) = rule
+            {
+                // Each leaf has its own report so that leaves that fail in dry runs do not fail
+                // rules that are satisfied anyway.
+                let mut nested = report.nested();
+                let result = match (self.leaves.verifiers.get(verifier), authorizations.get(id)) {
+                    (None, _) => Err(nested.fail(
+                        EvaluationStep::new("verifier"),
+                        Error::UnknownVerifier(verifier.clone()),
+                    )),
+                    (_, None) => Err(nested.fail(
+                        EvaluationStep::new("authorization"),
+                        Error::AttestationDecode(anyhow!("no authorization was provided")),
+                    )),
+                    (Some(v), Some(authorization)) => {
+                        v.evaluate_dyn(
+                            policy.clone(),
+                            VerificationRequest {
+                                authorization,
+                                ..req
+                            },
+                            &mut nested,
+                        )
+                        .await
+                    }
+                };
+                report.append(id, nested);
+                return result.map(|verification| vec![verification]);
+            }
+
+            let (k, rules) = rule.threshold();
+            let mut satisfied = vec![];
+            let mut failed = vec![];
+            let mut contributing = vec![];
+            let mut first_error = None;
+            for (i, rule) in rules.iter().enumerate() {
+                let decided = satisfied.len() >= k || failed.len() > rules.len() - k;
+                if decided && !report.dry_run {
+                    break;
+                }
+                match self
+                    .evaluate_rule(rule, format!("{path}/{i}"), req, authorizations, report)
+                    .await
+                {
+                    Ok(verifications) => {
+                        if satisfied.len() < k {
+                            contributing.extend(verifications);
+                        }
+                        satisfied.push(i);
+                    }
+                    Err(e) => {
+                        failed.push(i);
+                        first_error.get_or_insert(e);
+                    }
+                }
+            }
+
+            let step = EvaluationStep::new(path)
+                .expected(serde_json::json!({ "k": k, "n": rules.len() }))
+                .observed(serde_json::json!({ "satisfied": satisfied, "failed": failed }));
+            if satisfied.len() >= k {
+                report.pass(step);
+                return Ok(contributing);
+            }
+            // An all-of rule fails for the same reason as its first failed sub-rule.
+            let error = match first_error {
+                Some(e) if k == rules.len() => e,
+                _ => Error::Unauthorized(format!(
+                    "{} of {k} required sub-rules were satisfied",
+                    satisfied.len()
+                )),
+            };
+            Err(report.fail(step, error))
+        })
+    }
+}
+
+impl Verifier for CompositeVerifier {
+    fn name(&self) -> &str {
+        "composite"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        let verifiers: Vec<_> = self.leaves.info().into_iter().map(|i| i.name).collect();
+        let rules = serde_json::json!({
+            "type": "array",
+            "minItems": 1,
+            "items": { "$ref": "#/$defs/rule" },
+        });
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Composite policy",
+            "type": "object",
+            "required": ["version", "rule"],
+            "additionalProperties": false,
+            "properties": {
+                "version": { "const": 1 },
+                "rule": { "$ref": "#/$defs/rule" },
+            },
+            "$defs": {
+                "rule": {
+                    "type": "object",
+                    "minProperties": 1,
+                    "maxProperties": 1,
+                    "additionalProperties": false,
+                    "properties": {
+                        "all_of": rules,
+                        "any_of": rules,
+                        "k_of_n": {
+                            "type": "object",
+                            "required": ["k", "of"],
+                            "additionalProperties": false,
+                            "properties": {
+                                "k": { "type": "integer", "minimum": 1 },
+                                "of": rules,
+                            },
+                        },
+                        "leaf": {
+                            "type": "object",
+                            "required": ["id", "verifier", "policy"],
+                            "additionalProperties": false,
+                            "properties": {
+                                "id": {
+                                    "description": "The key of the leaf's authorization",
+                                    "type": "string",
+                                    "minLength": 1,
+                                },
+                                "verifier": { "enum": verifiers },
+                                "policy": {},
+                            },
+                        },
+                    },
+                },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        let policy = Policy::decode(raw_policy)?;
+        let mut leaves = vec![];
+        policy.rule.leaves(&mut leaves);
+        leaves.into_iter().try_for_each(
+            |Leaf {
+                 id,
+                 verifier,
+                 policy,
+             }| {
+                let Some(v) = self.leaves.verifiers.get(verifier) else {
+                    return Err(Error::PolicyDecode(anyhow!(
+                        "leaf `{id}` uses unknown verifier `{verifier}`"
+                    )));
+                };
+                v.validate_dyn(policy.clone())
+                    .map_err(|e| Error::PolicyDecode(anyhow!("leaf `{id}`: {e}")))
+            },
+        )
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        let authorizations: BTreeMap<String, Bytes> =
+            match serde_json::from_slice(req.authorization) {
+                Ok(authorizations) => authorizations,
+                Err(e) => {
+                    return Err(report.fail(
+                        EvaluationStep::new("authorization"),
+                        Error::AttestationDecode(e.into()),
+                    ))
+                }
+            };
+
+        let contributing = self
+            .evaluate_rule(&policy.rule, "rule".into(), req, &authorizations, report)
+            .await?;
+
+        let mut key: Option<(Vec<u8>, Vec<u8>)> = None;
+        let mut single_use = SingleUse::default();
+        let mut duration = None::<u64>;
+        let mut conflict = None;
+        for verification in contributing {
+            if !verification.public_key.is_empty() {
+                match &key {
+                    None => key = Some((verification.nonce, verification.public_key)),
+                    Some((_, pk)) if *pk == verification.public_key => {}
+                    Some(_) => {
+                        conflict.get_or_insert("sub-policies reported different public keys");
+                    }
+                }
+            }
+            if let Some(d) = verification.duration {
+                duration = Some(duration.map_or(d, |duration| duration.min(d)));
+            }
+            single_use.nonces.extend(verification.single_use.nonces);
+            single_use.expiry = single_use.expiry.max(verification.single_use.expiry);
+            if let Some(challenge) = verification.single_use.challenge {
+                match single_use.challenge {
+                    None => single_use.challenge = Some(challenge),
+                    Some(redeemed) if redeemed == challenge => {}
+                    Some(_) => {
+                        conflict.get_or_insert("sub-policies redeemed different challenges");
+                    }
+                }
+            }
+        }
+        report.check(
+            EvaluationStep::new("merge").observed(serde_json::json!({
+                "public_key": key.as_ref().map(|(_, pk)| hex::encode(pk)),
+                "duration": duration,
+            })),
+            match conflict {
+                Some(conflict) => Err(Error::Unauthorized(conflict.into())),
+                None => Ok(()),
+            },
+        )?;
+
+        let (nonce, public_key) = key.unwrap_or_default();
+        report.finish(Verification {
+            nonce,
+            public_key,
+            duration: match req.kind {
+                RequestKind::Grant { .. } => duration,
+                RequestKind::Revoke => None,
+            },
+            measurement: None,
+            single_use,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    version: u8,
+    rule: Rule,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        policy
+            .rule
+            .check(1, &mut HashSet::new())
+            .map_err(Error::PolicyDecode)?;
+        Ok(policy)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Rule {
+    AllOf(Vec<Rule>),
+    AnyOf(Vec<Rule>),
+    KOfN { k: usize, of: Vec<Rule> },
+    Leaf(Leaf),
+}
+
+impl Rule {
+    /// Returns how many of a group's sub-rules must be satisfied, and the sub-rules.
+    fn threshold(&self) -> (usize, &[Rule]) {
+        match self {
+            Self::AllOf(rules) => (rules.len(), rules),
+            Self::AnyOf(rules) => (1, rules),
+            Self::KOfN { k, of } => (*k, of),
+            Self::Leaf(_) => (1, &[]),
+        }
+    }
+
+    fn check<'a>(&'a self, depth: usize, ids: &mut HashSet<&'a str>) -> Result<(), anyhow::Error> {
+        ensure!(
+            depth <= MAX_DEPTH,
+            "rules may be nested at most {MAX_DEPTH} deep"
+        );
+        if let Self::Leaf(leaf) = self {
+            ensure!(
+                !leaf.id.is_empty() && ids.insert(&leaf.id),
+                "leaf ids must be unique and non-empty"
+            );
+            return Ok(());
+        }
+        let (k, rules) = self.threshold();
+        ensure!(
+            (1..=rules.len()).contains(&k),
+            "k must be between 1 and the number of sub-rules"
+        );
+        rules.iter().try_for_each(|rule| rule.check(depth + 1, ids))
+    }
+
+    fn leaves<'a>(&'a self, leaves: &mut Vec<&'a Leaf>) {
+        match self {
+            Self::Leaf(leaf) => leaves.push(leaf),
+            rule => rule
+                .threshold()
+                .1
+                .iter()
+                .for_each(|rule| rule.leaves(leaves)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Leaf {
+    /// The key of the leaf's authorization.
+    id: String,
+    verifier: String,
+    policy: serde_json::Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{types::NonceKind, verify::tests::request};
+
+    /// Accepts authorizations that equal its policy's password.
+    struct Password;
+
+    #[derive(Deserialize)]
+    struct PasswordPolicy {
+        password: String,
+        #[serde(default)]
+        max_duration: Option<u64>,
+        #[serde(default)]
+        public_key: Option<String>,
+        #[serde(default)]
+        challenge: bool,
+    }
+
+    impl Verifier for Password {
+        fn name(&self) -> &str {
+            "password"
+        }
+
+        fn version(&self) -> u64 {
+            1
+        }
+
+        fn schema(&self) -> serde_json::Value {
+            serde_json::json!({ "type": "object" })
+        }
+
+        fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+            serde_json::from_value::<PasswordPolicy>(raw_policy)
+                .map(|_| ())
+                .map_err(|e| Error::PolicyDecode(e.into()))
+        }
+
+        async fn verify(
+            &self,
+            raw_policy: serde_json::Value,
+            req: VerificationRequest<'_>,
+        ) -> Result<Verification, Error> {
+            let policy: PasswordPolicy =
+                serde_json::from_value(raw_policy).map_err(|e| Error::PolicyDecode(e.into()))?;
+            if req.authorization != policy.password.as_bytes() {
+                return Err(Error::Unauthorized("wrong password".into()));
+            }
+            let nonce = NonceId::new(NonceKind::Nonce, H256(keccak256(req.authorization)));
+            Ok(Verification {
+                nonce: policy.password.clone().into_bytes(),
+                public_key: policy.public_key.unwrap_or_default().into_bytes(),
+                duration: match req.kind {
+                    RequestKind::Grant { duration } => {
+                        Some(duration.min(policy.max_duration.unwrap_or(u64::MAX)))
+                    }
+                    RequestKind::Revoke => None,
+                },
+                measurement: None,
+                single_use: SingleUse {
+                    nonces: vec![nonce],
+                    expiry: policy.password.len() as u64,
+                    challenge: policy.challenge.then_some(nonce),
+                },
+            })
+        }
+    }
+
+    fn verifier() -> CompositeVerifier {
+        let mut leaves = VerifierRegistry::new();
+        leaves.register(Password).unwrap();
+        CompositeVerifier::new(leaves)
+    }
+
+    fn leaf(id: &str, policy: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({ "leaf": { "id": id, "verifier": "password", "policy": policy } })
+    }
+
+    fn password(password: &str) -> serde_json::Value {
+        serde_json::json!({ "password": password })
+    }
+
+    fn auth(authorizations: &[(&str, &str)]) -> Vec<u8> {
+        serde_json::to_vec(
+            &authorizations
+                .iter()
+                .map(|(id, auth)| (*id, Bytes::from(auth.as_bytes().to_vec())))
+                .collect::<BTreeMap<_, _>>(),
+        )
+        .unwrap()
+    }
+
+    /// Nitro OR 2-of-3 admins, where the enclave must also be relayed by a trusted relayer.
+    fn break_glass() -> serde_json::Value {
+        serde_json::json!({
+            "version": 1,
+            "rule": {
+                "any_of": [
+                    {
+                        "all_of": [
+                            leaf("enclave", serde_json::json!({
+                                "password": "enclave",
+                                "public_key": "enclave-key",
+                            })),
+                            leaf("relayer", password("relayer")),
+                        ],
+                    },
+                    {
+                        "k_of_n": {
+                            "k": 2,
+                            "of": [
+                                leaf("alice", serde_json::json!({
+                                    "password": "alice",
+                                    "max_duration": 10,
+                                })),
+                                leaf("bob", serde_json::json!({
+                                    "password": "bob",
+                                    "max_duration": 20,
+                                })),
+                                leaf("carol", password("carol")),
+                            ],
+                        },
+                    },
+                ],
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn verify_composite() {
+        let verifier = verifier();
+
+        let authorization = auth(&[("enclave", "enclave"), ("relayer", "relayer")]);
+        let verification = verifier
+            .verify(break_glass(), request(&authorization))
+            .await
+            .unwrap();
+        assert_eq!(verification.public_key, b"enclave-key");
+        assert_eq!(verification.nonce, b"enclave");
+        assert_eq!(verification.duration, Some(60));
+        assert_eq!(verification.single_use.nonces.len(), 2);
+        assert_eq!(verification.single_use.expiry, 7);
+
+        let authorization = auth(&[("bob", "bob"), ("carol", "carol"), ("enclave", "enclave")]);
+        let verification = verifier
+            .verify(break_glass(), request(&authorization))
+            .await
+            .unwrap();
+        assert!(verification.public_key.is_empty());
+        assert_eq!(verification.duration, Some(20));
+        assert_eq!(verification.single_use.nonces.len(), 2);
+
+        // Only the leaves that satisfy a rule contribute to the verification.
+        let authorization = auth(&[("alice", "alice"), ("bob", "bob"), ("carol", "carol")]);
+        let verification = verifier
+            .verify(break_glass(), request(&authorization))
+            .await
+            .unwrap();
+        assert_eq!(verification.duration, Some(10));
+        assert_eq!(verification.single_use.nonces.len(), 2);
+
+        let mut req = request(&authorization);
+        req.kind = RequestKind::Revoke;
+        let verification = verifier.verify(break_glass(), req).await.unwrap();
+        assert_eq!(verification.duration, None);
+
+        // Leaves that redeem the same challenge, such as two quotes made for it, may be combined.
+        let same_challenge = serde_json::json!({
+            "version": 1,
+            "rule": {
+                "all_of": [
+                    leaf("a", serde_json::json!({ "password": "a", "challenge": true })),
+                    leaf("b", serde_json::json!({ "password": "a", "challenge": true })),
+                ],
+            },
+        });
+        let verification = verifier
+            .verify(same_challenge, request(&auth(&[("a", "a"), ("b", "a")])))
+            .await
+            .unwrap();
+        assert_eq!(
+            verification.single_use.challenge,
+            Some(NonceId::new(NonceKind::Nonce, H256(keccak256(b"a"))))
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_composite() {
+        let verifier = verifier();
+
+        for authorizations in [
+            &[("enclave", "enclave")][..],
+            &[
+                ("enclave", "enclave"),
+                ("relayer", "wrong"),
+                ("alice", "alice"),
+            ],
+            &[("alice", "alice"), ("bob", "carol")],
+            &[],
+        ] {
+            assert!(matches!(
+                verifier
+                    .verify(break_glass(), request(&auth(authorizations)))
+                    .await,
+                Err(Error::Unauthorized(_))
+            ));
+        }
+        assert!(matches!(
+            verifier.verify(break_glass(), request(b"enclave")).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        // All-of rules fail for the same reason as their failed sub-rule.
+        let policy = serde_json::json!({
+            "version": 1,
+            "rule": { "all_of": [leaf("a", password("a")), leaf("b", password("b"))] },
+        });
+        let authorization = auth(&[("a", "a")]);
+        assert!(matches!(
+            verifier.verify(policy, request(&authorization)).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        let conflicting_keys = serde_json::json!({
+            "version": 1,
+            "rule": {
+                "all_of": [
+                    leaf("a", serde_json::json!({ "password": "a", "public_key": "a" })),
+                    leaf("b", serde_json::json!({ "password": "b", "public_key": "b" })),
+                ],
+            },
+        });
+        let authorization = auth(&[("a", "a"), ("b", "b")]);
+        assert!(matches!(
+            verifier
+                .verify(conflicting_keys, request(&authorization))
+                .await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let two_challenges = serde_json::json!({
+            "version": 1,
+            "rule": {
+                "all_of": [
+                    leaf("a", serde_json::json!({ "password": "a", "challenge": true })),
+                    leaf("b", serde_json::json!({ "password": "b", "challenge": true })),
+                ],
+            },
+        });
+        assert!(matches!(
+            verifier
+                .verify(two_challenges, request(&authorization))
+                .await,
+            Err(Error::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn evaluate_composite() {
+        let verifier = verifier();
+        let authorization = auth(&[("alice", "alice"), ("bob", "wrong"), ("carol", "carol")]);
+
+        let mut report = Report::dry_run();
+        verifier
+            .evaluate(break_glass(), request(&authorization), &mut report)
+            .await
+            .unwrap();
+        let steps = report.into_steps();
+        let step = |check: &str| steps.iter().find(|s| s.check == check).unwrap();
+        assert!(!step("rule/0").passed);
+        assert!(!step("enclave/authorization").passed);
+        assert!(!step("bob/password").passed);
+        assert!(step("alice/password").passed);
+        assert!(step("rule/1").passed);
+        assert!(step("rule").passed);
+    }
+
+    #[test]
+    fn validate_policy() {
+        let verifier = verifier();
+        verifier.validate(break_glass()).unwrap();
+
+        let invalid = [
+            serde_json::json!({
+                "version": 1,
+                "rule": { "any_of": [leaf("a", password("a")), leaf("a", password("b"))] },
+            }),
+            serde_json::json!({ "version": 1, "rule": { "all_of": [] } }),
+            serde_json::json!({
+                "version": 1,
+                "rule": { "k_of_n": { "k": 2, "of": [leaf("a", password("a"))] } },
+            }),
+            serde_json::json!({
+                "version": 1,
+                "rule": { "leaf": { "id": "a", "verifier": "nitro", "policy": {} } },
+            }),
+            serde_json::json!({
+                "version": 1,
+                "rule": leaf("a", serde_json::json!({ "passphrase": "a" })),
+            }),
+            (0..MAX_DEPTH).fold(
+                serde_json::json!({ "version": 1, "rule": leaf("a", password("a")) }),
+                |policy, _| {
+                    serde_json::json!({ "version": 1, "rule": { "any_of": [policy["rule"]] } })
+                },
+            ),
+        ];
+        for policy in invalid {
+            assert!(matches!(
+                verifier.validate(policy),
+                Err(Error::PolicyDecode(_))
+            ));
+        }
+        assert!(matches!(
+            verifier
+                .validate(serde_json::json!({ "version": 2, "rule": leaf("a", password("a")) })),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+    }
+}
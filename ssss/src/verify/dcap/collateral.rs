@@ -0,0 +1,503 @@
+//! Intel PCS collateral: TCB info, QE identities, and the SGX extensions of PCK certificates.
+
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, bail, ensure, Context as _};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+use webpki::types::{CertificateDer, UnixTime};
+
+use super::quote::{SgxReport, Tee};
+use crate::verify::{Error, ES256};
+
+/// The DER-encoded OID of the SGX extensions (1.2.840.113741.1.13.1).
+const SGX_EXTENSIONS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01];
+const SGX_EXTENSIONS_OID: &str = "1.2.840.113741.1.13.1";
+const SGX_EXT_TCB: u8 = 2;
+const SGX_EXT_FMSPC: u8 = 4;
+const SGX_TCB_PCESVN: u8 = 17;
+
+const DER_INTEGER: u8 = 0x02;
+const DER_OCTET_STRING: u8 = 0x04;
+const DER_OID: u8 = 0x06;
+const DER_SEQUENCE: u8 = 0x30;
+
+/// Collateral for the platforms that share an FMSPC, verified to have been signed by Intel.
+pub(super) struct Collateral {
+    pub(super) tcb_info: TcbInfo,
+    pub(super) qe_identity: EnclaveIdentity,
+    /// The DER-encoded CRLs of the Intel SGX Root CA and the PCK CAs.
+    pub(super) crls: Vec<Vec<u8>>,
+}
+
+impl Collateral {
+    pub(super) fn load(
+        dir: &Path,
+        root: &[u8],
+        tee: Tee,
+        fmspc: &[u8; 6],
+        now: UnixTime,
+    ) -> Result<Self, Error> {
+        let crls = read_crls(&dir.join("crls"))?;
+        let signer = read(&dir.join("tcb-signing.der"))?;
+        let tee_dir = dir.join(tee.to_string());
+        let tcb_info = read(
+            &tee_dir
+                .join("tcb-info")
+                .join(format!("{}.json", hex::encode(fmspc))),
+        )?;
+        let qe_identity = read(&tee_dir.join("qe-identity.json"))?;
+
+        let (tcb_info, qe_identity) = (|| {
+            let root = CertificateDer::from(root);
+            let anchor = webpki::anchor_from_trusted_cert(&root)?;
+            let signer_der = CertificateDer::from(signer.as_slice());
+            let signer = webpki::EndEntityCert::try_from(&signer_der)?;
+            signer
+                .verify_for_usage(
+                    webpki::ALL_VERIFICATION_ALGS,
+                    &[anchor],
+                    &[],
+                    now,
+                    webpki::KeyUsage::server_auth(),
+                    None,
+                    None,
+                )
+                .context("invalid TCB signing certificate")?;
+            check_revocation(&signer_der, &root, &crls, now)?;
+            let tcb_info: TcbInfo = verify_signed(&signer, &tcb_info, "tcbInfo")?;
+            let qe_identity: EnclaveIdentity =
+                verify_signed(&signer, &qe_identity, "enclaveIdentity")?;
+            ensure!(
+                tcb_info.id == tee.tcb_info_id(),
+                "TCB info is for {} not {}",
+                tcb_info.id,
+                tee.tcb_info_id()
+            );
+            ensure!(
+                hex::decode(&tcb_info.fmspc)? == fmspc,
+                "TCB info is for FMSPC {}",
+                tcb_info.fmspc
+            );
+            ensure!(
+                qe_identity.id == tee.qe_identity_id(),
+                "QE identity is for {} not {}",
+                qe_identity.id,
+                tee.qe_identity_id()
+            );
+            Ok((tcb_info, qe_identity))
+        })()
+        .map_err(Error::AttestationDecode)?;
+
+        for (what, next_update) in [
+            ("TCB info", &tcb_info.next_update),
+            ("QE identity", &qe_identity.next_update),
+        ] {
+            let next_update_secs = parse_time(next_update).map_err(Error::AttestationDecode)?;
+            if next_update_secs <= now.as_secs() {
+                return Err(Error::Timing(format!("{what} expired at {next_update}")));
+            }
+        }
+
+        Ok(Self {
+            tcb_info,
+            qe_identity,
+            crls,
+        })
+    }
+}
+
+/// Checks that the certificate was issued by `issuer` and is not revoked by an unexpired CRL
+/// of the issuer.
+///
+/// The CRLs are checked here rather than by webpki, which does not consider CRLs without an
+/// issuing distribution point, such as Intel's, authoritative for certificates that name a
+/// CRL distribution point, as Intel's do.
+pub(super) fn check_revocation(
+    cert: &[u8],
+    issuer: &[u8],
+    crls: &[Vec<u8>],
+    now: UnixTime,
+) -> Result<(), anyhow::Error> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|e| anyhow!("failed to parse certificate: {e}"))?;
+    let (_, issuer) = x509_parser::parse_x509_certificate(issuer)
+        .map_err(|e| anyhow!("failed to parse certificate: {e}"))?;
+    cert.verify_signature(Some(issuer.public_key()))
+        .map_err(|e| {
+            anyhow!(
+                "{} was not issued by {}: {e}",
+                cert.subject(),
+                issuer.subject()
+            )
+        })?;
+    let crl = crls
+        .iter()
+        .filter_map(|der| Some(x509_parser::parse_x509_crl(der).ok()?.1))
+        .find(|crl| crl.issuer().as_raw() == issuer.subject().as_raw())
+        .ok_or_else(|| anyhow!("no CRL of {} is available", issuer.subject()))?;
+    crl.verify_signature(issuer.public_key())
+        .map_err(|e| anyhow!("invalid CRL of {}: {e}", issuer.subject()))?;
+    ensure!(
+        crl.next_update()
+            .is_some_and(|next_update| next_update.timestamp() > now.as_secs() as i64),
+        "the CRL of {} has expired",
+        issuer.subject()
+    );
+    ensure!(
+        !crl.iter_revoked_certificates()
+            .any(|revoked| revoked.raw_serial() == cert.raw_serial()),
+        "{} has been revoked",
+        cert.subject()
+    );
+    Ok(())
+}
+
+fn read_crls(dir: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    (|| {
+        let mut crls = Vec::new();
+        for entry in std::fs::read_dir(dir).with_context(|| format!("failed to read {dir:?}"))? {
+            let path = entry?.path();
+            let der = std::fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+            x509_parser::parse_x509_crl(&der)
+                .map_err(|e| anyhow!("failed to parse CRL {path:?}: {e}"))?;
+            crls.push(der);
+        }
+        Ok(crls)
+    })()
+    .map_err(Error::AttestationDecode)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path)
+        .with_context(|| format!("failed to read {path:?}"))
+        .map_err(Error::AttestationDecode)
+}
+
+/// Decodes the object at `key` after checking the accompanying signature, which is made
+/// over the object exactly as it appears in the file.
+fn verify_signed<T: DeserializeOwned>(
+    signer: &webpki::EndEntityCert<'_>,
+    json: &[u8],
+    key: &str,
+) -> Result<T, anyhow::Error> {
+    let signed: HashMap<String, &RawValue> = serde_json::from_slice(json)?;
+    let body = signed.get(key).ok_or_else(|| anyhow!("missing `{key}`"))?;
+    let signature: String = serde_json::from_str(
+        signed
+            .get("signature")
+            .ok_or_else(|| anyhow!("missing `signature`"))?
+            .get(),
+    )?;
+    signer
+        .verify_signature(&ES256, body.get().as_bytes(), &hex::decode(signature)?)
+        .map_err(|e| anyhow!("invalid `{key}` signature: {e}"))?;
+    Ok(serde_json::from_str(body.get())?)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) enum TcbStatus {
+    UpToDate,
+    #[serde(rename = "SWHardeningNeeded")]
+    SwHardeningNeeded,
+    ConfigurationNeeded,
+    #[serde(rename = "ConfigurationAndSWHardeningNeeded")]
+    ConfigurationAndSwHardeningNeeded,
+    OutOfDate,
+    OutOfDateConfigurationNeeded,
+    Revoked,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct TcbInfo {
+    id: String,
+    next_update: String,
+    fmspc: String,
+    /// The TCB levels, highest first.
+    tcb_levels: Vec<TcbLevel>,
+}
+
+impl TcbInfo {
+    /// Returns the status of the highest TCB level that the platform meets, if any.
+    pub(super) fn status(&self, pck: &PckTcb, tee_tcb_svn: Option<&[u8; 16]>) -> Option<TcbStatus> {
+        self.tcb_levels
+            .iter()
+            .find(|level| level.tcb.is_met_by(pck, tee_tcb_svn))
+            .map(|level| level.tcb_status)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TcbLevel {
+    tcb: Tcb,
+    tcb_status: TcbStatus,
+}
+
+#[derive(Deserialize)]
+struct Tcb {
+    sgxtcbcomponents: Vec<TcbComponent>,
+    pcesvn: u16,
+    #[serde(default)]
+    tdxtcbcomponents: Vec<TcbComponent>,
+}
+
+impl Tcb {
+    fn is_met_by(&self, pck: &PckTcb, tee_tcb_svn: Option<&[u8; 16]>) -> bool {
+        fn components_met(components: &[TcbComponent], svns: &[u8; 16]) -> bool {
+            components.len() == svns.len()
+                && components.iter().zip(svns).all(|(c, &svn)| c.svn <= svn)
+        }
+        components_met(&self.sgxtcbcomponents, &pck.comp_svn)
+            && self.pcesvn <= pck.pce_svn
+            && tee_tcb_svn.map_or(true, |svns| components_met(&self.tdxtcbcomponents, svns))
+    }
+}
+
+#[derive(Deserialize)]
+struct TcbComponent {
+    svn: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct EnclaveIdentity {
+    id: String,
+    next_update: String,
+    #[serde(with = "hex::serde")]
+    miscselect: [u8; 4],
+    #[serde(with = "hex::serde")]
+    miscselect_mask: [u8; 4],
+    #[serde(with = "hex::serde")]
+    attributes: [u8; 16],
+    #[serde(with = "hex::serde")]
+    attributes_mask: [u8; 16],
+    #[serde(with = "hex::serde")]
+    mrsigner: [u8; 32],
+    isvprodid: u16,
+    /// The TCB levels, highest first.
+    tcb_levels: Vec<QeTcbLevel>,
+}
+
+impl EnclaveIdentity {
+    /// Returns the status of the TCB level of the quoting enclave that made the report.
+    pub(super) fn check(&self, qe_report: &SgxReport<'_>) -> Result<TcbStatus, anyhow::Error> {
+        ensure!(
+            qe_report.misc_select & u32::from_be_bytes(self.miscselect_mask)
+                == u32::from_be_bytes(self.miscselect),
+            "QE MISCSELECT does not match"
+        );
+        ensure!(
+            qe_report
+                .attributes
+                .iter()
+                .zip(&self.attributes_mask)
+                .map(|(a, m)| a & m)
+                .eq(self.attributes.iter().copied()),
+            "QE attributes do not match"
+        );
+        ensure!(
+            qe_report.mr_signer == &self.mrsigner,
+            "QE was not signed by Intel"
+        );
+        ensure!(
+            qe_report.isv_prod_id == self.isvprodid,
+            "QE has product ID {} not {}",
+            qe_report.isv_prod_id,
+            self.isvprodid
+        );
+        self.tcb_levels
+            .iter()
+            .find(|level| level.tcb.isvsvn <= qe_report.isv_svn)
+            .map(|level| level.tcb_status)
+            .ok_or_else(|| anyhow!("QE SVN {} is below every TCB level", qe_report.isv_svn))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QeTcbLevel {
+    tcb: QeTcb,
+    tcb_status: TcbStatus,
+}
+
+#[derive(Deserialize)]
+struct QeTcb {
+    isvsvn: u16,
+}
+
+/// The platform TCB recorded in the SGX extensions of a PCK certificate.
+pub(super) struct PckTcb {
+    pub(super) comp_svn: [u8; 16],
+    pub(super) pce_svn: u16,
+    pub(super) fmspc: [u8; 6],
+}
+
+impl PckTcb {
+    pub(super) fn from_cert(der: &[u8]) -> Result<Self, anyhow::Error> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der)
+            .map_err(|e| anyhow!("failed to parse PCK certificate: {e}"))?;
+        let ext = cert
+            .extensions()
+            .iter()
+            .find(|ext| ext.oid.to_id_string() == SGX_EXTENSIONS_OID)
+            .ok_or_else(|| anyhow!("PCK certificate has no SGX extensions"))?;
+
+        let mut comp_svn: Option<[u8; 16]> = None;
+        let mut pce_svn: Option<u16> = None;
+        let mut fmspc: Option<[u8; 6]> = None;
+        for (oid, tag, value) in der_entries(der_expect(ext.value, DER_SEQUENCE)?)? {
+            match oid.strip_prefix(SGX_EXTENSIONS) {
+                Some([SGX_EXT_TCB]) if tag == DER_SEQUENCE => {
+                    let mut svns: [Option<u8>; 16] = [None; 16];
+                    for (oid, tag, value) in der_entries(value)? {
+                        match oid.strip_prefix(SGX_EXTENSIONS) {
+                            Some(&[SGX_EXT_TCB, n @ 1..=16]) if tag == DER_INTEGER => {
+                                svns[usize::from(n - 1)] = Some(der_uint(value)?.try_into()?);
+                            }
+                            Some(&[SGX_EXT_TCB, SGX_TCB_PCESVN]) if tag == DER_INTEGER => {
+                                pce_svn = Some(der_uint(value)?.try_into()?);
+                            }
+                            _ => {}
+                        }
+                    }
+                    if svns.iter().all(Option::is_some) {
+                        comp_svn = Some(svns.map(Option::unwrap_or_default));
+                    }
+                }
+                Some([SGX_EXT_FMSPC]) if tag == DER_OCTET_STRING => {
+                    fmspc = Some(value.try_into()?);
+                }
+                _ => {}
+            }
+        }
+        match (comp_svn, pce_svn, fmspc) {
+            (Some(comp_svn), Some(pce_svn), Some(fmspc)) => Ok(Self {
+                comp_svn,
+                pce_svn,
+                fmspc,
+            }),
+            _ => bail!("PCK certificate SGX extensions are incomplete"),
+        }
+    }
+}
+
+/// Splits the next DER element into its tag, contents, and the remaining input.
+fn der_next(input: &[u8]) -> Result<(u8, &[u8], &[u8]), anyhow::Error> {
+    let truncated = || anyhow!("truncated DER");
+    let (&tag, rest) = input.split_first().ok_or_else(truncated)?;
+    let (&len, rest) = rest.split_first().ok_or_else(truncated)?;
+    let (len, rest) = match len {
+        0..=0x7f => (usize::from(len), rest),
+        0x81 => {
+            let (&len, rest) = rest.split_first().ok_or_else(truncated)?;
+            (usize::from(len), rest)
+        }
+        0x82 => {
+            ensure!(rest.len() >= 2, "truncated DER");
+            (
+                usize::from(u16::from_be_bytes([rest[0], rest[1]])),
+                &rest[2..],
+            )
+        }
+        _ => bail!("unsupported DER length"),
+    };
+    ensure!(rest.len() >= len, "truncated DER");
+    let (contents, rest) = rest.split_at(len);
+    Ok((tag, contents, rest))
+}
+
+fn der_expect(input: &[u8], tag: u8) -> Result<&[u8], anyhow::Error> {
+    match der_next(input)? {
+        (t, contents, []) if t == tag => Ok(contents),
+        _ => bail!("unexpected DER element"),
+    }
+}
+
+/// An OID and the tag and contents of its value.
+type DerEntry<'a> = (&'a [u8], u8, &'a [u8]);
+
+/// Decodes the contents of a sequence of `SEQUENCE { OID, value }`.
+fn der_entries(mut input: &[u8]) -> Result<Vec<DerEntry<'_>>, anyhow::Error> {
+    let mut entries = Vec::new();
+    while !input.is_empty() {
+        let (tag, entry, rest) = der_next(input)?;
+        ensure!(tag == DER_SEQUENCE, "unexpected DER element");
+        let (tag, oid, value) = der_next(entry)?;
+        ensure!(tag == DER_OID, "unexpected DER element");
+        let (tag, value, _) = der_next(value)?;
+        entries.push((oid, tag, value));
+        input = rest;
+    }
+    Ok(entries)
+}
+
+fn der_uint(contents: &[u8]) -> Result<u32, anyhow::Error> {
+    let contents = match contents {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        [b, ..] if b & 0x80 != 0 => bail!("negative DER integer"),
+        _ => contents,
+    };
+    ensure!(
+        !contents.is_empty() && contents.len() <= 4,
+        "DER integer out of range"
+    );
+    Ok(contents.iter().fold(0, |n, &b| (n << 8) | u32::from(b)))
+}
+
+/// Parses the `YYYY-MM-DDThh:mm:ssZ` timestamps of Intel collateral into unix seconds.
+fn parse_time(s: &str) -> Result<u64, anyhow::Error> {
+    let b = s.as_bytes();
+    ensure!(
+        b.len() >= 20
+            && b[4] == b'-'
+            && b[7] == b'-'
+            && b[10] == b'T'
+            && b[13] == b':'
+            && b[16] == b':'
+            && s.ends_with('Z'),
+        "malformed timestamp {s:?}"
+    );
+    let num = |range: std::ops::Range<usize>| {
+        s.get(range)
+            .and_then(|n| n.parse::<u64>().ok())
+            .ok_or_else(|| anyhow!("malformed timestamp {s:?}"))
+    };
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let secs = num(11..13)? * 3600 + num(14..16)? * 60 + num(17..19)?;
+    ensure!(
+        (1970..10000).contains(&year) && (1..=12).contains(&month) && (1..=31).contains(&day),
+        "malformed timestamp {s:?}"
+    );
+    // Days since the epoch of a proleptic Gregorian date, with years starting in March.
+    let year = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Ok((era * 146097 + day_of_era - 719468) * 86400 + secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collateral_time() {
+        assert_eq!(parse_time("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(parse_time("2000-03-01T00:00:01Z").unwrap(), 951868801);
+        assert_eq!(parse_time("2024-02-29T12:34:56Z").unwrap(), 1709210096);
+        assert_eq!(parse_time("2024-02-29T12:34:56.789Z").unwrap(), 1709210096);
+        assert!(parse_time("2024-02-29 12:34:56Z").is_err());
+        assert!(parse_time("2024-13-01T00:00:00Z").is_err());
+    }
+
+    #[test]
+    fn der_integers() {
+        assert_eq!(der_uint(&[0x05]).unwrap(), 5);
+        assert_eq!(der_uint(&[0x00, 0xd1]).unwrap(), 0xd1);
+        assert_eq!(der_uint(&[0x01, 0x00]).unwrap(), 0x100);
+        assert!(der_uint(&[0x80]).is_err());
+        assert!(der_uint(&[]).is_err());
+    }
+}
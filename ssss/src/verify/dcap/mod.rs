@@ -0,0 +1,1091 @@
+mod collateral;
+mod quote;
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, ensure};
+use base64::prelude::*;
+use ethers::types::{Bytes, H256};
+use serde::Deserialize;
+use sha2::{Digest as _, Sha256};
+use smallvec::SmallVec;
+use webpki::types::{CertificateDer, UnixTime};
+
+use self::{
+    collateral::{check_revocation, Collateral, PckTcb, TcbStatus},
+    quote::{Body, Quote, SgxReport, TdReport, Tee},
+};
+use super::*;
+use crate::types::NonceKind;
+
+static ROOT_CA: &[u8] = include_bytes!("./root.der");
+
+/// Verifies Intel DCAP quotes from SGX enclaves (version 3) and TDX trust domains (version 4).
+///
+/// The authorization is the quote, which must carry its PCK certificate chain. The first half
+/// of the report data must be the version 2 request binding, and the second half a challenge
+/// issued by this SSSS, as quotes carry no timestamp.
+///
+/// The Intel SGX Root CA is built in. The rest of the collateral is provisioned from the Intel
+/// PCS into the collateral directory, which contains the TCB signing certificate as
+/// `tcb-signing.der`, a `crls` directory of the DER-encoded CRLs of the Root CA and of each
+/// PCK CA, and, for each TEE (`sgx` or `tdx`), a subdirectory containing `qe-identity.json`
+/// and the `tcb-info/<fmspc>.json` of each platform model, where the FMSPC is in lowercase hex.
+#[derive(Clone, Debug)]
+pub struct DcapVerifier {
+    collateral_dir: Option<Arc<PathBuf>>,
+    /// The DER-encoded root to which PCK and TCB signing certificates must chain.
+    root: Cow<'static, [u8]>,
+}
+
+impl Default for DcapVerifier {
+    fn default() -> Self {
+        Self {
+            collateral_dir: None,
+            root: ROOT_CA.into(),
+        }
+    }
+}
+
+impl DcapVerifier {
+    pub fn with_collateral_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            collateral_dir: Some(Arc::new(dir.into())),
+            ..Default::default()
+        }
+    }
+}
+
+impl Verifier for DcapVerifier {
+    fn name(&self) -> &str {
+        "dcap"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        let hex_string = |len: usize| {
+            serde_json::json!({
+                "type": "string",
+                "pattern": format!("^0x[0-9a-fA-F]{{{}}}$", len * 2),
+            })
+        };
+        let u16_schema = serde_json::json!({ "type": "integer", "minimum": 0, "maximum": 65535 });
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Intel DCAP policy",
+            "type": "object",
+            "required": ["version", "tee", "measurements"],
+            "properties": {
+                "version": { "const": 1 },
+                "tee": { "enum": ["sgx", "tdx"] },
+                "measurements": { "type": "array", "minItems": 1 },
+                "tcb_statuses": {
+                    "type": "array",
+                    "items": {
+                        "enum": [
+                            "UpToDate",
+                            "SWHardeningNeeded",
+                            "ConfigurationNeeded",
+                            "ConfigurationAndSWHardeningNeeded",
+                            "OutOfDate",
+                            "OutOfDateConfigurationNeeded",
+                        ],
+                    },
+                },
+                "allow_debug": { "type": "boolean" },
+                "max_duration": { "type": "integer", "minimum": 0 },
+                "relayers": { "type": "array", "items": hex_string(20) },
+            },
+            "oneOf": [
+                {
+                    "properties": {
+                        "tee": { "const": "sgx" },
+                        "measurements": {
+                            "items": {
+                                "type": "object",
+                                "additionalProperties": false,
+                                "anyOf": [
+                                    { "required": ["mr_enclave"] },
+                                    { "required": ["mr_signer"] },
+                                ],
+                                "properties": {
+                                    "mr_enclave": hex_string(32),
+                                    "mr_signer": hex_string(32),
+                                    "isv_prod_id": u16_schema,
+                                    "min_isv_svn": u16_schema,
+                                },
+                            },
+                        },
+                    },
+                },
+                {
+                    "properties": {
+                        "tee": { "const": "tdx" },
+                        "measurements": {
+                            "items": {
+                                "type": "object",
+                                "required": ["mr_td"],
+                                "additionalProperties": false,
+                                "properties": {
+                                    "mr_td": hex_string(48),
+                                    "rtmrs": {
+                                        "type": "array",
+                                        "maxItems": 4,
+                                        "items": { "oneOf": [hex_string(48), { "type": "null" }] },
+                                    },
+                                },
+                            },
+                        },
+                    },
+                },
+            ],
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        Policy::decode(raw_policy).map(|_| ())
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        report.check(
+            EvaluationStep::new("relayer")
+                .expected(&policy.relayers)
+                .observed(req.relayer),
+            check_relayer(&policy.relayers, req.relayer),
+        )?;
+
+        let decoded = Quote::parse(req.authorization).and_then(|quote| {
+            let pck_chain = pem_certs(quote.pck_chain)?;
+            let pck = PckTcb::from_cert(&pck_chain[0])?;
+            Ok((quote, pck_chain, pck))
+        });
+        let (quote, pck_chain, pck) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("attestation"),
+                    Error::AttestationDecode(e),
+                ))
+            }
+        };
+
+        let now = UnixTime::now();
+        let tee = quote.body.tee();
+        let step = EvaluationStep::new("collateral").observed(serde_json::json!({
+            "tee": tee,
+            "fmspc": hex::encode(pck.fmspc),
+        }));
+        let collateral = match self.load_collateral(tee, &pck.fmspc, now) {
+            Ok(collateral) => {
+                report.pass(step);
+                collateral
+            }
+            Err(e) => return Err(report.fail(step, e)),
+        };
+
+        match Self::verify_quote(&quote, &self.root, &pck_chain, &collateral.crls, now) {
+            Ok(()) => report.pass(EvaluationStep::new("attestation")),
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("attestation"),
+                    Error::AttestationDecode(e),
+                ))
+            }
+        }
+
+        let qe_status = collateral.qe_identity.check(&quote.qe_report);
+        report.check(
+            EvaluationStep::new("qe-identity")
+                .expected(&policy.tcb_statuses)
+                .observed(qe_status.as_ref().ok()),
+            match qe_status {
+                Ok(status) => policy.check_tcb_status("QE", status),
+                Err(e) => Err(Error::Unauthorized(e.to_string())),
+            },
+        )?;
+
+        let tcb_status = collateral.tcb_info.status(&pck, quote.body.tee_tcb_svn());
+        report.check(
+            EvaluationStep::new("tcb")
+                .expected(&policy.tcb_statuses)
+                .observed(tcb_status),
+            match tcb_status {
+                Some(status) => policy.check_tcb_status("platform", status),
+                None => Err(Error::Unauthorized(
+                    "platform TCB is below every known level".into(),
+                )),
+            },
+        )?;
+
+        let expected_binding = match request_binding(2, &req) {
+            Ok(binding) => binding,
+            Err(e) => return Err(report.fail(EvaluationStep::new("binding"), e)),
+        };
+        report.check(
+            EvaluationStep::new("binding")
+                .expected(Bytes::from(expected_binding))
+                .observed(Bytes::from(quote.body.binding().to_vec())),
+            if quote.body.binding() == expected_binding {
+                Ok(())
+            } else {
+                Err(Error::BindingMismatch(SmallVec::from_buf(expected_binding)))
+            },
+        )?;
+
+        let challenge = NonceId::new(NonceKind::Challenge, H256::from_slice(quote.body.nonce()));
+        report.pass(EvaluationStep::new("challenge").observed(challenge.digest));
+
+        report.check(
+            EvaluationStep::new("debug")
+                .expected(policy.allow_debug)
+                .observed(quote.body.debug()),
+            if quote.body.debug() && !policy.allow_debug {
+                Err(Error::Unauthorized("quote is from a debuggable TEE".into()))
+            } else {
+                Ok(())
+            },
+        )?;
+
+        let matched = match (&policy.tee, &quote.body) {
+            (TeePolicy::Sgx { measurements }, Body::Sgx(body)) => {
+                Ok(measurements.iter().position(|m| m.matches(body)))
+            }
+            (TeePolicy::Tdx { measurements }, Body::Tdx(body)) => {
+                Ok(measurements.iter().position(|m| m.matches(body)))
+            }
+            _ => Err(Error::Unauthorized(format!(
+                "quote is from {} but the policy is for {}",
+                tee,
+                policy.tee.tee()
+            ))),
+        };
+        let matched_index = matched.as_ref().ok().copied().flatten();
+        report.check(
+            EvaluationStep::new("measurement").observed(serde_json::json!({
+                "matched": matched_index,
+                "quote": quote.body.measurement_json(),
+            })),
+            match matched {
+                Ok(Some(_)) => Ok(()),
+                Ok(None) => Err(Error::Unauthorized("unrecognized measurement".into())),
+                Err(e) => Err(e),
+            },
+        )?;
+
+        report.finish(Verification {
+            nonce: quote.body.nonce().to_vec(),
+            public_key: vec![],
+            duration: match req.kind {
+                RequestKind::Grant { duration } => Some(duration.min(policy.max_duration)),
+                RequestKind::Revoke => None,
+            },
+            measurement: matched_index,
+            single_use: SingleUse {
+                challenge: Some(challenge),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+impl DcapVerifier {
+    fn load_collateral(
+        &self,
+        tee: Tee,
+        fmspc: &[u8; 6],
+        now: UnixTime,
+    ) -> Result<Collateral, Error> {
+        let dir: &Path = self
+            .collateral_dir
+            .as_deref()
+            .ok_or_else(|| Error::AttestationDecode(anyhow!("no DCAP collateral is available")))?;
+        Collateral::load(dir, &self.root, tee, fmspc, now)
+    }
+
+    /// Checks that the quote was signed by an attestation key that was certified by a
+    /// quoting enclave running on a platform with an unrevoked PCK issued by Intel.
+    fn verify_quote(
+        quote: &Quote<'_>,
+        root: &[u8],
+        pck_chain: &[Vec<u8>],
+        crls: &[Vec<u8>],
+        now: UnixTime,
+    ) -> Result<(), anyhow::Error> {
+        let root = CertificateDer::from(root);
+        let anchor = webpki::anchor_from_trusted_cert(&root)?;
+        let pck_der = CertificateDer::from(pck_chain[0].as_slice());
+        let pck = webpki::EndEntityCert::try_from(&pck_der)?;
+        let intermediates: Vec<_> = pck_chain[1..]
+            .iter()
+            .map(|der| CertificateDer::from(der.as_slice()))
+            .collect();
+        pck.verify_for_usage(
+            webpki::ALL_VERIFICATION_ALGS,
+            &[anchor],
+            &intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+            None,
+        )?;
+        // PCK chains comprise the PCK, the PCK CA, and optionally the root.
+        let pck_ca = pck_chain
+            .get(1)
+            .ok_or_else(|| anyhow!("PCK chain has no PCK CA"))?;
+        check_revocation(&pck_chain[0], pck_ca, crls, now)?;
+        check_revocation(pck_ca, &root, crls, now)?;
+
+        pck.verify_signature(&ES256, quote.qe_report.raw, quote.qe_report_signature)
+            .map_err(|e| anyhow!("invalid QE report signature: {e}"))?;
+
+        let key_hash = Sha256::new()
+            .chain_update(quote.attestation_key)
+            .chain_update(quote.qe_auth_data)
+            .finalize();
+        let (qe_binding, qe_padding) = quote.qe_report.report_data.split_at(32);
+        ensure!(
+            qe_binding == key_hash.as_slice() && qe_padding.iter().all(|&b| b == 0),
+            "attestation key was not certified by the QE"
+        );
+
+        let attestation_key = [&[0x04], quote.attestation_key.as_slice()].concat();
+        ring::signature::UnparsedPublicKey::new(
+            &ring::signature::ECDSA_P256_SHA256_FIXED,
+            attestation_key,
+        )
+        .verify(quote.signed, quote.signature)
+        .map_err(|_| anyhow!("invalid quote signature"))
+    }
+}
+
+/// Decodes a PEM certificate chain.
+fn pem_certs(pem: &[u8]) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+    // The chain is sometimes NUL-terminated.
+    let pem = std::str::from_utf8(pem)?.trim_end_matches('\0');
+    let mut certs = Vec::new();
+    for block in pem.split(BEGIN).skip(1) {
+        let (body, _) = block
+            .split_once(END)
+            .ok_or_else(|| anyhow!("unterminated PEM certificate"))?;
+        let body: String = body.split_whitespace().collect();
+        certs.push(BASE64_STANDARD.decode(body)?);
+    }
+    ensure!(!certs.is_empty(), "quote has no PCK certificate chain");
+    Ok(certs)
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    version: u8,
+    #[serde(flatten)]
+    tee: TeePolicy,
+    /// The acceptable statuses of the platform and QE TCB levels.
+    #[serde(default = "default_tcb_statuses")]
+    tcb_statuses: Vec<TcbStatus>,
+    #[serde(default)]
+    allow_debug: bool,
+    #[serde(default)]
+    max_duration: u64,
+    #[serde(default)]
+    relayers: HashSet<Address>,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        if policy.tcb_statuses.contains(&TcbStatus::Revoked) {
+            return Err(Error::PolicyDecode(anyhow!(
+                "revoked TCB levels cannot be allowed"
+            )));
+        }
+        policy.tee.validate().map_err(Error::PolicyDecode)?;
+        Ok(policy)
+    }
+
+    fn check_tcb_status(&self, what: &str, status: TcbStatus) -> Result<(), Error> {
+        if self.tcb_statuses.contains(&status) {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized(format!(
+                "{what} TCB status is {status:?}"
+            )))
+        }
+    }
+}
+
+fn default_tcb_statuses() -> Vec<TcbStatus> {
+    vec![TcbStatus::UpToDate]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "tee", rename_all = "lowercase")]
+enum TeePolicy {
+    Sgx {
+        /// The acceptable enclaves. Any one of them must match the quote.
+        measurements: Vec<SgxMeasurement>,
+    },
+    Tdx {
+        /// The acceptable trust domains. Any one of them must match the quote.
+        measurements: Vec<TdxMeasurement>,
+    },
+}
+
+impl TeePolicy {
+    fn tee(&self) -> Tee {
+        match self {
+            Self::Sgx { .. } => Tee::Sgx,
+            Self::Tdx { .. } => Tee::Tdx,
+        }
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        match self {
+            Self::Sgx { measurements } => {
+                ensure!(
+                    !measurements.is_empty(),
+                    "at least one measurement is required"
+                );
+                for (i, m) in measurements.iter().enumerate() {
+                    ensure!(
+                        m.mr_enclave.is_some() || m.mr_signer.is_some(),
+                        "measurement {i} must have `mr_enclave` or `mr_signer`"
+                    );
+                }
+            }
+            Self::Tdx { measurements } => {
+                ensure!(
+                    !measurements.is_empty(),
+                    "at least one measurement is required"
+                );
+                for (i, m) in measurements.iter().enumerate() {
+                    ensure!(
+                        m.mr_td.len() == 48,
+                        "measurement {i} has an `mr_td` of length {} but must be 48 bytes",
+                        m.mr_td.len()
+                    );
+                    ensure!(
+                        m.rtmrs.len() <= 4,
+                        "measurement {i} has more than four RTMRs"
+                    );
+                    if let Some(j) = m.rtmrs.iter().flatten().position(|r| r.len() != 48) {
+                        return Err(anyhow!(
+                            "measurement {i} has an RTMR {j} that is not 48 bytes"
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct SgxMeasurement {
+    #[serde(default)]
+    mr_enclave: Option<H256>,
+    #[serde(default)]
+    mr_signer: Option<H256>,
+    #[serde(default)]
+    isv_prod_id: Option<u16>,
+    #[serde(default)]
+    min_isv_svn: u16,
+}
+
+impl SgxMeasurement {
+    fn matches(&self, body: &SgxReport<'_>) -> bool {
+        self.mr_enclave.map_or(true, |m| &m.0 == body.mr_enclave)
+            && self.mr_signer.map_or(true, |m| &m.0 == body.mr_signer)
+            && self.isv_prod_id.map_or(true, |id| id == body.isv_prod_id)
+            && body.isv_svn >= self.min_isv_svn
+    }
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct TdxMeasurement {
+    mr_td: Bytes,
+    /// The expected RTMRs, in order. A null RTMR may have any value.
+    #[serde(default)]
+    rtmrs: Vec<Option<Bytes>>,
+}
+
+impl TdxMeasurement {
+    fn matches(&self, body: &TdReport<'_>) -> bool {
+        self.mr_td.as_ref() == &body.mr_td[..]
+            && self.rtmrs.iter().zip(&body.rtmrs).all(|(expected, rtmr)| {
+                expected
+                    .as_ref()
+                    .map_or(true, |expected| expected.as_ref() == &rtmr[..])
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{
+        Certificate, CertificateParams, CertificateRevocationListParams, CustomExtension, DnType,
+        KeyIdMethod, KeyPair, RevokedCertParams, SerialNumber,
+    };
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+
+    use super::{quote::*, *};
+    use crate::verify::tests::{ca_params, request};
+
+    const FMSPC: [u8; 6] = [0x00, 0x90, 0x6e, 0xd5, 0x00, 0x00];
+    const QE_MR_SIGNER: [u8; 32] = [0x11; 32];
+    const MR_ENCLAVE: [u8; 32] = [0x22; 32];
+    const MR_TD: [u8; 48] = [0x33; 48];
+    const RTMR: [u8; 48] = [0x44; 48];
+    const NONCE: [u8; 32] = [5; 32];
+    /// The DER-encoded OID of the SGX extensions (1.2.840.113741.1.13.1).
+    const SGX_EXTENSIONS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf8, 0x4d, 0x01, 0x0d, 0x01];
+
+    const PCK_SERIAL: u64 = 7;
+
+    struct TestPlatform {
+        dir: tempfile::TempDir,
+        root: Certificate,
+        root_key: KeyPair,
+        platform_ca: Certificate,
+        platform_key: KeyPair,
+        pck_key: EcdsaKeyPair,
+        pck_chain: String,
+        tcb_signer: EcdsaKeyPair,
+        attestation_key: EcdsaKeyPair,
+    }
+
+    fn p256_key() -> (EcdsaKeyPair, KeyPair) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        (key, KeyPair::try_from(pkcs8.as_ref()).unwrap())
+    }
+
+    fn sign(key: &EcdsaKeyPair, data: &[u8]) -> Vec<u8> {
+        key.sign(&SystemRandom::new(), data)
+            .unwrap()
+            .as_ref()
+            .to_vec()
+    }
+
+    fn leaf(name: &str, key: &KeyPair, issuer: &Certificate, issuer_key: &KeyPair) -> Certificate {
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.signed_by(key, issuer, issuer_key).unwrap()
+    }
+
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut der = vec![tag];
+        match contents.len() {
+            len @ 0..=0x7f => der.push(len as u8),
+            len @ 0x80..=0xff => der.extend([0x81, len as u8]),
+            len => {
+                der.push(0x82);
+                der.extend((len as u16).to_be_bytes());
+            }
+        }
+        der.extend(contents);
+        der
+    }
+
+    /// Encodes an SGX extension entry whose OID is `SGX_EXTENSIONS.suffix`.
+    fn sgx_entry(suffix: &[u8], value: Vec<u8>) -> Vec<u8> {
+        let oid = der(0x06, &[SGX_EXTENSIONS, suffix].concat());
+        der(0x30, &[oid, value].concat())
+    }
+
+    fn sgx_extensions() -> Vec<u8> {
+        let mut tcb: Vec<u8> = (1..=16)
+            .flat_map(|n| sgx_entry(&[2, n], der(0x02, &[5])))
+            .collect();
+        tcb.extend(sgx_entry(&[2, 17], der(0x02, &[13])));
+        tcb.extend(sgx_entry(&[2, 18], der(0x04, &[5; 16])));
+        let entries = [
+            sgx_entry(&[1], der(0x04, &[0xab; 16])),
+            sgx_entry(&[2], der(0x30, &tcb)),
+            sgx_entry(&[3], der(0x04, &[0, 0])),
+            sgx_entry(&[4], der(0x04, &FMSPC)),
+        ]
+        .concat();
+        der(0x30, &entries)
+    }
+
+    fn tcb_level(svn: u8, status: &str) -> serde_json::Value {
+        let components: Vec<_> = (0..16).map(|_| serde_json::json!({ "svn": svn })).collect();
+        serde_json::json!({
+            "tcb": {
+                "sgxtcbcomponents": components,
+                "pcesvn": 13,
+                "tdxtcbcomponents": components,
+            },
+            "tcbDate": "2024-03-13T00:00:00Z",
+            "tcbStatus": status,
+        })
+    }
+
+    /// Sets up a collateral directory and PCK chain in which Intel's certificates are
+    /// replaced by generated ones.
+    fn make_platform() -> TestPlatform {
+        let root_key = KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+        let root = ca_params("Intel SGX Root CA")
+            .self_signed(&root_key)
+            .unwrap();
+        let platform_key = KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+        let platform_ca = ca_params("Intel SGX PCK Platform CA")
+            .signed_by(&platform_key, &root, &root_key)
+            .unwrap();
+
+        let (pck_key, pck_cert_key) = p256_key();
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Intel SGX PCK Certificate");
+        params.serial_number = Some(SerialNumber::from(PCK_SERIAL));
+        params.custom_extensions = vec![CustomExtension::from_oid_content(
+            &[1, 2, 840, 113741, 1, 13, 1],
+            sgx_extensions(),
+        )];
+        let pck = params
+            .signed_by(&pck_cert_key, &platform_ca, &platform_key)
+            .unwrap();
+
+        let (tcb_signer, tcb_signer_cert_key) = p256_key();
+        let tcb_signing = leaf(
+            "Intel SGX TCB Signing",
+            &tcb_signer_cert_key,
+            &root,
+            &root_key,
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("tcb-signing.der"), tcb_signing.der()).unwrap();
+
+        let platform = TestPlatform {
+            dir,
+            pck_chain: [pck.pem(), platform_ca.pem(), root.pem()].concat(),
+            root,
+            root_key,
+            platform_ca,
+            platform_key,
+            pck_key,
+            tcb_signer,
+            attestation_key: p256_key().0,
+        };
+        for tee in [Tee::Sgx, Tee::Tdx] {
+            platform.write_collateral(tee, "UpToDate", "2999-01-01T00:00:00Z");
+        }
+        platform.write_crls(&[], 2999);
+        platform
+    }
+
+    fn make_crl(
+        issuer: &Certificate,
+        issuer_key: &KeyPair,
+        revoked: &[u64],
+        next_update_year: i32,
+    ) -> Vec<u8> {
+        CertificateRevocationListParams {
+            this_update: rcgen::date_time_ymd(2023, 1, 1),
+            next_update: rcgen::date_time_ymd(next_update_year, 1, 1),
+            crl_number: SerialNumber::from(1u64),
+            issuing_distribution_point: None,
+            revoked_certs: revoked
+                .iter()
+                .map(|&serial| RevokedCertParams {
+                    serial_number: SerialNumber::from(serial),
+                    revocation_time: rcgen::date_time_ymd(2023, 1, 1),
+                    reason_code: Some(rcgen::RevocationReason::KeyCompromise),
+                    invalidity_date: None,
+                })
+                .collect(),
+            key_identifier_method: KeyIdMethod::Sha256,
+        }
+        .signed_by(issuer, issuer_key)
+        .unwrap()
+        .der()
+        .to_vec()
+    }
+
+    impl TestPlatform {
+        /// Writes the CRLs of the root and PCK Platform CAs, the latter revoking the given
+        /// PCK serial numbers.
+        fn write_crls(&self, revoked_pcks: &[u64], next_update_year: i32) {
+            let crl_dir = self.dir.path().join("crls");
+            std::fs::create_dir_all(&crl_dir).unwrap();
+            std::fs::write(
+                crl_dir.join("root.crl"),
+                make_crl(&self.root, &self.root_key, &[], next_update_year),
+            )
+            .unwrap();
+            std::fs::write(
+                crl_dir.join("platform.crl"),
+                make_crl(
+                    &self.platform_ca,
+                    &self.platform_key,
+                    revoked_pcks,
+                    next_update_year,
+                ),
+            )
+            .unwrap();
+        }
+
+        fn write_signed(&self, path: &Path, key: &str, body: serde_json::Value) {
+            let body = body.to_string();
+            let signature = hex::encode(sign(&self.tcb_signer, body.as_bytes()));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(
+                path,
+                format!(r#"{{"{key}":{body},"signature":"{signature}"}}"#),
+            )
+            .unwrap();
+        }
+
+        fn write_collateral(&self, tee: Tee, status: &str, next_update: &str) {
+            let tee_dir = self.dir.path().join(tee.to_string());
+            self.write_signed(
+                &tee_dir
+                    .join("tcb-info")
+                    .join(format!("{}.json", hex::encode(FMSPC))),
+                "tcbInfo",
+                serde_json::json!({
+                    "id": tee.tcb_info_id(),
+                    "version": 3,
+                    "issueDate": "2024-04-01T00:00:00Z",
+                    "nextUpdate": next_update,
+                    "fmspc": hex::encode(FMSPC),
+                    "pceId": "0000",
+                    "tcbType": 0,
+                    "tcbEvaluationDataNumber": 17,
+                    "tcbLevels": [tcb_level(5, status), tcb_level(0, "OutOfDate")],
+                }),
+            );
+            self.write_signed(
+                &tee_dir.join("qe-identity.json"),
+                "enclaveIdentity",
+                serde_json::json!({
+                    "id": tee.qe_identity_id(),
+                    "version": 2,
+                    "issueDate": "2024-04-01T00:00:00Z",
+                    "nextUpdate": next_update,
+                    "tcbEvaluationDataNumber": 17,
+                    "miscselect": "00000000",
+                    "miscselectMask": "FFFFFFFF",
+                    "attributes": "11000000000000000000000000000000",
+                    "attributesMask": "FBFFFFFFFFFFFFFF0000000000000000",
+                    "mrsigner": hex::encode(QE_MR_SIGNER),
+                    "isvprodid": 1,
+                    "tcbLevels": [
+                        {
+                            "tcb": { "isvsvn": 8 },
+                            "tcbDate": "2024-03-13T00:00:00Z",
+                            "tcbStatus": "UpToDate",
+                        },
+                    ],
+                }),
+            );
+        }
+
+        fn quote(&self, build: QuoteBuilder) -> Vec<u8> {
+            let attestation_key = self.attestation_key.public_key().as_ref()[1..]
+                .try_into()
+                .unwrap();
+            build
+                .qe_report(SGX_ATTRIBUTES, &[0x11])
+                .qe_report(SGX_MR_SIGNER, &QE_MR_SIGNER)
+                .qe_report(SGX_ISV_PROD_ID, &1u16.to_le_bytes())
+                .qe_report(SGX_ISV_SVN, &8u16.to_le_bytes())
+                .pck_chain(self.pck_chain.clone())
+                .sign(
+                    attestation_key,
+                    |data| sign(&self.attestation_key, data),
+                    |data| sign(&self.pck_key, data),
+                )
+        }
+
+        fn tdx_quote(&self, build: impl FnOnce(QuoteBuilder) -> QuoteBuilder) -> Vec<u8> {
+            self.quote(build(
+                QuoteBuilder::tdx()
+                    .report_data(&request_binding(2, &request(&[])).unwrap(), &NONCE)
+                    .set(TD_TEE_TCB_SVN, &[5; 16])
+                    .set(TD_MR_TD, &MR_TD)
+                    .set(TD_RTMR0 + 48, &RTMR),
+            ))
+        }
+
+        async fn verify(
+            &self,
+            policy: serde_json::Value,
+            authorization: &[u8],
+        ) -> Result<Verification, Error> {
+            let verifier = DcapVerifier {
+                root: self.root.der().to_vec().into(),
+                ..DcapVerifier::with_collateral_dir(self.dir.path())
+            };
+            verifier.verify(policy, request(authorization)).await
+        }
+    }
+
+    fn tdx_policy() -> serde_json::Value {
+        serde_json::json!({
+            "version": 1,
+            "tee": "tdx",
+            "measurements": [
+                { "mr_td": Bytes::from([1; 48]) },
+                { "mr_td": Bytes::from(MR_TD), "rtmrs": [null, Bytes::from(RTMR)] },
+            ],
+            "max_duration": 30,
+        })
+    }
+
+    #[tokio::test]
+    async fn verify_tdx_quote() {
+        let platform = make_platform();
+        let quote = platform.tdx_quote(|q| q);
+
+        let verification = platform.verify(tdx_policy(), &quote).await.unwrap();
+        assert_eq!(verification.measurement, Some(1));
+        assert_eq!(verification.duration, Some(30));
+        assert_eq!(
+            verification.single_use.challenge,
+            Some(NonceId::new(NonceKind::Challenge, H256(NONCE)))
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_sgx_quote() {
+        let platform = make_platform();
+        let quote = platform.quote(
+            QuoteBuilder::sgx()
+                .report_data(&request_binding(2, &request(&[])).unwrap(), &NONCE)
+                .set(SGX_MR_ENCLAVE, &MR_ENCLAVE)
+                .set(SGX_ISV_SVN, &3u16.to_le_bytes()),
+        );
+        let policy = |min_isv_svn: u16| {
+            serde_json::json!({
+                "version": 1,
+                "tee": "sgx",
+                "measurements": [{ "mr_enclave": H256(MR_ENCLAVE), "min_isv_svn": min_isv_svn }],
+            })
+        };
+
+        let verification = platform.verify(policy(3), &quote).await.unwrap();
+        assert_eq!(verification.measurement, Some(0));
+        assert_eq!(verification.duration, Some(0));
+        assert!(matches!(
+            platform.verify(policy(4), &quote).await,
+            Err(Error::Unauthorized(_))
+        ));
+        assert!(matches!(
+            platform.verify(tdx_policy(), &quote).await,
+            Err(Error::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_quote() {
+        let platform = make_platform();
+        let quote = platform.tdx_quote(|q| q);
+
+        let mut tampered = quote.clone();
+        tampered[48 + TD_MR_TD] ^= 1;
+        assert!(matches!(
+            platform.verify(tdx_policy(), &tampered).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        let unbound = platform.tdx_quote(|q| q.report_data(&[0; 32], &NONCE));
+        assert!(matches!(
+            platform.verify(tdx_policy(), &unbound).await,
+            Err(Error::BindingMismatch(_))
+        ));
+
+        let unknown = platform.tdx_quote(|q| q.set(TD_RTMR0 + 48, &[0; 48]));
+        assert!(matches!(
+            platform.verify(tdx_policy(), &unknown).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let debug = platform.tdx_quote(|q| q.set(TD_ATTRIBUTES, &[1]));
+        assert!(matches!(
+            platform.verify(tdx_policy(), &debug).await,
+            Err(Error::Unauthorized(_))
+        ));
+        let mut debug_policy = tdx_policy();
+        debug_policy["allow_debug"] = true.into();
+        platform.verify(debug_policy, &debug).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_collateral() {
+        let platform = make_platform();
+        let quote = platform.tdx_quote(|q| q);
+
+        platform.write_collateral(Tee::Tdx, "OutOfDate", "2999-01-01T00:00:00Z");
+        assert!(matches!(
+            platform.verify(tdx_policy(), &quote).await,
+            Err(Error::Unauthorized(_))
+        ));
+        let mut lenient = tdx_policy();
+        lenient["tcb_statuses"] = serde_json::json!(["UpToDate", "OutOfDate"]);
+        platform.verify(lenient, &quote).await.unwrap();
+
+        // A TDX module below the TCB level falls to the next level.
+        let old_module = platform.tdx_quote(|q| q.set(TD_TEE_TCB_SVN, &[4; 16]));
+        platform.write_collateral(Tee::Tdx, "UpToDate", "2999-01-01T00:00:00Z");
+        assert!(matches!(
+            platform.verify(tdx_policy(), &old_module).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        platform.write_collateral(Tee::Tdx, "UpToDate", "2020-01-01T00:00:00Z");
+        assert!(matches!(
+            platform.verify(tdx_policy(), &quote).await,
+            Err(Error::Timing(_))
+        ));
+
+        std::fs::remove_dir_all(platform.dir.path().join("tdx")).unwrap();
+        assert!(matches!(
+            platform.verify(tdx_policy(), &quote).await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn check_revocation() {
+        let platform = make_platform();
+        let quote = platform.tdx_quote(|q| q);
+        platform.verify(tdx_policy(), &quote).await.unwrap();
+
+        platform.write_crls(&[PCK_SERIAL], 2999);
+        assert!(matches!(
+            platform.verify(tdx_policy(), &quote).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        platform.write_crls(&[], 2024);
+        assert!(matches!(
+            platform.verify(tdx_policy(), &quote).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        // The status of the PCK must be known.
+        platform.write_crls(&[], 2999);
+        std::fs::remove_file(platform.dir.path().join("crls/platform.crl")).unwrap();
+        assert!(matches!(
+            platform.verify(tdx_policy(), &quote).await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    /// A quote recorded on an SGX platform and the PCS CRLs of the time, from the `dcap-qvl`
+    /// sample data.
+    #[test]
+    fn verify_recorded_quote() {
+        let raw = include_bytes!("./testdata/sgx-quote.bin");
+        let crls = [
+            include_bytes!("./testdata/root.crl").to_vec(),
+            include_bytes!("./testdata/pck-processor.crl").to_vec(),
+        ];
+        let quote = Quote::parse(raw).unwrap();
+        let pck_chain = pem_certs(quote.pck_chain).unwrap();
+        let now = UnixTime::since_unix_epoch(std::time::Duration::from_secs(1_701_400_000));
+        DcapVerifier::verify_quote(&quote, ROOT_CA, &pck_chain, &crls, now).unwrap();
+
+        // The CRLs expire.
+        let later = UnixTime::since_unix_epoch(std::time::Duration::from_secs(1_704_067_200));
+        assert!(DcapVerifier::verify_quote(&quote, ROOT_CA, &pck_chain, &crls, later).is_err());
+        assert!(DcapVerifier::verify_quote(&quote, ROOT_CA, &pck_chain, &crls[..1], now).is_err());
+    }
+
+    #[tokio::test]
+    async fn untrusted_root() {
+        let platform = make_platform();
+        let quote = platform.tdx_quote(|q| q);
+        assert!(matches!(
+            DcapVerifier::with_collateral_dir(platform.dir.path())
+                .verify(tdx_policy(), request(&quote))
+                .await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    #[test]
+    fn validate_policy() {
+        let verifier = DcapVerifier::default();
+        verifier.validate(tdx_policy()).unwrap();
+        let mut bad = tdx_policy();
+        bad["version"] = 2.into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+        let mut bad = tdx_policy();
+        bad["tcb_statuses"] = serde_json::json!(["Revoked"]);
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = tdx_policy();
+        bad["measurements"][0]["mr_td"] = "0x1234".into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = tdx_policy();
+        bad["tee"] = "sev".into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let sgx = serde_json::json!({
+            "version": 1,
+            "tee": "sgx",
+            "measurements": [{ "isv_prod_id": 1 }],
+        });
+        assert!(matches!(
+            verifier.validate(sgx),
+            Err(Error::PolicyDecode(_))
+        ));
+    }
+}
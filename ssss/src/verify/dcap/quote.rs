@@ -0,0 +1,433 @@
+//! The ECDSA quote structures of the Intel SGX and TDX DCAP quote libraries.
+
+use anyhow::{bail, ensure};
+use serde::{Deserialize, Serialize};
+
+const HEADER_LEN: usize = 48;
+const SGX_REPORT_LEN: usize = 384;
+const TD_REPORT_LEN: usize = 584;
+const ATT_KEY_TYPE_ECDSA_P256: u16 = 2;
+const TEE_TYPE_SGX: u32 = 0;
+const TEE_TYPE_TDX: u32 = 0x81;
+const CERT_DATA_PCK_CHAIN: u16 = 5;
+const CERT_DATA_QE_REPORT: u16 = 6;
+
+pub(super) const SGX_MISC_SELECT: usize = 16;
+pub(super) const SGX_ATTRIBUTES: usize = 48;
+pub(super) const SGX_MR_ENCLAVE: usize = 64;
+pub(super) const SGX_MR_SIGNER: usize = 128;
+pub(super) const SGX_ISV_PROD_ID: usize = 256;
+pub(super) const SGX_ISV_SVN: usize = 258;
+pub(super) const SGX_REPORT_DATA: usize = 320;
+pub(super) const TD_TEE_TCB_SVN: usize = 0;
+pub(super) const TD_ATTRIBUTES: usize = 120;
+pub(super) const TD_MR_TD: usize = 136;
+pub(super) const TD_RTMR0: usize = 328;
+pub(super) const TD_REPORT_DATA: usize = 520;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Tee {
+    Sgx,
+    Tdx,
+}
+
+impl Tee {
+    /// The `id` of the TCB info that covers this TEE.
+    pub(super) fn tcb_info_id(&self) -> &'static str {
+        match self {
+            Self::Sgx => "SGX",
+            Self::Tdx => "TDX",
+        }
+    }
+
+    /// The `id` of the identity of the quoting enclave for this TEE.
+    pub(super) fn qe_identity_id(&self) -> &'static str {
+        match self {
+            Self::Sgx => "QE",
+            Self::Tdx => "TD_QE",
+        }
+    }
+}
+
+impl std::fmt::Display for Tee {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Sgx => "sgx",
+            Self::Tdx => "tdx",
+        })
+    }
+}
+
+pub(super) struct Quote<'a> {
+    /// The header and body, which are covered by the quote signature.
+    pub(super) signed: &'a [u8],
+    pub(super) body: Body<'a>,
+    /// The P-256 signature (`r || s`) made by the attestation key.
+    pub(super) signature: &'a [u8; 64],
+    /// The uncompressed P-256 attestation key (`x || y`).
+    pub(super) attestation_key: &'a [u8; 64],
+    /// The report of the quoting enclave, whose report data binds the attestation key.
+    pub(super) qe_report: SgxReport<'a>,
+    /// The P-256 signature (`r || s`) made over the QE report by the PCK.
+    pub(super) qe_report_signature: &'a [u8; 64],
+    pub(super) qe_auth_data: &'a [u8],
+    /// The PEM-encoded PCK certificate chain, leaf first.
+    pub(super) pck_chain: &'a [u8],
+}
+
+impl<'a> Quote<'a> {
+    pub(super) fn parse(raw: &'a [u8]) -> Result<Self, anyhow::Error> {
+        let mut r = Reader(raw);
+        let version = r.u16()?;
+        let key_type = r.u16()?;
+        let tee_type = r.u32()?;
+        r.take(HEADER_LEN - 8)?;
+        ensure!(
+            key_type == ATT_KEY_TYPE_ECDSA_P256,
+            "unsupported attestation key type {key_type}"
+        );
+        let body = match (version, tee_type) {
+            (3 | 4, TEE_TYPE_SGX) => Body::Sgx(SgxReport::parse(r.array()?)),
+            (4, TEE_TYPE_TDX) => Body::Tdx(TdReport::parse(r.array()?)),
+            _ => bail!("unsupported quote version {version} for TEE type {tee_type:#x}"),
+        };
+        let signed = &raw[..raw.len() - r.0.len()];
+
+        let sig_data_len = r.u32()? as usize;
+        let mut sig_data = Reader(r.take(sig_data_len)?);
+        ensure!(r.0.is_empty(), "quote has trailing data");
+        let signature = sig_data.array()?;
+        let attestation_key = sig_data.array()?;
+        let mut qe_data = if version == 3 {
+            sig_data
+        } else {
+            let cert_type = sig_data.u16()?;
+            ensure!(
+                cert_type == CERT_DATA_QE_REPORT,
+                "unsupported certification data type {cert_type}"
+            );
+            let len = sig_data.u32()? as usize;
+            Reader(sig_data.take(len)?)
+        };
+        let qe_report = SgxReport::parse(qe_data.array()?);
+        let qe_report_signature = qe_data.array()?;
+        let auth_len = qe_data.u16()? as usize;
+        let qe_auth_data = qe_data.take(auth_len)?;
+        let cert_type = qe_data.u16()?;
+        ensure!(
+            cert_type == CERT_DATA_PCK_CHAIN,
+            "unsupported QE certification data type {cert_type}"
+        );
+        let len = qe_data.u32()? as usize;
+        let pck_chain = qe_data.take(len)?;
+
+        Ok(Self {
+            signed,
+            body,
+            signature,
+            attestation_key,
+            qe_report,
+            qe_report_signature,
+            qe_auth_data,
+            pck_chain,
+        })
+    }
+}
+
+pub(super) enum Body<'a> {
+    Sgx(SgxReport<'a>),
+    Tdx(TdReport<'a>),
+}
+
+impl<'a> Body<'a> {
+    pub(super) fn tee(&self) -> Tee {
+        match self {
+            Self::Sgx(_) => Tee::Sgx,
+            Self::Tdx(_) => Tee::Tdx,
+        }
+    }
+
+    pub(super) fn report_data(&self) -> &'a [u8; 64] {
+        match self {
+            Self::Sgx(r) => r.report_data,
+            Self::Tdx(r) => r.report_data,
+        }
+    }
+
+    /// The first half of the report data, which binds the quote to a request.
+    pub(super) fn binding(&self) -> &'a [u8] {
+        &self.report_data()[..32]
+    }
+
+    /// The second half of the report data, which carries a challenge.
+    pub(super) fn nonce(&self) -> &'a [u8] {
+        &self.report_data()[32..]
+    }
+
+    pub(super) fn debug(&self) -> bool {
+        match self {
+            Self::Sgx(r) => r.debug(),
+            Self::Tdx(r) => r.debug(),
+        }
+    }
+
+    /// The SVNs of the TDX module, which are checked against the TCB info.
+    pub(super) fn tee_tcb_svn(&self) -> Option<&'a [u8; 16]> {
+        match self {
+            Self::Sgx(_) => None,
+            Self::Tdx(r) => Some(r.tee_tcb_svn),
+        }
+    }
+
+    pub(super) fn measurement_json(&self) -> serde_json::Value {
+        match self {
+            Self::Sgx(r) => serde_json::json!({
+                "mr_enclave": hex::encode(r.mr_enclave),
+                "mr_signer": hex::encode(r.mr_signer),
+                "isv_prod_id": r.isv_prod_id,
+                "isv_svn": r.isv_svn,
+            }),
+            Self::Tdx(r) => serde_json::json!({
+                "mr_td": hex::encode(r.mr_td),
+                "rtmrs": r.rtmrs.iter().map(hex::encode).collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+/// An SGX enclave report, which is the body of SGX quotes and the QE report of all quotes.
+pub(super) struct SgxReport<'a> {
+    pub(super) raw: &'a [u8; SGX_REPORT_LEN],
+    pub(super) misc_select: u32,
+    pub(super) attributes: &'a [u8; 16],
+    pub(super) mr_enclave: &'a [u8; 32],
+    pub(super) mr_signer: &'a [u8; 32],
+    pub(super) isv_prod_id: u16,
+    pub(super) isv_svn: u16,
+    pub(super) report_data: &'a [u8; 64],
+}
+
+impl<'a> SgxReport<'a> {
+    fn parse(raw: &'a [u8; SGX_REPORT_LEN]) -> Self {
+        Self {
+            raw,
+            misc_select: u32::from_le_bytes(*array_at(raw, SGX_MISC_SELECT)),
+            attributes: array_at(raw, SGX_ATTRIBUTES),
+            mr_enclave: array_at(raw, SGX_MR_ENCLAVE),
+            mr_signer: array_at(raw, SGX_MR_SIGNER),
+            isv_prod_id: u16::from_le_bytes(*array_at(raw, SGX_ISV_PROD_ID)),
+            isv_svn: u16::from_le_bytes(*array_at(raw, SGX_ISV_SVN)),
+            report_data: array_at(raw, SGX_REPORT_DATA),
+        }
+    }
+
+    fn debug(&self) -> bool {
+        self.attributes[0] & 0x02 != 0
+    }
+}
+
+/// A TDX TD report body (TDX 1.0 layout).
+pub(super) struct TdReport<'a> {
+    pub(super) tee_tcb_svn: &'a [u8; 16],
+    pub(super) td_attributes: u64,
+    pub(super) mr_td: &'a [u8; 48],
+    pub(super) rtmrs: [&'a [u8; 48]; 4],
+    pub(super) report_data: &'a [u8; 64],
+}
+
+impl<'a> TdReport<'a> {
+    fn parse(raw: &'a [u8; TD_REPORT_LEN]) -> Self {
+        Self {
+            tee_tcb_svn: array_at(raw, TD_TEE_TCB_SVN),
+            td_attributes: u64::from_le_bytes(*array_at(raw, TD_ATTRIBUTES)),
+            mr_td: array_at(raw, TD_MR_TD),
+            rtmrs: std::array::from_fn(|i| array_at(raw, TD_RTMR0 + i * 48)),
+            report_data: array_at(raw, TD_REPORT_DATA),
+        }
+    }
+
+    fn debug(&self) -> bool {
+        self.td_attributes & 1 != 0
+    }
+}
+
+fn array_at<const N: usize>(raw: &[u8], offset: usize) -> &[u8; N] {
+    raw[offset..offset + N].try_into().unwrap()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], anyhow::Error> {
+        ensure!(self.0.len() >= n, "quote is truncated");
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<&'a [u8; N], anyhow::Error> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> Result<u16, anyhow::Error> {
+        Ok(u16::from_le_bytes(*self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, anyhow::Error> {
+        Ok(u32::from_le_bytes(*self.array()?))
+    }
+}
+
+/// Builds quotes in tests.
+#[cfg(test)]
+pub(super) struct QuoteBuilder {
+    version: u16,
+    tee_type: u32,
+    body: Vec<u8>,
+    qe_report: Vec<u8>,
+    qe_auth_data: Vec<u8>,
+    pck_chain: Vec<u8>,
+}
+
+#[cfg(test)]
+impl QuoteBuilder {
+    pub(super) fn sgx() -> Self {
+        Self::new(3, TEE_TYPE_SGX, SGX_REPORT_LEN)
+    }
+
+    pub(super) fn tdx() -> Self {
+        Self::new(4, TEE_TYPE_TDX, TD_REPORT_LEN)
+    }
+
+    fn new(version: u16, tee_type: u32, body_len: usize) -> Self {
+        Self {
+            version,
+            tee_type,
+            body: vec![0; body_len],
+            qe_report: vec![0; SGX_REPORT_LEN],
+            qe_auth_data: (0..32).collect(),
+            pck_chain: vec![],
+        }
+    }
+
+    pub(super) fn set(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.body[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub(super) fn report_data(self, binding: &[u8; 32], nonce: &[u8; 32]) -> Self {
+        let offset = match self.tee_type {
+            TEE_TYPE_SGX => SGX_REPORT_DATA,
+            _ => TD_REPORT_DATA,
+        };
+        self.set(offset, binding).set(offset + 32, nonce)
+    }
+
+    pub(super) fn qe_report(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.qe_report[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub(super) fn pck_chain(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.pck_chain = pem.into();
+        self
+    }
+
+    /// Signs the quote using functions that return P-256 `r || s` signatures made by the
+    /// attestation key and the PCK, respectively.
+    pub(super) fn sign(
+        mut self,
+        attestation_key: &[u8; 64],
+        sign_quote: impl FnOnce(&[u8]) -> Vec<u8>,
+        sign_qe_report: impl FnOnce(&[u8]) -> Vec<u8>,
+    ) -> Vec<u8> {
+        use sha2::{Digest as _, Sha256};
+
+        let key_hash = Sha256::new()
+            .chain_update(attestation_key)
+            .chain_update(&self.qe_auth_data)
+            .finalize();
+        self = self.qe_report(SGX_REPORT_DATA, &key_hash);
+
+        let mut quote = Vec::new();
+        quote.extend(self.version.to_le_bytes());
+        quote.extend(ATT_KEY_TYPE_ECDSA_P256.to_le_bytes());
+        quote.extend(self.tee_type.to_le_bytes());
+        quote.resize(HEADER_LEN, 0);
+        quote.extend(&self.body);
+
+        let mut qe_data = self.qe_report.clone();
+        qe_data.extend(sign_qe_report(&self.qe_report));
+        qe_data.extend((self.qe_auth_data.len() as u16).to_le_bytes());
+        qe_data.extend(&self.qe_auth_data);
+        qe_data.extend(CERT_DATA_PCK_CHAIN.to_le_bytes());
+        qe_data.extend((self.pck_chain.len() as u32).to_le_bytes());
+        qe_data.extend(&self.pck_chain);
+
+        let mut sig_data = sign_quote(&quote);
+        sig_data.extend(attestation_key);
+        if self.version != 3 {
+            sig_data.extend(CERT_DATA_QE_REPORT.to_le_bytes());
+            sig_data.extend((qe_data.len() as u32).to_le_bytes());
+        }
+        sig_data.extend(qe_data);
+
+        quote.extend((sig_data.len() as u32).to_le_bytes());
+        quote.extend(sig_data);
+        quote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(quote: QuoteBuilder) -> Vec<u8> {
+        quote
+            .report_data(&[1; 32], &[2; 32])
+            .qe_report(SGX_ISV_SVN, &8u16.to_le_bytes())
+            .pck_chain("chain")
+            .sign(&[3; 64], |_| vec![4; 64], |_| vec![5; 64])
+    }
+
+    #[test]
+    fn parse_quote() {
+        let raw = sign(QuoteBuilder::sgx().set(SGX_MR_ENCLAVE, &[6; 32]));
+        let quote = Quote::parse(&raw).unwrap();
+        let Body::Sgx(body) = &quote.body else {
+            panic!("not an SGX quote");
+        };
+        assert_eq!(body.mr_enclave, &[6; 32]);
+        assert_eq!(quote.body.binding(), [1; 32]);
+        assert_eq!(quote.body.nonce(), [2; 32]);
+        assert_eq!(quote.signed.len(), HEADER_LEN + SGX_REPORT_LEN);
+        assert_eq!(quote.signature, &[4; 64]);
+        assert_eq!(quote.attestation_key, &[3; 64]);
+        assert_eq!(quote.qe_report_signature, &[5; 64]);
+        assert_eq!(quote.qe_report.isv_svn, 8);
+        assert_eq!(quote.qe_auth_data.len(), 32);
+        assert_eq!(quote.pck_chain, b"chain");
+
+        let raw = sign(
+            QuoteBuilder::tdx()
+                .set(TD_RTMR0 + 48, &[7; 48])
+                .set(TD_ATTRIBUTES, &[1]),
+        );
+        let quote = Quote::parse(&raw).unwrap();
+        let Body::Tdx(body) = &quote.body else {
+            panic!("not a TDX quote");
+        };
+        assert_eq!(body.rtmrs[1], &[7; 48]);
+        assert!(quote.body.debug());
+        assert_eq!(quote.body.tee(), Tee::Tdx);
+        assert_eq!(quote.signed.len(), HEADER_LEN + TD_REPORT_LEN);
+        assert_eq!(quote.pck_chain, b"chain");
+
+        assert!(Quote::parse(&raw[..raw.len() - 1]).is_err());
+        assert!(Quote::parse(&[raw.as_slice(), &[0]].concat()).is_err());
+        let mut v3_tdx = raw.clone();
+        v3_tdx[0] = 3;
+        assert!(Quote::parse(&v3_tdx).is_err());
+    }
+}
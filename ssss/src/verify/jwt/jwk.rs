@@ -0,0 +1,127 @@
+//! JSON Web Keys (RFC 7517) and the JWS algorithms that can be verified with them.
+
+use anyhow::{anyhow, bail, ensure};
+use base64::prelude::*;
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+pub(super) struct Jwks {
+    pub(super) keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    pub(super) fn validate(&self) -> Result<(), anyhow::Error> {
+        ensure!(!self.keys.is_empty(), "JWKS has no keys");
+        for (i, key) in self.keys.iter().enumerate() {
+            key.validate().map_err(|e| {
+                anyhow!("key {}: {e}", key.kid.as_deref().unwrap_or(&i.to_string()))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the key that the token header names, or the only key if the header names none.
+    pub(super) fn find(&self, kid: Option<&str>) -> Result<&Jwk, anyhow::Error> {
+        match (kid, self.keys.as_slice()) {
+            (Some(kid), keys) => keys
+                .iter()
+                .find(|k| k.kid.as_deref() == Some(kid))
+                .ok_or_else(|| anyhow!("unknown key `{kid}`")),
+            (None, [key]) => Ok(key),
+            (None, _) => bail!("token does not name its key"),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub(super) struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    alg: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+impl Jwk {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        match (self.kty.as_str(), self.crv.as_deref()) {
+            ("RSA", _) => {
+                param(&self.n, "n")?;
+                param(&self.e, "e")?;
+            }
+            ("EC", Some("P-256" | "P-384")) => {
+                param(&self.x, "x")?;
+                param(&self.y, "y")?;
+            }
+            ("OKP", Some("Ed25519")) => {
+                param(&self.x, "x")?;
+            }
+            (kty, crv) => bail!("unsupported key type {kty} {}", crv.unwrap_or_default()),
+        }
+        Ok(())
+    }
+
+    /// Verifies a JWS signature made using the algorithm named by the token header.
+    pub(super) fn verify(
+        &self,
+        alg: &str,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        if let Some(key_alg) = &self.alg {
+            ensure!(key_alg == alg, "key is for {key_alg} not {alg}");
+        }
+        let rsa = |params: &'static signature::RsaParameters| {
+            RsaPublicKeyComponents {
+                n: param(&self.n, "n")?,
+                e: param(&self.e, "e")?,
+            }
+            .verify(params, message, signature)
+            .map_err(|_| anyhow!("invalid token signature"))
+        };
+        let ec = |alg: &'static dyn signature::VerificationAlgorithm| {
+            let mut point = vec![0x04];
+            point.extend(param(&self.x, "x")?);
+            point.extend(param(&self.y, "y")?);
+            UnparsedPublicKey::new(alg, point)
+                .verify(message, signature)
+                .map_err(|_| anyhow!("invalid token signature"))
+        };
+        match (self.kty.as_str(), self.crv.as_deref(), alg) {
+            ("RSA", _, "RS256") => rsa(&signature::RSA_PKCS1_2048_8192_SHA256),
+            ("RSA", _, "RS384") => rsa(&signature::RSA_PKCS1_2048_8192_SHA384),
+            ("RSA", _, "RS512") => rsa(&signature::RSA_PKCS1_2048_8192_SHA512),
+            ("RSA", _, "PS256") => rsa(&signature::RSA_PSS_2048_8192_SHA256),
+            ("RSA", _, "PS384") => rsa(&signature::RSA_PSS_2048_8192_SHA384),
+            ("RSA", _, "PS512") => rsa(&signature::RSA_PSS_2048_8192_SHA512),
+            ("EC", Some("P-256"), "ES256") => ec(&signature::ECDSA_P256_SHA256_FIXED),
+            ("EC", Some("P-384"), "ES384") => ec(&signature::ECDSA_P384_SHA384_FIXED),
+            ("OKP", Some("Ed25519"), "EdDSA") => {
+                UnparsedPublicKey::new(&signature::ED25519, param(&self.x, "x")?)
+                    .verify(message, signature)
+                    .map_err(|_| anyhow!("invalid token signature"))
+            }
+            (kty, _, alg) => bail!("unsupported algorithm {alg} for {kty} key"),
+        }
+    }
+}
+
+fn param(value: &Option<String>, name: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let value = value
+        .as_deref()
+        .ok_or_else(|| anyhow!("missing `{name}`"))?;
+    BASE64_URL_SAFE_NO_PAD
+        .decode(value)
+        .map_err(|e| anyhow!("invalid `{name}`: {e}"))
+}
@@ -0,0 +1,698 @@
+mod jwk;
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, ensure, Context as _};
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use smallvec::SmallVec;
+
+use self::jwk::Jwks;
+use super::*;
+use crate::types::NonceKind;
+
+/// Verifies OIDC ID tokens and other signed JWTs, such as those issued to GitHub Actions jobs
+/// and Kubernetes service accounts.
+///
+/// The authorization is the compact-serialized token. The policy's binding claim (`nonce` by
+/// default) must contain the version 2 request binding as a hex string. Policies name their
+/// issuer's keys either inline or as a JWKS file in the configured JWKS directory.
+#[derive(Clone, Debug, Default)]
+pub struct JwtVerifier {
+    jwks_dir: Option<Arc<PathBuf>>,
+}
+
+impl JwtVerifier {
+    pub fn with_jwks_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            jwks_dir: Some(Arc::new(dir.into())),
+        }
+    }
+
+    fn load_jwks<'a>(&self, source: &'a KeySource) -> Result<Cow<'a, Jwks>, anyhow::Error> {
+        match source {
+            KeySource::Jwks(jwks) => Ok(Cow::Borrowed(jwks)),
+            KeySource::JwksFile(name) => {
+                let dir = self
+                    .jwks_dir
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("no JWKS directory is configured"))?;
+                let path = dir.join(name);
+                let jwks: Jwks = serde_json::from_slice(
+                    &std::fs::read(&path).with_context(|| format!("failed to read {path:?}"))?,
+                )
+                .with_context(|| format!("failed to parse {path:?}"))?;
+                jwks.validate()?;
+                Ok(Cow::Owned(jwks))
+            }
+        }
+    }
+}
+
+impl Verifier for JwtVerifier {
+    fn name(&self) -> &str {
+        "jwt"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "JWT policy",
+            "type": "object",
+            "required": ["version", "issuer"],
+            "oneOf": [{ "required": ["jwks"] }, { "required": ["jwks_file"] }],
+            "additionalProperties": false,
+            "properties": {
+                "version": { "const": 1 },
+                "issuer": { "type": "string", "minLength": 1 },
+                "jwks": {
+                    "type": "object",
+                    "required": ["keys"],
+                    "properties": { "keys": { "type": "array", "minItems": 1 } },
+                },
+                "jwks_file": {
+                    "description": "The name of a JWKS file in the SSSS's JWKS directory",
+                    "type": "string",
+                    "pattern": "^[A-Za-z0-9_.-]+$",
+                },
+                "audience": { "type": "string" },
+                "claims": {
+                    "description": "Claims that must equal the value or one of the listed values",
+                    "type": "object",
+                },
+                "binding_claim": { "type": "string", "default": "nonce" },
+                "leeway": { "type": "integer", "minimum": 0, "default": 60 },
+                "max_duration": { "type": "integer", "minimum": 0 },
+                "relayers": {
+                    "type": "array",
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        Policy::decode(raw_policy).map(|_| ())
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        report.check(
+            EvaluationStep::new("relayer")
+                .expected(&policy.relayers)
+                .observed(req.relayer),
+            check_relayer(&policy.relayers, req.relayer),
+        )?;
+
+        let token = match Token::decode(req.authorization) {
+            Ok(token) => token,
+            Err(e) => {
+                return Err(report.fail(EvaluationStep::new("token"), Error::AttestationDecode(e)))
+            }
+        };
+
+        let step = EvaluationStep::new("signature").observed(serde_json::json!({
+            "alg": token.header.alg,
+            "kid": token.header.kid,
+        }));
+        let verified = self.load_jwks(&policy.keys).and_then(|jwks| {
+            jwks.find(token.header.kid.as_deref())?.verify(
+                &token.header.alg,
+                token.signing_input.as_bytes(),
+                &token.signature,
+            )
+        });
+        match verified {
+            Ok(()) => report.pass(step),
+            Err(e) => return Err(report.fail(step, Error::AttestationDecode(e))),
+        }
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let exp = token.claims.get("exp").and_then(Value::as_u64);
+        let nbf = token.claims.get("nbf").and_then(Value::as_u64);
+        report.check(
+            EvaluationStep::new("timing")
+                .expected(serde_json::json!({ "leeway": policy.leeway }))
+                .observed(serde_json::json!({ "exp": exp, "nbf": nbf, "now": now })),
+            match (exp, nbf) {
+                (None, _) => Err(Error::Timing("token does not expire".into())),
+                (Some(exp), _) if exp.saturating_add(policy.leeway) <= now => {
+                    Err(Error::Timing("token expired".into()))
+                }
+                (_, Some(nbf)) if nbf > now.saturating_add(policy.leeway) => {
+                    Err(Error::Timing("token not yet valid".into()))
+                }
+                _ => Ok(()),
+            },
+        )?;
+
+        let issuer = token.claims.get("iss");
+        report.check(
+            EvaluationStep::new("issuer")
+                .expected(&policy.issuer)
+                .observed(issuer),
+            if issuer.and_then(Value::as_str) == Some(policy.issuer.as_str()) {
+                Ok(())
+            } else {
+                Err(Error::Unauthorized("untrusted token issuer".into()))
+            },
+        )?;
+
+        if let Some(audience) = &policy.audience {
+            let aud = token.claims.get("aud");
+            report.check(
+                EvaluationStep::new("audience")
+                    .expected(audience)
+                    .observed(aud),
+                if aud.is_some_and(|aud| claim_contains(aud, |v| v == audience)) {
+                    Ok(())
+                } else {
+                    Err(Error::Unauthorized(
+                        "token is not intended for this audience".into(),
+                    ))
+                },
+            )?;
+        }
+
+        let binding_bytes = match request_binding(2, &req) {
+            Ok(binding) => binding,
+            Err(e) => return Err(report.fail(EvaluationStep::new("binding"), e)),
+        };
+        let expected_binding = hex::encode(binding_bytes);
+        let binding = token.claims.get(&policy.binding_claim);
+        report.check(
+            EvaluationStep::new("binding")
+                .expected(serde_json::json!({
+                    "claim": policy.binding_claim,
+                    "binding": format!("0x{expected_binding}"),
+                }))
+                .observed(binding),
+            match binding {
+                None => Err(Error::InvalidBinding),
+                Some(binding) => {
+                    if claim_contains(binding, |v| {
+                        v.strip_prefix("0x")
+                            .unwrap_or(v)
+                            .eq_ignore_ascii_case(&expected_binding)
+                    }) {
+                        Ok(())
+                    } else {
+                        Err(Error::BindingMismatch(SmallVec::from_buf(binding_bytes)))
+                    }
+                }
+            },
+        )?;
+
+        let mismatched: Vec<_> = policy
+            .claims
+            .iter()
+            .filter(|(name, matcher)| {
+                !token
+                    .claims
+                    .get(name.as_str())
+                    .is_some_and(|claim| matcher.matches(claim))
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+        report.check(
+            EvaluationStep::new("claims")
+                .expected(&policy.claims)
+                .observed(
+                    policy
+                        .claims
+                        .keys()
+                        .map(|name| (name, token.claims.get(name)))
+                        .collect::<BTreeMap<_, _>>(),
+                ),
+            match mismatched.first() {
+                None => Ok(()),
+                Some(name) => Err(Error::Unauthorized(format!(
+                    "claim `{name}` does not match"
+                ))),
+            },
+        )?;
+
+        // A token can be presented only once while it is valid.
+        let single_use = SingleUse {
+            nonces: vec![NonceId::new(
+                NonceKind::Document,
+                keccak256(token.signing_input.as_bytes()).into(),
+            )],
+            expiry: exp.unwrap_or_default().saturating_add(policy.leeway),
+            challenge: None,
+        };
+
+        report.finish(Verification {
+            nonce: vec![],
+            public_key: vec![],
+            duration: match req.kind {
+                RequestKind::Grant { duration } => Some(duration.min(policy.max_duration)),
+                RequestKind::Revoke => None,
+            },
+            measurement: None,
+            single_use,
+        })
+    }
+}
+
+/// Returns whether the claim, or any element of an array claim, is a matching string.
+fn claim_contains(claim: &Value, matches: impl Fn(&str) -> bool) -> bool {
+    match claim {
+        Value::String(s) => matches(s),
+        Value::Array(values) => values.iter().filter_map(Value::as_str).any(matches),
+        _ => false,
+    }
+}
+
+struct Token {
+    header: Header,
+    claims: serde_json::Map<String, Value>,
+    /// The encoded header and claims, over which the signature is made.
+    signing_input: String,
+    signature: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+impl Token {
+    fn decode(raw: &[u8]) -> Result<Self, anyhow::Error> {
+        let raw = std::str::from_utf8(raw)
+            .context("token is not UTF-8")?
+            .trim();
+        let (signing_input, signature) = raw
+            .rsplit_once('.')
+            .ok_or_else(|| anyhow!("token is not a compact JWS"))?;
+        let (header, claims) = signing_input
+            .split_once('.')
+            .ok_or_else(|| anyhow!("token is not a compact JWS"))?;
+        let header: Header = serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(header)?)
+            .context("malformed token header")?;
+        ensure!(header.alg != "none", "token is unsigned");
+        let claims = serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(claims)?)
+            .context("malformed token claims")?;
+        Ok(Self {
+            header,
+            claims,
+            signing_input: signing_input.into(),
+            signature: BASE64_URL_SAFE_NO_PAD.decode(signature)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    version: u8,
+    /// The `iss` claim of accepted tokens.
+    issuer: String,
+    #[serde(flatten)]
+    keys: KeySource,
+    /// A value that the `aud` claim must contain.
+    #[serde(default)]
+    audience: Option<String>,
+    #[serde(default)]
+    claims: BTreeMap<String, ClaimMatcher>,
+    /// The claim that carries the request binding.
+    #[serde(default = "default_binding_claim")]
+    binding_claim: String,
+    /// Allowed clock skew (seconds) when checking `exp` and `nbf`.
+    #[serde(default = "default_leeway")]
+    leeway: u64,
+    #[serde(default)]
+    max_duration: u64,
+    #[serde(default)]
+    relayers: HashSet<Address>,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        if policy.issuer.is_empty() {
+            return Err(Error::PolicyDecode(anyhow!("the issuer is required")));
+        }
+        match &policy.keys {
+            KeySource::Jwks(jwks) => jwks.validate().map_err(Error::PolicyDecode)?,
+            KeySource::JwksFile(name) => {
+                let valid = !name.is_empty()
+                    && !name.starts_with('.')
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+                if !valid {
+                    return Err(Error::PolicyDecode(anyhow!(
+                        "`jwks_file` must be the name of a file in the JWKS directory"
+                    )));
+                }
+            }
+        }
+        // Without an audience, a token issued to any other relying party would be accepted.
+        if policy.audience.is_none() && policy.binding_claim != "aud" {
+            return Err(Error::PolicyDecode(anyhow!(
+                "an audience is required unless the binding is the audience"
+            )));
+        }
+        Ok(policy)
+    }
+}
+
+fn default_binding_claim() -> String {
+    "nonce".into()
+}
+
+fn default_leeway() -> u64 {
+    60
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum KeySource {
+    Jwks(Jwks),
+    /// The name of a JWKS file in the JWKS directory.
+    JwksFile(String),
+}
+
+/// A claim value, or a list of values of which the claim must be one.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ClaimMatcher {
+    AnyOf(Vec<Value>),
+    Exact(Value),
+}
+
+impl ClaimMatcher {
+    fn matches(&self, claim: &Value) -> bool {
+        match self {
+            Self::AnyOf(values) => values.contains(claim),
+            Self::Exact(value) => value == claim,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair as _, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+
+    use super::*;
+    use crate::verify::tests::request;
+
+    const ISSUER: &str = "https://token.actions.githubusercontent.com";
+
+    fn b64(data: &[u8]) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(data)
+    }
+
+    fn encode(header: Value, claims: Value, sign: impl FnOnce(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let signing_input = format!(
+            "{}.{}",
+            b64(header.to_string().as_bytes()),
+            b64(claims.to_string().as_bytes())
+        );
+        let signature = sign(signing_input.as_bytes());
+        format!("{signing_input}.{}", b64(&signature)).into_bytes()
+    }
+
+    struct Issuer {
+        es256: EcdsaKeyPair,
+        ed25519: Ed25519KeyPair,
+    }
+
+    impl Issuer {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let es256 =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+            let ed25519 = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            Self { es256, ed25519 }
+        }
+
+        fn jwks(&self) -> Value {
+            let point = self.es256.public_key().as_ref();
+            serde_json::json!({
+                "keys": [
+                    {
+                        "kty": "EC",
+                        "kid": "ec",
+                        "crv": "P-256",
+                        "x": b64(&point[1..33]),
+                        "y": b64(&point[33..]),
+                    },
+                    {
+                        "kty": "OKP",
+                        "kid": "ed",
+                        "alg": "EdDSA",
+                        "crv": "Ed25519",
+                        "x": b64(self.ed25519.public_key().as_ref()),
+                    },
+                ],
+            })
+        }
+
+        fn token(&self, kid: &str, claims: Value) -> Vec<u8> {
+            match kid {
+                "ec" => encode(
+                    serde_json::json!({ "alg": "ES256", "kid": kid, "typ": "JWT" }),
+                    claims,
+                    |data| {
+                        self.es256
+                            .sign(&SystemRandom::new(), data)
+                            .unwrap()
+                            .as_ref()
+                            .to_vec()
+                    },
+                ),
+                _ => encode(
+                    serde_json::json!({ "alg": "EdDSA", "kid": kid }),
+                    claims,
+                    |data| self.ed25519.sign(data).as_ref().to_vec(),
+                ),
+            }
+        }
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn claims() -> Value {
+        serde_json::json!({
+            "iss": ISSUER,
+            "aud": ["ssss", "other"],
+            "sub": "repo:escrin/escrin:ref:refs/heads/main",
+            "repository": "escrin/escrin",
+            "ref": "refs/heads/main",
+            "nonce": format!("{:#x}", ethers::types::H256(request_binding(2, &request(&[])).unwrap())),
+            "iat": now(),
+            "exp": now() + 300,
+        })
+    }
+
+    fn policy(issuer: &Issuer) -> Value {
+        serde_json::json!({
+            "version": 1,
+            "issuer": ISSUER,
+            "jwks": issuer.jwks(),
+            "audience": "ssss",
+            "claims": {
+                "repository": "escrin/escrin",
+                "ref": ["refs/heads/main", "refs/heads/release"],
+            },
+            "max_duration": 30,
+        })
+    }
+
+    #[tokio::test]
+    async fn verify_token() {
+        let issuer = Issuer::new();
+        for kid in ["ec", "ed"] {
+            let token = issuer.token(kid, claims());
+            let verification = JwtVerifier::default()
+                .verify(policy(&issuer), request(&token))
+                .await
+                .unwrap();
+            assert_eq!(verification.duration, Some(30));
+            assert_eq!(verification.single_use.nonces.len(), 1);
+        }
+
+        // The binding may instead be the audience, as with GitHub Actions.
+        let mut claims = claims();
+        claims["aud"] = claims["nonce"].clone();
+        claims.as_object_mut().unwrap().remove("nonce");
+        let mut policy = policy(&issuer);
+        policy["binding_claim"] = "aud".into();
+        policy.as_object_mut().unwrap().remove("audience");
+        JwtVerifier::default()
+            .verify(policy, request(&issuer.token("ec", claims)))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verify_token_with_jwks_file() {
+        let issuer = Issuer::new();
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("github.json"), issuer.jwks().to_string()).unwrap();
+        let mut policy = policy(&issuer);
+        policy.as_object_mut().unwrap().remove("jwks");
+        policy["jwks_file"] = "github.json".into();
+
+        let token = issuer.token("ec", claims());
+        JwtVerifier::with_jwks_dir(dir.path())
+            .verify(policy.clone(), request(&token))
+            .await
+            .unwrap();
+        assert!(matches!(
+            JwtVerifier::default().verify(policy, request(&token)).await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_token() {
+        let issuer = Issuer::new();
+        let verify = |claims: Value| {
+            let token = issuer.token("ec", claims);
+            let policy = policy(&issuer);
+            async move { JwtVerifier::default().verify(policy, request(&token)).await }
+        };
+
+        let mut forged = issuer.token("ec", claims());
+        let len = forged.len();
+        forged[len - 2] ^= 1;
+        assert!(matches!(
+            JwtVerifier::default()
+                .verify(policy(&issuer), request(&forged))
+                .await,
+            Err(Error::AttestationDecode(_))
+        ));
+        let other = Issuer::new().token("ec", claims());
+        assert!(matches!(
+            JwtVerifier::default()
+                .verify(policy(&issuer), request(&other))
+                .await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        let mut expired = claims();
+        expired["exp"] = (now() - 120).into();
+        assert!(matches!(verify(expired).await, Err(Error::Timing(_))));
+
+        let mut wrong_issuer = claims();
+        wrong_issuer["iss"] = "https://example.com".into();
+        assert!(matches!(
+            verify(wrong_issuer).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let mut wrong_audience = claims();
+        wrong_audience["aud"] = "other".into();
+        assert!(matches!(
+            verify(wrong_audience).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let mut unbound = claims();
+        unbound["nonce"] = format!("{:#x}", ethers::types::H256::zero()).into();
+        assert!(matches!(
+            verify(unbound).await,
+            Err(Error::BindingMismatch(_))
+        ));
+
+        let mut wrong_ref = claims();
+        wrong_ref["ref"] = "refs/heads/feature".into();
+        assert!(matches!(
+            verify(wrong_ref).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let unsigned = encode(serde_json::json!({ "alg": "none" }), claims(), |_| vec![]);
+        assert!(matches!(
+            JwtVerifier::default()
+                .verify(policy(&issuer), request(&unsigned))
+                .await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    #[test]
+    fn validate_policy() {
+        let issuer = Issuer::new();
+        let verifier = JwtVerifier::default();
+        verifier.validate(policy(&issuer)).unwrap();
+        let mut bad = policy(&issuer);
+        bad["version"] = 2.into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+        let mut bad = policy(&issuer);
+        bad.as_object_mut().unwrap().remove("audience");
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = policy(&issuer);
+        bad.as_object_mut().unwrap().remove("jwks");
+        bad["jwks_file"] = "../keys.json".into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = policy(&issuer);
+        bad["jwks"]["keys"][0]["kty"] = "oct".into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+    }
+}
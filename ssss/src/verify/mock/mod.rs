@@ -0,0 +1,308 @@
+use ethers::types::Bytes;
+use serde::Deserialize;
+
+use super::*;
+
+/// Grants every request with the values that its policy chooses, or fails in the way that its
+/// policy chooses, without checking any authorization. Only for integration testing clients.
+///
+/// It is compiled in with the `mock` feature and must also be enabled when the SSSS starts.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MockVerifier;
+
+impl Verifier for MockVerifier {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        let message = serde_json::json!({ "type": "string" });
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Mock policy",
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "version": { "const": 1 },
+                "nonce": {
+                    "description": "The nonce to return instead of one derived from the time",
+                    "type": "string",
+                    "pattern": "^0x([0-9a-fA-F]{2})*$",
+                },
+                "public_key": { "type": "string", "pattern": "^0x([0-9a-fA-F]{2})*$" },
+                "duration": {
+                    "description": "The duration to grant instead of the requested one",
+                    "type": "integer",
+                    "minimum": 0,
+                },
+                "fail": {
+                    "description": "The error with which to reject every request",
+                    "oneOf": [
+                        {
+                            "properties": {
+                                "kind": { "const": "attestation-decode" },
+                                "message": message,
+                            },
+                        },
+                        { "properties": { "kind": { "const": "invalid-binding" } } },
+                        {
+                            "properties": {
+                                "kind": { "const": "binding-mismatch" },
+                                "expected": { "type": "string" },
+                            },
+                        },
+                        {
+                            "properties": {
+                                "kind": { "const": "pcr-mismatch" },
+                                "index": { "type": "integer", "minimum": 0 },
+                            },
+                        },
+                        {
+                            "properties": {
+                                "kind": { "const": "unauthorized" },
+                                "message": message,
+                            },
+                        },
+                        {
+                            "properties": { "kind": { "const": "timing" }, "message": message },
+                        },
+                    ],
+                    "required": ["kind"],
+                },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        Policy::decode(raw_policy).map(|_| ())
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        if let Some(failure) = policy.fail {
+            return Err(report.fail(EvaluationStep::new("mock"), failure.into()));
+        }
+        report.pass(EvaluationStep::new("mock"));
+
+        let nonce = match policy.nonce {
+            Some(nonce) => nonce.to_vec(),
+            None => keccak256(H256::from_low_u64_ne(
+                (crate::utils::now() >> 1) << 1, // permit up to 2s drift
+            ))
+            .into(),
+        };
+        Ok(Verification {
+            nonce,
+            public_key: policy.public_key.to_vec(),
+            duration: match req.kind {
+                RequestKind::Grant { duration } => Some(policy.duration.unwrap_or(duration)),
+                RequestKind::Revoke => None,
+            },
+            measurement: None,
+            single_use: Default::default(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    #[serde(default = "default_version")]
+    version: u8,
+    #[serde(default)]
+    nonce: Option<Bytes>,
+    #[serde(default)]
+    public_key: Bytes,
+    #[serde(default)]
+    duration: Option<u64>,
+    #[serde(default)]
+    fail: Option<Failure>,
+}
+
+/// Policies written before the mock verifier was configurable have no version.
+fn default_version() -> u8 {
+    1
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        Ok(policy)
+    }
+}
+
+/// The verification errors that a policy can make the mock verifier return.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum Failure {
+    AttestationDecode {
+        #[serde(default)]
+        message: String,
+    },
+    InvalidBinding,
+    BindingMismatch {
+        expected: Bytes,
+    },
+    PcrMismatch {
+        index: usize,
+    },
+    Unauthorized {
+        #[serde(default)]
+        message: String,
+    },
+    Timing {
+        #[serde(default)]
+        message: String,
+    },
+}
+
+impl From<Failure> for Error {
+    fn from(failure: Failure) -> Self {
+        match failure {
+            Failure::AttestationDecode { message } => {
+                Error::AttestationDecode(anyhow::Error::msg(message))
+            }
+            Failure::InvalidBinding => Error::InvalidBinding,
+            Failure::BindingMismatch { expected } => {
+                Error::BindingMismatch(smallvec::SmallVec::from_slice(&expected))
+            }
+            Failure::PcrMismatch { index } => Error::PcrMismatch(index),
+            Failure::Unauthorized { message } => Error::Unauthorized(message),
+            Failure::Timing { message } => Error::Timing(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::IdentityId;
+
+    fn request(kind: RequestKind) -> VerificationRequest<'static> {
+        VerificationRequest {
+            kind,
+            identity: IdentityLocator {
+                chain: 31337,
+                registry: Address::repeat_byte(1),
+                id: IdentityId(H256::repeat_byte(2)),
+            },
+            permitter: Address::repeat_byte(3),
+            recipient: Address::repeat_byte(4),
+            base_block: 100,
+            authorization: b"",
+            context: b"",
+            relayer: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn default_policy() {
+        let grant = RequestKind::Grant { duration: 42 };
+        let verification = MockVerifier
+            .verify(serde_json::json!({}), request(grant))
+            .await
+            .unwrap();
+        assert_eq!(verification.nonce.len(), 32);
+        assert!(verification.public_key.is_empty());
+        assert_eq!(verification.duration, Some(42));
+
+        let verification = MockVerifier
+            .verify(serde_json::json!({}), request(RequestKind::Revoke))
+            .await
+            .unwrap();
+        assert_eq!(verification.duration, None);
+    }
+
+    #[tokio::test]
+    async fn fixed_values() {
+        let policy = serde_json::json!({
+            "version": 1,
+            "nonce": "0x0102",
+            "public_key": "0x0304",
+            "duration": 7,
+        });
+        let verification = MockVerifier
+            .verify(policy, request(RequestKind::Grant { duration: 42 }))
+            .await
+            .unwrap();
+        assert_eq!(verification.nonce, [1, 2]);
+        assert_eq!(verification.public_key, [3, 4]);
+        assert_eq!(verification.duration, Some(7));
+    }
+
+    #[tokio::test]
+    async fn forced_failures() {
+        let verify = |fail: serde_json::Value| {
+            MockVerifier.verify(
+                serde_json::json!({ "version": 1, "fail": fail }),
+                request(RequestKind::Revoke),
+            )
+        };
+        assert!(matches!(
+            verify(serde_json::json!({ "kind": "pcr-mismatch", "index": 4 })).await,
+            Err(Error::PcrMismatch(4))
+        ));
+        assert!(matches!(
+            verify(serde_json::json!({ "kind": "binding-mismatch", "expected": "0xab" })).await,
+            Err(Error::BindingMismatch(expected)) if expected.as_slice() == [0xab]
+        ));
+        assert!(matches!(
+            verify(serde_json::json!({ "kind": "timing", "message": "expired" })).await,
+            Err(Error::Timing(message)) if message == "expired"
+        ));
+        assert!(matches!(
+            verify(serde_json::json!({ "kind": "invalid-binding" })).await,
+            Err(Error::InvalidBinding)
+        ));
+        assert!(matches!(
+            verify(serde_json::json!({ "kind": "explode" })).await,
+            Err(Error::PolicyDecode(_))
+        ));
+
+        let mut report = Report::dry_run();
+        let policy = serde_json::json!({ "fail": { "kind": "unauthorized", "message": "no" } });
+        assert!(MockVerifier
+            .evaluate(policy, request(RequestKind::Revoke), &mut report)
+            .await
+            .is_err());
+        let steps = report.into_steps();
+        assert_eq!(steps[1].check, "mock");
+        assert_eq!(steps[1].message.as_deref(), Some("no"));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        assert!(matches!(
+            MockVerifier.validate(serde_json::json!({ "version": 2 })),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+    }
+}
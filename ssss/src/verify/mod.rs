@@ -1,8 +1,53 @@
+#[cfg(feature = "composite")]
+mod composite;
+#[cfg(feature = "dcap")]
+mod dcap;
+#[cfg(feature = "jwt")]
+mod jwt;
+#[cfg(feature = "mock")]
+mod mock;
+#[cfg(feature = "multisig")]
+mod multisig;
+#[cfg(feature = "nitro")]
 mod nitro;
+#[cfg(feature = "snp")]
+mod snp;
+#[cfg(feature = "tpm")]
+mod tpm;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-use ethers::types::Address;
+use std::{collections::HashMap, future::Future, sync::Arc};
 
-use crate::types::{IdentityLocator, PolicyDocument};
+#[cfg(feature = "composite")]
+pub use composite::CompositeVerifier;
+#[cfg(feature = "dcap")]
+pub use dcap::DcapVerifier;
+use ethers::{
+    abi::AbiEncode as _,
+    types::{Address, H256},
+    utils::keccak256,
+};
+use futures_util::future::BoxFuture;
+#[cfg(feature = "jwt")]
+pub use jwt::JwtVerifier;
+#[cfg(feature = "mock")]
+pub use mock::MockVerifier;
+#[cfg(feature = "multisig")]
+pub use multisig::MultisigVerifier;
+#[cfg(feature = "nitro")]
+pub use nitro::{CrlStore, NitroEnclaveVerifier, StaleCrlPolicy, UnknownCrlStatus};
+#[cfg(feature = "snp")]
+pub use snp::SnpVerifier;
+#[cfg(feature = "tpm")]
+pub use tpm::TpmVerifier;
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmLimits, WasmVerifier};
+
+use crate::types::{
+    api::{ErrorCode, ErrorDetails, EvaluationStep, PolicyError, VerifierInfo},
+    IdentityLocator, NonceId, PolicyDocument,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RequestKind {
@@ -10,18 +55,251 @@ pub enum RequestKind {
     Revoke,
 }
 
-pub trait Verifier {
-    #[allow(clippy::too_many_arguments)]
-    async fn verify(
+/// A permit request that is to be checked against an identity's policy.
+#[derive(Clone, Copy, Debug)]
+pub struct VerificationRequest<'a> {
+    pub kind: RequestKind,
+    pub identity: IdentityLocator,
+    pub permitter: Address,
+    pub recipient: Address,
+    /// The block at which the permit becomes valid.
+    pub base_block: u64,
+    pub authorization: &'a [u8],
+    pub context: &'a [u8],
+    pub relayer: Option<Address>,
+}
+
+pub trait Verifier: Send + Sync + 'static {
+    /// The name by which policy documents select this verifier.
+    fn name(&self) -> &str;
+
+    /// The newest policy version understood by this verifier.
+    fn version(&self) -> u64;
+
+    /// A JSON Schema describing the policies accepted by this verifier.
+    fn schema(&self) -> serde_json::Value;
+
+    /// Checks that a policy can be used by this verifier, so that bad policies are rejected
+    /// when they are set rather than on every later permit request.
+    fn validate(&self, _raw_policy: serde_json::Value) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn verify(
         &self,
         raw_policy: serde_json::Value,
-        req: RequestKind,
-        identity: IdentityLocator,
-        recipient: Address,
-        authorization: &[u8],
-        context: &[u8],
-        relayer: Option<Address>,
-    ) -> Result<Verification, Error>;
+        req: VerificationRequest<'_>,
+    ) -> impl Future<Output = Result<Verification, Error>> + Send;
+
+    /// Runs the same checks as [`Verifier::verify`] while recording each in the report.
+    /// Verifiers should override this to report their individual checks.
+    fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> impl Future<Output = Result<Verification, Error>> + Send + 'a {
+        async move {
+            let step = EvaluationStep::new(self.name());
+            match self.verify(raw_policy, req).await {
+                Ok(verification) => {
+                    report.pass(step);
+                    Ok(verification)
+                }
+                Err(e) => Err(report.fail(step, e)),
+            }
+        }
+    }
+}
+
+/// The object-safe form of [`Verifier`] used by the registry.
+trait DynVerifier: Send + Sync {
+    fn info(&self) -> VerifierInfo;
+
+    fn validate_dyn(&self, raw_policy: serde_json::Value) -> Result<(), Error>;
+
+    fn verify_dyn<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+    ) -> BoxFuture<'a, Result<Verification, Error>>;
+
+    fn evaluate_dyn<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> BoxFuture<'a, Result<Verification, Error>>;
+}
+
+impl<V: Verifier> DynVerifier for V {
+    fn info(&self) -> VerifierInfo {
+        VerifierInfo {
+            name: self.name().into(),
+            version: self.version(),
+            schema: self.schema(),
+        }
+    }
+
+    fn validate_dyn(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        self.validate(raw_policy)
+    }
+
+    fn verify_dyn<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+    ) -> BoxFuture<'a, Result<Verification, Error>> {
+        Box::pin(self.verify(raw_policy, req))
+    }
+
+    fn evaluate_dyn<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> BoxFuture<'a, Result<Verification, Error>> {
+        Box::pin(self.evaluate(raw_policy, req, report))
+    }
+}
+
+static BINDING_V2_TAG: &[u8] = b"escrin.binding.v2";
+
+/// Returns the value with which an attestation binds itself to a request, such as by
+/// placing it at the start of the attestation's user data. All values are ABI-encoded
+/// as 32-byte words and then hashed.
+///
+/// Version 1 is `keccak256(abi.encode(uint64 chain, address registry, bytes32 identity,
+/// address recipient, bool grant))`.
+///
+/// Version 2 is `keccak256(abi.encode(bytes32 tag, uint64 chain, address registry,
+/// bytes32 identity, address permitter, address recipient, bool grant, uint64 duration,
+/// uint64 baseBlock, bytes32 contextHash))`, where `tag` is
+/// `keccak256("escrin.binding.v2")`, `duration` is zero for revocations, and
+/// `contextHash` is `keccak256(context)`. Every verifier that binds to the whole request
+/// uses this version.
+///
+/// Any other version is an error.
+pub(crate) fn request_binding(
+    version: u8,
+    req: &VerificationRequest<'_>,
+) -> Result<[u8; 32], Error> {
+    let IdentityLocator {
+        chain,
+        registry,
+        id,
+    } = req.identity;
+    let grant = matches!(req.kind, RequestKind::Grant { .. });
+    let encoded = match version {
+        1 => (chain, registry, id.0, req.recipient, grant).encode(),
+        2 => {
+            let duration = match req.kind {
+                RequestKind::Grant { duration } => duration,
+                RequestKind::Revoke => 0,
+            };
+            (
+                H256(keccak256(BINDING_V2_TAG)),
+                chain,
+                registry,
+                id.0,
+                req.permitter,
+                req.recipient,
+                grant,
+                duration,
+                req.base_block,
+                H256(keccak256(req.context)),
+            )
+                .encode()
+        }
+        version => {
+            return Err(Error::PolicyDecode(anyhow::anyhow!(
+                "unsupported binding version {version}"
+            )))
+        }
+    };
+    Ok(keccak256(encoded))
+}
+
+/// Checks that the request was relayed by one of the relayers that the policy trusts, if it
+/// names any.
+#[cfg(any(
+    feature = "dcap",
+    feature = "jwt",
+    feature = "multisig",
+    feature = "nitro",
+    feature = "snp",
+    feature = "tpm",
+    feature = "wasm"
+))]
+pub(crate) fn check_relayer(
+    relayers: &std::collections::HashSet<Address>,
+    relayer: Option<Address>,
+) -> Result<(), Error> {
+    if relayers.is_empty() || relayer.is_some_and(|r| relayers.contains(&r)) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized("not a trusted relayer".into()))
+    }
+}
+
+/// ECDSA P-256 with SHA-256 over fixed-length `r || s` signatures.
+#[cfg(any(feature = "dcap", feature = "tpm"))]
+#[derive(Clone, Copy, Debug)]
+struct ES256;
+
+#[cfg(any(feature = "dcap", feature = "tpm"))]
+impl webpki::types::SignatureVerificationAlgorithm for ES256 {
+    fn public_key_alg_id(&self) -> webpki::types::AlgorithmIdentifier {
+        webpki::alg_id::ECDSA_P256
+    }
+
+    fn signature_alg_id(&self) -> webpki::types::AlgorithmIdentifier {
+        webpki::alg_id::ECDSA_SHA256
+    }
+
+    fn verify_signature(
+        &self,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), webpki::types::InvalidSignature> {
+        ring::signature::UnparsedPublicKey::new(
+            &ring::signature::ECDSA_P256_SHA256_FIXED,
+            public_key,
+        )
+        .verify(message, signature)
+        .map_err(|_| webpki::types::InvalidSignature)
+    }
+}
+
+/// ECDSA P-384 with SHA-384 over fixed-length `r || s` signatures.
+#[cfg(any(feature = "nitro", feature = "snp", feature = "tpm"))]
+#[derive(Clone, Copy, Debug)]
+struct ES384;
+
+#[cfg(any(feature = "nitro", feature = "snp", feature = "tpm"))]
+impl webpki::types::SignatureVerificationAlgorithm for ES384 {
+    fn public_key_alg_id(&self) -> webpki::types::AlgorithmIdentifier {
+        webpki::alg_id::ECDSA_P384
+    }
+
+    fn signature_alg_id(&self) -> webpki::types::AlgorithmIdentifier {
+        webpki::alg_id::ECDSA_SHA384
+    }
+
+    fn verify_signature(
+        &self,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), webpki::types::InvalidSignature> {
+        ring::signature::UnparsedPublicKey::new(
+            &ring::signature::ECDSA_P384_SHA384_FIXED,
+            public_key,
+        )
+        .verify(message, signature)
+        .map_err(|_| webpki::types::InvalidSignature)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -30,6 +308,8 @@ pub enum Error {
     UnknownVerifier(String),
     #[error("failed to decode policy: {0}")]
     PolicyDecode(#[source] anyhow::Error),
+    #[error("unsupported policy version {0}")]
+    UnsupportedPolicyVersion(u64),
     #[error("failed to decode attesation document: {0}")]
     AttestationDecode(#[source] anyhow::Error),
     #[error("invalid binding provided")]
@@ -42,6 +322,67 @@ pub enum Error {
     Unauthorized(String),
     #[error("timing error: {0}")]
     Timing(String),
+    #[error("policy not current")]
+    StalePolicy { expected: H256, observed: H256 },
+    #[error("{0}")]
+    LimitExceeded(String),
+}
+
+impl Error {
+    /// Returns the code by which API clients can identify this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::UnknownVerifier(_) => ErrorCode::UnknownVerifier,
+            Self::PolicyDecode(_) => ErrorCode::PolicyDecode,
+            Self::UnsupportedPolicyVersion(_) => ErrorCode::UnsupportedPolicyVersion,
+            Self::AttestationDecode(_) => ErrorCode::AttestationDecode,
+            Self::InvalidBinding => ErrorCode::InvalidBinding,
+            Self::BindingMismatch(_) => ErrorCode::BindingMismatch,
+            Self::PcrMismatch(_) => ErrorCode::PcrMismatch,
+            Self::Unauthorized(_) => ErrorCode::Unauthorized,
+            Self::Timing(_) => ErrorCode::Timing,
+            Self::StalePolicy { .. } => ErrorCode::StalePolicy,
+            Self::LimitExceeded(_) => ErrorCode::LimitExceeded,
+        }
+    }
+
+    /// Returns the structured information that API clients receive along with the code.
+    pub fn details(&self) -> ErrorDetails {
+        match self {
+            Self::UnknownVerifier(verifier) => ErrorDetails {
+                verifier: Some(verifier.clone()),
+                ..Default::default()
+            },
+            Self::UnsupportedPolicyVersion(version) => ErrorDetails {
+                policy_version: Some(*version),
+                ..Default::default()
+            },
+            Self::BindingMismatch(expected) => ErrorDetails {
+                expected_binding: Some(expected.to_vec().into()),
+                ..Default::default()
+            },
+            Self::PcrMismatch(pcr) => ErrorDetails {
+                pcr: Some(*pcr),
+                ..Default::default()
+            },
+            Self::StalePolicy { expected, observed } => ErrorDetails {
+                expected_policy_hash: Some(*expected),
+                observed_policy_hash: Some(*observed),
+                ..Default::default()
+            },
+            _ => Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistryError {
+    #[error("verifier `{0}` is already registered")]
+    AlreadyRegistered(String),
+    #[error("verifier `{0}` is not available in this build")]
+    Unavailable(String),
+    #[error("verifier `{0}` has not been enabled")]
+    Disabled(String),
 }
 
 #[derive(Clone, Debug)]
@@ -49,43 +390,607 @@ pub struct Verification {
     pub nonce: Vec<u8>,
     pub public_key: Vec<u8>,
     pub duration: Option<u64>,
+    /// The index of the policy's measurement set that the attestation matched, if applicable.
+    pub measurement: Option<usize>,
+    /// The values that must be consumed for the request to be permitted.
+    pub single_use: SingleUse,
 }
 
-pub async fn verify(
-    policy_bytes: &[u8],
-    req: RequestKind,
-    identity: IdentityLocator,
-    recipient: Address,
-    auth: &[u8],
-    ctx: &[u8],
-    relayer: Option<Address>,
-) -> Result<Verification, Error> {
-    let PolicyDocument {
-        verifier,
-        policy: raw_policy,
-    } = serde_json::from_slice(policy_bytes).map_err(|e| Error::PolicyDecode(e.into()))?;
-
-    match verifier.as_str() {
-        "nitro" => {
-            nitro::NitroEnclaveVerifier
-                .verify(raw_policy, req, identity, recipient, auth, ctx, relayer)
-                .await
-        }
-        #[cfg(debug_assertions)]
-        "mock" => Ok(Verification {
-            nonce: ethers::utils::keccak256(ethers::types::H256::from_low_u64_ne(
-                (ssss::utils::now() >> 1) << 1, // permit up to 2s drift
-            ))
-            .into(),
-            public_key: vec![],
-            duration: Some(
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs()
-                    + 60,
-            ),
-        }),
-        sel => Err(Error::UnknownVerifier(sel.into())),
+/// Values that a request may present only once, which the caller records in the store
+/// before issuing a permit so that the request cannot be replayed.
+#[derive(Clone, Debug, Default)]
+pub struct SingleUse {
+    /// Values that must not have been presented before `expiry`.
+    pub nonces: Vec<NonceId>,
+    /// The time (unix seconds) after which the verifier rejects the request anyway,
+    /// so that the nonces no longer need to be remembered.
+    pub expiry: u64,
+    /// A challenge issued by this SSSS that the request must redeem.
+    pub challenge: Option<NonceId>,
+}
+
+/// Records the outcome of each check made while evaluating a permit request.
+#[derive(Debug, Default)]
+pub struct Report {
+    steps: Vec<EvaluationStep>,
+    /// Whether checks that fail without preventing later checks should be reported
+    /// instead of ending the evaluation.
+    dry_run: bool,
+    error: Option<Error>,
+}
+
+impl Report {
+    /// Returns a report that ends evaluation at the first failed check.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a report that continues past failed checks whenever possible.
+    pub fn dry_run() -> Self {
+        Self {
+            dry_run: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn pass(&mut self, mut step: EvaluationStep) {
+        step.passed = true;
+        self.steps.push(step);
+    }
+
+    /// Records a check whose failure does not prevent later checks from being made.
+    pub fn check(&mut self, step: EvaluationStep, result: Result<(), Error>) -> Result<(), Error> {
+        match result {
+            Ok(()) => {
+                self.pass(step);
+                Ok(())
+            }
+            Err(e) if self.dry_run => {
+                let e = self.fail(step, e);
+                self.error.get_or_insert(e);
+                Ok(())
+            }
+            Err(e) => Err(self.fail(step, e)),
+        }
+    }
+
+    /// Records a failed check after which evaluation cannot continue.
+    pub fn fail(&mut self, mut step: EvaluationStep, error: Error) -> Error {
+        step.passed = false;
+        step.message = Some(error.to_string());
+        self.steps.push(step);
+        error
+    }
+
+    /// Returns the first failure recorded by [`Report::check`], if any, or else `value`.
+    pub fn finish<T>(&mut self, value: T) -> Result<T, Error> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(value),
+        }
+    }
+
+    /// Returns an empty report that makes checks in the same way as this one.
+    #[cfg(feature = "composite")]
+    fn nested(&self) -> Self {
+        Self {
+            dry_run: self.dry_run,
+            ..Default::default()
+        }
+    }
+
+    /// Appends the checks of a nested report, naming each within `scope`.
+    #[cfg(feature = "composite")]
+    fn append(&mut self, scope: &str, nested: Report) {
+        self.steps.extend(nested.steps.into_iter().map(|mut step| {
+            step.check = format!("{scope}/{}", step.check);
+            step
+        }));
+    }
+
+    pub fn into_steps(self) -> Vec<EvaluationStep> {
+        self.steps
+    }
+}
+
+/// The configured instances of the built-in verifiers.
+#[derive(Clone, Debug, Default)]
+pub struct Builtins {
+    #[cfg(feature = "dcap")]
+    pub dcap: DcapVerifier,
+    #[cfg(feature = "jwt")]
+    pub jwt: JwtVerifier,
+    /// The mock verifier, which is only enabled when this is set.
+    #[cfg(feature = "mock")]
+    pub mock: Option<MockVerifier>,
+    #[cfg(feature = "multisig")]
+    pub multisig: MultisigVerifier,
+    #[cfg(feature = "nitro")]
+    pub nitro: NitroEnclaveVerifier,
+    #[cfg(feature = "snp")]
+    pub snp: SnpVerifier,
+    #[cfg(feature = "tpm")]
+    pub tpm: TpmVerifier,
+    #[cfg(feature = "wasm")]
+    pub wasm: WasmVerifier,
+}
+
+/// The verifiers that the SSSS can use to evaluate policies, keyed by name.
+#[derive(Clone, Default)]
+pub struct VerifierRegistry {
+    verifiers: HashMap<String, Arc<dyn DynVerifier>>,
+}
+
+impl VerifierRegistry {
+    /// The names of the verifiers that are compiled into this build.
+    pub const BUILTINS: &'static [&'static str] = &[
+        #[cfg(feature = "composite")]
+        "composite",
+        #[cfg(feature = "dcap")]
+        "dcap",
+        #[cfg(feature = "jwt")]
+        "jwt",
+        #[cfg(feature = "mock")]
+        "mock",
+        #[cfg(feature = "multisig")]
+        "multisig",
+        #[cfg(feature = "nitro")]
+        "nitro",
+        #[cfg(feature = "snp")]
+        "snp",
+        #[cfg(feature = "tpm")]
+        "tpm",
+        #[cfg(feature = "wasm")]
+        "wasm",
+    ];
+
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a registry containing the named built-in verifiers, or all of the enabled ones if
+    /// none are named.
+    pub fn with_builtins<S: AsRef<str>>(
+        enabled: &[S],
+        builtins: Builtins,
+    ) -> Result<Self, RegistryError> {
+        let mut registry = Self::new();
+        let enabled: Vec<&str> = match enabled {
+            #[cfg(feature = "mock")]
+            [] if builtins.mock.is_none() => Self::BUILTINS
+                .iter()
+                .copied()
+                .filter(|name| *name != "mock")
+                .collect(),
+            [] => Self::BUILTINS.to_vec(),
+            names => names.iter().map(|n| n.as_ref()).collect(),
+        };
+        // Composite policies may use any of the other enabled verifiers, so it is added last.
+        #[cfg(feature = "composite")]
+        let (composite, enabled): (Vec<_>, Vec<_>) =
+            enabled.into_iter().partition(|name| *name == "composite");
+        for name in enabled {
+            match name {
+                #[cfg(feature = "dcap")]
+                "dcap" => registry.register(builtins.dcap.clone())?,
+                #[cfg(feature = "jwt")]
+                "jwt" => registry.register(builtins.jwt.clone())?,
+                #[cfg(feature = "mock")]
+                "mock" => match builtins.mock {
+                    Some(mock) => registry.register(mock)?,
+                    None => return Err(RegistryError::Disabled(name.into())),
+                },
+                #[cfg(feature = "multisig")]
+                "multisig" => registry.register(builtins.multisig.clone())?,
+                #[cfg(feature = "nitro")]
+                "nitro" => registry.register(builtins.nitro.clone())?,
+                #[cfg(feature = "snp")]
+                "snp" => registry.register(builtins.snp.clone())?,
+                #[cfg(feature = "tpm")]
+                "tpm" => registry.register(builtins.tpm)?,
+                #[cfg(feature = "wasm")]
+                "wasm" => registry.register(builtins.wasm.clone())?,
+                name => return Err(RegistryError::Unavailable(name.into())),
+            };
+        }
+        #[cfg(feature = "composite")]
+        if !composite.is_empty() {
+            let leaves = registry.clone();
+            registry.register(CompositeVerifier::new(leaves))?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds a verifier that will be used for policies that name it.
+    pub fn register(&mut self, verifier: impl Verifier) -> Result<&mut Self, RegistryError> {
+        let name = verifier.name().to_string();
+        if self.verifiers.contains_key(&name) {
+            return Err(RegistryError::AlreadyRegistered(name));
+        }
+        self.verifiers.insert(name, Arc::new(verifier));
+        Ok(self)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.verifiers.contains_key(name)
+    }
+
+    /// Returns the name, version, and policy schema of each registered verifier.
+    pub fn info(&self) -> Vec<VerifierInfo> {
+        let mut infos: Vec<_> = self.verifiers.values().map(|v| v.info()).collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+
+    /// Decodes a policy document and checks it using the verifier that it names.
+    pub fn validate(&self, policy_bytes: &[u8]) -> Result<(), PolicyError> {
+        let PolicyDocument {
+            verifier: name,
+            policy: raw_policy,
+            limits,
+        } = serde_json::from_slice(policy_bytes).map_err(|e| PolicyError::Malformed {
+            message: e.to_string(),
+        })?;
+        if let Some(limits) = limits {
+            limits
+                .validate()
+                .map_err(|message| PolicyError::InvalidLimits { message })?;
+        }
+        let Some(verifier) = self.verifiers.get(&name) else {
+            return Err(PolicyError::UnknownVerifier { verifier: name });
+        };
+        verifier.validate_dyn(raw_policy).map_err(|e| match e {
+            Error::UnsupportedPolicyVersion(version) => PolicyError::UnsupportedVersion {
+                supported: verifier.info().version,
+                verifier: name,
+                version,
+            },
+            e => PolicyError::Invalid {
+                verifier: name,
+                message: e.to_string(),
+            },
+        })
+    }
+
+    pub async fn verify(
+        &self,
+        policy_bytes: &[u8],
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        let PolicyDocument {
+            verifier,
+            policy: raw_policy,
+            ..
+        } = serde_json::from_slice(policy_bytes).map_err(|e| Error::PolicyDecode(e.into()))?;
+
+        self.verifiers
+            .get(&verifier)
+            .ok_or(Error::UnknownVerifier(verifier))?
+            .verify_dyn(raw_policy, req)
+            .await
+    }
+
+    /// Verifies a request while recording each check that is made in the report.
+    pub async fn evaluate(
+        &self,
+        policy_bytes: &[u8],
+        req: VerificationRequest<'_>,
+        report: &mut Report,
+    ) -> Result<Verification, Error> {
+        let step = EvaluationStep::new("policy-document");
+        let PolicyDocument {
+            verifier,
+            policy: raw_policy,
+            ..
+        } = match serde_json::from_slice(policy_bytes) {
+            Ok(doc) => doc,
+            Err(e) => return Err(report.fail(step, Error::PolicyDecode(e.into()))),
+        };
+        let Some(v) = self.verifiers.get(&verifier) else {
+            return Err(report.fail(step, Error::UnknownVerifier(verifier)));
+        };
+        report.pass(step.observed(&verifier));
+        v.evaluate_dyn(raw_policy, req, report).await
+    }
+}
+
+impl std::fmt::Debug for VerifierRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.verifiers.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AllowAll;
+
+    impl Verifier for AllowAll {
+        fn name(&self) -> &str {
+            "allow-all"
+        }
+
+        fn version(&self) -> u64 {
+            3
+        }
+
+        fn schema(&self) -> serde_json::Value {
+            serde_json::json!({ "type": "object" })
+        }
+
+        async fn verify(
+            &self,
+            _raw_policy: serde_json::Value,
+            req: VerificationRequest<'_>,
+        ) -> Result<Verification, Error> {
+            Ok(Verification {
+                nonce: req.context.to_vec(),
+                public_key: req.authorization.to_vec(),
+                duration: match req.kind {
+                    RequestKind::Grant { duration } => Some(duration),
+                    RequestKind::Revoke => None,
+                },
+                measurement: None,
+                single_use: Default::default(),
+            })
+        }
+    }
+
+    /// Returns a grant request with fixed parameters.
+    pub(super) fn request(authorization: &[u8]) -> VerificationRequest<'_> {
+        VerificationRequest {
+            kind: RequestKind::Grant { duration: 60 },
+            identity: IdentityLocator {
+                chain: 31337,
+                registry: Address::repeat_byte(1),
+                id: crate::types::IdentityId(H256::repeat_byte(2)),
+            },
+            permitter: Address::repeat_byte(3),
+            recipient: Address::repeat_byte(4),
+            base_block: 100,
+            authorization,
+            context: b"ctx",
+            relayer: None,
+        }
+    }
+
+    /// Returns the parameters of a CA that may sign certificates and CRLs.
+    #[cfg(any(feature = "dcap", feature = "snp", feature = "tpm"))]
+    pub(super) fn ca_params(name: &str) -> rcgen::CertificateParams {
+        let mut params = rcgen::CertificateParams::default();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params.key_usages = vec![
+            rcgen::KeyUsagePurpose::KeyCertSign,
+            rcgen::KeyUsagePurpose::CrlSign,
+        ];
+        params
+    }
+
+    #[tokio::test]
+    async fn custom_verifier() {
+        let mut registry = VerifierRegistry::new();
+        registry.register(AllowAll).unwrap();
+        assert!(matches!(
+            registry.register(AllowAll),
+            Err(RegistryError::AlreadyRegistered(_))
+        ));
+
+        let info = registry.info();
+        assert_eq!(info.len(), 1);
+        assert_eq!(info[0].name, "allow-all");
+        assert_eq!(info[0].version, 3);
+
+        let policy = br#"{"verifier":"allow-all","policy":{}}"#;
+        let verification = registry.verify(policy, request(b"auth")).await.unwrap();
+        assert_eq!(verification.nonce, b"ctx");
+        assert_eq!(verification.duration, Some(60));
+
+        let policy = br#"{"verifier":"nitro","policy":{}}"#;
+        assert!(matches!(
+            registry.verify(policy, request(b"auth")).await,
+            Err(Error::UnknownVerifier(v)) if v == "nitro"
+        ));
+    }
+
+    #[test]
+    fn validate_policy() {
+        let mut registry = VerifierRegistry::new();
+        registry.register(AllowAll).unwrap();
+
+        registry
+            .validate(br#"{"verifier":"allow-all","policy":{}}"#)
+            .unwrap();
+        assert!(matches!(
+            registry.validate(br#"{"policy":{}}"#),
+            Err(PolicyError::Malformed { .. })
+        ));
+        assert_eq!(
+            registry.validate(br#"{"verifier":"sgx","policy":{}}"#),
+            Err(PolicyError::UnknownVerifier {
+                verifier: "sgx".into()
+            })
+        );
+
+        registry
+            .validate(br#"{"verifier":"allow-all","policy":{},"limits":{"max_holders":1}}"#)
+            .unwrap();
+        let empty_rate = br#"{"verifier":"allow-all","policy":{},
+            "limits":{"max_grants":{"count":0,"window":60}}}"#;
+        assert!(matches!(
+            registry.validate(empty_rate),
+            Err(PolicyError::InvalidLimits { .. })
+        ));
+        assert!(matches!(
+            registry.validate(br#"{"verifier":"allow-all","policy":{},"limits":{"cooldown":60}}"#),
+            Err(PolicyError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn error_codes() {
+        let e = Error::PcrMismatch(4);
+        assert_eq!(e.code(), ErrorCode::PcrMismatch);
+        assert_eq!(e.details().pcr, Some(4));
+
+        let e = Error::BindingMismatch(smallvec::SmallVec::from_slice(&[1, 2]));
+        assert_eq!(e.details().expected_binding, Some(vec![1, 2].into()));
+
+        let e = Error::Timing("attestation doc expired".into());
+        assert_eq!(e.code(), ErrorCode::Timing);
+        assert!(e.details().is_empty());
+    }
+
+    #[test]
+    fn report() {
+        let mut report = Report::new();
+        report.check(EvaluationStep::new("a"), Ok(())).unwrap();
+        assert!(report
+            .check(EvaluationStep::new("b"), Err(Error::InvalidBinding))
+            .is_err());
+        assert_eq!(report.into_steps().len(), 2);
+
+        let mut report = Report::dry_run();
+        report
+            .check(EvaluationStep::new("a"), Err(Error::PcrMismatch(2)))
+            .unwrap();
+        report
+            .check(EvaluationStep::new("b"), Err(Error::InvalidBinding))
+            .unwrap();
+        report.pass(EvaluationStep::new("c"));
+        assert!(matches!(report.finish(()), Err(Error::PcrMismatch(2))));
+        let steps = report.into_steps();
+        assert_eq!(
+            steps.iter().map(|s| s.passed).collect::<Vec<_>>(),
+            [false, false, true]
+        );
+        assert_eq!(
+            steps[1].message.as_deref(),
+            Some("invalid binding provided")
+        );
+    }
+
+    #[tokio::test]
+    async fn evaluate_default() {
+        let mut registry = VerifierRegistry::new();
+        registry.register(AllowAll).unwrap();
+        let mut report = Report::dry_run();
+        registry
+            .evaluate(
+                br#"{"verifier":"allow-all","policy":{}}"#,
+                request(b"auth"),
+                &mut report,
+            )
+            .await
+            .unwrap();
+        let steps = report.into_steps();
+        assert_eq!(steps.len(), 2);
+        assert!(steps.iter().all(|s| s.passed));
+        assert_eq!(steps[1].check, "allow-all");
+    }
+
+    #[test]
+    fn binding_layout() {
+        fn word(bytes: &[u8]) -> [u8; 32] {
+            let mut word = [0u8; 32];
+            word[32 - bytes.len()..].copy_from_slice(bytes);
+            word
+        }
+
+        let identity = IdentityLocator {
+            chain: 31337,
+            registry: Address::repeat_byte(1),
+            id: crate::types::IdentityId(H256::repeat_byte(2)),
+        };
+        let permitter = Address::repeat_byte(3);
+        let recipient = Address::repeat_byte(4);
+        let req = VerificationRequest {
+            kind: RequestKind::Grant { duration: 3600 },
+            identity,
+            permitter,
+            recipient,
+            base_block: 1234,
+            authorization: &[],
+            context: b"session-id",
+            relayer: None,
+        };
+
+        let v1 = [
+            word(&31337u64.to_be_bytes()),
+            word(identity.registry.as_bytes()),
+            word(identity.id.0.as_bytes()),
+            word(recipient.as_bytes()),
+            word(&[1]),
+        ]
+        .concat();
+        assert_eq!(request_binding(1, &req).unwrap(), keccak256(v1));
+
+        let v2 = |grant: bool, duration: u64, context: &[u8]| {
+            keccak256(
+                [
+                    keccak256(b"escrin.binding.v2"),
+                    word(&31337u64.to_be_bytes()),
+                    word(identity.registry.as_bytes()),
+                    word(identity.id.0.as_bytes()),
+                    word(permitter.as_bytes()),
+                    word(recipient.as_bytes()),
+                    word(&[grant as u8]),
+                    word(&duration.to_be_bytes()),
+                    word(&1234u64.to_be_bytes()),
+                    keccak256(context),
+                ]
+                .concat(),
+            )
+        };
+        assert_eq!(
+            request_binding(2, &req).unwrap(),
+            v2(true, 3600, b"session-id")
+        );
+        assert_eq!(
+            request_binding(
+                2,
+                &VerificationRequest {
+                    kind: RequestKind::Revoke,
+                    context: &[],
+                    ..req
+                }
+            )
+            .unwrap(),
+            v2(false, 0, &[])
+        );
+        assert!(matches!(
+            request_binding(3, &req),
+            Err(Error::PolicyDecode(_))
+        ));
+    }
+
+    #[test]
+    fn builtins() {
+        let registry = VerifierRegistry::with_builtins::<&str>(&[], Default::default()).unwrap();
+        for name in VerifierRegistry::BUILTINS {
+            assert_eq!(registry.contains(name), *name != "mock");
+        }
+        assert!(matches!(
+            VerifierRegistry::with_builtins(&["sgx"], Default::default()),
+            Err(RegistryError::Unavailable(_))
+        ));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_builtin() {
+        assert!(matches!(
+            VerifierRegistry::with_builtins(&["mock"], Default::default()),
+            Err(RegistryError::Disabled(_))
+        ));
+        let builtins = Builtins {
+            mock: Some(MockVerifier),
+            ..Default::default()
+        };
+        let registry = VerifierRegistry::with_builtins::<&str>(&[], builtins).unwrap();
+        assert!(registry.contains("mock"));
     }
 }
@@ -0,0 +1,509 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+};
+
+use anyhow::anyhow;
+use ethers::{
+    providers::Middleware,
+    types::{transaction::eip712::Eip712 as _, Bytes, Signature},
+};
+use serde::Deserialize;
+
+use super::*;
+use crate::{
+    eth,
+    types::{ChainId, SsssPermitApproval},
+};
+
+/// Verifies that a threshold of the policy's approvers consented to the permit request.
+///
+/// The authorization is a JSON array of `{ "approver", "signature" }` objects, each signing the
+/// EIP-712 [`SsssPermitApproval`] that describes the request. Approvers are externally owned
+/// accounts, whose ECDSA signatures are recovered, or contracts, which are asked whether their
+/// signature is valid (EIP-1271) using the provider for the identity's chain.
+pub struct MultisigVerifier<M = eth::RpcClient> {
+    providers: Arc<HashMap<ChainId, Arc<M>>>,
+}
+
+impl<M> MultisigVerifier<M> {
+    pub fn with_providers(providers: Arc<HashMap<ChainId, Arc<M>>>) -> Self {
+        Self { providers }
+    }
+}
+
+impl<M> Clone for MultisigVerifier<M> {
+    fn clone(&self) -> Self {
+        Self {
+            providers: self.providers.clone(),
+        }
+    }
+}
+
+impl<M> Default for MultisigVerifier<M> {
+    fn default() -> Self {
+        Self {
+            providers: Default::default(),
+        }
+    }
+}
+
+impl<M> fmt::Debug for MultisigVerifier<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultisigVerifier")
+            .field("chains", &self.providers.keys().collect::<BTreeSet<_>>())
+            .finish()
+    }
+}
+
+impl<M: Middleware + 'static> Verifier for MultisigVerifier<M> {
+    fn name(&self) -> &str {
+        "multisig"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Multisig policy",
+            "type": "object",
+            "required": ["version", "approvers", "threshold"],
+            "additionalProperties": false,
+            "properties": {
+                "version": { "const": 1 },
+                "approvers": {
+                    "description": "The accounts whose approval counts towards the threshold",
+                    "type": "array",
+                    "minItems": 1,
+                    "uniqueItems": true,
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                },
+                "threshold": { "type": "integer", "minimum": 1 },
+                "max_duration": { "type": "integer", "minimum": 0 },
+                "relayers": {
+                    "type": "array",
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        Policy::decode(raw_policy).map(|_| ())
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        report.check(
+            EvaluationStep::new("relayer")
+                .expected(&policy.relayers)
+                .observed(req.relayer),
+            check_relayer(&policy.relayers, req.relayer),
+        )?;
+
+        let approvals = match serde_json::from_slice::<Vec<Approval>>(req.authorization) {
+            // Each approval may need an RPC call to check, so there may be no more of them than
+            // there are approvers.
+            Ok(approvals) if approvals.len() > policy.approvers.len() => {
+                return Err(report.fail(
+                    EvaluationStep::new("approvals"),
+                    Error::AttestationDecode(anyhow!(
+                        "{} approvals were provided for {} approvers",
+                        approvals.len(),
+                        policy.approvers.len()
+                    )),
+                ))
+            }
+            Ok(approvals) => approvals,
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("approvals"),
+                    Error::AttestationDecode(e.into()),
+                ))
+            }
+        };
+
+        let approval = SsssPermitApproval {
+            chain: req.identity.chain,
+            registry: req.identity.registry,
+            identity: req.identity.id.0,
+            permitter: req.permitter,
+            recipient: req.recipient,
+            grant: matches!(req.kind, RequestKind::Grant { .. }),
+            duration: match req.kind {
+                RequestKind::Grant { duration } => duration,
+                RequestKind::Revoke => 0,
+            },
+            base_block: req.base_block,
+        };
+        let hash = match approval.encode_eip712() {
+            Ok(hash) => H256(hash),
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("approvals"),
+                    Error::AttestationDecode(e.into()),
+                ))
+            }
+        };
+
+        let mut approved = BTreeSet::new();
+        let mut rejected = BTreeSet::new();
+        let mut lookup_error = None;
+        for Approval {
+            approver,
+            signature,
+        } in approvals
+        {
+            // Each approver's signature is checked at most once.
+            if !policy.approvers.contains(&approver)
+                || approved.contains(&approver)
+                || rejected.contains(&approver)
+            {
+                continue;
+            }
+            match self
+                .is_valid_signature(req.identity.chain, approver, hash, signature)
+                .await
+            {
+                Ok(true) => {
+                    approved.insert(approver);
+                }
+                Ok(false) => {
+                    rejected.insert(approver);
+                }
+                Err(e) => {
+                    rejected.insert(approver);
+                    lookup_error.get_or_insert(e);
+                }
+            }
+        }
+
+        report.check(
+            EvaluationStep::new("threshold")
+                .expected(serde_json::json!({
+                    "threshold": policy.threshold,
+                    "approvers": policy.approvers,
+                }))
+                .observed(serde_json::json!({
+                    "approved": approved,
+                    "rejected": rejected,
+                })),
+            if approved.len() >= policy.threshold {
+                Ok(())
+            } else {
+                Err(Error::Unauthorized(match lookup_error {
+                    Some(e) => format!(
+                        "{} of {} required approvals ({e})",
+                        approved.len(),
+                        policy.threshold
+                    ),
+                    None => format!(
+                        "{} of {} required approvals",
+                        approved.len(),
+                        policy.threshold
+                    ),
+                }))
+            },
+        )?;
+
+        report.finish(Verification {
+            nonce: vec![],
+            public_key: vec![],
+            duration: match req.kind {
+                RequestKind::Grant { duration } => Some(duration.min(policy.max_duration)),
+                RequestKind::Revoke => None,
+            },
+            measurement: None,
+            single_use: Default::default(),
+        })
+    }
+}
+
+impl<M: Middleware + 'static> MultisigVerifier<M> {
+    /// Checks an approver's signature, first as that of an externally owned account and then,
+    /// if the approver's chain is available, as that of a contract account.
+    async fn is_valid_signature(
+        &self,
+        chain: ChainId,
+        approver: Address,
+        hash: H256,
+        signature: Bytes,
+    ) -> Result<bool, anyhow::Error> {
+        let recovered = Signature::try_from(&*signature)
+            .ok()
+            .and_then(|sig| sig.recover(hash).ok());
+        if recovered == Some(approver) {
+            return Ok(true);
+        }
+        let Some(provider) = self.providers.get(&chain) else {
+            return Ok(false);
+        };
+        eth::is_valid_contract_signature(provider.clone(), approver, hash, signature)
+            .await
+            .map_err(|e| anyhow!("failed to check the signature of {approver:?}: {e}"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Approval {
+    approver: Address,
+    signature: Bytes,
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    version: u8,
+    approvers: Vec<Address>,
+    threshold: usize,
+    #[serde(default)]
+    max_duration: u64,
+    #[serde(default)]
+    relayers: HashSet<Address>,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        if policy.approvers.iter().collect::<HashSet<_>>().len() != policy.approvers.len() {
+            return Err(Error::PolicyDecode(anyhow!("approvers must be unique")));
+        }
+        if policy.threshold == 0 || policy.threshold > policy.approvers.len() {
+            return Err(Error::PolicyDecode(anyhow!(
+                "the threshold must be between 1 and the number of approvers"
+            )));
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::AbiEncode as _,
+        providers::{MockProvider, Provider},
+        signers::{LocalWallet, Signer as _},
+    };
+
+    use super::*;
+    use crate::verify::tests::request;
+
+    type TestVerifier = MultisigVerifier<Provider<MockProvider>>;
+
+    fn approval_hash(kind: RequestKind) -> H256 {
+        H256(
+            SsssPermitApproval {
+                chain: 31337,
+                registry: Address::repeat_byte(1),
+                identity: H256::repeat_byte(2),
+                permitter: Address::repeat_byte(3),
+                recipient: Address::repeat_byte(4),
+                grant: matches!(kind, RequestKind::Grant { .. }),
+                duration: match kind {
+                    RequestKind::Grant { duration } => duration,
+                    RequestKind::Revoke => 0,
+                },
+                base_block: 100,
+            }
+            .encode_eip712()
+            .unwrap(),
+        )
+    }
+
+    fn approve(wallet: &LocalWallet, hash: H256) -> serde_json::Value {
+        serde_json::json!({
+            "approver": wallet.address(),
+            "signature": Bytes::from(wallet.sign_hash(hash).unwrap().to_vec()),
+        })
+    }
+
+    fn policy(approvers: &[&LocalWallet], threshold: usize) -> serde_json::Value {
+        serde_json::json!({
+            "version": 1,
+            "approvers": approvers.iter().map(|w| w.address()).collect::<Vec<_>>(),
+            "threshold": threshold,
+            "max_duration": 30,
+        })
+    }
+
+    fn wallets() -> [LocalWallet; 3] {
+        std::array::from_fn(|_| LocalWallet::new(&mut rand::thread_rng()))
+    }
+
+    #[tokio::test]
+    async fn verify_approvals() {
+        let [a, b, c] = wallets();
+        let hash = approval_hash(RequestKind::Grant { duration: 60 });
+        let auth = serde_json::to_vec(&[approve(&a, hash), approve(&c, hash)]).unwrap();
+        let verification = TestVerifier::default()
+            .verify(policy(&[&a, &b, &c], 2), request(&auth))
+            .await
+            .unwrap();
+        assert_eq!(verification.duration, Some(30));
+
+        let revoke =
+            serde_json::to_vec(&[approve(&b, approval_hash(RequestKind::Revoke))]).unwrap();
+        let mut req = request(&revoke);
+        req.kind = RequestKind::Revoke;
+        let verification = TestVerifier::default()
+            .verify(policy(&[&a, &b, &c], 1), req)
+            .await
+            .unwrap();
+        assert_eq!(verification.duration, None);
+    }
+
+    #[tokio::test]
+    async fn verify_contract_approval() {
+        let [a, b, _] = wallets();
+        let hash = approval_hash(RequestKind::Grant { duration: 60 });
+        let contract = Address::random();
+        let auth = serde_json::to_vec(&[
+            approve(&a, hash),
+            serde_json::json!({ "approver": contract, "signature": "0x010203" }),
+        ])
+        .unwrap();
+        let policy = serde_json::json!({
+            "version": 1,
+            "approvers": [a.address(), b.address(), contract],
+            "threshold": 2,
+        });
+
+        let (provider, mock) = Provider::mocked();
+        let verifier = TestVerifier::with_providers(Arc::new([(31337, Arc::new(provider))].into()));
+        mock.push::<Bytes, _>(Bytes::from(eth::ERC1271_MAGIC_VALUE.encode()))
+            .unwrap();
+        verifier
+            .verify(policy.clone(), request(&auth))
+            .await
+            .unwrap();
+
+        mock.push::<Bytes, _>(Bytes::from([0xff; 4].encode()))
+            .unwrap();
+        assert!(matches!(
+            verifier.verify(policy.clone(), request(&auth)).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        // Contract approvals cannot be checked without a provider for the identity's chain.
+        assert!(matches!(
+            TestVerifier::default().verify(policy, request(&auth)).await,
+            Err(Error::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_approvals() {
+        let [a, b, c] = wallets();
+        let outsider = LocalWallet::new(&mut rand::thread_rng());
+        let hash = approval_hash(RequestKind::Grant { duration: 60 });
+        let policy = policy(&[&a, &b, &c], 2);
+        let verify = |approvals: Vec<serde_json::Value>| {
+            let auth = serde_json::to_vec(&approvals).unwrap();
+            let policy = policy.clone();
+            async move { TestVerifier::default().verify(policy, request(&auth)).await }
+        };
+
+        assert!(matches!(
+            verify(vec![approve(&a, hash)]).await,
+            Err(Error::Unauthorized(_))
+        ));
+        assert!(matches!(
+            verify(vec![approve(&a, hash), approve(&a, hash)]).await,
+            Err(Error::Unauthorized(_))
+        ));
+        assert!(matches!(
+            verify(vec![approve(&a, hash), approve(&outsider, hash)]).await,
+            Err(Error::Unauthorized(_))
+        ));
+        assert!(matches!(
+            verify(vec![
+                approve(&a, hash),
+                approve(&a, hash),
+                approve(&a, hash),
+                approve(&b, hash)
+            ])
+            .await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        // A signature over a different request does not count.
+        let other = approval_hash(RequestKind::Grant { duration: 61 });
+        assert!(matches!(
+            verify(vec![approve(&a, hash), approve(&b, other)]).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        // Nor does one that is attributed to another approver.
+        let mut forged = approve(&outsider, hash);
+        forged["approver"] = serde_json::json!(b.address());
+        assert!(matches!(
+            verify(vec![approve(&a, hash), forged.clone()]).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        // An approver whose signature was rejected may not try again.
+        assert!(matches!(
+            verify(vec![forged, approve(&a, hash), approve(&b, hash)]).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        assert!(matches!(
+            TestVerifier::default()
+                .verify(policy.clone(), request(b"not json"))
+                .await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    #[test]
+    fn validate_policy() {
+        let [a, b, _] = wallets();
+        let verifier = TestVerifier::default();
+        verifier.validate(policy(&[&a, &b], 2)).unwrap();
+        assert!(matches!(
+            verifier.validate(policy(&[&a, &b], 3)),
+            Err(Error::PolicyDecode(_))
+        ));
+        assert!(matches!(
+            verifier.validate(policy(&[&a, &b], 0)),
+            Err(Error::PolicyDecode(_))
+        ));
+        assert!(matches!(
+            verifier.validate(policy(&[&a, &a], 1)),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = policy(&[&a], 1);
+        bad["version"] = 2.into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context as _};
+use webpki::{
+    CertRevocationList, OwnedCertRevocationList, RevocationCheckDepth, RevocationOptions,
+    RevocationOptionsBuilder, UnknownStatusPolicy,
+};
+
+/// What to do when a CRL has passed its next update time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "lowercase")]
+pub enum StaleCrlPolicy {
+    /// Fail attestation verification until fresh CRLs are available.
+    #[default]
+    Reject,
+    /// Log a warning and keep checking against the stale CRLs.
+    Warn,
+}
+
+/// What to do when a certificate's issuer has not published a CRL.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+#[value(rename_all = "lowercase")]
+pub enum UnknownCrlStatus {
+    /// Treat the certificate as revoked.
+    #[default]
+    Deny,
+    /// Treat the certificate as not revoked.
+    Allow,
+}
+
+impl From<UnknownCrlStatus> for UnknownStatusPolicy {
+    fn from(status: UnknownCrlStatus) -> Self {
+        match status {
+            UnknownCrlStatus::Deny => Self::Deny,
+            UnknownCrlStatus::Allow => Self::Allow,
+        }
+    }
+}
+
+/// Certificate revocation lists for the Nitro attestation chain.
+///
+/// CRLs are read from DER-encoded files in a local directory that is expected to be kept
+/// up to date by an external fetcher. A CRL is stale once its `nextUpdate` time has passed.
+pub struct CrlStore {
+    dir: PathBuf,
+    stale_policy: StaleCrlPolicy,
+    unknown_status: UnknownCrlStatus,
+    crls: RwLock<Arc<CrlSet>>,
+}
+
+pub(super) struct CrlSet {
+    crls: Vec<CertRevocationList<'static>>,
+    /// The earliest `nextUpdate` time of the CRLs.
+    next_update: Option<SystemTime>,
+    unknown_status: UnknownCrlStatus,
+}
+
+impl CrlStore {
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        stale_policy: StaleCrlPolicy,
+        unknown_status: UnknownCrlStatus,
+    ) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        let crls = CrlSet::load(&dir, unknown_status)?;
+        Ok(Self {
+            dir,
+            stale_policy,
+            unknown_status,
+            crls: RwLock::new(Arc::new(crls)),
+        })
+    }
+
+    /// Re-reads the CRL directory, keeping the previous CRLs if any cannot be loaded.
+    pub fn refresh(&self) -> Result<(), anyhow::Error> {
+        let crls = CrlSet::load(&self.dir, self.unknown_status)?;
+        *self.crls.write().unwrap() = Arc::new(crls);
+        Ok(())
+    }
+
+    /// Refreshes the CRLs every `interval` for as long as the store is in use.
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        let store = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                if let Err(e) = store.refresh() {
+                    tracing::warn!(error = ?e, dir = ?store.dir, "failed to refresh CRLs");
+                }
+            }
+        });
+    }
+
+    /// Returns the current CRLs, or an error if they are stale and stale CRLs are rejected.
+    pub(super) fn current(&self, now: SystemTime) -> Result<Arc<CrlSet>, anyhow::Error> {
+        let crls = self.crls.read().unwrap().clone();
+        let stale = crls.next_update.map(|t| now >= t).unwrap_or(true);
+        if stale {
+            match self.stale_policy {
+                StaleCrlPolicy::Reject => {
+                    return Err(anyhow!("certificate revocation lists are stale"))
+                }
+                StaleCrlPolicy::Warn => {
+                    tracing::warn!(dir = ?self.dir, "checking revocation using stale CRLs")
+                }
+            }
+        }
+        Ok(crls)
+    }
+}
+
+impl std::fmt::Debug for CrlStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CrlStore")
+            .field("dir", &self.dir)
+            .field("stale_policy", &self.stale_policy)
+            .field("unknown_status", &self.unknown_status)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CrlSet {
+    fn load(dir: &Path, unknown_status: UnknownCrlStatus) -> Result<Self, anyhow::Error> {
+        let mut crls = Vec::new();
+        let mut next_update: Option<SystemTime> = None;
+        for entry in std::fs::read_dir(dir).with_context(|| format!("failed to read {dir:?}"))? {
+            let entry = entry?;
+            if !entry.metadata()?.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let der = std::fs::read(&path)?;
+            let crl = OwnedCertRevocationList::from_der(&der)
+                .map_err(|e| anyhow!("failed to parse CRL {path:?}: {e:?}"))?;
+            crls.push(CertRevocationList::Owned(crl));
+            let crl_next_update = crl_next_update(&der)
+                .with_context(|| format!("failed to read the next update time of {path:?}"))?;
+            next_update = Some(next_update.map_or(crl_next_update, |t| t.min(crl_next_update)));
+        }
+        Ok(Self {
+            crls,
+            next_update,
+            unknown_status,
+        })
+    }
+
+    /// Returns options that check every certificate in the chain against the CRLs.
+    ///
+    /// Without any CRLs, the status of every certificate is unknown, so revocation is not
+    /// checked if that is allowed and is otherwise an error.
+    pub(super) fn revocation_options<'a>(
+        &self,
+        crls: &'a [&'a CertRevocationList<'a>],
+    ) -> Result<Option<RevocationOptions<'a>>, anyhow::Error> {
+        let Ok(builder) = RevocationOptionsBuilder::new(crls) else {
+            return match self.unknown_status {
+                UnknownCrlStatus::Allow => Ok(None),
+                UnknownCrlStatus::Deny => {
+                    Err(anyhow!("no certificate revocation lists are available"))
+                }
+            };
+        };
+        Ok(Some(
+            builder
+                .with_depth(RevocationCheckDepth::Chain)
+                .with_status_policy(self.unknown_status.into())
+                .build(),
+        ))
+    }
+
+    pub(super) fn crls(&self) -> Vec<&CertRevocationList<'static>> {
+        self.crls.iter().collect()
+    }
+}
+
+/// Returns the time by which the issuer will have published a newer CRL.
+fn crl_next_update(der: &[u8]) -> Result<SystemTime, anyhow::Error> {
+    let (_, crl) = x509_parser::parse_x509_crl(der).map_err(|e| anyhow!("{e}"))?;
+    let next_update = crl
+        .next_update()
+        .ok_or_else(|| anyhow!("CRL has no next update time"))?;
+    let secs = u64::try_from(next_update.timestamp())
+        .map_err(|_| anyhow!("CRL next update time is before the epoch"))?;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, CertificateRevocationListParams, DnType,
+        ExtendedKeyUsagePurpose, IsCa, KeyIdMethod, KeyPair, KeyUsagePurpose, RevokedCertParams,
+        SerialNumber,
+    };
+    use webpki::types::UnixTime;
+
+    use super::{super::*, *};
+
+    const INTERMEDIATE_SERIAL: u64 = 2;
+
+    struct TestPki {
+        root: Certificate,
+        root_key: KeyPair,
+        intermediate: Certificate,
+        intermediate_key: KeyPair,
+        leaf: Certificate,
+    }
+
+    fn params(name: &str, serial: u64, ca: bool) -> CertificateParams {
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.serial_number = Some(SerialNumber::from(serial));
+        if ca {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        } else {
+            params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        }
+        params
+    }
+
+    fn generate_pki() -> TestPki {
+        let alg = &rcgen::PKCS_ECDSA_P384_SHA384;
+        let root_key = KeyPair::generate_for(alg).unwrap();
+        let root = params("root", 1, true).self_signed(&root_key).unwrap();
+        let intermediate_key = KeyPair::generate_for(alg).unwrap();
+        let intermediate = params("intermediate", INTERMEDIATE_SERIAL, true)
+            .signed_by(&intermediate_key, &root, &root_key)
+            .unwrap();
+        let leaf_key = KeyPair::generate_for(alg).unwrap();
+        let leaf = params("leaf", 3, false)
+            .signed_by(&leaf_key, &intermediate, &intermediate_key)
+            .unwrap();
+        TestPki {
+            root,
+            root_key,
+            intermediate,
+            intermediate_key,
+            leaf,
+        }
+    }
+
+    fn make_crl(
+        issuer: &Certificate,
+        issuer_key: &KeyPair,
+        revoked: &[u64],
+        next_update_year: i32,
+    ) -> Vec<u8> {
+        CertificateRevocationListParams {
+            this_update: rcgen::date_time_ymd(2023, 1, 1),
+            next_update: rcgen::date_time_ymd(next_update_year, 1, 1),
+            crl_number: SerialNumber::from(1u64),
+            issuing_distribution_point: None,
+            revoked_certs: revoked
+                .iter()
+                .map(|&serial| RevokedCertParams {
+                    serial_number: SerialNumber::from(serial),
+                    revocation_time: rcgen::date_time_ymd(2023, 1, 1),
+                    reason_code: Some(rcgen::RevocationReason::KeyCompromise),
+                    invalidity_date: None,
+                })
+                .collect(),
+            key_identifier_method: KeyIdMethod::Sha256,
+        }
+        .signed_by(issuer, issuer_key)
+        .unwrap()
+        .der()
+        .to_vec()
+    }
+
+    /// Writes a CRL for each CA in the PKI, revoking the given serials in the root's CRL.
+    fn write_crls(pki: &TestPki, dir: &Path, revoked: &[u64], next_update_year: i32) {
+        std::fs::write(
+            dir.join("root.crl"),
+            make_crl(&pki.root, &pki.root_key, revoked, next_update_year),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("intermediate.crl"),
+            make_crl(&pki.intermediate, &pki.intermediate_key, &[], 3000),
+        )
+        .unwrap();
+    }
+
+    fn verify(pki: &TestPki, store: &CrlStore) -> Result<(), Error> {
+        let anchor = webpki::anchor_from_trusted_cert(pki.root.der()).unwrap();
+        let crls = store
+            .current(SystemTime::now())
+            .map_err(Error::AttestationDecode)?;
+        NitroEnclaveVerifier::verify_cert_chain(
+            &[anchor],
+            pki.leaf.der(),
+            &[pki.intermediate.der().clone()],
+            UnixTime::now(),
+            Some(&crls),
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn revoked_intermediate() {
+        let pki = generate_pki();
+        let dir = tempfile::tempdir().unwrap();
+
+        write_crls(&pki, dir.path(), &[], 3000);
+        let store = CrlStore::open(dir.path(), Default::default(), Default::default()).unwrap();
+        verify(&pki, &store).unwrap();
+
+        write_crls(&pki, dir.path(), &[INTERMEDIATE_SERIAL], 3000);
+        store.refresh().unwrap();
+        assert!(matches!(
+            verify(&pki, &store),
+            Err(Error::AttestationDecode(_))
+        ));
+
+        std::fs::write(dir.path().join("root.crl"), b"not a crl").unwrap();
+        assert!(store.refresh().is_err());
+        assert!(verify(&pki, &store).is_err(), "previous CRLs were not kept");
+    }
+
+    #[test]
+    fn unknown_status() {
+        let pki = generate_pki();
+        let dir = tempfile::tempdir().unwrap();
+        write_crls(&pki, dir.path(), &[], 3000);
+        std::fs::remove_file(dir.path().join("intermediate.crl")).unwrap();
+
+        // The intermediate has not published a CRL, so the leaf's status is unknown.
+        let store = CrlStore::open(dir.path(), Default::default(), UnknownCrlStatus::Deny).unwrap();
+        assert!(verify(&pki, &store).is_err());
+
+        let store =
+            CrlStore::open(dir.path(), Default::default(), UnknownCrlStatus::Allow).unwrap();
+        verify(&pki, &store).unwrap();
+    }
+
+    #[test]
+    fn stale_crls() {
+        let pki = generate_pki();
+        let dir = tempfile::tempdir().unwrap();
+        // The file is new, but the CRL says that a newer one has been published since.
+        write_crls(&pki, dir.path(), &[], 2024);
+
+        let store = CrlStore::open(dir.path(), StaleCrlPolicy::Reject, Default::default()).unwrap();
+        assert!(verify(&pki, &store).is_err());
+
+        let store = CrlStore::open(dir.path(), StaleCrlPolicy::Warn, Default::default()).unwrap();
+        verify(&pki, &store).unwrap();
+
+        let empty = tempfile::tempdir().unwrap();
+        let store =
+            CrlStore::open(empty.path(), StaleCrlPolicy::Reject, Default::default()).unwrap();
+        assert!(verify(&pki, &store).is_err());
+    }
+
+    #[test]
+    fn no_crls() {
+        let pki = generate_pki();
+        let empty = tempfile::tempdir().unwrap();
+
+        // Stale CRLs are allowed, but there are none against which to check the chain.
+        let store =
+            CrlStore::open(empty.path(), StaleCrlPolicy::Warn, UnknownCrlStatus::Deny).unwrap();
+        assert!(matches!(
+            verify(&pki, &store),
+            Err(Error::AttestationDecode(_))
+        ));
+
+        let store =
+            CrlStore::open(empty.path(), StaleCrlPolicy::Warn, UnknownCrlStatus::Allow).unwrap();
+        verify(&pki, &store).unwrap();
+    }
+}
@@ -1,91 +1,306 @@
-use std::collections::{HashMap, HashSet};
+mod crl;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
 
 use anyhow::anyhow;
-use ethers::{abi::AbiEncode as _, types::H256};
+use ethers::types::{Bytes, H256};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 use webpki::types::{CertificateDer, UnixTime};
 
+pub use self::crl::{CrlStore, StaleCrlPolicy, UnknownCrlStatus};
 use super::*;
+use crate::types::NonceKind;
 
 static CA_CERT_DER: &[u8] = include_bytes!("./root.der");
 static CA_CERT: Lazy<CertificateDer<'static>> = Lazy::new(|| CA_CERT_DER.into());
 static ANCHORS: Lazy<Vec<webpki::types::TrustAnchor<'static>>> =
     Lazy::new(|| vec![webpki::anchor_from_trusted_cert(&CA_CERT).unwrap()]);
 
-#[derive(Clone, Copy, Debug, Default)]
-pub struct NitroEnclaveVerifier;
+#[derive(Clone, Debug, Default)]
+pub struct NitroEnclaveVerifier {
+    crls: Option<Arc<CrlStore>>,
+}
+
+impl NitroEnclaveVerifier {
+    /// Checks the attestation certificate chain against the CRLs in the store.
+    pub fn with_crls(crls: Arc<CrlStore>) -> Self {
+        Self { crls: Some(crls) }
+    }
+}
 
 impl Verifier for NitroEnclaveVerifier {
+    fn name(&self) -> &str {
+        "nitro"
+    }
+
+    fn version(&self) -> u64 {
+        3
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        let pcr = serde_json::json!({
+            "type": "array",
+            "items": { "type": "integer", "minimum": 0, "maximum": 255 },
+            "minItems": 48,
+            "maxItems": 48,
+        });
+        let pcrs = serde_json::json!({
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "pcr0": pcr,
+                "pcr1": pcr,
+                "pcr2": pcr,
+                "pcr3": pcr,
+                "pcr4": pcr,
+                "pcr8": pcr,
+            },
+        });
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "AWS Nitro Enclave policy",
+            "type": "object",
+            "required": ["version"],
+            "additionalProperties": false,
+            "oneOf": [
+                {
+                    "properties": { "version": { "const": 1 } },
+                    "required": ["pcrs"],
+                },
+                {
+                    "properties": { "version": { "enum": [2, 3] } },
+                    "required": ["measurements"],
+                },
+            ],
+            "properties": {
+                "version": { "enum": [1, 2, 3] },
+                "pcrs": pcrs,
+                "measurements": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "required": ["pcrs"],
+                        "additionalProperties": false,
+                        "properties": {
+                            "pcrs": pcrs,
+                            "not_before": { "type": "integer", "minimum": 0 },
+                            "not_after": { "type": "integer", "minimum": 0 },
+                        },
+                    },
+                },
+                "max_duration": { "type": "integer", "minimum": 0 },
+                "relayers": {
+                    "type": "array",
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                },
+                "max_age": { "type": "integer", "minimum": 0 },
+                "require_challenge": { "type": "boolean" },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        Policy::decode(raw_policy).map(|_| ())
+    }
+
     async fn verify(
         &self,
         raw_policy: serde_json::Value,
-        req: RequestKind,
-        identity: IdentityLocator,
-        recipient: Address,
-        authorization: &[u8],
-        _context: &[u8],
-        relayer: Option<Address>,
+        req: VerificationRequest<'_>,
     ) -> Result<Verification, Error> {
-        let policy: Policy = serde_json::from_value(raw_policy)
-            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
-
-        if policy.version != 1 {
-            return Err(Error::PolicyDecode(anyhow::anyhow!(
-                "unsupported NE policy version {}",
-                policy.version
-            )));
-        }
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
 
-        if !policy.relayers.is_empty()
-            && !relayer
-                .map(|r| policy.relayers.contains(&r))
-                .unwrap_or_default()
-        {
-            return Err(Error::Unauthorized("not a trusted relayer".into()));
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        report.check(
+            EvaluationStep::new("relayer")
+                .expected(&policy.relayers)
+                .observed(req.relayer),
+            check_relayer(&policy.relayers, req.relayer),
+        )?;
+
+        let (sign1, doc) = match Self::decode_attestation_document(req.authorization) {
+            Ok(decoded) => decoded,
+            Err(e) => return Err(report.fail(EvaluationStep::new("attestation"), e)),
+        };
+
+        let now = UnixTime::now();
+        report.check(
+            EvaluationStep::new("timing")
+                .expected(serde_json::json!({ "max_age": policy.max_age }))
+                .observed(serde_json::json!({
+                    "timestamp": doc.timestamp / 1000,
+                    "now": now.as_secs(),
+                })),
+            Self::check_timing(doc.timestamp, policy.max_age, now),
+        )?;
+
+        match self.verify_attestation_signature(&sign1, &doc, now) {
+            Ok(()) => report.pass(EvaluationStep::new("attestation")),
+            Err(e) => return Err(report.fail(EvaluationStep::new("attestation"), e)),
         }
 
-        let (ud, pcrs) =
-            Self::verify_attestation_document(authorization, policy.max_age, UnixTime::now())?;
-        let binding = (ud.user_data.len() >= H256::len_bytes())
-            .then(|| &ud.user_data[0..H256::len_bytes()])
-            .ok_or(Error::InvalidBinding)?;
-
-        let expected_binding = ethers::core::utils::keccak256(
-            (
-                identity.chain,
-                identity.registry,
-                identity.id.0,
-                recipient,
-                matches!(req, RequestKind::Grant { .. }),
-            )
-                .encode(),
-        );
-
-        if binding != expected_binding {
-            return Err(Error::BindingMismatch(SmallVec::from_buf(expected_binding)));
+        let binding = (doc.user_data.len() >= H256::len_bytes())
+            .then(|| &doc.user_data[0..H256::len_bytes()]);
+        let binding_version = policy.binding_version();
+        let expected_binding = match request_binding(binding_version, &req) {
+            Ok(binding) => binding,
+            Err(e) => return Err(report.fail(EvaluationStep::new("binding"), e)),
+        };
+        report.check(
+            EvaluationStep::new("binding")
+                .expected(serde_json::json!({
+                    "version": binding_version,
+                    "binding": Bytes::from(expected_binding),
+                }))
+                .observed(binding.map(|b| Bytes::from(b.to_vec()))),
+            match binding {
+                None => Err(Error::InvalidBinding),
+                Some(binding) if binding != expected_binding => {
+                    Err(Error::BindingMismatch(SmallVec::from_buf(expected_binding)))
+                }
+                Some(_) => Ok(()),
+            },
+        )?;
+
+        let challenge = if policy.require_challenge {
+            let challenge = (doc.nonce.len() == H256::len_bytes())
+                .then(|| NonceId::new(NonceKind::Challenge, H256::from_slice(&doc.nonce)));
+            report.check(
+                EvaluationStep::new("challenge").observed(Bytes::from(doc.nonce.clone())),
+                match challenge {
+                    Some(_) => Ok(()),
+                    None => Err(Error::Unauthorized(
+                        "attestation nonce is not a challenge".into(),
+                    )),
+                },
+            )?;
+            challenge
+        } else {
+            None
+        };
+
+        let outcomes: Vec<_> = policy
+            .measurements
+            .iter()
+            .map(|m| m.check(&doc.pcrs, now.as_secs()))
+            .collect();
+        let matched = outcomes.iter().position(|o| o.is_match());
+        let step = EvaluationStep::new("pcrs");
+        let step = match matched {
+            Some(index) => step.observed(serde_json::json!({ "matched": index })),
+            None => step
+                .expected(
+                    outcomes
+                        .iter()
+                        .map(|o| o.expected_json())
+                        .collect::<Vec<_>>(),
+                )
+                .observed(
+                    outcomes
+                        .iter()
+                        .flat_map(|o| &o.mismatches)
+                        .map(|&(i, _)| (i, doc.pcrs.get(&i).map(hex::encode)))
+                        .collect::<BTreeMap<_, _>>(),
+                ),
+        };
+        report.check(
+            step,
+            match matched {
+                Some(_) => Ok(()),
+                None => Err(outcomes
+                    .iter()
+                    .find(|o| o.window_error.is_none())
+                    .and_then(|o| o.mismatches.first())
+                    .map(|&(i, _)| Error::PcrMismatch(i))
+                    .unwrap_or_else(|| Error::Timing("no PCR set is valid at this time".into()))),
+            },
+        )?;
+
+        // The payload is hashed rather than the whole document because the unprotected
+        // header and the ECDSA signature can be changed without invalidating the document.
+        let mut nonces = vec![NonceId::new(
+            NonceKind::Document,
+            ethers::core::utils::keccak256(sign1.payload.as_deref().unwrap_or_default()).into(),
+        )];
+        if !doc.nonce.is_empty() {
+            nonces.push(NonceId::new(
+                NonceKind::Nonce,
+                ethers::core::utils::keccak256(&doc.nonce).into(),
+            ));
         }
-
-        policy.pcrs.check(&pcrs)?;
-
-        Ok(Verification {
-            nonce: ud.nonce,
-            public_key: ud.public_key,
-            duration: match req {
+        let single_use = SingleUse {
+            nonces,
+            expiry: doc.timestamp / 1000 + policy.max_age,
+            challenge,
+        };
+
+        report.finish(Verification {
+            nonce: doc.nonce,
+            public_key: doc.public_key,
+            duration: match req.kind {
                 RequestKind::Grant { duration } => Some(duration.min(policy.max_duration)),
                 RequestKind::Revoke => None,
             },
+            measurement: matched,
+            single_use,
         })
     }
 }
 
 impl NitroEnclaveVerifier {
-    fn verify_attestation_document(
-        doc_bytes: &[u8],
-        max_age: u64,
+    fn verify_cert_chain<'a>(
+        anchors: &[webpki::types::TrustAnchor<'_>],
+        cert_der: &'a CertificateDer<'a>,
+        intermediates: &[CertificateDer<'_>],
         now: UnixTime,
-    ) -> Result<(AttestationUserData, HashMap<usize, Pcr>), Error> {
+        crls: Option<&crl::CrlSet>,
+    ) -> Result<webpki::EndEntityCert<'a>, Error> {
+        let ee_cert = webpki::EndEntityCert::try_from(cert_der)
+            .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))?;
+        let crl_refs = crls.map(crl::CrlSet::crls);
+        let revocation = match crls.zip(crl_refs.as_deref()) {
+            Some((crls, crl_refs)) => crls
+                .revocation_options(crl_refs)
+                .map_err(Error::AttestationDecode)?,
+            None => None,
+        };
+        ee_cert
+            .verify_for_usage(
+                webpki::ALL_VERIFICATION_ALGS,
+                anchors,
+                intermediates,
+                now,
+                webpki::KeyUsage::server_auth(),
+                revocation,
+                None,
+            )
+            .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))?;
+        Ok(ee_cert)
+    }
+
+    fn decode_attestation_document(
+        doc_bytes: &[u8],
+    ) -> Result<(coset::CoseSign1, AttestationDocument), Error> {
         let sign1 = <coset::CoseSign1 as coset::CborSerializable>::from_slice(doc_bytes)
             .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))?;
 
@@ -96,16 +311,28 @@ impl NitroEnclaveVerifier {
                 .ok_or_else(|| Error::AttestationDecode(anyhow!("missing Sign1 payload")))?,
             5,
         )
-        .unwrap();
+        .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))?;
+
+        Ok((sign1, doc))
+    }
 
-        let doc_timestamp_s = doc.timestamp / 1000;
+    fn check_timing(timestamp_ms: u64, max_age: u64, now: UnixTime) -> Result<(), Error> {
+        let doc_timestamp_s = timestamp_ms / 1000;
         if doc_timestamp_s > (now.as_secs() + 5) {
             return Err(Error::Timing("attestation doc not yet valid".into()));
         }
-        if now.as_secs() - doc_timestamp_s >= max_age {
+        if now.as_secs().saturating_sub(doc_timestamp_s) >= max_age {
             return Err(Error::Timing("attestation doc expired".into()));
         }
+        Ok(())
+    }
 
+    fn verify_attestation_signature(
+        &self,
+        sign1: &coset::CoseSign1,
+        doc: &AttestationDocument,
+        now: UnixTime,
+    ) -> Result<(), Error> {
         if doc.digest != "SHA384" {
             return Err(Error::AttestationDecode(anyhow!(
                 "unsupported attesation digest: {}",
@@ -113,37 +340,30 @@ impl NitroEnclaveVerifier {
             )));
         }
 
-        let cert_der = doc.certificate.into();
-        let ee_cert = webpki::EndEntityCert::try_from(&cert_der)
-            .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))?;
-        ee_cert
-            .verify_for_usage(
-                webpki::ALL_VERIFICATION_ALGS,
-                &ANCHORS,
-                &doc.cabundle
-                    .into_iter()
-                    .skip(1)
-                    .map(CertificateDer::from)
-                    .collect::<Vec<_>>(),
-                now,
-                webpki::KeyUsage::server_auth(),
-                None, // TODO: support CRL
-                None,
-            )
-            .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))?;
+        let crls = match &self.crls {
+            Some(store) => Some(
+                store
+                    .current(std::time::UNIX_EPOCH + std::time::Duration::from_secs(now.as_secs()))
+                    .map_err(Error::AttestationDecode)?,
+            ),
+            None => None,
+        };
+        let cert_der = CertificateDer::from(doc.certificate.as_slice());
+        let ee_cert = Self::verify_cert_chain(
+            &ANCHORS,
+            &cert_der,
+            &doc.cabundle
+                .iter()
+                .skip(1)
+                .map(|der| CertificateDer::from(der.as_slice()))
+                .collect::<Vec<_>>(),
+            now,
+            crls.as_deref(),
+        )?;
 
         sign1
             .verify_signature(&[], |sig, data| ee_cert.verify_signature(&ES384, data, sig))
-            .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))?;
-
-        Ok((
-            AttestationUserData {
-                user_data: doc.user_data,
-                public_key: doc.public_key,
-                nonce: doc.nonce,
-            },
-            doc.pcrs,
-        ))
+            .map_err(|e| Error::AttestationDecode(anyhow::Error::from(e)))
     }
 }
 
@@ -166,29 +386,16 @@ struct AttestationDocument {
     nonce: Vec<u8>,
 }
 
-#[derive(Default, Deserialize)]
-#[serde(default)]
-struct AttestationUserData {
-    public_key: Vec<u8>,
-    user_data: Vec<u8>,
-    nonce: Vec<u8>,
-}
-
-impl std::fmt::Debug for AttestationUserData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("AttestationUserData")
-            .field("public_key", &hex::encode(&self.public_key))
-            .field("user_data", &hex::encode(&self.user_data))
-            .field("nonce", &hex::encode(&self.nonce))
-            .finish()
-    }
-}
-
 #[derive(Deserialize)]
 #[forbid(unused)]
 struct Policy {
     version: u8,
-    pcrs: PolicyPcrs,
+    /// The PCRs of a version 1 policy, which become its only measurement set.
+    #[serde(default)]
+    pcrs: Option<PolicyPcrs>,
+    /// The acceptable measurement sets. Any one of them must match the attestation.
+    #[serde(default)]
+    measurements: Vec<Measurement>,
     #[serde(default)]
     max_duration: u64,
     #[serde(default)]
@@ -196,12 +403,118 @@ struct Policy {
     /// Attestation max age (seconds)
     #[serde(default = "default_max_age")]
     max_age: u64,
+    /// Whether the attestation nonce must be a challenge issued by this SSSS.
+    #[serde(default)]
+    require_challenge: bool,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let mut policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        match (policy.version, policy.pcrs.take()) {
+            (1, Some(pcrs)) if policy.measurements.is_empty() => {
+                policy.measurements = vec![Measurement {
+                    pcrs,
+                    not_before: None,
+                    not_after: None,
+                }];
+            }
+            (1, _) => {
+                return Err(Error::PolicyDecode(anyhow!(
+                    "version 1 policies must have `pcrs` and not `measurements`"
+                )))
+            }
+            (2 | 3, None) if !policy.measurements.is_empty() => {}
+            (version @ (2 | 3), _) => {
+                return Err(Error::PolicyDecode(anyhow!(
+                    "version {version} policies must have `measurements` and not `pcrs`"
+                )))
+            }
+            (version, _) => return Err(Error::UnsupportedPolicyVersion(version.into())),
+        }
+        for (i, m) in policy.measurements.iter().enumerate() {
+            m.validate()
+                .map_err(|e| Error::PolicyDecode(anyhow!("measurement {i}: {e}")))?;
+        }
+        Ok(policy)
+    }
+
+    /// Version 3 policies bind attestations to the whole request, including its context.
+    fn binding_version(&self) -> u8 {
+        if self.version >= 3 {
+            2
+        } else {
+            1
+        }
+    }
 }
 
 fn default_max_age() -> u64 {
     15 * 60 // 15 minutes
 }
 
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Measurement {
+    pcrs: PolicyPcrs,
+    /// The time (unix seconds) before which this measurement set is not accepted.
+    #[serde(default)]
+    not_before: Option<u64>,
+    /// The time (unix seconds) after which this measurement set is no longer accepted.
+    #[serde(default)]
+    not_after: Option<u64>,
+}
+
+impl Measurement {
+    fn validate(&self) -> Result<(), Error> {
+        if let (Some(not_before), Some(not_after)) = (self.not_before, self.not_after) {
+            if not_before > not_after {
+                return Err(Error::PolicyDecode(anyhow!(
+                    "not_before is after not_after"
+                )));
+            }
+        }
+        self.pcrs.validate()
+    }
+
+    fn check(&self, pcr_map: &HashMap<usize, Pcr>, now: u64) -> MeasurementOutcome<'_> {
+        let window_error = if self.not_before.is_some_and(|t| now < t) {
+            Some("not yet valid")
+        } else if self.not_after.is_some_and(|t| now > t) {
+            Some("expired")
+        } else {
+            None
+        };
+        MeasurementOutcome {
+            window_error,
+            mismatches: self.pcrs.mismatches(pcr_map),
+        }
+    }
+}
+
+struct MeasurementOutcome<'a> {
+    window_error: Option<&'static str>,
+    mismatches: Vec<(usize, &'a Pcr)>,
+}
+
+impl MeasurementOutcome<'_> {
+    fn is_match(&self) -> bool {
+        self.window_error.is_none() && self.mismatches.is_empty()
+    }
+
+    fn expected_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "window": self.window_error,
+            "mismatched": self
+                .mismatches
+                .iter()
+                .map(|&(i, pcr)| (i, hex::encode(pcr)))
+                .collect::<BTreeMap<_, _>>(),
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct PolicyPcrs {
     /// A contiguous measure of the contents of the image file, without the section data.
@@ -225,60 +538,42 @@ struct PolicyPcrs {
 }
 
 impl PolicyPcrs {
-    fn check(&self, pcr_map: &HashMap<usize, Pcr>) -> Result<(), Error> {
-        let PolicyPcrs {
-            pcr0,
-            pcr1,
-            pcr2,
-            pcr3,
-            pcr4,
-            pcr8,
-        } = &self;
-        macro_rules! check_pcrs {
-            ($($pcr:literal),+ $(,)?) => {
-                $(
-                    if let Some(expected_pcr) = &paste::paste!([< pcr $pcr >]) {
-                        if pcr_map.get(&$pcr).ok_or(Error::PcrMismatch($pcr))? != expected_pcr {
-                            return Err(Error::PcrMismatch($pcr));
-                        }
-                    }
-                )+
+    /// Rejects PCRs that could never match, as all Nitro PCRs are SHA-384 digests.
+    fn validate(&self) -> Result<(), Error> {
+        for (i, pcr) in self.entries() {
+            if let Some(pcr) = pcr.as_ref().filter(|pcr| pcr.len() != 48) {
+                return Err(Error::PolicyDecode(anyhow!(
+                    "pcr{i} has length {} but must be 48 bytes",
+                    pcr.len()
+                )));
             }
         }
-        check_pcrs!(0, 1, 2, 3, 4, 8);
         Ok(())
     }
-}
-
-type Pcr = SmallVec<[u8; 48]>;
-
-#[derive(Clone, Copy, Debug)]
-struct ES384;
-
-impl webpki::types::SignatureVerificationAlgorithm for ES384 {
-    fn public_key_alg_id(&self) -> webpki::types::AlgorithmIdentifier {
-        webpki::alg_id::ECDSA_P384
-    }
 
-    fn signature_alg_id(&self) -> webpki::types::AlgorithmIdentifier {
-        webpki::alg_id::ECDSA_SHA384
+    /// Returns the index and expected value of each PCR that does not match the attestation.
+    fn mismatches(&self, pcr_map: &HashMap<usize, Pcr>) -> Vec<(usize, &Pcr)> {
+        self.entries()
+            .into_iter()
+            .filter_map(|(i, pcr)| Some((i, pcr.as_ref()?)))
+            .filter(|(i, expected)| pcr_map.get(i) != Some(*expected))
+            .collect()
     }
 
-    fn verify_signature(
-        &self,
-        public_key: &[u8],
-        message: &[u8],
-        signature: &[u8],
-    ) -> Result<(), webpki::types::InvalidSignature> {
-        ring::signature::UnparsedPublicKey::new(
-            &ring::signature::ECDSA_P384_SHA384_FIXED,
-            public_key,
-        )
-        .verify(message, signature)
-        .map_err(|_| webpki::types::InvalidSignature)
+    fn entries(&self) -> [(usize, &Option<Pcr>); 6] {
+        [
+            (0, &self.pcr0),
+            (1, &self.pcr1),
+            (2, &self.pcr2),
+            (3, &self.pcr3),
+            (4, &self.pcr4),
+            (8, &self.pcr8),
+        ]
     }
 }
 
+type Pcr = SmallVec<[u8; 48]>;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -292,11 +587,102 @@ mod tests {
                     .join("../evm/test/identity/v1/permitters/att_doc_sample.bin")
             });
         let attestation_doc = std::fs::read(attestation_doc_path).unwrap();
-        NitroEnclaveVerifier::verify_attestation_document(
-            &attestation_doc,
-            u64::max_value(),
-            UnixTime::since_unix_epoch(std::time::Duration::from_secs(1703101376)),
-        )
+        let (sign1, doc) =
+            NitroEnclaveVerifier::decode_attestation_document(&attestation_doc).unwrap();
+        NitroEnclaveVerifier::default()
+            .verify_attestation_signature(
+                &sign1,
+                &doc,
+                UnixTime::since_unix_epoch(std::time::Duration::from_secs(1703101376)),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn validate_policy() {
+        let pcr = vec![0u8; 48];
+        let verifier = NitroEnclaveVerifier::default();
+        verifier
+            .validate(serde_json::json!({ "version": 1, "pcrs": { "pcr0": pcr } }))
+            .unwrap();
+        verifier
+            .validate(serde_json::json!({
+                "version": 2,
+                "measurements": [{ "pcrs": { "pcr0": pcr }, "not_after": 1000 }],
+                "require_challenge": true,
+            }))
+            .unwrap();
+        assert!(matches!(
+            verifier.validate(serde_json::json!({ "version": 3, "pcrs": {} })),
+            Err(Error::PolicyDecode(_))
+        ));
+        assert!(matches!(
+            verifier.validate(serde_json::json!({ "version": 4, "pcrs": {} })),
+            Err(Error::UnsupportedPolicyVersion(4))
+        ));
+        assert!(matches!(
+            verifier.validate(serde_json::json!({ "version": 2, "pcrs": {} })),
+            Err(Error::PolicyDecode(_))
+        ));
+        assert!(matches!(
+            verifier.validate(serde_json::json!({ "version": 2, "measurements": [] })),
+            Err(Error::PolicyDecode(_))
+        ));
+        assert!(matches!(
+            verifier.validate(serde_json::json!({
+                "version": 2,
+                "measurements": [{ "pcrs": {}, "not_before": 2, "not_after": 1 }],
+            })),
+            Err(Error::PolicyDecode(_))
+        ));
+        assert!(matches!(
+            verifier.validate(serde_json::json!({ "version": 1, "pcrs": { "pcr0": [1, 2, 3] } })),
+            Err(Error::PolicyDecode(_))
+        ));
+        assert!(matches!(
+            verifier.validate(serde_json::json!({ "version": 1, "pcrs": { "pcr0": "0xzz" } })),
+            Err(Error::PolicyDecode(_))
+        ));
+    }
+
+    #[test]
+    fn binding_version() {
+        let policy = |version| {
+            Policy::decode(serde_json::json!({
+                "version": version,
+                "measurements": [{ "pcrs": {} }],
+            }))
+            .unwrap()
+            .binding_version()
+        };
+        assert_eq!(policy(2), 1);
+        assert_eq!(policy(3), 2);
+    }
+
+    #[test]
+    fn match_measurements() {
+        let old = vec![1u8; 48];
+        let new = vec![2u8; 48];
+        let policy = Policy::decode(serde_json::json!({
+            "version": 2,
+            "measurements": [
+                { "pcrs": { "pcr0": old }, "not_after": 2000 },
+                { "pcrs": { "pcr0": new }, "not_before": 1000 },
+            ],
+        }))
         .unwrap();
+        let matched = |pcr0: &[u8], now| {
+            let pcrs = HashMap::from([(0, Pcr::from_slice(pcr0))]);
+            policy
+                .measurements
+                .iter()
+                .position(|m| m.check(&pcrs, now).is_match())
+        };
+        assert_eq!(matched(&old, 500), Some(0));
+        assert_eq!(matched(&new, 500), None);
+        assert_eq!(matched(&old, 1500), Some(0));
+        assert_eq!(matched(&new, 1500), Some(1));
+        assert_eq!(matched(&old, 2500), None);
+        assert_eq!(matched(&new, 2500), Some(1));
     }
 }
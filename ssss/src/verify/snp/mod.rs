@@ -0,0 +1,800 @@
+mod report;
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, ensure, Context as _};
+use ethers::types::{Bytes, H256};
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use webpki::types::{
+    AlgorithmIdentifier, CertificateDer, InvalidSignature, SignatureVerificationAlgorithm, UnixTime,
+};
+
+use self::report::{AttestationReport, TcbVersion, REPORT_LEN};
+use super::*;
+use crate::types::NonceKind;
+
+const OID_BL_SPL: &str = "1.3.6.1.4.1.3704.1.3.1";
+const OID_TEE_SPL: &str = "1.3.6.1.4.1.3704.1.3.2";
+const OID_SNP_SPL: &str = "1.3.6.1.4.1.3704.1.3.3";
+const OID_UCODE_SPL: &str = "1.3.6.1.4.1.3704.1.3.8";
+const OID_HW_ID: &str = "1.3.6.1.4.1.3704.1.4";
+
+static MILAN_ARK: &[u8] = include_bytes!("./ark-milan.der");
+static GENOA_ARK: &[u8] = include_bytes!("./ark-genoa.der");
+
+/// The algorithms that AMD certificates may be signed with.
+static CERT_ALGS: &[&dyn SignatureVerificationAlgorithm] =
+    &[&AmdRsaPssSha384, webpki::ring::ECDSA_P384_SHA384];
+
+/// Verifies AMD SEV-SNP attestation reports.
+///
+/// The authorization is the raw attestation report, optionally followed by the DER-encoded
+/// VCEK that signed it. The first half of the report data must be the version 2 request
+/// binding, and the second half a challenge issued by this SSSS, as reports carry no timestamp.
+///
+/// The AMD root keys (ARKs) are built in. The other AMD certificates are read from the
+/// certificate directory, which has a subdirectory for each product (e.g., `milan`) that
+/// contains the product's `ask.der`, and a `vcek` directory of VCEKs named
+/// `<chip_id>-<reported_tcb>.der` in lowercase hex.
+#[derive(Clone, Debug, Default)]
+pub struct SnpVerifier {
+    cert_dir: Option<Arc<PathBuf>>,
+    arks: Arks,
+}
+
+impl SnpVerifier {
+    pub fn with_cert_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_dir: Some(Arc::new(dir.into())),
+            ..Default::default()
+        }
+    }
+}
+
+/// The DER-encoded ARK of each product, which all of the product's VCEKs must chain to.
+#[derive(Clone, Debug)]
+struct Arks {
+    milan: Cow<'static, [u8]>,
+    genoa: Cow<'static, [u8]>,
+}
+
+impl Default for Arks {
+    fn default() -> Self {
+        Self {
+            milan: MILAN_ARK.into(),
+            genoa: GENOA_ARK.into(),
+        }
+    }
+}
+
+impl Arks {
+    fn get(&self, product: Product) -> &[u8] {
+        match product {
+            Product::Milan => &self.milan,
+            Product::Genoa => &self.genoa,
+        }
+    }
+}
+
+impl Verifier for SnpVerifier {
+    fn name(&self) -> &str {
+        "snp"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        let spl = serde_json::json!({ "type": "integer", "minimum": 0, "maximum": 255 });
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "AMD SEV-SNP policy",
+            "type": "object",
+            "required": ["version", "product", "measurements"],
+            "additionalProperties": false,
+            "properties": {
+                "version": { "const": 1 },
+                "product": { "enum": ["milan", "genoa"] },
+                "measurements": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{96}$" },
+                },
+                "min_tcb": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "bootloader": spl,
+                        "tee": spl,
+                        "snp": spl,
+                        "microcode": spl,
+                    },
+                },
+                "min_guest_svn": { "type": "integer", "minimum": 0 },
+                "allow_debug": { "type": "boolean" },
+                "allow_migration_agent": { "type": "boolean" },
+                "vmpl": { "type": "integer", "minimum": 0, "maximum": 3, "default": 0 },
+                "max_duration": { "type": "integer", "minimum": 0 },
+                "relayers": {
+                    "type": "array",
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        Policy::decode(raw_policy).map(|_| ())
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        report.check(
+            EvaluationStep::new("relayer")
+                .expected(&policy.relayers)
+                .observed(req.relayer),
+            check_relayer(&policy.relayers, req.relayer),
+        )?;
+
+        let (report_bytes, attached_vcek) = req
+            .authorization
+            .split_at(req.authorization.len().min(REPORT_LEN));
+        let snp_report = match AttestationReport::parse(report_bytes) {
+            Ok(snp_report) => snp_report,
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("attestation"),
+                    Error::AttestationDecode(e),
+                ))
+            }
+        };
+
+        let step = EvaluationStep::new("attestation").observed(serde_json::json!({
+            "chip_id": Bytes::from(snp_report.chip_id.to_vec()),
+            "reported_tcb": snp_report.reported_tcb,
+        }));
+        match self.verify_report(policy.product, &snp_report, attached_vcek, UnixTime::now()) {
+            Ok(()) => report.pass(step),
+            Err(e) => return Err(report.fail(step, Error::AttestationDecode(e))),
+        }
+
+        let expected_binding = match request_binding(2, &req) {
+            Ok(binding) => binding,
+            Err(e) => return Err(report.fail(EvaluationStep::new("binding"), e)),
+        };
+        report.check(
+            EvaluationStep::new("binding")
+                .expected(Bytes::from(expected_binding))
+                .observed(Bytes::from(snp_report.binding().to_vec())),
+            if snp_report.binding() == expected_binding {
+                Ok(())
+            } else {
+                Err(Error::BindingMismatch(SmallVec::from_buf(expected_binding)))
+            },
+        )?;
+
+        let challenge = NonceId::new(NonceKind::Challenge, H256::from_slice(snp_report.nonce()));
+        report.pass(EvaluationStep::new("challenge").observed(challenge.digest));
+
+        let matched = policy
+            .measurements
+            .iter()
+            .position(|m| m.as_ref() == &snp_report.measurement[..]);
+        report.check(
+            EvaluationStep::new("measurement")
+                .expected(&policy.measurements)
+                .observed(Bytes::from(snp_report.measurement.to_vec())),
+            match matched {
+                Some(_) => Ok(()),
+                None => Err(Error::Unauthorized(
+                    "unrecognized launch measurement".into(),
+                )),
+            },
+        )?;
+
+        report.check(
+            EvaluationStep::new("guest-policy")
+                .expected(serde_json::json!({
+                    "allow_debug": policy.allow_debug,
+                    "allow_migration_agent": policy.allow_migration_agent,
+                    "vmpl": policy.vmpl,
+                }))
+                .observed(serde_json::json!({
+                    "policy": format!("{:#x}", snp_report.policy.0),
+                    "vmpl": snp_report.vmpl,
+                })),
+            policy.check_guest(&snp_report),
+        )?;
+
+        report.check(
+            EvaluationStep::new("tcb")
+                .expected(serde_json::json!({
+                    "min_tcb": policy.min_tcb,
+                    "min_guest_svn": policy.min_guest_svn,
+                }))
+                .observed(serde_json::json!({
+                    "reported_tcb": snp_report.reported_tcb,
+                    "guest_svn": snp_report.guest_svn,
+                })),
+            if !snp_report.reported_tcb.meets(&policy.min_tcb) {
+                Err(Error::Unauthorized(
+                    "platform TCB is below the minimum".into(),
+                ))
+            } else if snp_report.guest_svn < policy.min_guest_svn {
+                Err(Error::Unauthorized("guest SVN is below the minimum".into()))
+            } else {
+                Ok(())
+            },
+        )?;
+
+        report.finish(Verification {
+            nonce: snp_report.nonce().to_vec(),
+            public_key: vec![],
+            duration: match req.kind {
+                RequestKind::Grant { duration } => Some(duration.min(policy.max_duration)),
+                RequestKind::Revoke => None,
+            },
+            measurement: matched,
+            single_use: SingleUse {
+                challenge: Some(challenge),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+impl SnpVerifier {
+    /// Checks that the report was signed by a VCEK that AMD issued for the reporting chip at
+    /// the reported TCB.
+    fn verify_report(
+        &self,
+        product: Product,
+        snp_report: &AttestationReport<'_>,
+        attached_vcek: &[u8],
+        now: UnixTime,
+    ) -> Result<(), anyhow::Error> {
+        let dir = self
+            .cert_dir
+            .as_deref()
+            .ok_or_else(|| anyhow!("no AMD certificates are available"))?
+            .join(product.to_string());
+        let ask = read_cert(&dir.join("ask.der"))?;
+        let vcek = if attached_vcek.is_empty() {
+            read_cert(&dir.join("vcek").join(format!(
+                "{}-{:016x}.der",
+                hex::encode(snp_report.chip_id),
+                u64::from(snp_report.reported_tcb),
+            )))?
+        } else {
+            attached_vcek.to_vec()
+        };
+        Self::verify_cert_chain(self.arks.get(product), &ask, &vcek, snp_report, now)
+    }
+
+    fn verify_cert_chain(
+        ark: &[u8],
+        ask: &[u8],
+        vcek: &[u8],
+        snp_report: &AttestationReport<'_>,
+        now: UnixTime,
+    ) -> Result<(), anyhow::Error> {
+        let ark = CertificateDer::from(ark);
+        let anchor = webpki::anchor_from_trusted_cert(&ark)?;
+        let vcek_der = CertificateDer::from(vcek);
+        let vcek_cert = webpki::EndEntityCert::try_from(&vcek_der)?;
+        vcek_cert.verify_for_usage(
+            CERT_ALGS,
+            &[anchor],
+            &[CertificateDer::from(ask)],
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+            None,
+        )?;
+        check_vcek_extensions(vcek, snp_report)?;
+        vcek_cert
+            .verify_signature(&ES384, snp_report.signed_bytes(), &snp_report.signature()?)
+            .map_err(|e| anyhow!("invalid attestation report signature: {e}"))
+    }
+}
+
+/// RSASSA-PSS with SHA-384 as AMD encodes it, with the default trailer field made explicit,
+/// which webpki does not otherwise accept.
+#[derive(Debug)]
+struct AmdRsaPssSha384;
+
+impl SignatureVerificationAlgorithm for AmdRsaPssSha384 {
+    fn public_key_alg_id(&self) -> AlgorithmIdentifier {
+        webpki::alg_id::RSA_ENCRYPTION
+    }
+
+    fn signature_alg_id(&self) -> AlgorithmIdentifier {
+        AlgorithmIdentifier::from_slice(&[
+            0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a, // id-RSASSA-PSS
+            0x30, 0x39, // RSASSA-PSS-params
+            0xa0, 0x0f, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x02, 0x05, 0x00, // hashAlgorithm: sha384
+            0xa1, 0x1c, 0x30, 0x1a, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01,
+            0x08, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02,
+            0x05, 0x00, // maskGenAlgorithm: mgf1 with sha384
+            0xa2, 0x03, 0x02, 0x01, 0x30, // saltLength: 48
+            0xa3, 0x03, 0x02, 0x01, 0x01, // trailerField: 1
+        ])
+    }
+
+    fn verify_signature(
+        &self,
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), InvalidSignature> {
+        ring::signature::UnparsedPublicKey::new(
+            &ring::signature::RSA_PSS_2048_8192_SHA384,
+            public_key,
+        )
+        .verify(message, signature)
+        .map_err(|_| InvalidSignature)
+    }
+}
+
+fn read_cert(path: &Path) -> Result<Vec<u8>, anyhow::Error> {
+    std::fs::read(path).with_context(|| format!("failed to read {path:?}"))
+}
+
+/// Checks that the VCEK was issued for the chip and TCB in the report.
+fn check_vcek_extensions(
+    vcek: &[u8],
+    snp_report: &AttestationReport<'_>,
+) -> Result<(), anyhow::Error> {
+    let (_, cert) = x509_parser::parse_x509_certificate(vcek)
+        .map_err(|e| anyhow!("failed to parse VCEK: {e}"))?;
+    let mut tcb = TcbVersion::default();
+    let mut hw_id = None;
+    for ext in cert.extensions() {
+        match ext.oid.to_id_string().as_str() {
+            OID_BL_SPL => tcb.bootloader = der_u8(ext.value)?,
+            OID_TEE_SPL => tcb.tee = der_u8(ext.value)?,
+            OID_SNP_SPL => tcb.snp = der_u8(ext.value)?,
+            OID_UCODE_SPL => tcb.microcode = der_u8(ext.value)?,
+            OID_HW_ID => hw_id = Some(der_octets(ext.value)?),
+            _ => {}
+        }
+    }
+    ensure!(
+        tcb == snp_report.reported_tcb,
+        "VCEK was issued for TCB {tcb:?}, not {:?}",
+        snp_report.reported_tcb
+    );
+    ensure!(
+        hw_id == Some(&snp_report.chip_id[..]),
+        "VCEK was not issued for the reporting chip"
+    );
+    Ok(())
+}
+
+/// Decodes a DER INTEGER holding a security patch level.
+fn der_u8(value: &[u8]) -> Result<u8, anyhow::Error> {
+    match *value {
+        [0x02, 1, v] if v < 0x80 => Ok(v),
+        [0x02, 2, 0, v] if v >= 0x80 => Ok(v),
+        _ => Err(anyhow!("malformed VCEK TCB extension")),
+    }
+}
+
+/// Decodes the hardware ID, which may be a DER OCTET STRING or the raw bytes.
+fn der_octets(value: &[u8]) -> Result<&[u8], anyhow::Error> {
+    match value {
+        [0x04, 0x40, rest @ ..] if rest.len() == 0x40 => Ok(rest),
+        _ if value.len() == 0x40 => Ok(value),
+        _ => Err(anyhow!("malformed VCEK hardware ID extension")),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Product {
+    Milan,
+    Genoa,
+}
+
+impl std::fmt::Display for Product {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Milan => "milan",
+            Self::Genoa => "genoa",
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    version: u8,
+    product: Product,
+    /// The accepted launch measurements.
+    measurements: Vec<Bytes>,
+    /// The minimum security patch levels of the platform firmware.
+    #[serde(default)]
+    min_tcb: TcbVersion,
+    #[serde(default)]
+    min_guest_svn: u32,
+    #[serde(default)]
+    allow_debug: bool,
+    #[serde(default)]
+    allow_migration_agent: bool,
+    /// The VMPL from which the report must have been requested. Only VMPL 0 is accepted
+    /// unless the policy names a less privileged level.
+    #[serde(default)]
+    vmpl: u32,
+    #[serde(default)]
+    max_duration: u64,
+    #[serde(default)]
+    relayers: HashSet<Address>,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        if policy.measurements.is_empty() {
+            return Err(Error::PolicyDecode(anyhow!(
+                "at least one measurement is required"
+            )));
+        }
+        if let Some((i, m)) = policy
+            .measurements
+            .iter()
+            .enumerate()
+            .find(|(_, m)| m.len() != 48)
+        {
+            return Err(Error::PolicyDecode(anyhow!(
+                "measurement {i} has length {} but must be 48 bytes",
+                m.len()
+            )));
+        }
+        Ok(policy)
+    }
+
+    fn check_guest(&self, snp_report: &AttestationReport<'_>) -> Result<(), Error> {
+        if snp_report.policy.debug() && !self.allow_debug {
+            return Err(Error::Unauthorized("guest allows debugging".into()));
+        }
+        if snp_report.policy.migrate_ma() && !self.allow_migration_agent {
+            return Err(Error::Unauthorized("guest allows a migration agent".into()));
+        }
+        if snp_report.vmpl != self.vmpl {
+            return Err(Error::Unauthorized(
+                "report was requested from an unexpected VMPL".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{Certificate, CertificateParams, CustomExtension, DnType, KeyPair};
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, ECDSA_P384_SHA384_FIXED_SIGNING},
+    };
+
+    use super::{report::ReportBuilder, *};
+    use crate::verify::tests::{ca_params, request};
+
+    const MEASUREMENT: [u8; 48] = [7; 48];
+    const CHIP_ID: [u8; 64] = [9; 64];
+    const TCB: TcbVersion = TcbVersion {
+        bootloader: 3,
+        tee: 0,
+        snp: 14,
+        microcode: 209,
+    };
+
+    struct TestPlatform {
+        dir: tempfile::TempDir,
+        ark: Vec<u8>,
+        vcek_key: EcdsaKeyPair,
+        vcek: Vec<u8>,
+    }
+
+    fn der_int(v: u8) -> Vec<u8> {
+        if v < 0x80 {
+            vec![0x02, 1, v]
+        } else {
+            vec![0x02, 2, 0, v]
+        }
+    }
+
+    fn oid(s: &str) -> Vec<u64> {
+        s.split('.').map(|arc| arc.parse().unwrap()).collect()
+    }
+
+    fn make_vcek(
+        ask: &Certificate,
+        ask_key: &KeyPair,
+        vcek_key: &KeyPair,
+        tcb: TcbVersion,
+    ) -> Vec<u8> {
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "SEV-VCEK");
+        let mut hw_id = vec![0x04, 0x40];
+        hw_id.extend(CHIP_ID);
+        params.custom_extensions = vec![
+            CustomExtension::from_oid_content(&oid(OID_BL_SPL), der_int(tcb.bootloader)),
+            CustomExtension::from_oid_content(&oid(OID_TEE_SPL), der_int(tcb.tee)),
+            CustomExtension::from_oid_content(&oid(OID_SNP_SPL), der_int(tcb.snp)),
+            CustomExtension::from_oid_content(&oid(OID_UCODE_SPL), der_int(tcb.microcode)),
+            CustomExtension::from_oid_content(&oid(OID_HW_ID), hw_id),
+        ];
+        params
+            .signed_by(vcek_key, ask, ask_key)
+            .unwrap()
+            .der()
+            .to_vec()
+    }
+
+    /// Sets up a certificate directory for a platform whose AMD certificates are replaced
+    /// by generated ones.
+    fn make_platform() -> TestPlatform {
+        let alg = &rcgen::PKCS_ECDSA_P384_SHA384;
+        let ark_key = KeyPair::generate_for(alg).unwrap();
+        let ark = ca_params("ARK-Milan").self_signed(&ark_key).unwrap();
+        let ask_key = KeyPair::generate_for(alg).unwrap();
+        let ask = ca_params("SEV-Milan")
+            .signed_by(&ask_key, &ark, &ark_key)
+            .unwrap();
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, &rng).unwrap();
+        let vcek_key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+        let vcek_cert_key = KeyPair::try_from(pkcs8.as_ref()).unwrap();
+        let vcek = make_vcek(&ask, &ask_key, &vcek_cert_key, TCB);
+
+        let dir = tempfile::tempdir().unwrap();
+        let milan = dir.path().join("milan");
+        std::fs::create_dir_all(milan.join("vcek")).unwrap();
+        std::fs::write(milan.join("ask.der"), ask.der()).unwrap();
+        std::fs::write(
+            milan.join("vcek").join(format!(
+                "{}-{:016x}.der",
+                hex::encode(CHIP_ID),
+                u64::from(TCB)
+            )),
+            &vcek,
+        )
+        .unwrap();
+
+        // A VCEK for an older TCB, as would be used by downgraded firmware.
+        let stale_vcek = make_vcek(&ask, &ask_key, &vcek_cert_key, TcbVersion { snp: 8, ..TCB });
+        std::fs::write(dir.path().join("stale-vcek.der"), stale_vcek).unwrap();
+
+        TestPlatform {
+            dir,
+            ark: ark.der().to_vec(),
+            vcek_key,
+            vcek,
+        }
+    }
+
+    impl TestPlatform {
+        fn report(&self, build: impl FnOnce(ReportBuilder) -> ReportBuilder) -> Vec<u8> {
+            build(
+                ReportBuilder::new()
+                    .report_data(&request_binding(2, &request(&[])).unwrap(), &[5; 32])
+                    .measurement(&MEASUREMENT)
+                    .chip_id(&CHIP_ID)
+                    .reported_tcb(TCB),
+            )
+            .sign(|data| {
+                self.vcek_key
+                    .sign(&SystemRandom::new(), data)
+                    .unwrap()
+                    .as_ref()
+                    .to_vec()
+            })
+        }
+
+        async fn verify(
+            &self,
+            policy: serde_json::Value,
+            authorization: &[u8],
+        ) -> Result<Verification, Error> {
+            let verifier = SnpVerifier {
+                arks: Arks {
+                    milan: self.ark.clone().into(),
+                    ..Default::default()
+                },
+                ..SnpVerifier::with_cert_dir(self.dir.path())
+            };
+            verifier.verify(policy, request(authorization)).await
+        }
+    }
+
+    fn policy() -> serde_json::Value {
+        serde_json::json!({
+            "version": 1,
+            "product": "milan",
+            "measurements": [Bytes::from([1; 48]), Bytes::from(MEASUREMENT)],
+            "min_tcb": { "snp": 14 },
+            "max_duration": 30,
+        })
+    }
+
+    #[tokio::test]
+    async fn verify_report() {
+        let platform = make_platform();
+        let report = platform.report(|r| r);
+
+        let verification = platform.verify(policy(), &report).await.unwrap();
+        assert_eq!(verification.measurement, Some(1));
+        assert_eq!(verification.duration, Some(30));
+        assert_eq!(
+            verification.single_use.challenge,
+            Some(NonceId::new(NonceKind::Challenge, H256([5; 32])))
+        );
+
+        // The VCEK may be attached instead of cached.
+        let attached = [report.as_slice(), &platform.vcek].concat();
+        std::fs::remove_dir_all(platform.dir.path().join("milan/vcek")).unwrap();
+        platform.verify(policy(), &attached).await.unwrap();
+        assert!(matches!(
+            platform.verify(policy(), &report).await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_report() {
+        let platform = make_platform();
+        let report = platform.report(|r| r);
+
+        let mut tampered = report.clone();
+        tampered[0x90] ^= 1;
+        assert!(matches!(
+            platform.verify(policy(), &tampered).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        let stale_vcek = std::fs::read(platform.dir.path().join("stale-vcek.der")).unwrap();
+        assert!(matches!(
+            platform
+                .verify(policy(), &[report.as_slice(), &stale_vcek].concat())
+                .await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        let unbound = platform.report(|r| r.report_data(&[0; 32], &[5; 32]));
+        assert!(matches!(
+            platform.verify(policy(), &unbound).await,
+            Err(Error::BindingMismatch(_))
+        ));
+
+        let unknown = platform.report(|r| r.measurement(&[2; 48]));
+        assert!(matches!(
+            platform.verify(policy(), &unknown).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let debug = platform.report(|r| r.policy(0x30000 | 1 << 19));
+        assert!(matches!(
+            platform.verify(policy(), &debug).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let vmpl2 = platform.report(|r| r.vmpl(2));
+        assert!(matches!(
+            platform.verify(policy(), &vmpl2).await,
+            Err(Error::Unauthorized(_))
+        ));
+        let mut lenient = policy();
+        lenient["vmpl"] = 2.into();
+        platform.verify(lenient.clone(), &vmpl2).await.unwrap();
+        assert!(matches!(
+            platform.verify(lenient, &report).await,
+            Err(Error::Unauthorized(_))
+        ));
+
+        let mut strict = policy();
+        strict["min_tcb"]["microcode"] = 210.into();
+        assert!(matches!(
+            platform.verify(strict, &report).await,
+            Err(Error::Unauthorized(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn untrusted_ark() {
+        let platform = make_platform();
+        let report = platform.report(|r| r);
+        // Only the built-in ARKs are trusted, whatever the certificate directory contains.
+        std::fs::write(platform.dir.path().join("milan/ark.der"), &platform.ark).unwrap();
+        assert!(matches!(
+            SnpVerifier::with_cert_dir(platform.dir.path())
+                .verify(policy(), request(&report))
+                .await,
+            Err(Error::AttestationDecode(_))
+        ));
+    }
+
+    /// A report and VCEK recorded on a Milan platform, from the `virtee/sev` test data.
+    #[test]
+    fn verify_recorded_report() {
+        let raw = include_bytes!("./testdata/report-milan.bin");
+        let ask = include_bytes!("./testdata/ask-milan.der");
+        let vcek = include_bytes!("./testdata/vcek-milan.der");
+        let snp_report = AttestationReport::parse(raw).unwrap();
+        let now = UnixTime::since_unix_epoch(std::time::Duration::from_secs(1_700_000_000));
+        SnpVerifier::verify_cert_chain(MILAN_ARK, ask, vcek, &snp_report, now).unwrap();
+        assert!(SnpVerifier::verify_cert_chain(GENOA_ARK, ask, vcek, &snp_report, now).is_err());
+
+        let mut tampered = raw.to_vec();
+        tampered[0x90] ^= 1;
+        let snp_report = AttestationReport::parse(&tampered).unwrap();
+        assert!(SnpVerifier::verify_cert_chain(MILAN_ARK, ask, vcek, &snp_report, now).is_err());
+    }
+
+    #[test]
+    fn validate_policy() {
+        let verifier = SnpVerifier::default();
+        verifier.validate(policy()).unwrap();
+        let mut bad = policy();
+        bad["version"] = 2.into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+        let mut bad = policy();
+        bad["measurements"] = serde_json::json!(["0x1234"]);
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = policy();
+        bad["measurements"] = serde_json::json!([]);
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = policy();
+        bad["product"] = "naples".into();
+        assert!(matches!(
+            verifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+    }
+}
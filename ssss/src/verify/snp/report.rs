@@ -0,0 +1,261 @@
+//! The SEV-SNP `ATTESTATION_REPORT` structure, as laid out in the SEV-SNP firmware ABI.
+
+use anyhow::{anyhow, ensure};
+use serde::{Deserialize, Serialize};
+
+/// The length of a version 2 or later attestation report.
+pub(super) const REPORT_LEN: usize = 0x4a0;
+/// The length of the prefix of the report that is covered by the signature.
+const SIGNED_LEN: usize = 0x2a0;
+/// The length of each little-endian signature component.
+const SIG_COMPONENT_LEN: usize = 72;
+/// ECDSA P-384 with SHA-384, the only signature algorithm used by the firmware.
+const SIG_ALGO_ECDSA_P384_SHA384: u32 = 1;
+
+pub(super) struct AttestationReport<'a> {
+    raw: &'a [u8],
+    pub(super) guest_svn: u32,
+    pub(super) policy: GuestPolicy,
+    pub(super) vmpl: u32,
+    pub(super) report_data: &'a [u8; 64],
+    pub(super) measurement: &'a [u8; 48],
+    pub(super) chip_id: &'a [u8; 64],
+    pub(super) reported_tcb: TcbVersion,
+}
+
+impl<'a> AttestationReport<'a> {
+    pub(super) fn parse(raw: &'a [u8]) -> Result<Self, anyhow::Error> {
+        ensure!(
+            raw.len() == REPORT_LEN,
+            "attestation report has length {} but must be {REPORT_LEN} bytes",
+            raw.len()
+        );
+        let version = u32_at(raw, 0x00);
+        ensure!(
+            version >= 2,
+            "unsupported attestation report version {version}"
+        );
+        let signature_algo = u32_at(raw, 0x34);
+        ensure!(
+            signature_algo == SIG_ALGO_ECDSA_P384_SHA384,
+            "unsupported attestation report signature algorithm {signature_algo}"
+        );
+        Ok(Self {
+            raw,
+            guest_svn: u32_at(raw, 0x04),
+            policy: GuestPolicy(u64_at(raw, 0x08)),
+            vmpl: u32_at(raw, 0x30),
+            report_data: array_at(raw, 0x50),
+            measurement: array_at(raw, 0x90),
+            chip_id: array_at(raw, 0x1a0),
+            reported_tcb: TcbVersion::from(u64_at(raw, 0x180)),
+        })
+    }
+
+    /// The bytes over which the report is signed.
+    pub(super) fn signed_bytes(&self) -> &'a [u8] {
+        &self.raw[..SIGNED_LEN]
+    }
+
+    /// Returns the signature as big-endian `r || s`.
+    pub(super) fn signature(&self) -> Result<[u8; 96], anyhow::Error> {
+        let mut sig = [0u8; 96];
+        for (i, component) in self.raw[SIGNED_LEN..SIGNED_LEN + 2 * SIG_COMPONENT_LEN]
+            .chunks_exact(SIG_COMPONENT_LEN)
+            .enumerate()
+        {
+            // Components are zero-extended little-endian integers.
+            let (value, padding) = component.split_at(48);
+            if padding.iter().any(|&b| b != 0) {
+                return Err(anyhow!("malformed attestation report signature"));
+            }
+            let out = &mut sig[i * 48..(i + 1) * 48];
+            out.copy_from_slice(value);
+            out.reverse();
+        }
+        Ok(sig)
+    }
+
+    /// The first half of the report data, which binds the report to a request.
+    pub(super) fn binding(&self) -> &'a [u8] {
+        &self.report_data[..32]
+    }
+
+    /// The second half of the report data, which carries a challenge.
+    pub(super) fn nonce(&self) -> &'a [u8] {
+        &self.report_data[32..]
+    }
+}
+
+/// The guest policy that was set when the guest was launched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct GuestPolicy(pub(super) u64);
+
+impl GuestPolicy {
+    const MIGRATE_MA: u64 = 1 << 18;
+    const DEBUG: u64 = 1 << 19;
+
+    pub(super) fn debug(&self) -> bool {
+        self.0 & Self::DEBUG != 0
+    }
+
+    pub(super) fn migrate_ma(&self) -> bool {
+        self.0 & Self::MIGRATE_MA != 0
+    }
+}
+
+/// The security patch levels of the platform's firmware components (Milan and Genoa layout).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct TcbVersion {
+    #[serde(default)]
+    pub(super) bootloader: u8,
+    #[serde(default)]
+    pub(super) tee: u8,
+    #[serde(default)]
+    pub(super) snp: u8,
+    #[serde(default)]
+    pub(super) microcode: u8,
+}
+
+impl TcbVersion {
+    /// Returns whether every component is at least that of `min`.
+    pub(super) fn meets(&self, min: &Self) -> bool {
+        self.bootloader >= min.bootloader
+            && self.tee >= min.tee
+            && self.snp >= min.snp
+            && self.microcode >= min.microcode
+    }
+}
+
+impl From<u64> for TcbVersion {
+    fn from(raw: u64) -> Self {
+        let b = raw.to_le_bytes();
+        Self {
+            bootloader: b[0],
+            tee: b[1],
+            snp: b[6],
+            microcode: b[7],
+        }
+    }
+}
+
+impl From<TcbVersion> for u64 {
+    fn from(tcb: TcbVersion) -> Self {
+        u64::from_le_bytes([tcb.bootloader, tcb.tee, 0, 0, 0, 0, tcb.snp, tcb.microcode])
+    }
+}
+
+fn array_at<const N: usize>(raw: &[u8], offset: usize) -> &[u8; N] {
+    raw[offset..offset + N].try_into().unwrap()
+}
+
+fn u32_at(raw: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(*array_at(raw, offset))
+}
+
+fn u64_at(raw: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(*array_at(raw, offset))
+}
+
+/// Builds reports in tests.
+#[cfg(test)]
+pub(super) struct ReportBuilder {
+    raw: Vec<u8>,
+}
+
+#[cfg(test)]
+impl ReportBuilder {
+    pub(super) fn new() -> Self {
+        let mut raw = vec![0u8; REPORT_LEN];
+        raw[0x00..0x04].copy_from_slice(&2u32.to_le_bytes());
+        raw[0x34..0x38].copy_from_slice(&SIG_ALGO_ECDSA_P384_SHA384.to_le_bytes());
+        // Bit 17 of the guest policy is reserved and must be set.
+        raw[0x08..0x10].copy_from_slice(&(1u64 << 17).to_le_bytes());
+        Self { raw }
+    }
+
+    pub(super) fn set(mut self, offset: usize, bytes: &[u8]) -> Self {
+        self.raw[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub(super) fn report_data(self, binding: &[u8; 32], nonce: &[u8; 32]) -> Self {
+        self.set(0x50, binding).set(0x70, nonce)
+    }
+
+    pub(super) fn measurement(self, measurement: &[u8; 48]) -> Self {
+        self.set(0x90, measurement)
+    }
+
+    pub(super) fn vmpl(self, vmpl: u32) -> Self {
+        self.set(0x30, &vmpl.to_le_bytes())
+    }
+
+    pub(super) fn policy(self, policy: u64) -> Self {
+        self.set(0x08, &policy.to_le_bytes())
+    }
+
+    pub(super) fn chip_id(self, chip_id: &[u8; 64]) -> Self {
+        self.set(0x1a0, chip_id)
+    }
+
+    pub(super) fn reported_tcb(self, tcb: TcbVersion) -> Self {
+        self.set(0x180, &u64::from(tcb).to_le_bytes())
+    }
+
+    /// Signs the report using a function that returns a big-endian `r || s` signature.
+    pub(super) fn sign(mut self, sign: impl FnOnce(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let sig = sign(&self.raw[..SIGNED_LEN]);
+        for (i, component) in sig.chunks_exact(48).enumerate() {
+            let offset = SIGNED_LEN + i * SIG_COMPONENT_LEN;
+            let out = &mut self.raw[offset..offset + 48];
+            out.copy_from_slice(component);
+            out.reverse();
+        }
+        self.raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_report() {
+        let tcb = TcbVersion {
+            bootloader: 3,
+            tee: 0,
+            snp: 14,
+            microcode: 209,
+        };
+        let raw = ReportBuilder::new()
+            .report_data(&[1; 32], &[2; 32])
+            .measurement(&[3; 48])
+            .chip_id(&[4; 64])
+            .reported_tcb(tcb)
+            .policy(0x30000)
+            .vmpl(2)
+            .sign(|_| (1..=96).collect());
+        let report = AttestationReport::parse(&raw).unwrap();
+        assert_eq!(report.binding(), [1; 32]);
+        assert_eq!(report.nonce(), [2; 32]);
+        assert_eq!(report.measurement, &[3; 48]);
+        assert_eq!(report.chip_id, &[4; 64]);
+        assert_eq!(report.reported_tcb, tcb);
+        assert!(!report.policy.debug());
+        assert_eq!(report.vmpl, 2);
+        assert_eq!(
+            report.signature().unwrap().to_vec(),
+            (1..=96).collect::<Vec<u8>>()
+        );
+        assert_eq!(report.signed_bytes().len(), SIGNED_LEN);
+        // The reserved bytes of the TCB version are ignored.
+        assert_eq!(TcbVersion::from(u64::from(tcb) | 0xffff_0000), tcb);
+
+        assert!(AttestationReport::parse(&raw[..REPORT_LEN - 1]).is_err());
+        let mut v1 = raw.clone();
+        v1[0] = 1;
+        assert!(AttestationReport::parse(&v1).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! The TPM 2.0 quote structures (`TPMS_ATTEST` and `TPMT_SIGNATURE`), as specified in
+//! part 2 of the TPM 2.0 library specification.
+
+use anyhow::{anyhow, bail, ensure};
+use serde::{Deserialize, Serialize};
+
+const TPM_GENERATED_VALUE: u32 = 0xff54_4347;
+const TPM_ST_ATTEST_QUOTE: u16 = 0x8018;
+const TPM_ALG_RSASSA: u16 = 0x0014;
+const TPM_ALG_RSAPSS: u16 = 0x0016;
+const TPM_ALG_ECDSA: u16 = 0x0018;
+
+/// The number of PCRs in each bank.
+pub(super) const PCR_COUNT: u8 = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum HashAlg {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlg {
+    fn from_id(id: u16) -> Result<Self, anyhow::Error> {
+        Ok(match id {
+            0x0004 => Self::Sha1,
+            0x000b => Self::Sha256,
+            0x000c => Self::Sha384,
+            0x000d => Self::Sha512,
+            _ => bail!("unsupported hash algorithm {id:#06x}"),
+        })
+    }
+
+    #[cfg(test)]
+    fn id(&self) -> u16 {
+        match self {
+            Self::Sha1 => 0x0004,
+            Self::Sha256 => 0x000b,
+            Self::Sha384 => 0x000c,
+            Self::Sha512 => 0x000d,
+        }
+    }
+
+    fn algorithm(&self) -> &'static ring::digest::Algorithm {
+        match self {
+            Self::Sha1 => &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+            Self::Sha256 => &ring::digest::SHA256,
+            Self::Sha384 => &ring::digest::SHA384,
+            Self::Sha512 => &ring::digest::SHA512,
+        }
+    }
+
+    pub(super) fn digest(&self, data: &[u8]) -> Vec<u8> {
+        ring::digest::digest(self.algorithm(), data)
+            .as_ref()
+            .to_vec()
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.algorithm().output_len()
+    }
+}
+
+/// A `TPMS_ATTEST` that carries a `TPMS_QUOTE_INFO`.
+pub(super) struct Quote<'a> {
+    /// The qualifying data provided by the caller of `TPM2_Quote`.
+    pub(super) extra_data: &'a [u8],
+    /// The selected PCRs of each bank, in the order in which they were digested.
+    pub(super) pcr_select: Vec<(HashAlg, Vec<u8>)>,
+    pub(super) pcr_digest: &'a [u8],
+}
+
+impl<'a> Quote<'a> {
+    pub(super) fn parse(raw: &'a [u8]) -> Result<Self, anyhow::Error> {
+        let mut r = Reader(raw);
+        ensure!(
+            r.u32()? == TPM_GENERATED_VALUE,
+            "quote was not generated by a TPM"
+        );
+        ensure!(
+            r.u16()? == TPM_ST_ATTEST_QUOTE,
+            "attestation is not a quote"
+        );
+        let _qualified_signer = r.tpm2b()?;
+        let extra_data = r.tpm2b()?;
+        let _clock_info = r.take(17)?;
+        let _firmware_version = r.take(8)?;
+
+        let count = r.u32()?;
+        let mut pcr_select = Vec::new();
+        for _ in 0..count {
+            let hash = HashAlg::from_id(r.u16()?)?;
+            let size = r.u8()?;
+            let bitmap = r.take(size.into())?;
+            let pcrs = (0..bitmap.len() * 8)
+                .filter(|&i| bitmap[i / 8] & (1 << (i % 8)) != 0)
+                .map(|i| u8::try_from(i).map_err(|_| anyhow!("too many PCRs selected")))
+                .collect::<Result<Vec<_>, _>>()?;
+            pcr_select.push((hash, pcrs));
+        }
+        let pcr_digest = r.tpm2b()?;
+        ensure!(r.0.is_empty(), "quote has trailing data");
+
+        Ok(Self {
+            extra_data,
+            pcr_select,
+            pcr_digest,
+        })
+    }
+
+    pub(super) fn is_selected(&self, hash: HashAlg, pcr: u8) -> bool {
+        self.pcr_select
+            .iter()
+            .any(|(h, pcrs)| *h == hash && pcrs.contains(&pcr))
+    }
+}
+
+/// A `TPMT_SIGNATURE`.
+pub(super) enum Signature<'a> {
+    RsaSsa {
+        hash: HashAlg,
+        sig: &'a [u8],
+    },
+    RsaPss {
+        hash: HashAlg,
+        sig: &'a [u8],
+    },
+    Ecdsa {
+        hash: HashAlg,
+        r: &'a [u8],
+        s: &'a [u8],
+    },
+}
+
+impl<'a> Signature<'a> {
+    pub(super) fn parse(raw: &'a [u8]) -> Result<Self, anyhow::Error> {
+        let mut r = Reader(raw);
+        let alg = r.u16()?;
+        let hash = HashAlg::from_id(r.u16()?)?;
+        let signature = match alg {
+            TPM_ALG_RSASSA => Self::RsaSsa {
+                hash,
+                sig: r.tpm2b()?,
+            },
+            TPM_ALG_RSAPSS => Self::RsaPss {
+                hash,
+                sig: r.tpm2b()?,
+            },
+            TPM_ALG_ECDSA => Self::Ecdsa {
+                hash,
+                r: r.tpm2b()?,
+                s: r.tpm2b()?,
+            },
+            _ => bail!("unsupported signature algorithm {alg:#06x}"),
+        };
+        ensure!(r.0.is_empty(), "signature has trailing data");
+        Ok(signature)
+    }
+
+    /// The hash algorithm of the signing scheme, which is also that of the PCR digest.
+    pub(super) fn hash(&self) -> HashAlg {
+        match self {
+            Self::RsaSsa { hash, .. } | Self::RsaPss { hash, .. } | Self::Ecdsa { hash, .. } => {
+                *hash
+            }
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], anyhow::Error> {
+        ensure!(self.0.len() >= n, "TPM structure is truncated");
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, anyhow::Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, anyhow::Error> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, anyhow::Error> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a sized buffer (`TPM2B_*`).
+    fn tpm2b(&mut self) -> Result<&'a [u8], anyhow::Error> {
+        let size = self.u16()?;
+        self.take(size.into())
+    }
+}
+
+/// Builds quotes in tests, as `tpm2_quote` would output them.
+#[cfg(test)]
+pub(super) struct QuoteBuilder {
+    extra_data: Vec<u8>,
+    banks: Vec<(HashAlg, std::collections::BTreeMap<u8, Vec<u8>>)>,
+}
+
+#[cfg(test)]
+impl QuoteBuilder {
+    pub(super) fn new(extra_data: &[u8]) -> Self {
+        Self {
+            extra_data: extra_data.to_vec(),
+            banks: vec![],
+        }
+    }
+
+    pub(super) fn pcr(mut self, hash: HashAlg, pcr: u8, value: &[u8]) -> Self {
+        match self.banks.iter_mut().find(|(h, _)| *h == hash) {
+            Some((_, bank)) => {
+                bank.insert(pcr, value.to_vec());
+            }
+            None => self.banks.push((hash, [(pcr, value.to_vec())].into())),
+        }
+        self
+    }
+
+    /// Returns the quoted `TPMS_ATTEST`, whose PCR digest is made using `hash`.
+    pub(super) fn build(&self, hash: HashAlg) -> Vec<u8> {
+        fn tpm2b(out: &mut Vec<u8>, data: &[u8]) {
+            out.extend((data.len() as u16).to_be_bytes());
+            out.extend(data);
+        }
+
+        let mut out = Vec::new();
+        out.extend(TPM_GENERATED_VALUE.to_be_bytes());
+        out.extend(TPM_ST_ATTEST_QUOTE.to_be_bytes());
+        tpm2b(&mut out, &[0x00, 0x0b, 0xaa, 0xbb]);
+        tpm2b(&mut out, &self.extra_data);
+        out.extend([0; 17]);
+        out.extend([0; 8]);
+        out.extend((self.banks.len() as u32).to_be_bytes());
+        let mut pcr_values = Vec::new();
+        for (bank_hash, bank) in &self.banks {
+            out.extend(bank_hash.id().to_be_bytes());
+            out.push(3);
+            let mut bitmap = [0u8; 3];
+            for (&pcr, value) in bank {
+                bitmap[usize::from(pcr / 8)] |= 1 << (pcr % 8);
+                pcr_values.extend(value);
+            }
+            out.extend(bitmap);
+        }
+        tpm2b(&mut out, &hash.digest(&pcr_values));
+        out
+    }
+
+    /// Returns the PCR values in the form expected in the authorization.
+    pub(super) fn pcrs_json(&self) -> serde_json::Value {
+        serde_json::json!(self
+            .banks
+            .iter()
+            .map(|(hash, bank)| {
+                (
+                    hash,
+                    bank.iter()
+                        .map(|(pcr, value)| (pcr.to_string(), hex::encode(value)))
+                        .collect::<std::collections::BTreeMap<_, _>>(),
+                )
+            })
+            .collect::<std::collections::BTreeMap<_, _>>())
+    }
+}
+
+/// Encodes an ECDSA `TPMT_SIGNATURE` from a fixed-length `r || s` signature.
+#[cfg(test)]
+pub(super) fn ecdsa_signature(hash: HashAlg, sig: &[u8]) -> Vec<u8> {
+    let (r, s) = sig.split_at(sig.len() / 2);
+    let mut out = Vec::new();
+    out.extend(TPM_ALG_ECDSA.to_be_bytes());
+    out.extend(hash.id().to_be_bytes());
+    for component in [r, s] {
+        out.extend((component.len() as u16).to_be_bytes());
+        out.extend(component);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quote() {
+        let builder = QuoteBuilder::new(&[1; 32])
+            .pcr(HashAlg::Sha256, 7, &[7; 32])
+            .pcr(HashAlg::Sha256, 0, &[0; 32])
+            .pcr(HashAlg::Sha1, 23, &[23; 20]);
+        let raw = builder.build(HashAlg::Sha256);
+        let quote = Quote::parse(&raw).unwrap();
+        assert_eq!(quote.extra_data, [1; 32]);
+        assert_eq!(
+            quote.pcr_select,
+            [(HashAlg::Sha256, vec![0, 7]), (HashAlg::Sha1, vec![23])]
+        );
+        assert_eq!(
+            quote.pcr_digest,
+            HashAlg::Sha256.digest(&[[0; 32].as_slice(), &[7; 32], &[23; 20]].concat())
+        );
+        assert!(quote.is_selected(HashAlg::Sha1, 23));
+        assert!(!quote.is_selected(HashAlg::Sha256, 23));
+        assert!(Quote::parse(&raw[..raw.len() - 1]).is_err());
+        assert!(Quote::parse(&raw[1..]).is_err());
+
+        let sig = ecdsa_signature(HashAlg::Sha256, &[[1; 32], [2; 32]].concat());
+        let Signature::Ecdsa { hash, r, s } = Signature::parse(&sig).unwrap() else {
+            panic!("not an ECDSA signature");
+        };
+        assert_eq!((hash, r, s), (HashAlg::Sha256, &[1; 32][..], &[2; 32][..]));
+        assert!(Signature::parse(&sig[..sig.len() - 1]).is_err());
+    }
+}
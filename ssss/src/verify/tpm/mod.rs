@@ -0,0 +1,679 @@
+mod attest;
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+};
+
+use anyhow::{anyhow, bail, ensure};
+use ethers::types::{Bytes, H256};
+use serde::Deserialize;
+use smallvec::SmallVec;
+use webpki::types::{CertificateDer, SignatureVerificationAlgorithm, UnixTime};
+
+use self::attest::{HashAlg, Quote, Signature, PCR_COUNT};
+use super::*;
+use crate::types::NonceKind;
+
+/// The DER-encoded `tcg-kp-AIKCertificate` extended key usage (2.23.133.8.3).
+static TCG_KP_AIK_CERTIFICATE: &[u8] = &[0x67, 0x81, 0x05, 0x08, 0x03];
+
+/// Verifies TPM 2.0 quotes made by attestation keys (AKs) that are certified by a CA that
+/// the policy trusts.
+///
+/// The authorization is a JSON object containing the `quote` (the quoted `TPMS_ATTEST`), its
+/// `signature` (a `TPMT_SIGNATURE`), the values of the quoted `pcrs` of each bank, and the
+/// `ak_chain` of DER-encoded certificates, leaf first, and a `challenge` issued by this SSSS,
+/// as quotes carry no timestamp. The qualifying data of the quote must be the keccak256 hash
+/// of the version 2 request binding followed by the challenge, which fits the qualifying data
+/// of TPMs that support only SHA-256.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TpmVerifier;
+
+impl Verifier for TpmVerifier {
+    fn name(&self) -> &str {
+        "tpm"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        let bank = |len: usize| {
+            serde_json::json!({
+                "type": "object",
+                "propertyNames": { "pattern": "^(1?[0-9]|2[0-3])$" },
+                "additionalProperties": {
+                    "type": "string",
+                    "pattern": format!("^0x[0-9a-fA-F]{{{}}}$", len * 2),
+                },
+            })
+        };
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "TPM 2.0 quote policy",
+            "type": "object",
+            "required": ["version", "trust_anchors", "measurements"],
+            "additionalProperties": false,
+            "properties": {
+                "version": { "const": 1 },
+                "trust_anchors": {
+                    "description": "DER-encoded certificates of the CAs that certify AKs",
+                    "type": "array",
+                    "minItems": 1,
+                    "items": { "type": "string", "pattern": "^0x([0-9a-fA-F]{2})+$" },
+                },
+                "measurements": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "minProperties": 1,
+                        "additionalProperties": false,
+                        "properties": {
+                            "sha1": bank(20),
+                            "sha256": bank(32),
+                            "sha384": bank(48),
+                            "sha512": bank(64),
+                        },
+                    },
+                },
+                "max_duration": { "type": "integer", "minimum": 0 },
+                "relayers": {
+                    "type": "array",
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        Policy::decode(raw_policy).map(|_| ())
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        report.check(
+            EvaluationStep::new("relayer")
+                .expected(&policy.relayers)
+                .observed(req.relayer),
+            check_relayer(&policy.relayers, req.relayer),
+        )?;
+
+        let decoded = serde_json::from_slice::<Authorization>(req.authorization)
+            .map_err(anyhow::Error::from)
+            .and_then(|auth| {
+                ensure!(!auth.ak_chain.is_empty(), "missing AK certificate");
+                Ok(auth)
+            });
+        let auth = match decoded {
+            Ok(auth) => auth,
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("attestation"),
+                    Error::AttestationDecode(e),
+                ))
+            }
+        };
+        let (quote, signature) = match Quote::parse(&auth.quote)
+            .and_then(|quote| Ok((quote, Signature::parse(&auth.signature)?)))
+        {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("attestation"),
+                    Error::AttestationDecode(e),
+                ))
+            }
+        };
+
+        match Self::verify_quote(&policy, &auth, &signature, UnixTime::now()) {
+            Ok(()) => report.pass(EvaluationStep::new("attestation")),
+            Err(e) => {
+                return Err(report.fail(
+                    EvaluationStep::new("attestation"),
+                    Error::AttestationDecode(e),
+                ))
+            }
+        }
+
+        let step = EvaluationStep::new("pcr-digest")
+            .expected(Bytes::from(quote.pcr_digest.to_vec()))
+            .observed(&auth.pcrs);
+        match Self::check_pcr_digest(&quote, signature.hash(), &auth.pcrs) {
+            Ok(()) => report.pass(step),
+            Err(e) => return Err(report.fail(step, Error::AttestationDecode(e))),
+        }
+
+        let expected_binding = match request_binding(2, &req) {
+            Ok(binding) => keccak256([binding, auth.challenge.0].concat()),
+            Err(e) => return Err(report.fail(EvaluationStep::new("binding"), e)),
+        };
+        report.check(
+            EvaluationStep::new("binding")
+                .expected(Bytes::from(expected_binding))
+                .observed(Bytes::from(quote.extra_data.to_vec())),
+            if quote.extra_data == expected_binding {
+                Ok(())
+            } else {
+                Err(Error::BindingMismatch(SmallVec::from_buf(expected_binding)))
+            },
+        )?;
+
+        let challenge = NonceId::new(NonceKind::Challenge, auth.challenge);
+        report.pass(EvaluationStep::new("challenge").observed(challenge.digest));
+
+        let mismatches: Vec<_> = policy
+            .measurements
+            .iter()
+            .map(|m| m.mismatches(&quote, &auth.pcrs))
+            .collect();
+        let matched = mismatches.iter().position(|m| m.is_empty());
+        let step = EvaluationStep::new("pcrs");
+        let step = match matched {
+            Some(index) => step.observed(serde_json::json!({ "matched": index })),
+            None => step.expected(&mismatches).observed(&auth.pcrs),
+        };
+        report.check(
+            step,
+            match matched {
+                Some(_) => Ok(()),
+                None => Err(mismatches
+                    .iter()
+                    .find_map(|m| m.first())
+                    .map(|&(_, pcr)| Error::PcrMismatch(pcr.into()))
+                    .unwrap_or_else(|| Error::Unauthorized("no measurement matched".into()))),
+            },
+        )?;
+
+        report.finish(Verification {
+            nonce: vec![],
+            public_key: vec![],
+            duration: match req.kind {
+                RequestKind::Grant { duration } => Some(duration.min(policy.max_duration)),
+                RequestKind::Revoke => None,
+            },
+            measurement: matched,
+            single_use: SingleUse {
+                challenge: Some(challenge),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+impl TpmVerifier {
+    /// Checks that the quote was signed by an AK that was certified by a trusted CA.
+    fn verify_quote(
+        policy: &Policy,
+        auth: &Authorization,
+        signature: &Signature<'_>,
+        now: UnixTime,
+    ) -> Result<(), anyhow::Error> {
+        let anchor_ders: Vec<_> = policy
+            .trust_anchors
+            .iter()
+            .map(|der| CertificateDer::from(der.as_ref()))
+            .collect();
+        let anchors = anchor_ders
+            .iter()
+            .map(webpki::anchor_from_trusted_cert)
+            .collect::<Result<Vec<_>, _>>()?;
+        let ak_der = CertificateDer::from(auth.ak_chain[0].as_ref());
+        let ak = webpki::EndEntityCert::try_from(&ak_der)?;
+        let intermediates: Vec<_> = auth.ak_chain[1..]
+            .iter()
+            .map(|der| CertificateDer::from(der.as_ref()))
+            .collect();
+        ak.verify_for_usage(
+            webpki::ALL_VERIFICATION_ALGS,
+            &anchors,
+            &intermediates,
+            now,
+            webpki::KeyUsage::required_if_present(TCG_KP_AIK_CERTIFICATE),
+            None,
+            None,
+        )?;
+
+        let (alg, sig): (&dyn SignatureVerificationAlgorithm, Cow<'_, [u8]>) = match *signature {
+            Signature::RsaSsa { hash, sig } => (
+                match hash {
+                    HashAlg::Sha256 => webpki::ring::RSA_PKCS1_2048_8192_SHA256,
+                    HashAlg::Sha384 => webpki::ring::RSA_PKCS1_2048_8192_SHA384,
+                    HashAlg::Sha512 => webpki::ring::RSA_PKCS1_2048_8192_SHA512,
+                    HashAlg::Sha1 => bail!("SHA-1 signatures are not accepted"),
+                },
+                sig.into(),
+            ),
+            Signature::RsaPss { hash, sig } => (
+                match hash {
+                    HashAlg::Sha256 => webpki::ring::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+                    HashAlg::Sha384 => webpki::ring::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+                    HashAlg::Sha512 => webpki::ring::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+                    HashAlg::Sha1 => bail!("SHA-1 signatures are not accepted"),
+                },
+                sig.into(),
+            ),
+            Signature::Ecdsa { hash, r, s } => match hash {
+                HashAlg::Sha256 => (&ES256, fixed_ecdsa_signature(r, s, 32)?.into()),
+                HashAlg::Sha384 => (&ES384, fixed_ecdsa_signature(r, s, 48)?.into()),
+                _ => bail!("unsupported ECDSA signing scheme {hash:?}"),
+            },
+        };
+        ak.verify_signature(alg, &auth.quote, &sig)
+            .map_err(|e| anyhow!("invalid quote signature: {e}"))
+    }
+
+    /// Checks that the PCR values are those that were quoted.
+    fn check_pcr_digest(
+        quote: &Quote<'_>,
+        hash: HashAlg,
+        pcrs: &PcrBanks,
+    ) -> Result<(), anyhow::Error> {
+        let mut selected = Vec::new();
+        for (bank, indices) in &quote.pcr_select {
+            for pcr in indices {
+                let value = pcrs
+                    .get(bank)
+                    .and_then(|values| values.get(pcr))
+                    .ok_or_else(|| anyhow!("missing value of quoted PCR {bank:?}:{pcr}"))?;
+                selected.extend_from_slice(value);
+            }
+        }
+        ensure!(
+            hash.digest(&selected) == quote.pcr_digest,
+            "PCR values do not match the quoted digest"
+        );
+        Ok(())
+    }
+}
+
+/// Left-pads the big-endian ECDSA signature components to `len` bytes each.
+fn fixed_ecdsa_signature(r: &[u8], s: &[u8], len: usize) -> Result<Vec<u8>, anyhow::Error> {
+    let mut sig = vec![0; 2 * len];
+    for (component, out) in [r, s].into_iter().zip(sig.chunks_exact_mut(len)) {
+        let start = component
+            .iter()
+            .position(|&b| b != 0)
+            .unwrap_or(component.len());
+        let component = &component[start..];
+        ensure!(component.len() <= len, "malformed ECDSA signature");
+        out[len - component.len()..].copy_from_slice(component);
+    }
+    Ok(sig)
+}
+
+/// PCR values by bank and index.
+type PcrBanks = BTreeMap<HashAlg, BTreeMap<u8, Bytes>>;
+
+#[derive(Deserialize)]
+struct Authorization {
+    quote: Bytes,
+    signature: Bytes,
+    pcrs: PcrBanks,
+    ak_chain: Vec<Bytes>,
+    challenge: H256,
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    version: u8,
+    /// DER-encoded certificates of the CAs that certify AKs.
+    trust_anchors: Vec<Bytes>,
+    /// The acceptable PCR values. Any one set must match the quote.
+    measurements: Vec<Measurement>,
+    #[serde(default)]
+    max_duration: u64,
+    #[serde(default)]
+    relayers: HashSet<Address>,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        if policy.trust_anchors.is_empty() {
+            return Err(Error::PolicyDecode(anyhow!(
+                "at least one trust anchor is required"
+            )));
+        }
+        for (i, der) in policy.trust_anchors.iter().enumerate() {
+            webpki::anchor_from_trusted_cert(&CertificateDer::from(der.as_ref()))
+                .map_err(|e| Error::PolicyDecode(anyhow!("trust anchor {i}: {e}")))?;
+        }
+        if policy.measurements.is_empty() {
+            return Err(Error::PolicyDecode(anyhow!(
+                "at least one measurement is required"
+            )));
+        }
+        for (i, m) in policy.measurements.iter().enumerate() {
+            m.validate()
+                .map_err(|e| Error::PolicyDecode(anyhow!("measurement {i}: {e}")))?;
+        }
+        Ok(policy)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct Measurement(PcrBanks);
+
+impl Measurement {
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        ensure!(!self.0.is_empty(), "no PCRs are specified");
+        for (bank, values) in &self.0 {
+            for (&pcr, value) in values {
+                ensure!(pcr < PCR_COUNT, "PCR {pcr} does not exist");
+                ensure!(
+                    value.len() == bank.len(),
+                    "{bank:?} PCR {pcr} has length {} but must be {} bytes",
+                    value.len(),
+                    bank.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the PCRs whose values were not quoted or differ from those expected.
+    fn mismatches(&self, quote: &Quote<'_>, pcrs: &PcrBanks) -> Vec<(HashAlg, u8)> {
+        self.0
+            .iter()
+            .flat_map(|(&bank, values)| {
+                values.iter().filter_map(move |(&pcr, expected)| {
+                    let quoted = quote.is_selected(bank, pcr)
+                        && pcrs.get(&bank).and_then(|v| v.get(&pcr)) == Some(expected);
+                    (!quoted).then_some((bank, pcr))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{CertificateParams, DnType, KeyPair};
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+
+    use super::{attest::*, *};
+    use crate::verify::tests::{ca_params, request};
+
+    const CHALLENGE: [u8; 32] = [5; 32];
+
+    struct TestTpm {
+        ca: Vec<u8>,
+        ak_chain: Vec<Vec<u8>>,
+        ak: EcdsaKeyPair,
+    }
+
+    /// Sets up an AK that is certified through an intermediate, as by a privacy CA.
+    fn make_tpm() -> TestTpm {
+        let root_key = KeyPair::generate().unwrap();
+        let root = ca_params("TPM Root CA").self_signed(&root_key).unwrap();
+        let issuing_key = KeyPair::generate().unwrap();
+        let issuing = ca_params("AK Issuing CA")
+            .signed_by(&issuing_key, &root, &root_key)
+            .unwrap();
+
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let ak = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+            .unwrap();
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "AK");
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::Other(vec![
+            2, 23, 133, 8, 3,
+        ])];
+        let ak_cert = params
+            .signed_by(
+                &KeyPair::try_from(pkcs8.as_ref()).unwrap(),
+                &issuing,
+                &issuing_key,
+            )
+            .unwrap();
+
+        TestTpm {
+            ca: root.der().to_vec(),
+            ak_chain: vec![ak_cert.der().to_vec(), issuing.der().to_vec()],
+            ak,
+        }
+    }
+
+    impl TestTpm {
+        fn authorization(&self, quote: &QuoteBuilder) -> Vec<u8> {
+            let quoted = quote.build(HashAlg::Sha256);
+            let sig = self
+                .ak
+                .sign(&SystemRandom::new(), &quoted)
+                .unwrap()
+                .as_ref()
+                .to_vec();
+            serde_json::to_vec(&serde_json::json!({
+                "quote": Bytes::from(quoted),
+                "signature": Bytes::from(ecdsa_signature(HashAlg::Sha256, &sig)),
+                "pcrs": quote.pcrs_json(),
+                "ak_chain": self.ak_chain.iter().cloned().map(Bytes::from).collect::<Vec<_>>(),
+                "challenge": H256(CHALLENGE),
+            }))
+            .unwrap()
+        }
+
+        fn policy(&self) -> serde_json::Value {
+            serde_json::json!({
+                "version": 1,
+                "trust_anchors": [Bytes::from(self.ca.clone())],
+                "measurements": [
+                    { "sha256": { "7": Bytes::from([0; 32]) } },
+                    {
+                        "sha256": { "0": Bytes::from([0; 32]), "7": Bytes::from([7; 32]) },
+                        "sha1": { "23": Bytes::from([23; 20]) },
+                    },
+                ],
+                "max_duration": 30,
+            })
+        }
+    }
+
+    fn quote() -> QuoteBuilder {
+        QuoteBuilder::new(&keccak256(
+            [request_binding(2, &request(&[])).unwrap(), CHALLENGE].concat(),
+        ))
+        .pcr(HashAlg::Sha256, 0, &[0; 32])
+        .pcr(HashAlg::Sha256, 7, &[7; 32])
+        .pcr(HashAlg::Sha1, 23, &[23; 20])
+    }
+
+    #[tokio::test]
+    async fn verify_quote() {
+        let tpm = make_tpm();
+        let auth = tpm.authorization(&quote());
+        let verification = TpmVerifier
+            .verify(tpm.policy(), request(&auth))
+            .await
+            .unwrap();
+        assert_eq!(verification.measurement, Some(1));
+        assert_eq!(verification.duration, Some(30));
+        assert_eq!(
+            verification.single_use.challenge,
+            Some(NonceId::new(NonceKind::Challenge, H256(CHALLENGE)))
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_quote() {
+        let tpm = make_tpm();
+
+        let mut untrusted = tpm.policy();
+        untrusted["trust_anchors"] = serde_json::json!([Bytes::from(make_tpm().ca)]);
+        let auth = tpm.authorization(&quote());
+        assert!(matches!(
+            TpmVerifier.verify(untrusted, request(&auth)).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        // PCR values that differ from those that were quoted.
+        let mut forged: serde_json::Value = serde_json::from_slice(&auth).unwrap();
+        forged["pcrs"]["sha256"]["0"] = serde_json::json!(Bytes::from([1; 32]));
+        let forged = serde_json::to_vec(&forged).unwrap();
+        assert!(matches!(
+            TpmVerifier.verify(tpm.policy(), request(&forged)).await,
+            Err(Error::AttestationDecode(_))
+        ));
+
+        let unbound = tpm.authorization(
+            &QuoteBuilder::new(&[0; 32])
+                .pcr(HashAlg::Sha256, 0, &[0; 32])
+                .pcr(HashAlg::Sha256, 7, &[7; 32]),
+        );
+        assert!(matches!(
+            TpmVerifier.verify(tpm.policy(), request(&unbound)).await,
+            Err(Error::BindingMismatch(_))
+        ));
+
+        // A quote made for a different challenge.
+        let mut rechallenged: serde_json::Value = serde_json::from_slice(&auth).unwrap();
+        rechallenged["challenge"] = serde_json::json!(H256::repeat_byte(6));
+        let rechallenged = serde_json::to_vec(&rechallenged).unwrap();
+        assert!(matches!(
+            TpmVerifier
+                .verify(tpm.policy(), request(&rechallenged))
+                .await,
+            Err(Error::BindingMismatch(_))
+        ));
+
+        let unknown = tpm.authorization(&quote().pcr(HashAlg::Sha256, 7, &[8; 32]));
+        assert!(matches!(
+            TpmVerifier.verify(tpm.policy(), request(&unknown)).await,
+            Err(Error::PcrMismatch(7))
+        ));
+    }
+
+    /// The RSA public key of a recorded AK, in PKCS #1 form.
+    struct RecordedAk(&'static [u8]);
+
+    impl rcgen::PublicKeyData for RecordedAk {
+        fn der_bytes(&self) -> &[u8] {
+            self.0
+        }
+
+        fn algorithm(&self) -> &rcgen::SignatureAlgorithm {
+            &rcgen::PKCS_RSA_SHA256
+        }
+    }
+
+    /// A quote of all SHA-256 PCRs made by the AK of an Azure confidential VM's vTPM for the
+    /// qualifying data `challenge`, from the `az-cvm-vtpm` test data. The AK is certified by a
+    /// test CA because the recording does not include its certificate.
+    #[test]
+    fn verify_recorded_quote() {
+        let raw = include_bytes!("./testdata/quote-azure.bin");
+        let raw_sig = include_bytes!("./testdata/signature-azure.bin");
+        let pcrs: PcrBanks =
+            serde_json::from_slice(include_bytes!("./testdata/pcrs-azure.json")).unwrap();
+        let quote = Quote::parse(raw).unwrap();
+        let signature = Signature::parse(raw_sig).unwrap();
+        assert_eq!(quote.extra_data, b"challenge");
+        assert!((0..PCR_COUNT).all(|pcr| quote.is_selected(HashAlg::Sha256, pcr)));
+        TpmVerifier::check_pcr_digest(&quote, signature.hash(), &pcrs).unwrap();
+
+        let ca_key = KeyPair::generate().unwrap();
+        let ca = ca_params("TPM Root CA").self_signed(&ca_key).unwrap();
+        let mut params = CertificateParams::default();
+        params.distinguished_name.push(DnType::CommonName, "AK");
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::Other(vec![
+            2, 23, 133, 8, 3,
+        ])];
+        let ak_cert = params
+            .signed_by(
+                &RecordedAk(include_bytes!("./testdata/ak-azure.der")),
+                &ca,
+                &ca_key,
+            )
+            .unwrap();
+        let policy = Policy::decode(serde_json::json!({
+            "version": 1,
+            "trust_anchors": [Bytes::from(ca.der().to_vec())],
+            "measurements": [{ "sha256": {} }],
+        }))
+        .unwrap();
+        let auth = |quote: &[u8]| Authorization {
+            quote: quote.to_vec().into(),
+            signature: raw_sig.to_vec().into(),
+            pcrs: pcrs.clone(),
+            ak_chain: vec![ak_cert.der().to_vec().into()],
+            challenge: H256::zero(),
+        };
+        TpmVerifier::verify_quote(&policy, &auth(raw), &signature, UnixTime::now()).unwrap();
+
+        let mut tampered = raw.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(
+            TpmVerifier::verify_quote(&policy, &auth(&tampered), &signature, UnixTime::now())
+                .is_err()
+        );
+        let mut forged = pcrs.clone();
+        forged
+            .get_mut(&HashAlg::Sha256)
+            .unwrap()
+            .insert(23, Bytes::from([1; 32]));
+        assert!(TpmVerifier::check_pcr_digest(&quote, signature.hash(), &forged).is_err());
+    }
+
+    #[test]
+    fn validate_policy() {
+        let tpm = make_tpm();
+        TpmVerifier.validate(tpm.policy()).unwrap();
+        let mut bad = tpm.policy();
+        bad["version"] = 2.into();
+        assert!(matches!(
+            TpmVerifier.validate(bad),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+        let mut bad = tpm.policy();
+        bad["trust_anchors"] = serde_json::json!(["0x1234"]);
+        assert!(matches!(
+            TpmVerifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = tpm.policy();
+        bad["measurements"][0]["sha256"]["24"] = serde_json::json!(Bytes::from([0; 32]));
+        assert!(matches!(
+            TpmVerifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+        let mut bad = tpm.policy();
+        bad["measurements"][0]["sha256"]["7"] = serde_json::json!(Bytes::from([0; 20]));
+        assert!(matches!(
+            TpmVerifier.validate(bad),
+            Err(Error::PolicyDecode(_))
+        ));
+    }
+}
@@ -0,0 +1,448 @@
+mod sandbox;
+
+use std::{collections::HashSet, path::PathBuf};
+
+use anyhow::anyhow;
+use ethers::types::Bytes;
+use serde::{Deserialize, Serialize};
+
+use self::sandbox::Modules;
+pub use self::sandbox::WasmLimits;
+use super::*;
+
+/// Runs custom policies written as WebAssembly modules.
+///
+/// The policy names a module by its SHA-256 hash, so the policy hash that is committed on chain
+/// pins the module's exact code. Modules are loaded from the configured module directory, where
+/// each is named `<hash>.wasm`. See the `sandbox` module for the interface that modules export.
+///
+/// A module receives the request, its authorization and context, the version 2 request binding,
+/// the current time, and the policy's `config` as a JSON object. It returns either
+/// `{ "accept": { "nonce", "public_key", "duration" } }`, all of which are optional, or
+/// `{ "reject": { "reason" } }`.
+#[derive(Clone, Debug, Default)]
+pub struct WasmVerifier {
+    modules: Option<Arc<Modules>>,
+    limits: WasmLimits,
+}
+
+impl WasmVerifier {
+    pub fn with_module_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            modules: Some(Arc::new(Modules::new(dir.into()))),
+            limits: Default::default(),
+        }
+    }
+
+    pub fn with_limits(self, limits: WasmLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    fn load(&self, hash: &H256) -> Result<(&Modules, Arc<wasmi::Module>), anyhow::Error> {
+        let modules = self
+            .modules
+            .as_deref()
+            .ok_or_else(|| anyhow!("no module directory is configured"))?;
+        Ok((modules, modules.load(&hash.0)?))
+    }
+}
+
+impl Verifier for WasmVerifier {
+    fn name(&self) -> &str {
+        "wasm"
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+
+    fn schema(&self) -> serde_json::Value {
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "WebAssembly policy",
+            "type": "object",
+            "required": ["version", "module"],
+            "additionalProperties": false,
+            "properties": {
+                "version": { "const": 1 },
+                "module": {
+                    "description": "The SHA-256 hash of the policy module",
+                    "type": "string",
+                    "pattern": "^0x[0-9a-fA-F]{64}$",
+                },
+                "config": { "description": "Passed to the module as is" },
+                "max_duration": { "type": "integer", "minimum": 0 },
+                "relayers": {
+                    "type": "array",
+                    "items": { "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" },
+                },
+            },
+        })
+    }
+
+    fn validate(&self, raw_policy: serde_json::Value) -> Result<(), Error> {
+        let policy = Policy::decode(raw_policy)?;
+        self.load(&policy.module)
+            .map(|_| ())
+            .map_err(Error::PolicyDecode)
+    }
+
+    async fn verify(
+        &self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'_>,
+    ) -> Result<Verification, Error> {
+        self.evaluate(raw_policy, req, &mut Report::new()).await
+    }
+
+    async fn evaluate<'a>(
+        &'a self,
+        raw_policy: serde_json::Value,
+        req: VerificationRequest<'a>,
+        report: &'a mut Report,
+    ) -> Result<Verification, Error> {
+        let policy = match Policy::decode(raw_policy) {
+            Ok(policy) => {
+                report.pass(EvaluationStep::new("policy").observed(policy.version));
+                policy
+            }
+            Err(e) => return Err(report.fail(EvaluationStep::new("policy"), e)),
+        };
+
+        report.check(
+            EvaluationStep::new("relayer")
+                .expected(&policy.relayers)
+                .observed(req.relayer),
+            check_relayer(&policy.relayers, req.relayer),
+        )?;
+
+        let step = EvaluationStep::new("module").expected(policy.module);
+        let (modules, module) = match self.load(&policy.module) {
+            Ok(loaded) => {
+                report.pass(step);
+                loaded
+            }
+            Err(e) => return Err(report.fail(step, Error::PolicyDecode(e))),
+        };
+
+        let input = match Input::new(&req, &policy.config) {
+            Ok(input) => serde_json::to_vec(&input).expect("input is serializable"),
+            Err(e) => return Err(report.fail(EvaluationStep::new("binding"), e)),
+        };
+        let engine = modules.engine().clone();
+        let limits = self.limits;
+        let output =
+            tokio::task::spawn_blocking(move || sandbox::run(&engine, &module, limits, &input))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|output| output)
+                .and_then(|output| {
+                    serde_json::from_slice::<Output>(&output)
+                        .map_err(|e| anyhow!("module returned invalid output: {e}"))
+                });
+        let step = EvaluationStep::new("execution").expected(serde_json::json!({
+            "fuel": limits.fuel,
+            "memory": limits.memory,
+        }));
+        let output = match output {
+            Ok(output) => {
+                report.pass(step);
+                output
+            }
+            Err(e) => return Err(report.fail(step, Error::Unauthorized(format!("{e:#}")))),
+        };
+
+        let step = EvaluationStep::new("result");
+        let (nonce, public_key, duration) = match output {
+            Output::Accept {
+                nonce,
+                public_key,
+                duration,
+            } => {
+                report.pass(step.observed(serde_json::json!({
+                    "nonce": nonce,
+                    "public_key": public_key,
+                    "duration": duration,
+                })));
+                (nonce, public_key, duration)
+            }
+            Output::Reject { reason } => {
+                return Err(report.fail(step, Error::Unauthorized(reason)));
+            }
+        };
+
+        report.finish(Verification {
+            nonce: nonce.to_vec(),
+            public_key: public_key.to_vec(),
+            duration: match req.kind {
+                RequestKind::Grant {
+                    duration: requested,
+                } => Some(
+                    requested
+                        .min(duration.unwrap_or(u64::MAX))
+                        .min(policy.max_duration),
+                ),
+                RequestKind::Revoke => None,
+            },
+            measurement: None,
+            single_use: Default::default(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Input<'a> {
+    /// `grant` or `revoke`.
+    kind: &'static str,
+    /// The requested duration of a grant.
+    duration: Option<u64>,
+    chain: u64,
+    registry: Address,
+    identity: H256,
+    permitter: Address,
+    recipient: Address,
+    base_block: u64,
+    authorization: Bytes,
+    context: Bytes,
+    relayer: Option<Address>,
+    binding: H256,
+    now: u64,
+    config: &'a serde_json::Value,
+}
+
+impl<'a> Input<'a> {
+    fn new(req: &VerificationRequest<'_>, config: &'a serde_json::Value) -> Result<Self, Error> {
+        let (kind, duration) = match req.kind {
+            RequestKind::Grant { duration } => ("grant", Some(duration)),
+            RequestKind::Revoke => ("revoke", None),
+        };
+        Ok(Self {
+            kind,
+            duration,
+            chain: req.identity.chain,
+            registry: req.identity.registry,
+            identity: req.identity.id.0,
+            permitter: req.permitter,
+            recipient: req.recipient,
+            base_block: req.base_block,
+            authorization: req.authorization.to_vec().into(),
+            context: req.context.to_vec().into(),
+            relayer: req.relayer,
+            binding: H256(request_binding(2, req)?),
+            now: crate::utils::now(),
+            config,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Output {
+    Accept {
+        #[serde(default)]
+        nonce: Bytes,
+        #[serde(default)]
+        public_key: Bytes,
+        #[serde(default)]
+        duration: Option<u64>,
+    },
+    Reject {
+        reason: String,
+    },
+}
+
+#[derive(Deserialize)]
+#[forbid(unused)]
+struct Policy {
+    version: u8,
+    /// The SHA-256 hash of the module.
+    module: H256,
+    #[serde(default)]
+    config: serde_json::Value,
+    #[serde(default)]
+    max_duration: u64,
+    #[serde(default)]
+    relayers: HashSet<Address>,
+}
+
+impl Policy {
+    fn decode(raw_policy: serde_json::Value) -> Result<Self, Error> {
+        let policy: Policy = serde_json::from_value(raw_policy)
+            .map_err(|e| Error::PolicyDecode(anyhow::Error::from(e)))?;
+        if policy.version != 1 {
+            return Err(Error::UnsupportedPolicyVersion(policy.version.into()));
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest as _, Sha256};
+
+    use super::*;
+    use crate::verify::tests::request;
+
+    /// A module whose `verify` runs `body` and then returns `output`.
+    fn module(memory_pages: u32, body: &str, output: &str) -> Vec<u8> {
+        let escaped = output.replace('\\', "\\\\").replace('"', "\\\"");
+        wat::parse_str(format!(
+            r#"(module
+                (memory (export "memory") {memory_pages})
+                (data (i32.const 0) "{escaped}")
+                (func (export "alloc") (param i32) (result i32) (i32.const 4096))
+                (func (export "verify") (param i32 i32) (result i64)
+                    {body}
+                    (i64.const {len})))"#,
+            len = output.len(),
+        ))
+        .unwrap()
+    }
+
+    struct Fixture {
+        dir: tempfile::TempDir,
+        verifier: WasmVerifier,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let verifier = WasmVerifier::with_module_dir(dir.path()).with_limits(WasmLimits {
+                fuel: 100_000,
+                memory: 4 * 65536,
+            });
+            Self { dir, verifier }
+        }
+
+        /// Installs the module and returns a policy that uses it.
+        fn install(&self, wasm: &[u8]) -> serde_json::Value {
+            let hash = H256(Sha256::digest(wasm).into());
+            std::fs::write(
+                self.dir.path().join(format!("{}.wasm", hex::encode(hash))),
+                wasm,
+            )
+            .unwrap();
+            serde_json::json!({
+                "version": 1,
+                "module": hash,
+                "config": { "threshold": 3 },
+                "max_duration": 30,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_module() {
+        let fixture = Fixture::new();
+        let policy = fixture.install(&module(
+            1,
+            "",
+            r#"{"accept":{"public_key":"0x0102","nonce":"0x03","duration":20}}"#,
+        ));
+        let verification = fixture
+            .verifier
+            .verify(policy, request(b"auth"))
+            .await
+            .unwrap();
+        assert_eq!(verification.public_key, [1, 2]);
+        assert_eq!(verification.nonce, [3]);
+        assert_eq!(verification.duration, Some(20));
+
+        let policy = fixture.install(&module(1, "", r#"{"accept":{}}"#));
+        let verification = fixture
+            .verifier
+            .verify(policy, request(b"auth"))
+            .await
+            .unwrap();
+        assert_eq!(verification.duration, Some(30));
+    }
+
+    #[tokio::test]
+    async fn reject_module() {
+        let fixture = &Fixture::new();
+        let verify = |wasm: Vec<u8>| {
+            let policy = fixture.install(&wasm);
+            async move { fixture.verifier.verify(policy, request(b"auth")).await }
+        };
+
+        let rejected = verify(module(1, "", r#"{"reject":{"reason":"denied"}}"#)).await;
+        assert!(matches!(rejected, Err(Error::Unauthorized(reason)) if reason == "denied"));
+
+        for wasm in [
+            // Runs out of fuel.
+            module(1, "(loop $forever (br $forever))", r#"{"accept":{}}"#),
+            // Needs more memory than is allowed.
+            module(16, "", r#"{"accept":{}}"#),
+            module(
+                1,
+                "(if (i32.eq (memory.grow (i32.const 16)) (i32.const -1)) (then unreachable))",
+                r#"{"accept":{}}"#,
+            ),
+            module(1, "", "not json"),
+        ] {
+            assert!(matches!(verify(wasm).await, Err(Error::Unauthorized(_))));
+        }
+    }
+
+    #[test]
+    fn module_input() {
+        let config = serde_json::json!({ "threshold": 3 });
+        let input = serde_json::to_value(Input::new(&request(b"auth"), &config).unwrap()).unwrap();
+        assert_eq!(input["kind"], "grant");
+        assert_eq!(input["duration"], 60);
+        assert_eq!(input["authorization"], "0x61757468");
+        assert_eq!(input["config"], config);
+        assert_eq!(
+            input["binding"],
+            serde_json::json!(H256(request_binding(2, &request(b"auth")).unwrap()))
+        );
+    }
+
+    #[test]
+    fn validate_policy() {
+        let fixture = Fixture::new();
+        let policy = fixture.install(&module(1, "", r#"{"accept":{}}"#));
+        fixture.verifier.validate(policy.clone()).unwrap();
+        assert!(matches!(
+            WasmVerifier::default().validate(policy.clone()),
+            Err(Error::PolicyDecode(_))
+        ));
+
+        let mut bad = policy.clone();
+        bad["version"] = 2.into();
+        assert!(matches!(
+            fixture.verifier.validate(bad),
+            Err(Error::UnsupportedPolicyVersion(2))
+        ));
+
+        let mut missing = policy.clone();
+        missing["module"] = serde_json::json!(H256::repeat_byte(1));
+        assert!(matches!(
+            fixture.verifier.validate(missing),
+            Err(Error::PolicyDecode(_))
+        ));
+
+        // A module is pinned by its contents, not its file name.
+        let dir = tempfile::tempdir().unwrap();
+        let hash = H256(Sha256::digest(module(1, "", r#"{"reject":{"reason":""}}"#)).into());
+        std::fs::write(
+            dir.path().join(format!("{}.wasm", hex::encode(hash))),
+            module(1, "", r#"{"accept":{}}"#),
+        )
+        .unwrap();
+        let mut tampered = policy.clone();
+        tampered["module"] = serde_json::json!(hash);
+        assert!(matches!(
+            WasmVerifier::with_module_dir(dir.path()).validate(tampered),
+            Err(Error::PolicyDecode(_))
+        ));
+
+        let importer =
+            wat::parse_str(r#"(module (import "env" "now" (func)) (memory (export "memory") 1))"#)
+                .unwrap();
+        assert!(matches!(
+            fixture.verifier.validate(fixture.install(&importer)),
+            Err(Error::PolicyDecode(_))
+        ));
+    }
+}
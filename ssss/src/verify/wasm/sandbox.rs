@@ -0,0 +1,150 @@
+//! Runs policy modules in a fuel- and memory-limited WebAssembly interpreter.
+//!
+//! A module must export its `memory`, an `alloc(len: i32) -> i32` function that returns space
+//! for the input, and a `verify(ptr: i32, len: i32) -> i64` function that receives the JSON
+//! input and returns the location of its JSON output as `ptr << 32 | len`. Modules cannot
+//! import anything, so they have no access to the host beyond their input.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, ensure, Context as _};
+use sha2::{Digest as _, Sha256};
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+/// Outputs larger than this are rejected without being read.
+const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+/// The resources that a module may use while verifying one request.
+#[derive(Clone, Copy, Debug)]
+pub struct WasmLimits {
+    /// The number of fuel units (roughly, instructions) that a module may consume.
+    pub fuel: u64,
+    /// The size (bytes) to which a module's memory may grow.
+    pub memory: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 100_000_000,
+            memory: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Loads modules from a directory in which each is named by its SHA-256 hash.
+pub(super) struct Modules {
+    dir: PathBuf,
+    engine: Engine,
+    compiled: Mutex<HashMap<[u8; 32], Arc<Module>>>,
+}
+
+impl std::fmt::Debug for Modules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Modules").field("dir", &self.dir).finish()
+    }
+}
+
+impl Modules {
+    pub(super) fn new(dir: PathBuf) -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Self {
+            dir,
+            engine: Engine::new(&config),
+            compiled: Default::default(),
+        }
+    }
+
+    /// Returns the module whose hash is `hash`, compiling it if it has not been used before.
+    pub(super) fn load(&self, hash: &[u8; 32]) -> Result<Arc<Module>, anyhow::Error> {
+        if let Some(module) = self.compiled.lock().unwrap().get(hash) {
+            return Ok(module.clone());
+        }
+        let path = self.dir.join(format!("{}.wasm", hex::encode(hash)));
+        let wasm = std::fs::read(&path).with_context(|| format!("failed to read {path:?}"))?;
+        // The file is named by its hash, but the policy only pins the module if it is checked.
+        ensure!(
+            Sha256::digest(&wasm)[..] == hash[..],
+            "{path:?} does not have the expected hash"
+        );
+        let module = Module::new(&self.engine, &wasm[..])
+            .map_err(|e| anyhow!("failed to compile {path:?}: {e}"))?;
+        ensure!(
+            module.imports().next().is_none(),
+            "policy modules may not import anything"
+        );
+        let module = Arc::new(module);
+        self.compiled.lock().unwrap().insert(*hash, module.clone());
+        Ok(module)
+    }
+
+    pub(super) fn engine(&self) -> &Engine {
+        &self.engine
+    }
+}
+
+struct Host {
+    limits: StoreLimits,
+}
+
+/// Calls the module's `verify` function with `input` and returns its output.
+pub(super) fn run(
+    engine: &Engine,
+    module: &Module,
+    limits: WasmLimits,
+    input: &[u8],
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut store = Store::new(
+        engine,
+        Host {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory)
+                .instances(1)
+                .memories(1)
+                .tables(1)
+                .build(),
+        },
+    );
+    store.limiter(|host| &mut host.limits);
+    store
+        .add_fuel(limits.fuel)
+        .map_err(|e| anyhow!("failed to fuel module: {e}"))?;
+
+    let instance = Linker::<Host>::new(engine)
+        .instantiate(&mut store, module)
+        .and_then(|instance| instance.start(&mut store))
+        .map_err(|e| anyhow!("failed to instantiate module: {e}"))?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or_else(|| anyhow!("module does not export its memory"))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&store, "alloc")
+        .map_err(|e| anyhow!("module does not export `alloc`: {e}"))?;
+    let verify = instance
+        .get_typed_func::<(i32, i32), i64>(&store, "verify")
+        .map_err(|e| anyhow!("module does not export `verify`: {e}"))?;
+
+    let input_len = i32::try_from(input.len()).context("input is too large")?;
+    let input_ptr = alloc
+        .call(&mut store, input_len)
+        .map_err(|e| anyhow!("module trapped: {e}"))?;
+    memory
+        .write(&mut store, input_ptr as u32 as usize, input)
+        .map_err(|e| anyhow!("module allocated invalid memory: {e}"))?;
+    let output = verify
+        .call(&mut store, (input_ptr, input_len))
+        .map_err(|e| anyhow!("module trapped: {e}"))?;
+
+    let (output_ptr, output_len) = ((output >> 32) as u32 as usize, output as u32 as usize);
+    ensure!(output_len <= MAX_OUTPUT_LEN, "module output is too large");
+    let mut buf = vec![0; output_len];
+    memory
+        .read(&store, output_ptr, &mut buf)
+        .map_err(|e| anyhow!("module returned invalid memory: {e}"))?;
+    Ok(buf)
+}